@@ -0,0 +1,5851 @@
+//! Rename engine shared by the BulkReName window and `--cli`: blocks, templates,
+//! name generation, collision handling and the two-phase rename.
+//!
+//! ```no_run
+//! use bulkrename_core::{Block, Plan, Template};
+//!
+//! let files = vec![std::path::PathBuf::from("photos/IMG_0001.jpg")];
+//! let template = Template::new(vec![
+//!     Block::Literal("holiday_".into()),
+//!     Block::Number {
+//!         width: 3,
+//!         start: 1,
+//!         step: 1,
+//!         locale: Default::default(),
+//!         style: Default::default(),
+//!         pad: '0',
+//!         next: None,
+//!         group: Default::default(),
+//!     },
+//!     Block::Extension,
+//! ]);
+//! let plan = Plan::build(&files, &template);
+//! plan.execute().expect("rename failed");
+//! plan.undo().expect("undo failed");
+//! ```
+
+use chrono::{DateTime, FixedOffset, Local, TimeZone};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Block {
+    Literal(String),
+    Number {
+        width: usize,
+        start: i64,
+        step: i64,
+        // digit grouping (default: none)
+        #[serde(default)]
+        locale: Locale,
+        #[serde(default)]
+        style: NumberStyle,
+        // fills decimal, hex and binary numbers up to `width`
+        #[serde(default = "zero_pad")]
+        pad: char,
+        // continue across batches: the number the next batch starts from
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        next: Option<i64>,
+        // count separately for each key, see `group_positions`
+        #[serde(default, skip_serializing_if = "GroupKey::is_none")]
+        group: GroupKey,
+    },
+    Date {
+        format: String,
+        // month/weekday names and Japanese era
+        #[serde(default)]
+        locale: Locale,
+        // which timestamp of the file (default: the current time)
+        #[serde(default)]
+        source: DateSource,
+        #[serde(default)]
+        zone: DateZone,
+    },
+    Original {
+        mode: OriginalMode,
+        // applied after the mode
+        #[serde(default)]
+        case: NameCase,
+    },
+    // the original extension with its dot, after the template's ExtPolicy
+    Extension,
+    // the file's tag from the list
+    Tag,
+    // star rating from XMP/EXIF
+    Rating { style: RatingStyle },
+    // duration / bitrate / sample rate of audio files
+    Audio { field: AudioField },
+    // first `len` hex digits of the image's perceptual hash (dHash)
+    PHash { len: usize },
+    // EXIF DateTimeOriginal (file mtime when missing)
+    ExifDate {
+        format: String,
+        #[serde(default)]
+        locale: Locale,
+    },
+    // `len` characters of the original stem from `start` (0 = to the other end);
+    // with `from_end` both count back from the end
+    OriginalSlice { start: usize, len: usize, from_end: bool },
+    // the original stem with every match of `pattern` replaced ($1, ${name})
+    RegexReplace { pattern: String, replacement: String },
+    // the original stem tidied up, see `clean_stem`
+    Cleanup { trim: bool, collapse: bool, brackets: bool, strip: String },
+    // the original stem in ASCII, see `transliterate`
+    Transliterate { romaji: bool, fallback: String },
+    // artist / title / album / track of music files; `placeholder` when the tag is missing
+    AudioTag { field: TagField, placeholder: String },
+    // camera make / model / lens / ISO / GPS of photos; `placeholder` when EXIF doesn't have it
+    Camera { field: CameraField, placeholder: String },
+    // file size, e.g. 4.5MB
+    FileSize { unit: SizeUnit },
+    // image width x height, e.g. 1920x1080 (empty for other files)
+    Dimensions,
+    // orientation or aspect ratio of images (empty for other files)
+    Shape { field: ShapeField },
+    // text from a rhai script over the file's name and metadata (see `run_script`)
+    Script { code: String },
+    // date found in the original stem (`pattern` as for DatePattern, empty = guess) written in `format`;
+    // `missing` when there is none
+    NameDate {
+        pattern: String,
+        format: String,
+        #[serde(default)]
+        locale: Locale,
+        missing: String,
+    },
+    // `block` for the files matching `when`, `otherwise` for the rest
+    Conditional { when: Condition, block: Box<Block>, otherwise: String },
+}
+
+impl Block {
+    /// The block doing the work: the wrapped one for Conditional, `self` otherwise.
+    pub fn inner(&self) -> &Block {
+        match self {
+            Block::Conditional { block, .. } => block,
+            b => b,
+        }
+    }
+
+    pub fn inner_mut(&mut self) -> &mut Block {
+        match self {
+            Block::Conditional { block, .. } => block,
+            b => b,
+        }
+    }
+}
+
+/// What a grouped Number block counts by: each key value has its own 0, 1, 2...
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+pub enum GroupKey {
+    #[default]
+    None,
+    // the text of another block of the template (by index)
+    Block(usize),
+    // the folder the file is in
+    Folder,
+    // first capture group (or the whole match) of a regex on the original stem
+    Pattern(String),
+}
+
+impl GroupKey {
+    pub fn is_none(&self) -> bool {
+        *self == GroupKey::None
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            GroupKey::None => "all files",
+            GroupKey::Block(_) => "per block",
+            GroupKey::Folder => "per folder",
+            GroupKey::Pattern(_) => "per regex",
+        }
+    }
+
+    /// Points `Block(i)` keys at `map(i)` after blocks were moved or removed; `None` drops the grouping.
+    pub fn remap(blocks: &mut [Block], map: impl Fn(usize) -> Option<usize>) {
+        for block in blocks {
+            if let Block::Number { group, .. } = block.inner_mut() {
+                if let GroupKey::Block(i) = *group {
+                    *group = map(i).map_or(GroupKey::None, GroupKey::Block);
+                }
+            }
+        }
+    }
+
+    fn key<F: NameSource + ?Sized>(&self, blocks: &[Block], idx: usize, file: &F, now: DateTime<Local>) -> String {
+        match self {
+            GroupKey::None => String::new(),
+            GroupKey::Block(k) => match blocks.get(*k) {
+                Some(b) => render_name(std::slice::from_ref(b), idx, file, now, &ExtPolicy::Keep),
+                None => String::new(),
+            },
+            GroupKey::Folder => file.path().parent().map(|p| p.to_string_lossy().to_string()).unwrap_or_default(),
+            GroupKey::Pattern(pattern) => {
+                let stem = split_name(file).0;
+                with_regex(pattern, |re| {
+                    let caps = re?.captures(&stem)?;
+                    Some(caps.get(1).or(caps.get(0))?.as_str().to_string())
+                })
+                .unwrap_or_default()
+            }
+        }
+    }
+}
+
+/// For each of `files` (in batch order) and each block, the file's position among the earlier
+/// files with the same key of that block's group; `None` for blocks that are not grouped Numbers.
+/// `now` gives the time Date blocks use for a file.
+pub fn group_positions<F: NameSource>(
+    blocks: &[Block],
+    files: &[F],
+    now: impl Fn(&F) -> DateTime<Local>,
+) -> Vec<Vec<Option<usize>>> {
+    let grouped: Vec<usize> = (0..blocks.len())
+        .filter(|&i| matches!(blocks[i].inner(), Block::Number { group, .. } if !group.is_none()))
+        .collect();
+    if grouped.is_empty() {
+        return vec![Vec::new(); files.len()];
+    }
+    let mut counts: Vec<HashMap<String, usize>> = vec![HashMap::new(); blocks.len()];
+    let mut positions = Vec::with_capacity(files.len());
+    for (idx, file) in files.iter().enumerate() {
+        let mut pos = vec![None; blocks.len()];
+        for &i in &grouped {
+            match &blocks[i] {
+                Block::Conditional { when, .. } if !when.matches(file) => continue,
+                _ => {}
+            }
+            let Block::Number { group, .. } = blocks[i].inner() else { continue };
+            let n = counts[i].entry(group.key(blocks, idx, file, now(file))).or_insert(0);
+            pos[i] = Some(*n);
+            *n += 1;
+        }
+        positions.push(pos);
+    }
+    positions
+}
+
+/// Move the continuing Number blocks past the `used` numbers of a finished batch.
+pub fn advance_counters(blocks: &mut [Block], used: usize) {
+    for b in blocks {
+        if let Block::Number { step, next: Some(next), .. } = b.inner_mut() {
+            *next = next.saturating_add(step.saturating_mul(used as i64));
+        }
+    }
+}
+
+/// Sidecar groups among `files`: files in one folder sharing a stem ("IMG_1.CR2", "IMG_1.jpg",
+/// "IMG_1.xmp"; "IMG_1.CR2.xmp" joins when "IMG_1.CR2" is listed). For every file but the first
+/// of its group: that first file's index and the file's name after the shared stem (".jpg").
+/// Folders and files without an extension are never grouped.
+pub fn sidecar_groups<F: NameSource>(files: &[F]) -> Vec<Option<(usize, String)>> {
+    let name = |f: &F| f.path().file_name().and_then(|n| n.to_str()).unwrap_or("").to_string();
+    let listed: HashSet<(Option<&Path>, String)> =
+        files.iter().filter(|f| !f.is_dir()).map(|f| (f.path().parent(), name(f))).collect();
+    // the shared stem: the file's own, or its stem's when the stem is a listed file's name
+    let base = |f: &F| -> Option<String> {
+        let path = f.path();
+        path.extension().filter(|_| !f.is_dir())?;
+        let stem = path.file_stem()?.to_str()?;
+        let inner = Path::new(stem);
+        match inner.file_stem().and_then(|s| s.to_str()) {
+            Some(s) if inner.extension().is_some() && listed.contains(&(path.parent(), stem.to_string())) => {
+                Some(s.to_string())
+            }
+            _ => Some(stem.to_string()),
+        }
+    };
+    let bases: Vec<Option<String>> = files.iter().map(base).collect();
+    let mut first: HashMap<(Option<&Path>, &str), usize> = HashMap::new();
+    for (i, (f, b)) in files.iter().zip(&bases).enumerate() {
+        // the group is led by a file with a single extension
+        let single = f.path().file_stem().and_then(|s| s.to_str()) == b.as_deref();
+        if let (Some(b), true) = (b, single) {
+            first.entry((f.path().parent(), b)).or_insert(i);
+        }
+    }
+    files
+        .iter()
+        .zip(&bases)
+        .enumerate()
+        .map(|(i, (f, b))| {
+            let b = b.as_deref()?;
+            let &leader = first.get(&(f.path().parent(), b)).filter(|&&l| l != i)?;
+            Some((leader, name(f)[b.len()..].to_string()))
+        })
+        .collect()
+}
+
+/// Name of a sidecar that follows its group's first file, renamed `leader_new` from a name
+/// with extension `leader_ext`: the new stem plus the sidecar's own `suffix` (see `sidecar_groups`).
+/// Only the case of `ext` is applied to the suffix, so the files of a group stay apart.
+pub fn sidecar_name(leader_new: &str, leader_ext: &str, suffix: &str, ext: &ExtPolicy) -> String {
+    let written = ext.apply(leader_ext);
+    let stem = match leader_new.strip_suffix(&written) {
+        Some(s) if !written.is_empty() && s.ends_with('.') => &s[..s.len() - 1],
+        _ => leader_new,
+    };
+    let suffix = match ext {
+        ExtPolicy::Lower => suffix.to_lowercase(),
+        ExtPolicy::Upper => suffix.to_uppercase(),
+        _ => suffix.to_string(),
+    };
+    format!("{}{}", stem, suffix)
+}
+
+// which files a Conditional block applies to
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub enum Condition {
+    // one of these comma-separated extensions (case-insensitive, dots optional)
+    ExtIn(String),
+    // the original name (without extension) matches this regex
+    NameMatches(String),
+    // one of these comma-separated orientations or aspect ratios (portrait, 16x9); images only
+    ShapeIn(String),
+}
+
+impl Condition {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Condition::ExtIn(_) => "ext in",
+            Condition::NameMatches(_) => "name matches",
+            Condition::ShapeIn(_) => "shape in",
+        }
+    }
+
+    pub fn matches<F: NameSource + ?Sized>(&self, file: &F) -> bool {
+        let (stem, ext) = split_name(file);
+        match self {
+            Condition::ExtIn(list) => {
+                let ext = ext.unwrap_or_default();
+                list.split(',')
+                    .map(|e| e.trim().trim_start_matches('.'))
+                    .any(|e| !e.is_empty() && e.eq_ignore_ascii_case(&ext))
+            }
+            Condition::NameMatches(pattern) => with_regex(pattern, |re| re.is_some_and(|re| re.is_match(&stem))),
+            Condition::ShapeIn(list) => file.dimensions().is_some_and(|size| {
+                let shape = [ShapeField::Orientation.value(size), ShapeField::Aspect.value(size)];
+                list.split(',').any(|s| shape.iter().any(|v| v.eq_ignore_ascii_case(s.trim())))
+            }),
+        }
+    }
+}
+
+fn zero_pad() -> char {
+    '0'
+}
+
+#[derive(PartialEq, Copy, Clone, Debug, Default, Serialize, Deserialize)]
+pub enum DateSource {
+    #[default]
+    Now,
+    Modified,
+    // creation time; the mtime where the file system doesn't record it
+    Created,
+}
+
+impl DateSource {
+    pub const ALL: [DateSource; 3] = [DateSource::Now, DateSource::Modified, DateSource::Created];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            DateSource::Now => "now",
+            DateSource::Modified => "modified",
+            DateSource::Created => "created",
+        }
+    }
+
+    /// The date this source gives for `file`; `now` when the file can't be read.
+    pub fn date<F: NameSource + ?Sized>(self, file: &F, now: DateTime<Local>) -> DateTime<Local> {
+        let date = match self {
+            DateSource::Now => None,
+            DateSource::Modified => file.modified(),
+            DateSource::Created => file.created().or_else(|| file.modified()),
+        };
+        date.unwrap_or(now)
+    }
+}
+
+// time zone a Date block writes its time in
+#[derive(PartialEq, Copy, Clone, Debug, Default, Serialize, Deserialize)]
+pub enum DateZone {
+    #[default]
+    Local,
+    Utc,
+    // minutes east of UTC
+    Offset(i32),
+}
+
+impl DateZone {
+    pub fn label(self) -> String {
+        match self {
+            DateZone::Local => "local".to_string(),
+            DateZone::Utc => "UTC".to_string(),
+            DateZone::Offset(minutes) => format!("UTC{}", format_offset(minutes)),
+        }
+    }
+
+    /// `date` as a time in this zone.
+    pub fn convert(self, date: DateTime<Local>) -> DateTime<FixedOffset> {
+        let offset = match self {
+            DateZone::Local => return date.fixed_offset(),
+            DateZone::Utc => 0,
+            DateZone::Offset(minutes) => minutes,
+        };
+        // out-of-range offsets fall back to UTC
+        date.with_timezone(&FixedOffset::east_opt(offset * 60).unwrap_or(FixedOffset::east_opt(0).unwrap()))
+    }
+}
+
+/// `+09:00` / `-05:30` for an offset in minutes.
+pub fn format_offset(minutes: i32) -> String {
+    let sign = if minutes < 0 { '-' } else { '+' };
+    format!("{}{:02}:{:02}", sign, minutes.abs() / 60, minutes.abs() % 60)
+}
+
+/// A date written in file names, e.g. `IMG_%Y%m%d_%H%M%S`: `%Y %y %m %d %H %M %S`, `%%` for '%'.
+pub struct DatePattern {
+    re: Regex,
+    // field letter of each capture group
+    fields: Vec<char>,
+    // only where no digit comes right before or after (guessed layouts)
+    bounded: bool,
+}
+
+impl DatePattern {
+    /// `None` for a pattern without date fields or with one not listed above.
+    pub fn new(pattern: &str) -> Option<Self> {
+        Self::build(pattern, false)
+    }
+
+    fn build(pattern: &str, bounded: bool) -> Option<Self> {
+        let mut re = String::new();
+        let mut fields = Vec::new();
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                re.push_str(&regex::escape(c.encode_utf8(&mut [0; 4])));
+                continue;
+            }
+            match chars.next()? {
+                '%' => re.push('%'),
+                'Y' => {
+                    re.push_str(r"(\d{4})");
+                    fields.push('Y');
+                }
+                spec @ ('y' | 'm' | 'd' | 'H' | 'M' | 'S') => {
+                    re.push_str(r"(\d{2})");
+                    fields.push(spec);
+                }
+                _ => return None,
+            }
+        }
+        if fields.is_empty() {
+            return None;
+        }
+        Some(Self { re: Regex::new(&re).ok()?, fields, bounded })
+    }
+
+    /// The first valid date found anywhere in `name`; fields the pattern leaves out are
+    /// January 1st, midnight. `None` without a match or when every match is an impossible date.
+    pub fn parse(&self, name: &str) -> Option<DateTime<Local>> {
+        let digit = |c: Option<char>| c.is_some_and(|c| c.is_ascii_digit());
+        let mut at = 0;
+        while let Some(caps) = self.re.captures_at(name, at) {
+            let m = caps.get(0)?;
+            let bounded = !digit(name[..m.start()].chars().next_back()) && !digit(name[m.end()..].chars().next());
+            if let Some(date) = (bounded || !self.bounded).then(|| self.date(&caps)).flatten() {
+                return Some(date);
+            }
+            // a later match may overlap this one
+            at = m.start() + name[m.start()..].chars().next().map_or(1, char::len_utf8);
+        }
+        None
+    }
+
+    fn date(&self, caps: &regex::Captures) -> Option<DateTime<Local>> {
+        let (mut year, mut month, mut day, mut hms) = (1970, 1, 1, [0; 3]);
+        for (spec, m) in self.fields.iter().zip(caps.iter().skip(1)) {
+            let v: u32 = m?.as_str().parse().ok()?;
+            match spec {
+                'Y' => year = v as i32,
+                'y' => year = 2000 + v as i32,
+                'm' => month = v,
+                'd' => day = v,
+                'H' => hms[0] = v,
+                'M' => hms[1] = v,
+                _ => hms[2] = v,
+            }
+        }
+        let naive = chrono::NaiveDate::from_ymd_opt(year, month, day)?.and_hms_opt(hms[0], hms[1], hms[2])?;
+        naive.and_local_timezone(Local).earliest()
+    }
+}
+
+// layouts `date_in_name` tries without a pattern, most specific first
+const NAME_DATE_GUESSES: [&str; 6] = ["%Y%m%d_%H%M%S", "%Y-%m-%d_%H%M%S", "%Y-%m-%d", "%Y_%m_%d", "%Y.%m.%d", "%Y%m%d"];
+
+/// The date in `name` found with `pattern` (see `DatePattern`); an empty pattern first tries 10 or 13
+/// digit unix timestamps, then the usual layouts (20240305_142233, 2024-03-05, 2024_03_05, 2024.03.05,
+/// 20240305) where no other digit touches them.
+pub fn date_in_name(name: &str, pattern: &str) -> Option<DateTime<Local>> {
+    thread_local! {
+        static CACHE: std::cell::RefCell<HashMap<(String, bool), Option<DatePattern>>> = Default::default();
+    }
+    let parse = |pattern: &str, bounded: bool| {
+        CACHE.with(|cache| {
+            let mut cache = cache.borrow_mut();
+            let key = (pattern.to_string(), bounded);
+            cached(&mut cache, key, || DatePattern::build(pattern, bounded)).as_ref()?.parse(name)
+        })
+    };
+    if !pattern.is_empty() {
+        return parse(pattern, false);
+    }
+    let timestamp = with_regex(r"\d+", |re| {
+        re?.find_iter(name).map(|m| m.as_str()).filter(|d| d.len() == 10 || d.len() == 13).find_map(|digits| {
+            let secs: i64 = digits.parse().ok()?;
+            let secs = if digits.len() == 13 { secs / 1000 } else { secs };
+            Local.timestamp_opt(secs, 0).single()
+        })
+    });
+    timestamp.or_else(|| NAME_DATE_GUESSES.iter().find_map(|p| parse(p, true)))
+}
+
+#[derive(PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum AudioField {
+    // 03m21s
+    Duration,
+    // 320kbps
+    Bitrate,
+    // 44.1kHz
+    SampleRate,
+}
+
+impl AudioField {
+    pub const ALL: [AudioField; 3] = [AudioField::Duration, AudioField::Bitrate, AudioField::SampleRate];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            AudioField::Duration => "duration (03m21s)",
+            AudioField::Bitrate => "bitrate (320kbps)",
+            AudioField::SampleRate => "sample rate (44.1kHz)",
+        }
+    }
+
+    /// Empty when the value is unknown.
+    pub fn format(self, info: &AudioInfo) -> String {
+        match self {
+            AudioField::Duration if info.seconds > 0.0 => {
+                let total = info.seconds.round() as u64;
+                let (h, m, s) = (total / 3600, total / 60 % 60, total % 60);
+                if h > 0 {
+                    format!("{}h{:02}m{:02}s", h, m, s)
+                } else {
+                    format!("{:02}m{:02}s", m, s)
+                }
+            }
+            AudioField::Bitrate if info.kbps > 0 => format!("{}kbps", info.kbps),
+            AudioField::SampleRate if info.sample_rate > 0 => {
+                let khz = info.sample_rate as f64 / 1000.0;
+                format!("{}kHz", (khz * 10.0).round() / 10.0)
+            }
+            _ => String::new(),
+        }
+    }
+}
+
+// from an audio file's header
+#[derive(Copy, Clone, Debug, Default)]
+pub struct AudioInfo {
+    pub seconds: f64,
+    pub kbps: u32,
+    pub sample_rate: u32,
+}
+
+/// Duration, bitrate and sample rate of WAV, MP3, FLAC, Ogg Vorbis/Opus and MP4 audio.
+pub fn read_audio_info(path: &Path) -> Option<AudioInfo> {
+    let size = fs::metadata(path).ok()?.len();
+    let head = read_head(path, 256 * 1024)?;
+    let mut info = if head.starts_with(b"RIFF") && head.get(8..12) == Some(b"WAVE") {
+        wav_info(&head)?
+    } else if head.starts_with(b"fLaC") {
+        flac_info(&head)?
+    } else if head.starts_with(b"OggS") {
+        ogg_info(path, &head)?
+    } else if head.get(4..8) == Some(b"ftyp") {
+        mp4_info(path)?
+    } else {
+        mp3_info(&head, size)?
+    };
+    if info.kbps == 0 && info.seconds > 0.0 {
+        info.kbps = (size as f64 * 8.0 / info.seconds / 1000.0).round() as u32;
+    }
+    Some(info)
+}
+
+fn wav_info(head: &[u8]) -> Option<AudioInfo> {
+    let le32 = |at: usize| Some(u32::from_le_bytes(head.get(at..at + 4)?.try_into().ok()?));
+    let mut pos = 12;
+    let (mut sample_rate, mut byte_rate) = (0, 0);
+    while pos + 8 <= head.len() {
+        let len = le32(pos + 4)?;
+        match &head[pos..pos + 4] {
+            b"fmt " => {
+                sample_rate = le32(pos + 12)?;
+                byte_rate = le32(pos + 16)?;
+            }
+            b"data" if byte_rate > 0 => {
+                return Some(AudioInfo {
+                    seconds: len as f64 / byte_rate as f64,
+                    kbps: byte_rate / 125,
+                    sample_rate,
+                });
+            }
+            _ => {}
+        }
+        pos += 8 + len as usize + (len as usize & 1);
+    }
+    None
+}
+
+fn flac_info(head: &[u8]) -> Option<AudioInfo> {
+    // STREAMINFO is the first metadata block: rate (20 bits), channels, depth, total samples (36 bits)
+    let bits = u64::from_be_bytes(head.get(18..26)?.try_into().ok()?);
+    let sample_rate = (bits >> 44) as u32;
+    let samples = bits & 0xF_FFFF_FFFF;
+    (sample_rate > 0).then(|| AudioInfo { seconds: samples as f64 / sample_rate as f64, kbps: 0, sample_rate })
+}
+
+fn ogg_info(path: &Path, head: &[u8]) -> Option<AudioInfo> {
+    let find = |hay: &[u8], needle: &[u8]| hay.windows(needle.len()).position(|w| w == needle);
+    let le32 = |b: &[u8], at: usize| Some(u32::from_le_bytes(b.get(at..at + 4)?.try_into().ok()?));
+    // granule positions count samples at `clock` Hz (Opus: always 48 kHz, minus the pre-skip)
+    let (sample_rate, clock, skip, kbps) = if let Some(at) = find(head, b"\x01vorbis") {
+        let rate = le32(head, at + 12)?;
+        let nominal = le32(head, at + 20)? as i32;
+        (rate, rate, 0, if nominal > 0 { nominal as u32 / 1000 } else { 0 })
+    } else if let Some(at) = find(head, b"OpusHead") {
+        let pre_skip = u16::from_le_bytes(head.get(at + 10..at + 12)?.try_into().ok()?);
+        (le32(head, at + 12)?, 48000, pre_skip as u64, 0)
+    } else {
+        return None;
+    };
+    // the last page's granule position is the stream length
+    use std::io::{Read, Seek, SeekFrom};
+    let mut file = fs::File::open(path).ok()?;
+    let len = file.metadata().ok()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(64 * 1024))).ok()?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail).ok()?;
+    let last = tail.windows(4).rposition(|w| w == b"OggS")?;
+    let granule = u64::from_le_bytes(tail.get(last + 6..last + 14)?.try_into().ok()?);
+    Some(AudioInfo { seconds: granule.saturating_sub(skip) as f64 / clock.max(1) as f64, kbps, sample_rate })
+}
+
+/// The "moov" atom of an MP4 file (capped at 16 MB).
+fn mp4_moov(path: &Path) -> Option<Vec<u8>> {
+    use std::io::{Read, Seek, SeekFrom};
+    let mut file = fs::File::open(path).ok()?;
+    let len = file.metadata().ok()?.len();
+    // top-level atoms until "moov" (it may come after the media data)
+    let mut pos = 0;
+    loop {
+        if pos + 8 > len {
+            return None;
+        }
+        let mut header = [0u8; 16];
+        file.seek(SeekFrom::Start(pos)).ok()?;
+        file.read_exact(&mut header[..8]).ok()?;
+        let mut size = u32::from_be_bytes(header[..4].try_into().ok()?) as u64;
+        if size == 1 {
+            file.read_exact(&mut header[8..]).ok()?;
+            size = u64::from_be_bytes(header[8..].try_into().ok()?);
+        }
+        if &header[4..8] == b"moov" {
+            let mut moov = Vec::new();
+            file.take(size.min(16 * 1024 * 1024)).read_to_end(&mut moov).ok()?;
+            return Some(moov);
+        }
+        if size < 8 {
+            return None;
+        }
+        pos += size;
+    }
+}
+
+fn mp4_info(path: &Path) -> Option<AudioInfo> {
+    let moov = mp4_moov(path)?;
+    let find = |hay: &[u8], needle: &[u8]| hay.windows(needle.len()).position(|w| w == needle);
+    let be32 = |b: &[u8], at: usize| Some(u32::from_be_bytes(b.get(at..at + 4)?.try_into().ok()?));
+    let mvhd = find(&moov, b"mvhd")? + 4;
+    let (scale, duration) = if moov.get(mvhd) == Some(&1) {
+        (be32(&moov, mvhd + 20)?, u64::from_be_bytes(moov.get(mvhd + 24..mvhd + 32)?.try_into().ok()?))
+    } else {
+        (be32(&moov, mvhd + 12)?, be32(&moov, mvhd + 16)? as u64)
+    };
+    // the media timescale of the audio track (its "mdhd" comes before the "soun" handler) is its sample rate
+    let sample_rate = find(&moov, b"soun")
+        .and_then(|soun| moov[..soun].windows(4).rposition(|w| w == b"mdhd"))
+        .and_then(|mdhd| {
+            let at = mdhd + 4;
+            if moov.get(at) == Some(&1) { be32(&moov, at + 20) } else { be32(&moov, at + 12) }
+        })
+        .unwrap_or(0);
+    (scale > 0).then(|| AudioInfo { seconds: duration as f64 / scale as f64, kbps: 0, sample_rate })
+}
+
+fn mp3_info(head: &[u8], file_size: u64) -> Option<AudioInfo> {
+    // skip an ID3v2 tag (size is syncsafe)
+    let mut start = 0;
+    if head.starts_with(b"ID3") {
+        let b = head.get(6..10)?;
+        let size = (b[0] as usize) << 21 | (b[1] as usize) << 14 | (b[2] as usize) << 7 | b[3] as usize;
+        start = 10 + size + if head[5] & 0x10 != 0 { 10 } else { 0 };
+    }
+    // first Layer III frame header
+    let at = (start..head.len().saturating_sub(4)).find(|&i| head[i] == 0xFF && head[i + 1] & 0xE6 == 0xE2)?;
+    let (b1, b2, b3) = (head[at + 1], head[at + 2], head[at + 3]);
+    let version = (b1 >> 3) & 3; // 3 = MPEG1, 2 = MPEG2, 0 = MPEG2.5
+    let mpeg1 = version == 3;
+    const RATES_V1: [u32; 15] = [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320];
+    const RATES_V2: [u32; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+    let kbps = *if mpeg1 { &RATES_V1 } else { &RATES_V2 }.get((b2 >> 4) as usize)?;
+    let base = *[44100, 48000, 32000].get(((b2 >> 2) & 3) as usize)?;
+    let sample_rate = match version {
+        3 => base,
+        2 => base / 2,
+        0 => base / 4,
+        _ => return None,
+    };
+    let samples_per_frame = if mpeg1 { 1152 } else { 576 };
+    let mono = b3 >> 6 == 3;
+    let side_info = match (mpeg1, mono) {
+        (true, false) => 32,
+        (true, true) | (false, false) => 17,
+        (false, true) => 9,
+    };
+    let be32 = |i: usize| Some(u32::from_be_bytes(head.get(i..i + 4)?.try_into().ok()?));
+    // VBR headers carry the frame count
+    let xing = at + 4 + side_info;
+    let frames = match head.get(xing..xing + 4) {
+        Some(b"Xing") | Some(b"Info") if be32(xing + 4)? & 1 != 0 => Some(be32(xing + 8)?),
+        _ if head.get(at + 36..at + 40) == Some(b"VBRI") => Some(be32(at + 50)?),
+        _ => None,
+    };
+    let audio_bytes = file_size.saturating_sub(at as u64);
+    match frames {
+        Some(frames) if frames > 0 => {
+            let seconds = frames as f64 * samples_per_frame as f64 / sample_rate as f64;
+            let kbps = (audio_bytes as f64 * 8.0 / seconds / 1000.0).round() as u32;
+            Some(AudioInfo { seconds, kbps, sample_rate })
+        }
+        None if kbps > 0 => Some(AudioInfo {
+            seconds: audio_bytes as f64 * 8.0 / (kbps as f64 * 1000.0),
+            kbps,
+            sample_rate,
+        }),
+        _ => None,
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug, Default, Serialize, Deserialize)]
+pub enum Locale {
+    // chrono's English names, no digit grouping
+    #[default]
+    Default,
+    Ja,
+    En,
+    De,
+    Fr,
+}
+
+impl Locale {
+    pub const ALL: [Locale; 5] = [Locale::Default, Locale::Ja, Locale::En, Locale::De, Locale::Fr];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Locale::Default => "default",
+            Locale::Ja => "日本語",
+            Locale::En => "English",
+            Locale::De => "Deutsch",
+            Locale::Fr => "Français",
+        }
+    }
+
+    /// (full, short) month names, January first.
+    pub fn months(self) -> Option<([&'static str; 12], [&'static str; 12])> {
+        match self {
+            Locale::Default | Locale::En => None,
+            Locale::Ja => {
+                let m = ["1月", "2月", "3月", "4月", "5月", "6月", "7月", "8月", "9月", "10月", "11月", "12月"];
+                Some((m, m))
+            }
+            Locale::De => Some((
+                ["Januar", "Februar", "März", "April", "Mai", "Juni", "Juli", "August", "September", "Oktober", "November", "Dezember"],
+                ["Jan", "Feb", "Mär", "Apr", "Mai", "Jun", "Jul", "Aug", "Sep", "Okt", "Nov", "Dez"],
+            )),
+            Locale::Fr => Some((
+                ["janvier", "février", "mars", "avril", "mai", "juin", "juillet", "août", "septembre", "octobre", "novembre", "décembre"],
+                ["janv", "févr", "mars", "avr", "mai", "juin", "juil", "août", "sept", "oct", "nov", "déc"],
+            )),
+        }
+    }
+
+    /// (full, short) weekday names, Sunday first.
+    pub fn weekdays(self) -> Option<([&'static str; 7], [&'static str; 7])> {
+        match self {
+            Locale::Default | Locale::En => None,
+            Locale::Ja => Some((
+                ["日曜日", "月曜日", "火曜日", "水曜日", "木曜日", "金曜日", "土曜日"],
+                ["日", "月", "火", "水", "木", "金", "土"],
+            )),
+            Locale::De => Some((
+                ["Sonntag", "Montag", "Dienstag", "Mittwoch", "Donnerstag", "Freitag", "Samstag"],
+                ["So", "Mo", "Di", "Mi", "Do", "Fr", "Sa"],
+            )),
+            Locale::Fr => Some((
+                ["dimanche", "lundi", "mardi", "mercredi", "jeudi", "vendredi", "samedi"],
+                ["dim", "lun", "mar", "mer", "jeu", "ven", "sam"],
+            )),
+        }
+    }
+
+    pub fn group_separator(self) -> Option<char> {
+        match self {
+            Locale::Default => None,
+            Locale::Ja | Locale::En => Some(','),
+            Locale::De => Some('.'),
+            Locale::Fr => Some(' '),
+        }
+    }
+
+    /// `digits` with a separator every three places.
+    pub fn group(self, digits: &str) -> String {
+        let Some(sep) = self.group_separator() else {
+            return digits.to_string();
+        };
+        let (sign, digits) = match digits.strip_prefix('-') {
+            Some(rest) => ("-", rest),
+            None => ("", digits),
+        };
+        let mut out = String::from(sign);
+        for (i, c) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i) % 3 == 0 {
+                out.push(sep);
+            }
+            out.push(c);
+        }
+        out
+    }
+
+    /// Replace the name specifiers (%a %A %b %B %h) and, for Japanese, the era
+    /// (%EY = 令和6年, %Ey = R6) before the rest goes to chrono.
+    pub fn localize_format<Tz: TimeZone>(self, format: &str, date: &DateTime<Tz>) -> String {
+        use chrono::Datelike;
+        let months = self.months();
+        let weekdays = self.weekdays();
+        let month = date.month0() as usize;
+        let weekday = date.weekday().num_days_from_sunday() as usize;
+        let mut out = String::with_capacity(format.len());
+        let mut chars = format.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                out.push(c);
+                continue;
+            }
+            let named = match chars.peek() {
+                Some('B') => months.map(|m| m.0[month]),
+                Some('b' | 'h') => months.map(|m| m.1[month]),
+                Some('A') => weekdays.map(|w| w.0[weekday]),
+                Some('a') => weekdays.map(|w| w.1[weekday]),
+                _ => None,
+            };
+            if let Some(text) = named {
+                chars.next();
+                out.push_str(text);
+                continue;
+            }
+            if self == Locale::Ja && chars.peek() == Some(&'E') {
+                let mut ahead = chars.clone();
+                ahead.next();
+                if let Some(long @ ('Y' | 'y')) = ahead.next() {
+                    if let Some((name, initial, year)) = japanese_era(date) {
+                        chars.next();
+                        chars.next();
+                        if long == 'Y' {
+                            let year = if year == 1 { "元".to_string() } else { year.to_string() };
+                            out.push_str(&format!("{}{}年", name, year));
+                        } else {
+                            out.push_str(&format!("{}{}", initial, year));
+                        }
+                        continue;
+                    }
+                }
+            }
+            // not ours: keep it (and an escaped %) for chrono
+            out.push('%');
+            if let Some(&next) = chars.peek() {
+                chars.next();
+                out.push(next);
+            }
+        }
+        out
+    }
+}
+
+/// (era name, initial, year of era) for dates from Meiji on.
+pub fn japanese_era<D: chrono::Datelike>(date: &D) -> Option<(&'static str, char, i32)> {
+    const ERAS: [(&str, char, (i32, u32, u32)); 5] = [
+        ("令和", 'R', (2019, 5, 1)),
+        ("平成", 'H', (1989, 1, 8)),
+        ("昭和", 'S', (1926, 12, 25)),
+        ("大正", 'T', (1912, 7, 30)),
+        ("明治", 'M', (1868, 10, 23)),
+    ];
+    let ymd = (date.year(), date.month(), date.day());
+    ERAS.iter()
+        .find(|(_, _, start)| ymd >= *start)
+        .map(|(name, initial, start)| (*name, *initial, ymd.0 - start.0 + 1))
+}
+
+// tags of a music file
+#[derive(Clone, Debug, Default)]
+pub struct AudioTags {
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    pub album: Option<String>,
+    // as written, e.g. "3" or "3/12"
+    pub track: Option<String>,
+}
+
+impl AudioTags {
+    /// Take the fields still missing from `other`.
+    pub fn fill_from(&mut self, other: AudioTags) {
+        self.artist = self.artist.take().or(other.artist);
+        self.title = self.title.take().or(other.title);
+        self.album = self.album.take().or(other.album);
+        self.track = self.track.take().or(other.track);
+    }
+}
+
+/// Artist, title, album and track from ID3v2 (ID3v1 as fallback), Vorbis comments
+/// (FLAC, Ogg Vorbis, Opus) or MP4 metadata.
+pub fn read_audio_tags(path: &Path) -> AudioTags {
+    let Some(head) = read_head(path, 256 * 1024) else {
+        return AudioTags::default();
+    };
+    if head.starts_with(b"fLaC") {
+        flac_tags(&head)
+    } else if head.starts_with(b"OggS") {
+        ogg_tags(&head)
+    } else if head.get(4..8) == Some(b"ftyp") {
+        mp4_moov(path).map(|moov| mp4_tags(&moov)).unwrap_or_default()
+    } else {
+        let mut tags = id3v2_tags(path).unwrap_or_default();
+        if let Some(v1) = id3v1_tags(path) {
+            tags.fill_from(v1);
+        }
+        tags
+    }
+}
+
+/// Text of an ID3v2 text frame (first value only).
+fn id3_text(body: &[u8]) -> Option<String> {
+    let (&encoding, body) = body.split_first()?;
+    let text = match encoding {
+        0 => body.iter().map(|&b| b as char).collect(),
+        1 | 2 => {
+            let (be, body) = match body {
+                [0xFE, 0xFF, rest @ ..] => (true, rest),
+                [0xFF, 0xFE, rest @ ..] => (false, rest),
+                _ => (encoding == 2, body),
+            };
+            let units: Vec<u16> = body
+                .chunks_exact(2)
+                .map(|c| if be { u16::from_be_bytes([c[0], c[1]]) } else { u16::from_le_bytes([c[0], c[1]]) })
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        _ => String::from_utf8_lossy(body).into_owned(),
+    };
+    // v2.4 separates several values with NUL
+    let text = text.split('\0').next().unwrap_or("").trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+fn id3v2_tags(path: &Path) -> Option<AudioTags> {
+    let syncsafe = |b: &[u8]| b.iter().fold(0usize, |acc, &x| acc << 7 | (x & 0x7F) as usize);
+    let header = read_head(path, 10)?;
+    if !header.starts_with(b"ID3") || header.len() < 10 {
+        return None;
+    }
+    let version = header[3];
+    let size = syncsafe(&header[6..10]);
+    let data = read_head(path, (10 + size).min(16 << 20) as u64)?;
+    let mut pos = 10;
+    if header[5] & 0x40 != 0 {
+        // extended header: v2.4 counts itself, v2.3 does not
+        let b = data.get(10..14)?;
+        pos += if version == 4 { syncsafe(b) } else { u32::from_be_bytes(b.try_into().ok()?) as usize + 4 };
+    }
+    // v2.2 frames: 3-byte id and size; v2.3/2.4: 4-byte id, size and 2 flag bytes
+    let (id_len, header_len) = if version == 2 { (3, 6) } else { (4, 10) };
+    let mut tags = AudioTags::default();
+    while pos + header_len <= data.len() && data[pos] != 0 {
+        let id = &data[pos..pos + id_len];
+        let size_bytes = &data[pos + id_len..pos + id_len + if version == 2 { 3 } else { 4 }];
+        let frame_size = match version {
+            4 => syncsafe(size_bytes),
+            _ => size_bytes.iter().fold(0usize, |acc, &b| acc << 8 | b as usize),
+        };
+        let Some(body) = data.get(pos + header_len..pos + header_len + frame_size) else {
+            break;
+        };
+        let slot = match id {
+            b"TPE1" | b"TP1" => &mut tags.artist,
+            b"TIT2" | b"TT2" => &mut tags.title,
+            b"TALB" | b"TAL" => &mut tags.album,
+            b"TRCK" | b"TRK" => &mut tags.track,
+            _ => {
+                pos += header_len + frame_size;
+                continue;
+            }
+        };
+        if slot.is_none() {
+            *slot = id3_text(body);
+        }
+        pos += header_len + frame_size;
+    }
+    Some(tags)
+}
+
+/// The 128-byte tag at the end of older MP3s.
+fn id3v1_tags(path: &Path) -> Option<AudioTags> {
+    use std::io::{Read, Seek, SeekFrom};
+    let mut file = fs::File::open(path).ok()?;
+    file.seek(SeekFrom::End(-128)).ok()?;
+    let mut tag = [0u8; 128];
+    file.read_exact(&mut tag).ok()?;
+    if !tag.starts_with(b"TAG") {
+        return None;
+    }
+    let field = |range: std::ops::Range<usize>| {
+        let text: String = tag[range].iter().take_while(|&&b| b != 0).map(|&b| b as char).collect();
+        let text = text.trim().to_string();
+        (!text.is_empty()).then_some(text)
+    };
+    Some(AudioTags {
+        title: field(3..33),
+        artist: field(33..63),
+        album: field(63..93),
+        // ID3v1.1: zero byte, then the track number
+        track: (tag[125] == 0 && tag[126] != 0).then(|| tag[126].to_string()),
+    })
+}
+
+/// Fields of a Vorbis comment block (vendor string, then "KEY=value" entries).
+fn vorbis_comment_tags(data: &[u8]) -> AudioTags {
+    let le32 = |at: usize| Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?) as usize);
+    let mut tags = AudioTags::default();
+    let Some(vendor) = le32(0) else { return tags };
+    let mut pos = 4 + vendor;
+    let Some(count) = le32(pos) else { return tags };
+    pos += 4;
+    for _ in 0..count {
+        let Some(entry) = le32(pos).and_then(|len| data.get(pos + 4..pos + 4 + len)) else {
+            break;
+        };
+        pos += 4 + entry.len();
+        let entry = String::from_utf8_lossy(entry);
+        let Some((key, value)) = entry.split_once('=') else { continue };
+        let slot = match key.to_ascii_uppercase().as_str() {
+            "ARTIST" => &mut tags.artist,
+            "TITLE" => &mut tags.title,
+            "ALBUM" => &mut tags.album,
+            "TRACKNUMBER" => &mut tags.track,
+            _ => continue,
+        };
+        if slot.is_none() && !value.trim().is_empty() {
+            *slot = Some(value.trim().to_string());
+        }
+    }
+    tags
+}
+
+fn flac_tags(head: &[u8]) -> AudioTags {
+    // metadata blocks: last-flag + type, 24-bit length; type 4 = VORBIS_COMMENT
+    let mut pos = 4;
+    while let Some(header) = head.get(pos..pos + 4) {
+        let len = (header[1] as usize) << 16 | (header[2] as usize) << 8 | header[3] as usize;
+        if header[0] & 0x7F == 4 {
+            return vorbis_comment_tags(&head[pos + 4..(pos + 4 + len).min(head.len())]);
+        }
+        if header[0] & 0x80 != 0 {
+            break;
+        }
+        pos += 4 + len;
+    }
+    AudioTags::default()
+}
+
+fn ogg_tags(head: &[u8]) -> AudioTags {
+    let find = |needle: &[u8]| head.windows(needle.len()).position(|w| w == needle).map(|at| at + needle.len());
+    match find(b"\x03vorbis").or_else(|| find(b"OpusTags")) {
+        Some(at) => vorbis_comment_tags(&head[at..]),
+        None => AudioTags::default(),
+    }
+}
+
+fn mp4_tags(moov: &[u8]) -> AudioTags {
+    let be32 = |b: &[u8], at: usize| Some(u32::from_be_bytes(b.get(at..at + 4)?.try_into().ok()?) as usize);
+    let mut tags = AudioTags::default();
+    let Some(ilst) = moov.windows(4).position(|w| w == b"ilst") else { return tags };
+    // the atom's size comes right before its name
+    let Some(start) = ilst.checked_sub(4) else { return tags };
+    let end = be32(moov, start).map_or(moov.len(), |size| (start + size).min(moov.len()));
+    let mut pos = ilst + 4;
+    while pos + 8 <= end {
+        let Some(size) = be32(moov, pos).filter(|&s| s >= 8) else { break };
+        let item = &moov[pos + 8..(pos + size).min(end)];
+        // first child: size, "data", type, locale, value
+        let value = match (be32(item, 0), item.get(4..8)) {
+            (Some(len), Some(b"data")) => item.get(16..len.min(item.len())),
+            _ => None,
+        };
+        if let Some(value) = value {
+            let text = || {
+                let s = String::from_utf8_lossy(value).trim().to_string();
+                (!s.is_empty()).then_some(s)
+            };
+            match &moov[pos + 4..pos + 8] {
+                b"\xA9ART" => tags.artist = text(),
+                b"\xA9nam" => tags.title = text(),
+                b"\xA9alb" => tags.album = text(),
+                // reserved, track, total
+                b"trkn" if value.len() >= 4 => {
+                    let track = u16::from_be_bytes([value[2], value[3]]);
+                    tags.track = (track > 0).then(|| track.to_string());
+                }
+                _ => {}
+            }
+        }
+        pos += size;
+    }
+    tags
+}
+
+#[derive(PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum TagField {
+    Artist,
+    Title,
+    Album,
+    // two digits, 01
+    Track,
+}
+
+impl TagField {
+    pub const ALL: [TagField; 4] = [TagField::Artist, TagField::Title, TagField::Album, TagField::Track];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            TagField::Artist => "artist",
+            TagField::Title => "title",
+            TagField::Album => "album",
+            TagField::Track => "track (01)",
+        }
+    }
+
+    /// The field's text; slashes become `_` so a tag can't reach into another folder.
+    pub fn value(self, tags: &AudioTags) -> Option<String> {
+        let text = match self {
+            TagField::Artist => tags.artist.clone()?,
+            TagField::Title => tags.title.clone()?,
+            TagField::Album => tags.album.clone()?,
+            TagField::Track => {
+                let track = tags.track.as_deref()?;
+                let number = track.split('/').next().unwrap_or(track).trim();
+                match number.parse::<u32>() {
+                    Ok(n) => format!("{:02}", n),
+                    Err(_) => number.to_string(),
+                }
+            }
+        };
+        Some(text.replace(['/', '\\'], "_"))
+    }
+}
+
+// camera settings from a photo's EXIF
+#[derive(Clone, Debug, Default)]
+pub struct CameraInfo {
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub lens: Option<String>,
+    pub iso: Option<u32>,
+    // latitude and longitude in degrees, negative for S and W
+    pub gps: Option<(f64, f64)>,
+}
+
+#[derive(PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum CameraField {
+    Make,
+    Model,
+    Lens,
+    // ISO200
+    Iso,
+    // 35.681N_139.767E
+    Gps,
+}
+
+impl CameraField {
+    pub const ALL: [CameraField; 5] =
+        [CameraField::Make, CameraField::Model, CameraField::Lens, CameraField::Iso, CameraField::Gps];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            CameraField::Make => "make",
+            CameraField::Model => "model",
+            CameraField::Lens => "lens",
+            CameraField::Iso => "ISO (ISO200)",
+            CameraField::Gps => "GPS (35.681N_139.767E)",
+        }
+    }
+
+    /// The field's text; GPS is rounded to 3 decimals (about 100 m), slashes become `_`.
+    pub fn value(self, info: &CameraInfo) -> Option<String> {
+        let text = match self {
+            CameraField::Make => info.make.clone()?,
+            CameraField::Model => info.model.clone()?,
+            CameraField::Lens => info.lens.clone()?,
+            CameraField::Iso => format!("ISO{}", info.iso?),
+            CameraField::Gps => {
+                let (lat, lon) = info.gps?;
+                let ns = if lat < 0.0 { 'S' } else { 'N' };
+                let ew = if lon < 0.0 { 'W' } else { 'E' };
+                format!("{:.3}{}_{:.3}{}", lat.abs(), ns, lon.abs(), ew)
+            }
+        };
+        let text = text.trim().replace(['/', '\\'], "_");
+        (!text.is_empty()).then_some(text)
+    }
+}
+
+/// EXIF data as TIFF (the APP1 payload after "Exif\0\0").
+struct Tiff<'a> {
+    data: &'a [u8],
+    le: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let le = match data.get(..2)? {
+            b"II" => true,
+            b"MM" => false,
+            _ => return None,
+        };
+        Some(Self { data, le })
+    }
+
+    fn u16(&self, off: usize) -> Option<u16> {
+        let b: [u8; 2] = self.data.get(off..off + 2)?.try_into().ok()?;
+        Some(if self.le { u16::from_le_bytes(b) } else { u16::from_be_bytes(b) })
+    }
+
+    fn u32(&self, off: usize) -> Option<u32> {
+        let b: [u8; 4] = self.data.get(off..off + 4)?.try_into().ok()?;
+        Some(if self.le { u32::from_le_bytes(b) } else { u32::from_be_bytes(b) })
+    }
+
+    fn ifd0(&self) -> Option<usize> {
+        self.u32(4).map(|o| o as usize)
+    }
+
+    /// (type, count, offset of the value field) of `tag` in the IFD at `ifd`.
+    fn entry(&self, ifd: usize, tag: u16) -> Option<(u16, u32, usize)> {
+        let count = self.u16(ifd)? as usize;
+        (0..count)
+            .map(|i| ifd + 2 + i * 12)
+            .find(|&e| self.u16(e) == Some(tag))
+            .and_then(|e| Some((self.u16(e + 2)?, self.u32(e + 4)?, e + 8)))
+    }
+
+    /// ASCII value of `tag`, without the trailing NULs.
+    fn text(&self, ifd: usize, tag: u16) -> Option<String> {
+        let (_, count, value) = self.entry(ifd, tag)?;
+        let at = if count > 4 { self.u32(value)? as usize } else { value };
+        let raw = self.data.get(at..at + count as usize)?;
+        Some(String::from_utf8_lossy(raw).trim_end_matches('\0').to_string())
+    }
+
+    /// SHORT or LONG value of `tag`.
+    fn number(&self, ifd: usize, tag: u16) -> Option<u32> {
+        match self.entry(ifd, tag)? {
+            (3, _, value) => self.u16(value).map(u32::from),
+            (4, _, value) => self.u32(value),
+            _ => None,
+        }
+    }
+
+    /// The `count` RATIONALs of `tag` as floats.
+    fn rationals(&self, ifd: usize, tag: u16) -> Option<Vec<f64>> {
+        let (kind, count, value) = self.entry(ifd, tag)?;
+        if kind != 5 {
+            return None;
+        }
+        let at = self.u32(value)? as usize;
+        (0..count as usize)
+            .map(|i| {
+                let (num, den) = (self.u32(at + i * 8)?, self.u32(at + i * 8 + 4)?);
+                (den != 0).then(|| num as f64 / den as f64)
+            })
+            .collect()
+    }
+
+    /// IFD the pointer `tag` (0x8769 EXIF, 0x8825 GPS) leads to.
+    fn sub_ifd(&self, ifd: usize, tag: u16) -> Option<usize> {
+        self.entry(ifd, tag).and_then(|(_, _, value)| self.u32(value)).map(|o| o as usize)
+    }
+
+    /// All SHORT or LONG values of `tag`, stored in the entry or where it points.
+    fn numbers(&self, ifd: usize, tag: u16) -> Vec<u32> {
+        let Some((kind, count, value)) = self.entry(ifd, tag) else { return Vec::new() };
+        let size = match kind {
+            3 => 2,
+            4 => 4,
+            _ => return Vec::new(),
+        };
+        let count = (count as usize).min(1024);
+        let at = if count * size > 4 { self.u32(value).map_or(usize::MAX, |o| o as usize) } else { value };
+        (0..count)
+            .map_while(|i| match size {
+                2 => self.u16(at.checked_add(i * 2)?).map(u32::from),
+                _ => self.u32(at.checked_add(i * 4)?),
+            })
+            .collect()
+    }
+
+    /// Offset of the IFD after the one at `ifd` (0 ends the chain).
+    fn next_ifd(&self, ifd: usize) -> Option<usize> {
+        let count = self.u16(ifd)? as usize;
+        self.u32(ifd + 2 + count * 12).map(|o| o as usize).filter(|&o| o != 0)
+    }
+}
+
+/// Width and height of the JPEG starting at `data` (baseline or progressive only:
+/// the lossless JPEG some RAW formats use for the sensor data is skipped).
+fn jpeg_size(data: &[u8]) -> Option<(usize, usize)> {
+    let be16 = |at: usize| Some(u16::from_be_bytes([*data.get(at)?, *data.get(at + 1)?]) as usize);
+    if data.get(..2)? != [0xFF, 0xD8] {
+        return None;
+    }
+    let mut pos = 2;
+    while *data.get(pos)? == 0xFF {
+        match *data.get(pos + 1)? {
+            0xFF => pos += 1,
+            0xC0..=0xC2 => return Some((be16(pos + 7)?, be16(pos + 5)?)),
+            // other frame types (lossless, hierarchical, arithmetic); C4, C8 and CC aren't frames
+            0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF | 0xD9 | 0xDA => return None,
+            0x01 | 0xD0..=0xD7 => pos += 2,
+            _ => pos += 2 + be16(pos + 2)?,
+        }
+    }
+    None
+}
+
+/// (offset, length) of the JPEG previews a RAW file points at: the IFDs of TIFF-based
+/// formats (DNG, CR2, NEF, ARW), the PRVW and THMB boxes of CR3.
+fn raw_previews(data: &[u8]) -> Vec<(usize, usize)> {
+    let Some(tiff) = Tiff::new(data) else {
+        // CR3: the box is followed by a short header, then the JPEG with its length before it
+        let head = &data[..data.len().min(4 << 20)];
+        return [b"PRVW", b"THMB"]
+            .iter()
+            .filter_map(|name| head.windows(4).position(|w| w == *name))
+            .filter_map(|at| {
+                let soi = at + head[at..(at + 64).min(head.len())].windows(3).position(|w| w == [0xFF, 0xD8, 0xFF])?;
+                Some((soi, data.len() - soi))
+            })
+            .collect();
+    };
+    let mut queue: Vec<usize> = tiff.ifd0().into_iter().collect();
+    let mut seen = HashSet::new();
+    let mut previews = Vec::new();
+    // a broken file may link IFDs in a loop
+    while let Some(ifd) = queue.pop().filter(|_| seen.len() < 64) {
+        if !seen.insert(ifd) {
+            continue;
+        }
+        let single = |tag: u16| Some(*tiff.numbers(ifd, tag).first()? as usize);
+        if let (Some(at), Some(len)) = (single(0x0201), single(0x0202)) {
+            previews.push((at, len));
+        }
+        // one JPEG-compressed strip (old-style or new-style JPEG)
+        if matches!(tiff.number(ifd, 0x0103), Some(6 | 7)) && tiff.numbers(ifd, 0x0111).len() == 1 {
+            if let (Some(at), Some(len)) = (single(0x0111), single(0x0117)) {
+                previews.push((at, len));
+            }
+        }
+        queue.extend(tiff.numbers(ifd, 0x014A).into_iter().map(|o| o as usize));
+        queue.extend(tiff.next_ifd(ifd));
+    }
+    previews
+}
+
+/// The embedded JPEG of a RAW file best suited for a `want`-sized thumbnail: the smallest
+/// one at least that big, else the biggest.
+pub fn embedded_jpeg(data: &[u8], want: (usize, usize)) -> Option<&[u8]> {
+    // (jpeg, width, height)
+    let previews: Vec<(&[u8], usize, usize)> = raw_previews(data)
+        .into_iter()
+        .filter_map(|(at, len)| data.get(at..at.checked_add(len)?.min(data.len())))
+        .filter_map(|jpeg| jpeg_size(jpeg).map(|(w, h)| (jpeg, w, h)))
+        .filter(|&(_, w, h)| w * h > 0)
+        .collect();
+    let best = match previews.iter().filter(|p| p.1 >= want.0 || p.2 >= want.1).min_by_key(|p| p.1 * p.2) {
+        Some(p) => p,
+        None => previews.iter().max_by_key(|p| p.1 * p.2)?,
+    };
+    Some(best.0)
+}
+
+/// First `limit` bytes of a file.
+fn read_head(path: &Path, limit: u64) -> Option<Vec<u8>> {
+    use std::io::Read;
+    let mut buf = Vec::new();
+    fs::File::open(path).ok()?.take(limit).read_to_end(&mut buf).ok()?;
+    Some(buf)
+}
+
+/// EXIF block of a JPEG file.
+fn jpeg_exif(head: &[u8]) -> Option<&[u8]> {
+    if head.get(..2)? != [0xFF, 0xD8] {
+        return None;
+    }
+    let mut pos = 2;
+    while pos + 4 <= head.len() && head[pos] == 0xFF {
+        let marker = head[pos + 1];
+        let len = u16::from_be_bytes([head[pos + 2], head[pos + 3]]) as usize;
+        // start of scan: no more metadata
+        if marker == 0xDA {
+            return None;
+        }
+        let payload = head.get(pos + 4..pos + 2 + len)?;
+        if marker == 0xE1 && payload.starts_with(b"Exif\0\0") {
+            return Some(&payload[6..]);
+        }
+        pos += 2 + len;
+    }
+    None
+}
+
+/// EXIF block of a JPEG, TIFF-based (TIFF, DNG, CR2, NEF, ARW …) or HEIC file.
+fn find_exif(head: &[u8]) -> Option<&[u8]> {
+    if head.starts_with(b"II*\0") || head.starts_with(b"MM\0*") {
+        return Some(head);
+    }
+    jpeg_exif(head).or_else(|| {
+        // HEIC keeps it in an item of its own, marked like in JPEG
+        let at = head.windows(8).position(|w| w == b"Exif\0\0MM" || w == b"Exif\0\0II")?;
+        Some(&head[at + 6..])
+    })
+}
+
+/// `xmp:Rating` in XMP text, as attribute or element; -1 (rejected) counts as 0.
+fn xmp_rating(xmp: &[u8]) -> Option<u8> {
+    let key = b"xmp:Rating";
+    let at = xmp.windows(key.len()).position(|w| w == key)? + key.len();
+    let rest = &xmp[at..(at + 8).min(xmp.len())];
+    let rest = rest.strip_prefix(b"=\"").or_else(|| rest.strip_prefix(b"='")).or_else(|| rest.strip_prefix(b">"))?;
+    if rest.first() == Some(&b'-') {
+        return Some(0);
+    }
+    let digit = *rest.first()?;
+    digit.is_ascii_digit().then(|| (digit - b'0').min(5))
+}
+
+/// Star rating from an .xmp sidecar, embedded XMP or the EXIF Rating tag.
+pub fn read_rating(path: &Path) -> Option<u8> {
+    // "IMG_1.xmp" (Lightroom) or "IMG_1.CR2.xmp" (darktable)
+    let mut sidecar = path.as_os_str().to_os_string();
+    sidecar.push(".xmp");
+    for xmp in [path.with_extension("xmp"), PathBuf::from(sidecar)] {
+        if xmp != path {
+            if let Some(rating) = fs::read(&xmp).ok().and_then(|x| xmp_rating(&x)) {
+                return Some(rating);
+            }
+        }
+    }
+    embedded_rating(&read_head(path, 512 * 1024)?)
+}
+
+/// Rating in a file's own XMP packet, else its EXIF Rating tag.
+fn embedded_rating(head: &[u8]) -> Option<u8> {
+    xmp_rating(head).or_else(|| {
+        let tiff = Tiff::new(jpeg_exif(head)?)?;
+        let (_, _, value) = tiff.entry(tiff.ifd0()?, 0x4746)?;
+        tiff.u16(value).map(|r| r.min(5) as u8)
+    })
+}
+
+/// DateTimeOriginal (else DateTimeDigitized, else DateTime) from EXIF.
+pub fn read_exif_date(path: &Path) -> Option<DateTime<Local>> {
+    exif_date(&read_head(path, 1024 * 1024)?)?.and_local_timezone(Local).earliest()
+}
+
+/// `read_exif_date` on the first bytes of a file.
+fn exif_date(head: &[u8]) -> Option<chrono::NaiveDateTime> {
+    let tiff = Tiff::new(find_exif(head)?)?;
+    let ifd0 = tiff.ifd0()?;
+    let stamp = tiff
+        .sub_ifd(ifd0, 0x8769)
+        .and_then(|ifd| tiff.text(ifd, 0x9003).or_else(|| tiff.text(ifd, 0x9004)))
+        .or_else(|| tiff.text(ifd0, 0x0132))?;
+    chrono::NaiveDateTime::parse_from_str(stamp.trim(), "%Y:%m:%d %H:%M:%S").ok()
+}
+
+/// Make, model, lens, ISO and GPS position from EXIF.
+pub fn read_camera_info(path: &Path) -> Option<CameraInfo> {
+    camera_info(&read_head(path, 1024 * 1024)?)
+}
+
+/// `read_camera_info` on the first bytes of a file.
+fn camera_info(head: &[u8]) -> Option<CameraInfo> {
+    let tiff = Tiff::new(find_exif(head)?)?;
+    let ifd0 = tiff.ifd0()?;
+    let exif = tiff.sub_ifd(ifd0, 0x8769);
+    let gps = tiff.sub_ifd(ifd0, 0x8825).and_then(|ifd| {
+        // degrees, minutes, seconds; the reference letter gives the sign
+        let coord = |tag: u16, negative: &str| {
+            let dms = tiff.rationals(ifd, tag)?;
+            let degrees = dms.first()? + dms.get(1).unwrap_or(&0.0) / 60.0 + dms.get(2).unwrap_or(&0.0) / 3600.0;
+            Some(if tiff.text(ifd, tag - 1)?.trim() == negative { -degrees } else { degrees })
+        };
+        Some((coord(2, "S")?, coord(4, "W")?))
+    });
+    Some(CameraInfo {
+        make: tiff.text(ifd0, 0x010F),
+        model: tiff.text(ifd0, 0x0110),
+        lens: exif.and_then(|ifd| tiff.text(ifd, 0xA434)),
+        iso: exif.and_then(|ifd| tiff.number(ifd, 0x8827)),
+        gps,
+    })
+}
+
+#[derive(PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum ShapeField {
+    // portrait / landscape / square
+    Orientation,
+    // 16x9, 3x2, 9x16
+    Aspect,
+}
+
+impl ShapeField {
+    pub const ALL: [ShapeField; 2] = [ShapeField::Orientation, ShapeField::Aspect];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ShapeField::Orientation => "orientation (portrait)",
+            ShapeField::Aspect => "aspect (16x9)",
+        }
+    }
+
+    pub fn value(self, (width, height): (u32, u32)) -> String {
+        match self {
+            ShapeField::Orientation => orientation(width, height).to_string(),
+            ShapeField::Aspect => aspect_ratio(width, height),
+        }
+    }
+}
+
+/// "portrait", "landscape" or "square" (sides within 1% of each other).
+pub fn orientation(width: u32, height: u32) -> &'static str {
+    if width.abs_diff(height) * 100 <= width.max(height) {
+        "square"
+    } else if width > height {
+        "landscape"
+    } else {
+        "portrait"
+    }
+}
+
+/// Aspect ratio as `16x9`: the nearest common ratio within 3%, else the reduced fraction.
+pub fn aspect_ratio(width: u32, height: u32) -> String {
+    const COMMON: [(u32, u32); 8] = [(1, 1), (5, 4), (4, 3), (3, 2), (16, 10), (16, 9), (21, 9), (32, 9)];
+    if width == 0 || height == 0 {
+        return String::new();
+    }
+    let (long, short) = (width.max(height), width.min(height));
+    let ratio = long as f64 / short as f64;
+    let nearest = COMMON
+        .iter()
+        .map(|&(a, b)| ((a, b), (ratio / (a as f64 / b as f64) - 1.0).abs()))
+        .filter(|&(_, off)| off < 0.03)
+        .min_by(|x, y| x.1.total_cmp(&y.1));
+    let (a, b) = match nearest {
+        Some((ab, _)) => ab,
+        None => {
+            let gcd = (1..=short).rev().find(|d| long % d == 0 && short % d == 0).unwrap_or(1);
+            (long / gcd, short / gcd)
+        }
+    };
+    if width >= height {
+        format!("{}x{}", a, b)
+    } else {
+        format!("{}x{}", b, a)
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug, Default, Serialize, Deserialize)]
+pub enum SizeUnit {
+    // B below 1KB, then KB, MB or GB
+    #[default]
+    Auto,
+    Bytes,
+    Kilobytes,
+    Megabytes,
+}
+
+impl SizeUnit {
+    pub const ALL: [SizeUnit; 4] = [SizeUnit::Auto, SizeUnit::Bytes, SizeUnit::Kilobytes, SizeUnit::Megabytes];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SizeUnit::Auto => "auto (845B, 12KB, 4.5MB)",
+            SizeUnit::Bytes => "bytes (123456B)",
+            SizeUnit::Kilobytes => "KB (121KB)",
+            SizeUnit::Megabytes => "MB (0.1MB)",
+        }
+    }
+
+    /// Units of 1024, like the size field of Scope.
+    pub fn format(self, bytes: u64) -> String {
+        const KB: f64 = 1024.0;
+        let b = bytes as f64;
+        match self {
+            SizeUnit::Bytes => format!("{}B", bytes),
+            SizeUnit::Kilobytes => format!("{:.0}KB", b / KB),
+            SizeUnit::Megabytes => format!("{:.1}MB", b / (KB * KB)),
+            SizeUnit::Auto if bytes < 1024 => format!("{}B", bytes),
+            SizeUnit::Auto if b < KB * KB => format!("{:.0}KB", b / KB),
+            SizeUnit::Auto if b < KB * KB * KB => format!("{:.1}MB", b / (KB * KB)),
+            SizeUnit::Auto => format!("{:.1}GB", b / (KB * KB * KB)),
+        }
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum RatingStyle {
+    // ★★★ (nothing when unrated)
+    Stars,
+    // r3 (r0 when unrated)
+    Short,
+}
+
+impl RatingStyle {
+    pub fn format(self, rating: u8) -> String {
+        match self {
+            RatingStyle::Stars => "★".repeat(rating as usize),
+            RatingStyle::Short => format!("r{}", rating),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum OriginalMode {
+    Keep,
+    RemoveRange { start: i32, end: i32, },
+    RemoveSubstring { pattern: String, case_sensitive: bool, },
+}
+
+#[derive(PartialEq, Copy, Clone, Debug, Default, Serialize, Deserialize)]
+pub enum NameCase {
+    #[default]
+    Keep,
+    Upper,
+    Lower,
+    Title,
+    Snake,
+    Kebab,
+}
+
+impl NameCase {
+    pub const ALL: [NameCase; 6] = [
+        NameCase::Keep,
+        NameCase::Upper,
+        NameCase::Lower,
+        NameCase::Title,
+        NameCase::Snake,
+        NameCase::Kebab,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            NameCase::Keep => "as is",
+            NameCase::Upper => "UPPERCASE",
+            NameCase::Lower => "lowercase",
+            NameCase::Title => "Title Case",
+            NameCase::Snake => "snake_case",
+            NameCase::Kebab => "kebab-case",
+        }
+    }
+
+    pub fn apply(self, name: &str) -> String {
+        match self {
+            NameCase::Keep => name.to_string(),
+            NameCase::Upper => name.to_uppercase(),
+            NameCase::Lower => name.to_lowercase(),
+            NameCase::Title => title_case(name),
+            NameCase::Snake => words(name).join("_"),
+            NameCase::Kebab => words(name).join("-"),
+        }
+    }
+}
+
+/// First letter of each word upper case, the rest lower case.
+pub fn title_case(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    let mut word_start = true;
+    for c in name.chars() {
+        if word_start {
+            out.extend(c.to_uppercase());
+        } else {
+            out.extend(c.to_lowercase());
+        }
+        word_start = matches!(c, ' ' | '_' | '-' | '.' | '(' | '[');
+    }
+    out
+}
+
+/// Order names the way people read them: digit runs compare as numbers
+/// (`img2` before `img10`), letters ignore case.
+pub fn natural_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    use std::cmp::Ordering;
+    let (mut ai, mut bi) = (a.chars().peekable(), b.chars().peekable());
+    while let (Some(&ca), Some(&cb)) = (ai.peek(), bi.peek()) {
+        let ord = if ca.is_ascii_digit() && cb.is_ascii_digit() {
+            let run = |it: &mut std::iter::Peekable<std::str::Chars>| {
+                let mut digits = String::new();
+                while let Some(c) = it.next_if(char::is_ascii_digit) {
+                    digits.push(c);
+                }
+                digits
+            };
+            let (da, db) = (run(&mut ai), run(&mut bi));
+            let (ta, tb) = (da.trim_start_matches('0'), db.trim_start_matches('0'));
+            // "007" after "7"
+            ta.len().cmp(&tb.len()).then_with(|| ta.cmp(tb)).then_with(|| da.len().cmp(&db.len()))
+        } else {
+            ai.next();
+            bi.next();
+            ca.to_lowercase().cmp(cb.to_lowercase())
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    ai.count().cmp(&bi.count()).then_with(|| a.cmp(b))
+}
+
+/// Lower-case words split at spaces, `_`, `-`, `.` and camelCase humps.
+fn words(name: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut prev: Option<char> = None;
+    for c in name.chars() {
+        if c.is_whitespace() || matches!(c, '_' | '-' | '.') {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            prev = None;
+            continue;
+        }
+        if c.is_uppercase() && prev.is_some_and(|p| p.is_lowercase() || p.is_numeric()) {
+            words.push(std::mem::take(&mut word));
+        }
+        word.extend(c.to_lowercase());
+        prev = Some(c);
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// The original stem after `mode`.
+pub fn process_original(name: &str, mode: &OriginalMode) -> String {
+    match mode {
+        OriginalMode::Keep => name.to_string(),
+
+        OriginalMode::RemoveSubstring { pattern, case_sensitive } => {
+            let mut result = name.to_string();
+
+            for p in pattern.split('/') {
+                let p = p.trim();
+
+                // 空要素無視
+                if p.is_empty() {
+                    continue;
+                }
+
+                if *case_sensitive {
+                    result = result.replace(p, "");
+                } else {
+                    // case-insensitive replace
+                    let mut tmp = String::new();
+                    let mut rest = result.as_str();
+
+                    let p_lower = p.to_lowercase();
+
+                    while let Some(pos) = rest.to_lowercase().find(&p_lower) {
+                        tmp.push_str(&rest[..pos]);
+                        rest = &rest[pos + p.len()..];
+                    }
+                    tmp.push_str(rest);
+                    result = tmp;
+                }
+            }
+            result
+        }
+
+        OriginalMode::RemoveRange { start, end } => {
+            let chars: Vec<char> = name.chars().collect();
+            let len = chars.len() as i32;
+            let s = if *start < 0 { len + *start } else { *start };
+            let e = if *end < 0 { len + *end + 1 } else { *end };
+            let s = s.clamp(0, len);
+            let e = e.clamp(0, len);
+            if s >= e {
+                return name.to_string();
+            }
+            chars[..s as usize]
+                .iter()
+                .chain(chars[e as usize..].iter())
+                .collect()
+        }
+    }
+}
+
+/// `len` user-perceived characters of `stem` starting `start` from the beginning, or with
+/// `from_end` ending `start` before the end. `len` 0 takes everything on that side.
+pub fn slice_stem(stem: &str, start: usize, len: usize, from_end: bool) -> String {
+    use unicode_segmentation::UnicodeSegmentation;
+    let graphemes: Vec<&str> = stem.graphemes(true).collect();
+    let n = graphemes.len();
+    let (from, to) = if from_end {
+        let to = n.saturating_sub(start);
+        let from = if len == 0 { 0 } else { to.saturating_sub(len) };
+        (from, to)
+    } else {
+        let from = start.min(n);
+        let to = if len == 0 { n } else { (from + len).min(n) };
+        (from, to)
+    };
+    graphemes[from..to].concat()
+}
+
+/// `stem` with every match of `pattern` replaced; capture groups are `$1` or `${name}`.
+/// An invalid pattern leaves the stem unchanged.
+pub fn regex_replace(stem: &str, pattern: &str, replacement: &str) -> String {
+    with_regex(pattern, |re| match re {
+        Some(re) => re.replace_all(stem, replacement).into_owned(),
+        None => stem.to_string(),
+    })
+}
+
+/// `stem` with the characters in `strip` removed, then `brackets`: `[1080p]`, `(copy)`, `{x}`, `【x】`
+/// and `（x）` segments dropped, `collapse`: runs of spaces or of `_` cut to one, `trim`: spaces and `_`
+/// at both ends removed.
+pub fn clean_stem(stem: &str, trim: bool, collapse: bool, brackets: bool, strip: &str) -> String {
+    let mut name: String = stem.chars().filter(|c| !strip.contains(*c)).collect();
+    if brackets {
+        // innermost first, so nested segments go as a whole
+        let re = r"\[[^\[\]]*\]|\([^()]*\)|\{[^{}]*\}|【[^【】]*】|（[^（）]*）";
+        loop {
+            let next = with_regex(re, |re| re.map(|re| re.replace_all(&name, "").into_owned()));
+            match next {
+                Some(next) if next != name => name = next,
+                _ => break,
+            }
+        }
+    }
+    if collapse {
+        name = with_regex(r"\s{2,}", |re| re.map(|re| re.replace_all(&name, " ").into_owned())).unwrap_or(name);
+        name = with_regex("_{2,}", |re| re.map(|re| re.replace_all(&name, "_").into_owned())).unwrap_or(name);
+    }
+    if trim {
+        name = name.trim_matches(|c: char| c.is_whitespace() || c == '_').to_string();
+    }
+    name
+}
+
+/// `text` in ASCII: full-width forms narrowed, accents stripped (é → e, ß → ss), kana in Hepburn
+/// romaji with `romaji` (コーヒー → koohii), anything else left (kanji, other scripts) → `fallback`.
+pub fn transliterate(text: &str, romaji: bool, fallback: &str) -> String {
+    use unicode_normalization::UnicodeNormalization;
+    let text: String = text.nfkc().collect();
+    let text = if romaji { kana_to_romaji(&text) } else { text };
+    let mut out = String::with_capacity(text.len());
+    for c in text.nfd() {
+        if c.is_ascii() {
+            out.push(c);
+        } else if unicode_normalization::char::is_combining_mark(c) {
+            // accents, kana voicing marks
+        } else if let Some(s) = latin_ascii(c) {
+            out.push_str(s);
+        } else {
+            out.push_str(fallback);
+        }
+    }
+    out
+}
+
+/// Latin letters and punctuation that have no decomposition.
+fn latin_ascii(c: char) -> Option<&'static str> {
+    Some(match c {
+        'ß' => "ss",
+        'æ' => "ae",
+        'Æ' => "AE",
+        'œ' => "oe",
+        'Œ' => "OE",
+        'ø' => "o",
+        'Ø' => "O",
+        'đ' | 'ð' => "d",
+        'Đ' | 'Ð' => "D",
+        'ł' => "l",
+        'Ł' => "L",
+        'þ' => "th",
+        'Þ' => "Th",
+        'ı' => "i",
+        '‐' | '‑' | '‒' | '–' | '—' => "-",
+        '‘' | '’' => "'",
+        _ => return None,
+    })
+}
+
+/// Kana (hiragana and katakana) in Hepburn romaji; other characters are kept.
+fn kana_to_romaji(text: &str) -> String {
+    // katakana → hiragana
+    let chars: Vec<char> = text
+        .chars()
+        .map(|c| match c {
+            'ァ'..='ヶ' => char::from_u32(c as u32 - 0x60).unwrap_or(c),
+            c => c,
+        })
+        .collect();
+    let mut out = String::new();
+    let mut double = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+        let Some(syllable) = kana_syllable(c) else {
+            match c {
+                'っ' => double = true,
+                // long vowel: repeat the last one
+                'ー' => {
+                    if let Some(v) = out.chars().last().filter(|v| "aiueo".contains(*v)) {
+                        out.push(v);
+                    }
+                }
+                c => out.push(c),
+            }
+            continue;
+        };
+        let mut syllable = syllable.to_string();
+        let next = chars.get(i).copied().unwrap_or(' ');
+        if syllable.len() > 1 {
+            // きゃ → kya, しゅ → shu, じょ → jo
+            let yoon = match next {
+                'ゃ' => Some('a'),
+                'ゅ' => Some('u'),
+                'ょ' => Some('o'),
+                _ => None,
+            };
+            if let Some(v) = yoon.filter(|_| syllable.ends_with('i')) {
+                syllable.pop();
+                if !(syllable.ends_with("sh") || syllable.ends_with("ch") || syllable.ends_with('j')) {
+                    syllable.push('y');
+                }
+                syllable.push(v);
+                i += 1;
+            } else if matches!(next, 'ぁ' | 'ぃ' | 'ぅ' | 'ぇ' | 'ぉ') {
+                // ふぁ → fa, てぃ → ti
+                let v = kana_syllable(next).unwrap_or_default();
+                syllable.pop();
+                syllable.push_str(v);
+                i += 1;
+            }
+        }
+        if std::mem::take(&mut double) {
+            // っ doubles the consonant (っち → tchi)
+            match syllable.chars().next() {
+                Some('c') => out.push('t'),
+                Some(c) if !"aiueon".contains(c) => out.push(c),
+                _ => {}
+            }
+        }
+        out.push_str(&syllable);
+    }
+    out
+}
+
+fn kana_syllable(c: char) -> Option<&'static str> {
+    Some(match c {
+        'あ' | 'ぁ' => "a",
+        'い' | 'ぃ' => "i",
+        'う' | 'ぅ' => "u",
+        'え' | 'ぇ' => "e",
+        'お' | 'ぉ' | 'を' => "o",
+        'か' | 'ゕ' => "ka",
+        'き' => "ki",
+        'く' => "ku",
+        'け' | 'ゖ' => "ke",
+        'こ' => "ko",
+        'が' => "ga",
+        'ぎ' => "gi",
+        'ぐ' => "gu",
+        'げ' => "ge",
+        'ご' => "go",
+        'さ' => "sa",
+        'し' => "shi",
+        'す' => "su",
+        'せ' => "se",
+        'そ' => "so",
+        'ざ' => "za",
+        'じ' | 'ぢ' => "ji",
+        'ず' | 'づ' => "zu",
+        'ぜ' => "ze",
+        'ぞ' => "zo",
+        'た' => "ta",
+        'ち' => "chi",
+        'つ' => "tsu",
+        'て' => "te",
+        'と' => "to",
+        'だ' => "da",
+        'で' => "de",
+        'ど' => "do",
+        'な' => "na",
+        'に' => "ni",
+        'ぬ' => "nu",
+        'ね' => "ne",
+        'の' => "no",
+        'は' => "ha",
+        'ひ' => "hi",
+        'ふ' => "fu",
+        'へ' => "he",
+        'ほ' => "ho",
+        'ば' => "ba",
+        'び' => "bi",
+        'ぶ' => "bu",
+        'べ' => "be",
+        'ぼ' => "bo",
+        'ぱ' => "pa",
+        'ぴ' => "pi",
+        'ぷ' => "pu",
+        'ぺ' => "pe",
+        'ぽ' => "po",
+        'ま' => "ma",
+        'み' => "mi",
+        'む' => "mu",
+        'め' => "me",
+        'も' => "mo",
+        'や' | 'ゃ' => "ya",
+        'ゆ' | 'ゅ' => "yu",
+        'よ' | 'ょ' => "yo",
+        'ら' => "ra",
+        'り' => "ri",
+        'る' => "ru",
+        'れ' => "re",
+        'ろ' => "ro",
+        'わ' | 'ゎ' => "wa",
+        'ゐ' => "i",
+        'ゑ' => "e",
+        'ん' => "n",
+        'ゔ' => "vu",
+        _ => return None,
+    })
+}
+
+/// Plain-text find/replace run on the original stem before the blocks see it.
+#[derive(PartialEq, Clone, Debug)]
+pub struct FindReplace {
+    pub find: String,
+    pub replace: String,
+    pub case_sensitive: bool,
+    // every occurrence instead of the first one
+    pub all: bool,
+}
+
+impl Default for FindReplace {
+    fn default() -> Self {
+        Self { find: String::new(), replace: String::new(), case_sensitive: false, all: true }
+    }
+}
+
+impl FindReplace {
+    pub fn is_active(&self) -> bool {
+        !self.find.is_empty()
+    }
+
+    pub fn apply(&self, stem: &str) -> String {
+        if !self.is_active() {
+            return stem.to_string();
+        }
+        let flags = if self.case_sensitive { "" } else { "(?i)" };
+        let pattern = format!("{}{}", flags, regex::escape(&self.find));
+        let limit = if self.all { 0 } else { 1 };
+        with_regex(&pattern, |re| match re {
+            Some(re) => re.replacen(stem, limit, regex::NoExpand(&self.replace)).into_owned(),
+            None => stem.to_string(),
+        })
+    }
+}
+
+/// Past this many entries a compile cache starts over; patterns are typed one keystroke at a time.
+const CACHE_LIMIT: usize = 256;
+
+/// `cache[key]`, made with `make` when missing; a full cache is emptied first.
+fn cached<K: Eq + std::hash::Hash, V>(cache: &mut HashMap<K, V>, key: K, make: impl FnOnce() -> V) -> &V {
+    if cache.len() >= CACHE_LIMIT && !cache.contains_key(&key) {
+        cache.clear();
+    }
+    cache.entry(key).or_insert_with(make)
+}
+
+/// Run `f` with `pattern` compiled (`None` when invalid).
+fn with_regex<R>(pattern: &str, f: impl FnOnce(Option<&Regex>) -> R) -> R {
+    thread_local! {
+        // compiled once per pattern; names are rebuilt every frame
+        static CACHE: std::cell::RefCell<HashMap<String, Option<Regex>>> = Default::default();
+    }
+    CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        f(cached(&mut cache, pattern.to_string(), || Regex::new(pattern).ok()).as_ref())
+    })
+}
+
+#[derive(PartialEq, Copy, Clone, Debug, Default, Serialize, Deserialize)]
+pub enum NumberStyle {
+    #[default]
+    Decimal,
+    // lower case, 1f
+    Hex,
+    // 101
+    Binary,
+    // a, b, …, z, aa, ab
+    Letters,
+    // I, II, III, IV
+    Roman,
+}
+
+impl NumberStyle {
+    pub const ALL: [NumberStyle; 5] =
+        [NumberStyle::Decimal, NumberStyle::Hex, NumberStyle::Binary, NumberStyle::Letters, NumberStyle::Roman];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            NumberStyle::Decimal => "1, 2, 3",
+            NumberStyle::Hex => "hex (9, a, b)",
+            NumberStyle::Binary => "binary (1, 10, 11)",
+            NumberStyle::Letters => "a, b, … aa",
+            NumberStyle::Roman => "I, II, III",
+        }
+    }
+
+    /// `val` in this style; None for decimal and for values the style can't show
+    /// (letters start at 1 = a, Roman numerals cover 1-3999).
+    fn digits(self, val: i64) -> Option<String> {
+        match self {
+            NumberStyle::Hex if val < 0 => Some(format!("-{:x}", val.unsigned_abs())),
+            NumberStyle::Hex => Some(format!("{:x}", val)),
+            NumberStyle::Binary if val < 0 => Some(format!("-{:b}", val.unsigned_abs())),
+            NumberStyle::Binary => Some(format!("{:b}", val)),
+            NumberStyle::Letters if val > 0 => {
+                // bijective base 26: z = 26, aa = 27
+                let mut n = val as u64;
+                let mut out = Vec::new();
+                while n > 0 {
+                    n -= 1;
+                    out.push(b'a' + (n % 26) as u8);
+                    n /= 26;
+                }
+                Some(out.iter().rev().map(|&b| b as char).collect())
+            }
+            NumberStyle::Roman if (1..4000).contains(&val) => {
+                const NUMERALS: [(i64, &str); 13] = [
+                    (1000, "M"), (900, "CM"), (500, "D"), (400, "CD"), (100, "C"), (90, "XC"),
+                    (50, "L"), (40, "XL"), (10, "X"), (9, "IX"), (5, "V"), (4, "IV"), (1, "I"),
+                ];
+                let mut n = val;
+                let mut out = String::new();
+                for (value, numeral) in NUMERALS {
+                    while n >= value {
+                        out.push_str(numeral);
+                        n -= value;
+                    }
+                }
+                Some(out)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// `start + idx * step` in `style`. Decimal, hex and binary are padded with `pad` to `width`
+/// characters, counting the minus sign; decimal is also grouped for `locale`.
+pub fn format_number(
+    idx: usize,
+    width: usize,
+    start: i64,
+    step: i64,
+    style: NumberStyle,
+    pad: char,
+    locale: Locale,
+) -> String {
+    let val = start + (idx as i64) * step;
+    let fill = |s: String| {
+        let missing = width.saturating_sub(s.chars().count());
+        let fill = pad.to_string().repeat(missing);
+        match s.strip_prefix('-') {
+            // -007, but   -7
+            Some(digits) if pad == '0' => format!("-{}{}", fill, digits),
+            _ => fill + s.as_str(),
+        }
+    };
+    match style.digits(val) {
+        Some(s) if matches!(style, NumberStyle::Hex | NumberStyle::Binary) => fill(s),
+        Some(s) => s,
+        None if pad == '0' => locale.group(&fill(val.to_string())),
+        None => fill(locale.group(&val.to_string())),
+    }
+}
+
+/// What a file offers to the blocks besides its path.
+pub trait NameSource {
+    fn path(&self) -> &Path;
+
+    /// Tag typed into the list.
+    fn tag(&self) -> &str {
+        ""
+    }
+
+    /// Star rating 0-5 (0 = unrated).
+    fn rating(&self) -> u8 {
+        0
+    }
+
+    fn audio(&self) -> Option<&AudioInfo> {
+        None
+    }
+
+    fn audio_tags(&self) -> Option<&AudioTags> {
+        None
+    }
+
+    /// Camera make/model, lens, ISO and GPS position from EXIF.
+    fn camera(&self) -> Option<&CameraInfo> {
+        None
+    }
+
+    fn size(&self) -> Option<u64> {
+        fs::metadata(self.path()).ok().map(|m| m.len())
+    }
+
+    /// Pixel size of an image (only the header is read).
+    fn dimensions(&self) -> Option<(u32, u32)> {
+        image::image_dimensions(self.path()).ok()
+    }
+
+    /// Last modification time.
+    fn modified(&self) -> Option<DateTime<Local>> {
+        Some(fs::metadata(self.path()).ok()?.modified().ok()?.into())
+    }
+
+    /// Creation time, where the file system records it.
+    fn created(&self) -> Option<DateTime<Local>> {
+        Some(fs::metadata(self.path()).ok()?.created().ok()?.into())
+    }
+
+    /// Date for EXIF date blocks; the file's mtime unless overridden.
+    fn capture_date(&self) -> Option<DateTime<Local>> {
+        Some(fs::metadata(self.path()).ok()?.modified().ok()?.into())
+    }
+
+    /// Perceptual hash: `None` while still being computed, `Some(None)` for non-images.
+    fn phash(&self) -> Option<Option<u64>> {
+        Some(None)
+    }
+
+    /// Folders are renamed as a whole: no extension is split off their name.
+    fn is_dir(&self) -> bool {
+        self.path().is_dir()
+    }
+
+    /// Stem the blocks start from when it was edited before them (Find/Replace).
+    fn edited_stem(&self) -> Option<&str> {
+        None
+    }
+}
+
+impl NameSource for Path {
+    fn path(&self) -> &Path {
+        self
+    }
+}
+
+impl NameSource for PathBuf {
+    fn path(&self) -> &Path {
+        self
+    }
+}
+
+/// New name for `file` from `blocks`; `idx` is its position for Number blocks,
+/// `now` the current time for Date blocks and `extension` how Extension blocks write it.
+pub fn render_name<F: NameSource + ?Sized>(
+    blocks: &[Block],
+    idx: usize,
+    file: &F,
+    now: DateTime<Local>,
+    extension: &ExtPolicy,
+) -> String {
+    render_grouped(blocks, idx, &[], file, now, extension)
+}
+
+/// `render_name` with the file's positions from `group_positions` for grouped Number blocks.
+pub fn render_grouped<F: NameSource + ?Sized>(
+    blocks: &[Block],
+    idx: usize,
+    groups: &[Option<usize>],
+    file: &F,
+    now: DateTime<Local>,
+    extension: &ExtPolicy,
+) -> String {
+    let (file_name, raw_ext) = split_name(file);
+    let ext = raw_ext.map(|e| extension.apply(&e)).unwrap_or_default();
+
+    let mut parts = Vec::new();
+    for (i, b) in blocks.iter().enumerate() {
+        match b {
+            Block::Literal(s) => parts.push(s.clone()),
+            Block::Number { width, start, step, locale, style, pad, next, .. } => {
+                let start = next.unwrap_or(*start);
+                let idx = groups.get(i).copied().flatten().unwrap_or(idx);
+                parts.push(format_number(idx, *width, start, *step, *style, *pad, *locale))
+            }
+            Block::Date { format, locale, .. } | Block::ExifDate { format, locale } => {
+                let date = match b {
+                    Block::ExifDate { .. } => file.capture_date().unwrap_or(now).fixed_offset(),
+                    Block::Date { source, zone, .. } => zone.convert(source.date(file, now)),
+                    _ => now.fixed_offset(),
+                };
+                parts.push(format_date(&date, format, *locale).unwrap_or_else(|| "[INVALID_DATE]".to_string()));
+            }
+            Block::Original { mode, case } => {
+                parts.push(case.apply(&process_original(&file_name, mode)));
+            }
+            Block::OriginalSlice { start, len, from_end } => {
+                parts.push(slice_stem(&file_name, *start, *len, *from_end));
+            }
+            Block::RegexReplace { pattern, replacement } => {
+                parts.push(regex_replace(&file_name, pattern, replacement));
+            }
+            Block::Cleanup { trim, collapse, brackets, strip } => {
+                parts.push(clean_stem(&file_name, *trim, *collapse, *brackets, strip));
+            }
+            Block::Transliterate { romaji, fallback } => parts.push(transliterate(&file_name, *romaji, fallback)),
+            Block::Extension => {
+                if !ext.is_empty() {
+                    parts.push(format!(".{}", ext));
+                }
+            }
+            Block::Tag => parts.push(file.tag().to_string()),
+            Block::AudioTag { field, placeholder } => {
+                parts.push(file.audio_tags().and_then(|t| field.value(t)).unwrap_or_else(|| placeholder.clone()))
+            }
+            Block::Camera { field, placeholder } => {
+                parts.push(file.camera().and_then(|c| field.value(c)).unwrap_or_else(|| placeholder.clone()))
+            }
+            Block::FileSize { unit } => parts.push(file.size().map(|b| unit.format(b)).unwrap_or_default()),
+            Block::Shape { field } => parts.push(file.dimensions().map(|size| field.value(size)).unwrap_or_default()),
+            Block::Dimensions => parts.push(file.dimensions().map(|(w, h)| format!("{}x{}", w, h)).unwrap_or_default()),
+            Block::Script { code } => {
+                parts.push(run_script(code, idx, file).unwrap_or_else(|_| "[SCRIPT_ERROR]".to_string()))
+            }
+            Block::NameDate { pattern, format, locale, missing } => parts.push(match date_in_name(&file_name, pattern) {
+                Some(date) => format_date(&date, format, *locale).unwrap_or_else(|| "[INVALID_DATE]".to_string()),
+                None => missing.clone(),
+            }),
+            Block::Conditional { when, block, otherwise } => parts.push(if when.matches(file) {
+                let groups = groups.get(i..=i).unwrap_or_default();
+                render_grouped(std::slice::from_ref(&**block), idx, groups, file, now, extension)
+            } else {
+                otherwise.clone()
+            }),
+            Block::Rating { style } => parts.push(style.format(file.rating())),
+            Block::Audio { field } => parts.push(file.audio().map(|a| field.format(a)).unwrap_or_default()),
+            Block::PHash { len } => parts.push(match file.phash() {
+                Some(Some(hash)) => format!("{:016x}", hash)[..(*len).min(16)].to_string(),
+                Some(None) => String::new(),
+                // still computing
+                None => "?".repeat(*len),
+            }),
+        }
+    }
+    parts.join("")
+}
+
+/// `date` in the strftime `format` with `locale`'s names; None when the format has an unknown specifier.
+pub fn format_date<Tz: TimeZone>(date: &DateTime<Tz>, format: &str, locale: Locale) -> Option<String>
+where
+    Tz::Offset: std::fmt::Display,
+{
+    use chrono::format::{Item, StrftimeItems};
+    let format = locale.localize_format(format, date);
+    if StrftimeItems::new(&format).any(|item| item == Item::Error) {
+        return None;
+    }
+    Some(date.format(&format).to_string())
+}
+
+/// Stem and extension (no dot) of `file`; folders keep their whole name and have no extension.
+/// The stem is the edited one when the file has it.
+pub fn split_name<F: NameSource + ?Sized>(file: &F) -> (String, Option<String>) {
+    let path = file.path();
+    let text = |s: Option<&std::ffi::OsStr>| s.and_then(|s| s.to_str()).unwrap_or("").to_string();
+    let (stem, ext) = if file.is_dir() {
+        (text(path.file_name()), None)
+    } else {
+        (text(path.file_stem()), Some(text(path.extension())))
+    };
+    (file.edited_stem().map_or(stem, str::to_string), ext)
+}
+
+/// The engine Script blocks run on, with limits so a runaway loop can't hang the preview.
+fn script_engine() -> rhai::Engine {
+    let mut engine = rhai::Engine::new();
+    engine.set_max_operations(100_000);
+    engine.set_max_call_levels(32);
+    engine.set_max_string_size(4096);
+    let regex_replace = |text: &str, pattern: &str, with: &str| -> Result<String, Box<rhai::EvalAltResult>> {
+        with_regex(pattern, |re| match re {
+            Some(re) => Ok(re.replace_all(text, with).into_owned()),
+            None => Err(format!("invalid regex {:?}", pattern).into()),
+        })
+    };
+    engine.register_fn("regex_replace", regex_replace);
+    engine
+}
+
+/// Run `f` with the engine and `code` compiled, as `with_regex` does for patterns.
+fn with_script<R>(code: &str, f: impl FnOnce(&rhai::Engine, Result<&rhai::AST, &String>) -> R) -> R {
+    thread_local! {
+        static ENGINE: rhai::Engine = script_engine();
+        // compiled once per script; names are rebuilt every frame
+        static CACHE: std::cell::RefCell<HashMap<String, Result<rhai::AST, String>>> = Default::default();
+    }
+    ENGINE.with(|engine| {
+        CACHE.with(|cache| {
+            let mut cache = cache.borrow_mut();
+            let ast = cached(&mut cache, code.to_string(), || engine.compile(code).map_err(|e| e.to_string()));
+            f(engine, ast.as_ref())
+        })
+    })
+}
+
+/// Whether a Script block's `code` compiles.
+pub fn check_script(code: &str) -> Result<(), String> {
+    with_script(code, |_, ast| ast.map(|_| ()).map_err(Clone::clone))
+}
+
+/// Run a Script block's rhai `code` for `file`, the `idx`-th of the batch; the value of the last statement
+/// is the text. The script sees `name` (stem), `ext`, `index`, `size`, `modified`, `created`
+/// ("YYYY-MM-DD HH:MM:SS"), `tag`, `rating`, `width` and `height` (0 for non-images).
+pub fn run_script<F: NameSource + ?Sized>(code: &str, idx: usize, file: &F) -> Result<String, String> {
+    let (name, ext) = split_name(file);
+    let date = |d: Option<DateTime<Local>>| d.map(|d| d.format("%Y-%m-%d %H:%M:%S").to_string()).unwrap_or_default();
+    let (width, height) = if code.contains("width") || code.contains("height") {
+        file.dimensions().unwrap_or((0, 0))
+    } else {
+        (0, 0)
+    };
+    let mut scope = rhai::Scope::new();
+    scope
+        .push("name", name)
+        .push("ext", ext.unwrap_or_default())
+        .push("index", idx as i64)
+        .push("size", file.size().unwrap_or(0) as i64)
+        .push("modified", date(file.modified()))
+        .push("created", date(file.created()))
+        .push("tag", file.tag().to_string())
+        .push("rating", i64::from(file.rating()))
+        .push("width", i64::from(width))
+        .push("height", i64::from(height));
+    with_script(code, |engine, ast| {
+        let value: rhai::Dynamic = engine.eval_ast_with_scope(&mut scope, ast?).map_err(|e| e.to_string())?;
+        Ok(if value.is_unit() { String::new() } else { value.to_string() })
+    })
+}
+
+// how Extension blocks write the original extension
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+pub enum ExtPolicy {
+    #[default]
+    Keep,
+    Lower,
+    Upper,
+    // this extension for every file (with or without a leading dot)
+    Replace(String),
+    Strip,
+}
+
+impl ExtPolicy {
+    /// The extension to write for `ext` (no dot); empty for none.
+    pub fn apply(&self, ext: &str) -> String {
+        match self {
+            ExtPolicy::Keep => ext.to_string(),
+            ExtPolicy::Lower => ext.to_lowercase(),
+            ExtPolicy::Upper => ext.to_uppercase(),
+            ExtPolicy::Replace(new) => new.trim_start_matches('.').to_string(),
+            ExtPolicy::Strip => String::new(),
+        }
+    }
+}
+
+// what to do with names ending in '.' or ' ' (troublesome on Windows)
+#[derive(PartialEq, Copy, Clone, Debug, Default, Serialize, Deserialize)]
+pub enum TrailingFix {
+    #[default]
+    Warn,
+    Trim,
+    Replace,
+}
+
+impl TrailingFix {
+    pub fn apply(self, name: String) -> String {
+        let body = name.trim_end_matches(['.', ' ']);
+        if body.len() == name.len() {
+            return name;
+        }
+        match self {
+            TrailingFix::Warn => name,
+            TrailingFix::Trim => body.to_string(),
+            TrailingFix::Replace => {
+                let count = name[body.len()..].chars().count();
+                format!("{}{}", body, "_".repeat(count))
+            }
+        }
+    }
+}
+
+const WINDOWS_RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9", "LPT1",
+    "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// `name` made valid on Windows whatever the OS: `<>:"/\|?*` and control characters
+/// become `_`, trailing dots/spaces go and reserved device names (`CON`, `nul.txt`) get a `_`.
+/// Also returns what was changed.
+pub fn portable_name(name: &str) -> (String, Vec<String>) {
+    let mut notes = Vec::new();
+    let mut bad: Vec<char> = Vec::new();
+    let mut out: String = name
+        .chars()
+        .map(|c| {
+            if c.is_control() || matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*') {
+                if !bad.contains(&c) {
+                    bad.push(c);
+                }
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    if !bad.is_empty() {
+        let shown: Vec<String> = bad.iter().map(|c| format!("{:?}", c)).collect();
+        notes.push(format!("{} → '_'", shown.join(" ")));
+    }
+    let body = out.trim_end_matches(['.', ' ']);
+    if body.len() < out.len() {
+        out = if body.is_empty() { "_".to_string() } else { body.to_string() };
+        notes.push("trailing dot/space removed".into());
+    }
+    // Windows reserves the device names with any extension
+    let stem_end = out.find('.').unwrap_or(out.len());
+    let stem = out[..stem_end].trim_end();
+    if WINDOWS_RESERVED.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+        notes.push(format!("reserved name {}", stem.to_uppercase()));
+        out.insert(stem_end, '_');
+    }
+    (out, notes)
+}
+
+#[derive(PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum CollisionStrategy {
+    Overwrite,
+    Skip,
+    Suffix,
+    // existing target with identical content: skip; different content: suffix
+    Dedup,
+    // stop at each conflict and let the user choose; unanswered conflicts are skipped
+    Ask,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Template {
+    pub name: String,
+    pub blocks: Vec<Block>,
+    pub collision: CollisionStrategy,
+    pub use_mtime_for_date: bool,
+    #[serde(default)]
+    pub mtime_block: Option<usize>,
+    // date pattern read from the original name for the mtime post-step; wins over `mtime_block`
+    #[serde(default)]
+    pub mtime_pattern: Option<String>,
+    #[serde(default)]
+    pub trailing_fix: TrailingFix,
+    #[serde(default)]
+    pub extension: ExtPolicy,
+}
+
+impl Template {
+    /// Unnamed template with the default settings (Suffix on collision).
+    pub fn new(blocks: Vec<Block>) -> Self {
+        Self {
+            name: String::new(),
+            blocks,
+            collision: CollisionStrategy::Suffix,
+            use_mtime_for_date: true,
+            mtime_block: None,
+            mtime_pattern: None,
+            trailing_fix: TrailingFix::default(),
+            extension: ExtPolicy::default(),
+        }
+    }
+
+    /// Settings other than the blocks, as (label, value) for display.
+    pub fn settings(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("collision", format!("{:?}", self.collision)),
+            ("use mtime for date", self.use_mtime_for_date.to_string()),
+            ("set mtime from", format!("{:?}", self.mtime_block)),
+            ("mtime from name", format!("{:?}", self.mtime_pattern)),
+            ("trailing dot/space", format!("{:?}", self.trailing_fix)),
+            ("extension", format!("{:?}", self.extension)),
+        ]
+    }
+
+    /// Line diff of `self` (stored) against `new`: (' ' | '-' | '+', text).
+    pub fn diff(&self, new: &Template) -> Vec<(char, String)> {
+        let old_blocks: Vec<String> = self.blocks.iter().map(|b| format!("{:?}", b)).collect();
+        let new_blocks: Vec<String> = new.blocks.iter().map(|b| format!("{:?}", b)).collect();
+        let mut out = lcs_diff(&old_blocks, &new_blocks);
+        for ((label, old), (_, new)) in self.settings().into_iter().zip(new.settings()) {
+            if old == new {
+                out.push((' ', format!("{}: {}", label, old)));
+            } else {
+                out.push(('-', format!("{}: {}", label, old)));
+                out.push(('+', format!("{}: {}", label, new)));
+            }
+        }
+        out
+    }
+
+    /// What makes the template unusable: no name, no blocks, a bad regex, date format or mtime block.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.name.trim().is_empty() {
+            problems.push("template without a name".to_string());
+        }
+        let name = &self.name;
+        if self.blocks.is_empty() {
+            problems.push(format!("\"{}\": no blocks", name));
+        }
+        for block in &self.blocks {
+            if let Block::Conditional { when: Condition::NameMatches(pattern), .. } = block {
+                if let Err(e) = Regex::new(pattern) {
+                    problems.push(format!("\"{}\": invalid condition regex {:?}: {}", name, pattern, e));
+                }
+            }
+            let block = block.inner();
+            if let Block::NameDate { pattern, .. } = block {
+                if !pattern.is_empty() && DatePattern::new(pattern).is_none() {
+                    problems.push(format!("\"{}\": invalid date pattern {:?}", name, pattern));
+                }
+            }
+            let date_format = match block {
+                Block::Date { format, .. } | Block::ExifDate { format, .. } | Block::NameDate { format, .. } => {
+                    Some(format)
+                }
+                _ => None,
+            };
+            if let Some(format) = date_format {
+                if format_date(&Local::now(), format, Locale::Default).is_none() {
+                    problems.push(format!("\"{}\": invalid date format {:?}", name, format));
+                }
+            }
+            if let Block::RegexReplace { pattern, .. } = block {
+                if let Err(e) = Regex::new(pattern) {
+                    problems.push(format!("\"{}\": invalid regex {:?}: {}", name, pattern, e));
+                }
+            }
+            if let Block::Script { code } = block {
+                if let Err(e) = check_script(code) {
+                    problems.push(format!("\"{}\": invalid script {:?}: {}", name, code, e));
+                }
+            }
+        }
+        if self.mtime_block.is_some_and(|k| k >= self.blocks.len()) {
+            problems.push(format!("\"{}\": \"set mtime from\" points past the last block", name));
+        }
+        if let Some(pattern) = &self.mtime_pattern {
+            if DatePattern::new(pattern).is_none() {
+                problems.push(format!("\"{}\": invalid date pattern {:?}", name, pattern));
+            }
+        }
+        problems
+    }
+}
+
+/// Marker and version of template files written by `export_templates`.
+pub const TEMPLATE_FILE_FORMAT: &str = "bulkrename-templates";
+pub const TEMPLATE_FILE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct TemplateFile {
+    format: String,
+    version: u32,
+    templates: Vec<Template>,
+}
+
+/// Templates as a standalone JSON file for sharing.
+pub fn export_templates(templates: &[Template]) -> String {
+    let file = TemplateFile {
+        format: TEMPLATE_FILE_FORMAT.to_string(),
+        version: TEMPLATE_FILE_VERSION,
+        templates: templates.to_vec(),
+    };
+    serde_json::to_string_pretty(&file).unwrap_or_default()
+}
+
+/// Read a file written by `export_templates` (a bare template or `templates.json` list works too).
+/// Files from a newer version and templates with `problems` are refused as a whole.
+pub fn import_templates(text: &str) -> Result<Vec<Template>, Vec<String>> {
+    let value: serde_json::Value = serde_json::from_str(text).map_err(|e| vec![format!("not JSON: {}", e)])?;
+    let templates = if value.get("format").is_some() {
+        let file: TemplateFile = serde_json::from_value(value).map_err(|e| vec![e.to_string()])?;
+        if file.format != TEMPLATE_FILE_FORMAT {
+            return Err(vec![format!("not a template file (format {:?})", file.format)]);
+        }
+        if file.version > TEMPLATE_FILE_VERSION {
+            return Err(vec![format!(
+                "made by a newer BulkReName (version {}, this one reads up to {})",
+                file.version, TEMPLATE_FILE_VERSION
+            )]);
+        }
+        file.templates
+    } else if value.is_array() {
+        serde_json::from_value(value).map_err(|e| vec![e.to_string()])?
+    } else {
+        vec![serde_json::from_value(value).map_err(|e| vec![e.to_string()])?]
+    };
+    let mut problems: Vec<String> = templates.iter().flat_map(Template::problems).collect();
+    let mut seen = HashSet::new();
+    for t in &templates {
+        if !seen.insert(&t.name) {
+            problems.push(format!("\"{}\" appears twice", t.name));
+        }
+    }
+    if templates.is_empty() {
+        problems.push("no templates in the file".to_string());
+    }
+    if problems.is_empty() {
+        Ok(templates)
+    } else {
+        Err(problems)
+    }
+}
+
+/// Blocks for an inline CLI pattern such as `{date:%Y%m%d}_{n:3}{ext}`.
+pub fn parse_pattern(pattern: &str) -> Result<Vec<Block>, String> {
+    let mut blocks = Vec::new();
+    let mut literal = String::new();
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '}' => return Err("unmatched '}' (write }} for a literal brace)".into()),
+            '{' => {
+                let mut field = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => field.push(c),
+                        None => return Err("unterminated {".into()),
+                    }
+                }
+                if !literal.is_empty() {
+                    blocks.push(Block::Literal(std::mem::take(&mut literal)));
+                }
+                let (name, arg) = field.split_once(':').unwrap_or((field.as_str(), ""));
+                let date = |source| Block::Date {
+                    format: arg.into(),
+                    locale: Locale::Default,
+                    source,
+                    zone: DateZone::Local,
+                };
+                let block = match name {
+                    "name" => Block::Original { mode: OriginalMode::Keep, case: NameCase::Keep },
+                    "ext" => Block::Extension,
+                    "date" => date(DateSource::Now),
+                    "mtime" => date(DateSource::Modified),
+                    "created" => date(DateSource::Created),
+                    "exif" => Block::ExifDate { format: arg.into(), locale: Locale::Default },
+                    "n" => {
+                        let nums: Vec<&str> = if arg.is_empty() { Vec::new() } else { arg.split(':').collect() };
+                        let num = |i: usize, default: i64| -> Result<i64, String> {
+                            nums.get(i).map_or(Ok(default), |s| {
+                                s.parse().map_err(|_| format!("invalid number '{}' in {{{}}}", s, field))
+                            })
+                        };
+                        if nums.len() > 3 {
+                            return Err(format!("too many values in {{{}}}", field));
+                        }
+                        Block::Number {
+                            width: num(0, 0)?.max(0) as usize,
+                            start: num(1, 1)?,
+                            step: num(2, 1)?,
+                            locale: Locale::Default,
+                            style: NumberStyle::Decimal,
+                            pad: '0',
+                            next: None,
+                            group: GroupKey::None,
+                        }
+                    }
+                    _ => return Err(format!("unknown field {{{}}}", field)),
+                };
+                blocks.push(block);
+            }
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        blocks.push(Block::Literal(literal));
+    }
+    if blocks.is_empty() {
+        return Err("empty pattern".into());
+    }
+    Ok(blocks)
+}
+
+/// Files named on the command line. Folders and wildcards expand in name order.
+pub fn expand_cli_paths(args: &[String], recursive: bool) -> Result<Vec<PathBuf>, String> {
+    let mut out = Vec::new();
+    for arg in args {
+        let path = PathBuf::from(arg);
+        let name = path.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        let mut found = Vec::new();
+        if name.contains(['*', '?']) {
+            let dir = match path.parent() {
+                Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+                _ => PathBuf::from("."),
+            };
+            let pattern: Vec<char> = name.chars().collect();
+            for entry in fs::read_dir(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?.flatten() {
+                let entry_name: Vec<char> = entry.file_name().to_string_lossy().chars().collect();
+                let path = entry.path();
+                if path.is_file() && !FolderLog::is_log_file(&path) && wildcard_match(&pattern, &entry_name) {
+                    found.push(path);
+                }
+            }
+        } else if path.is_dir() {
+            collect_files(&path, if recursive { None } else { Some(0) }, &mut found);
+        } else if path.is_file() {
+            found.push(path);
+        } else {
+            return Err(format!("{}: no such file or folder", arg));
+        }
+        found.sort();
+        out.extend(found);
+    }
+    Ok(out)
+}
+
+/// `*` matches any run of characters, `?` a single one.
+pub fn wildcard_match(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|i| wildcard_match(rest, &name[i..])),
+        Some((&c, rest)) => match name.split_first() {
+            Some((&n, name_rest)) => (c == '?' || c == n) && wildcard_match(rest, name_rest),
+            None => false,
+        },
+    }
+}
+
+/// Files under `dir`, audit logs left out; `depth` is how many subfolder levels to descend (None = all).
+pub fn collect_files(dir: &Path, depth: Option<usize>, out: &mut Vec<PathBuf>) {
+    if let Ok(read_dir) = fs::read_dir(dir) {
+        for entry in read_dir.flatten() {
+            let path = entry.path();
+            if path.is_file() && !FolderLog::is_log_file(&path) {
+                out.push(path);
+            } else if path.is_dir() && depth != Some(0) {
+                collect_files(&path, depth.map(|d| d - 1), out);
+            }
+        }
+    }
+}
+
+// one step of an imported rename plan
+#[derive(Debug, Deserialize)]
+pub struct PlanEntry {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+/// Rows of a CSV (or `;`/tab separated) text; fields may be quoted with `"`.
+pub fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let text = text.trim_start_matches('\u{feff}');
+    let first = text.lines().next().unwrap_or("");
+    let sep = [',', '\t', ';'].into_iter().find(|&c| first.contains(c)).unwrap_or(',');
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            c if c == sep && !quoted => row.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows.retain(|r| r.iter().any(|f| !f.trim().is_empty()));
+    rows
+}
+
+/// Plan entries for `old_name,new_name` rows. The old name is a path or the name of one of `files`;
+/// a first row reading `old_name,new_name` is a header.
+pub fn mapping_to_plan<P: AsRef<Path>>(rows: &[Vec<String>], files: &[P]) -> Result<Vec<PlanEntry>, Vec<String>> {
+    let mut plan = Vec::new();
+    let mut problems = Vec::new();
+    let header = |row: &Vec<String>| {
+        let names = row.iter().map(|f| f.trim().to_lowercase()).collect::<Vec<_>>();
+        names == ["old_name", "new_name"]
+    };
+    let skip = rows.first().is_some_and(header) as usize;
+    for (i, row) in rows.iter().enumerate().skip(skip) {
+        let (Some(old), Some(new)) = (row.first().map(|s| s.trim()), row.get(1).map(|s| s.trim())) else {
+            problems.push(format!("line {}: needs two columns", i + 1));
+            continue;
+        };
+        let old_path = Path::new(old);
+        let matches: Vec<&Path> = if old_path.is_absolute() {
+            files.iter().map(AsRef::as_ref).filter(|&f| f == old_path).collect()
+        } else {
+            files.iter().map(AsRef::as_ref).filter(|f| f.file_name().is_some_and(|n| n == old)).collect()
+        };
+        match matches.as_slice() {
+            [path] => plan.push(PlanEntry { from: path.to_path_buf(), to: PathBuf::from(new) }),
+            // not loaded: check_plan reports it if missing
+            [] if old_path.is_absolute() => {
+                plan.push(PlanEntry { from: old_path.to_path_buf(), to: PathBuf::from(new) })
+            }
+            [] => problems.push(format!("line {}: {:?} is not in the list", i + 1, old)),
+            _ => problems.push(format!("line {}: {:?} matches {} files, use the full path", i + 1, old, matches.len())),
+        }
+    }
+    if plan.is_empty() && problems.is_empty() {
+        problems.push("no rows".into());
+    }
+    if problems.is_empty() {
+        Ok(plan)
+    } else {
+        Err(problems)
+    }
+}
+
+/// What changed from `old` to `new` by character: runs of (' ' | '-' | '+', text).
+pub fn diff_chars(old: &str, new: &str) -> Vec<(char, String)> {
+    let old: Vec<char> = old.chars().collect();
+    let new: Vec<char> = new.chars().collect();
+    let mut runs: Vec<(char, String)> = Vec::new();
+    for (kind, c) in lcs_diff(&old, &new) {
+        match runs.last_mut() {
+            Some((last, text)) if *last == kind => text.push(c),
+            _ => runs.push((kind, c.to_string())),
+        }
+    }
+    runs
+}
+
+/// LCS-based diff of two sequences.
+fn lcs_diff<T: PartialEq + Clone>(old: &[T], new: &[T]) -> Vec<(char, T)> {
+    let (n, m) = (old.len(), new.len());
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut out = Vec::new();
+    while i < n || j < m {
+        if i < n && j < m && old[i] == new[j] {
+            out.push((' ', old[i].clone()));
+            i += 1;
+            j += 1;
+        } else if j < m && (i == n || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            out.push(('+', new[j].clone()));
+            j += 1;
+        } else {
+            out.push(('-', old[i].clone()));
+            i += 1;
+        }
+    }
+    out
+}
+
+/// SHA-256 of a file's content.
+pub fn file_sha256(path: &Path) -> std::io::Result<[u8; 32]> {
+    use sha2::{Digest, Sha256};
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(hasher.finalize().into())
+}
+
+/// Byte-identical check (size first, then hash).
+pub fn same_content(a: &Path, b: &Path) -> bool {
+    let size = |p: &Path| fs::metadata(p).map(|m| m.len()).ok();
+    match (size(a), size(b)) {
+        (Some(sa), Some(sb)) if sa == sb => {}
+        _ => return false,
+    }
+    match (file_sha256(a), file_sha256(b)) {
+        (Ok(ha), Ok(hb)) => ha == hb,
+        _ => false,
+    }
+}
+
+/// Groups of files with identical content, in the order of `paths`; files of a unique size are not hashed.
+/// Only groups of two or more are returned, and files that can't be read are left out.
+pub fn find_duplicates(paths: &[PathBuf]) -> Vec<Vec<PathBuf>> {
+    let mut by_size: HashMap<u64, Vec<&PathBuf>> = HashMap::new();
+    for path in paths {
+        if let Ok(meta) = fs::metadata(path) {
+            if meta.is_file() {
+                by_size.entry(meta.len()).or_default().push(path);
+            }
+        }
+    }
+    let mut by_hash: HashMap<[u8; 32], usize> = HashMap::new();
+    let mut groups: Vec<Vec<PathBuf>> = Vec::new();
+    for path in paths {
+        let same_size = fs::metadata(path).ok().and_then(|m| by_size.get(&m.len()));
+        if same_size.is_none_or(|s| s.len() < 2) {
+            continue;
+        }
+        let Ok(hash) = file_sha256(path) else {
+            continue;
+        };
+        match by_hash.get(&hash) {
+            Some(&k) => groups[k].push(path.clone()),
+            None => {
+                by_hash.insert(hash, groups.len());
+                groups.push(vec![path.clone()]);
+            }
+        }
+    }
+    groups.retain(|g| g.len() > 1);
+    groups
+}
+
+fn split_stem_and_number(stem: &str) -> (String, Option<u32>) {
+    if let Some(idx) = stem.rfind('(') {
+        if stem.ends_with(')') {
+            let num_part = &stem[idx + 1..stem.len() - 1];
+            if let Ok(n) = num_part.parse::<u32>() {
+                let base = stem[..idx].trim_end().to_string();
+                return (base, Some(n));
+            }
+        }
+    }
+    (stem.to_string(), None)
+}
+
+/// `dir/stem (n).ext`, replacing an existing ` (k)` suffix.
+pub fn make_numbered_path(path: &Path, n: u32) -> PathBuf {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+    let ext = path.extension().and_then(|s| s.to_str());
+    let dir = path.parent().unwrap_or(Path::new("."));
+
+    let (base, _) = split_stem_and_number(stem);
+    let new_stem = format!("{} ({})", base, n);
+
+    if let Some(e) = ext {
+        dir.join(format!("{}.{}", new_stem, e))
+    } else {
+        dir.join(new_stem)
+    }
+}
+
+/// `dir/name (n)` for a folder: the whole name is numbered, dots and all.
+pub fn make_numbered_dir_path(path: &Path, n: u32) -> PathBuf {
+    let name = path.file_name().and_then(|s| s.to_str()).unwrap_or("");
+    let (base, _) = split_stem_and_number(name);
+    path.with_file_name(format!("{} ({})", base, n))
+}
+
+/// Apply the collision strategy to the desired paths.
+///
+/// Names of batch members that stay where they are (unchanged or skipped) are
+/// reserved for them; names of members that move away are free for the others,
+/// which is what makes swaps and cycles work. Returns the resolved paths and,
+/// for Dedup with `dedup_delete_source`, the sources found identical to an existing target.
+pub fn resolve_targets(
+    collision: CollisionStrategy,
+    dedup_delete_source: bool,
+    origs: &[PathBuf],
+    final_paths: &[PathBuf],
+) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    resolve_targets_each(&vec![collision; origs.len()], dedup_delete_source, origs, final_paths)
+}
+
+/// `resolve_targets` with a strategy per member, e.g. the answers to Ask.
+pub fn resolve_targets_each(
+    collision: &[CollisionStrategy],
+    dedup_delete_source: bool,
+    origs: &[PathBuf],
+    final_paths: &[PathBuf],
+) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    let mut reserved: HashSet<PathBuf> = origs
+        .iter()
+        .zip(final_paths)
+        .filter(|(orig, p)| orig == p)
+        .map(|(orig, _)| orig.clone())
+        .collect();
+    // duplicates stay reserved once found, so later passes don't report them again
+    let mut duplicates = Vec::new();
+    loop {
+        let (resolved, found) =
+            resolve_with_reserved(collision, dedup_delete_source, origs, final_paths, &reserved, false);
+        duplicates.extend(found);
+        // a member that ended up skipped now keeps its name too; redo until stable
+        let newly_staying: Vec<PathBuf> = origs
+            .iter()
+            .zip(&resolved)
+            .filter(|(orig, r)| orig == r && !reserved.contains(*orig))
+            .map(|(orig, _)| orig.clone())
+            .collect();
+        if newly_staying.is_empty() || collision.iter().all(|&c| c == CollisionStrategy::Overwrite) {
+            return (resolved, duplicates);
+        }
+        reserved.extend(newly_staying);
+    }
+}
+
+/// Like `resolve_targets` for copies: every source keeps its file, so no target may take
+/// a source's path (not even with Overwrite). Members resolved to their own path are not copied.
+pub fn resolve_copy_targets(collision: CollisionStrategy, origs: &[PathBuf], final_paths: &[PathBuf]) -> Vec<PathBuf> {
+    resolve_copy_targets_each(&vec![collision; origs.len()], origs, final_paths)
+}
+
+/// `resolve_copy_targets` with a strategy per member.
+pub fn resolve_copy_targets_each(
+    collision: &[CollisionStrategy],
+    origs: &[PathBuf],
+    final_paths: &[PathBuf],
+) -> Vec<PathBuf> {
+    resolve_with_reserved(collision, false, origs, final_paths, &HashSet::new(), true).0
+}
+
+/// Members whose target is already taken, before any strategy is applied: (member, the member
+/// holding the name) or (member, None) for a file on disk that isn't part of the batch.
+/// With `sources_stay` (copies) every source keeps its name.
+pub fn find_collisions(origs: &[PathBuf], final_paths: &[PathBuf], sources_stay: bool) -> Vec<(usize, Option<usize>)> {
+    let position: HashMap<&PathBuf, usize> = origs.iter().enumerate().map(|(i, p)| (p, i)).collect();
+    let stays = |j: usize| sources_stay || origs[j] == final_paths[j];
+    let mut claimed: HashMap<&PathBuf, usize> = HashMap::new();
+    let mut collisions = Vec::new();
+    for (i, (orig, path)) in origs.iter().zip(final_paths).enumerate() {
+        if orig == path {
+            continue;
+        }
+        if let Some(&j) = claimed.get(path) {
+            collisions.push((i, Some(j)));
+        } else if let Some(&j) = position.get(path).filter(|&&j| stays(j)) {
+            collisions.push((i, Some(j)));
+        } else if !case_only_change(orig, path) && !position.contains_key(path) && path.exists() {
+            collisions.push((i, None));
+        }
+        claimed.entry(path).or_insert(i);
+    }
+    collisions
+}
+
+/// Files on disk a resolved batch would replace: existing targets that no member frees
+/// by moving away. With `sources_stay` (copies) no name is freed.
+pub fn replaced_files(pairs: &[(PathBuf, PathBuf)], sources_stay: bool) -> Vec<PathBuf> {
+    let moving: HashSet<&PathBuf> =
+        if sources_stay { HashSet::new() } else { pairs.iter().map(|(from, _)| from).collect() };
+    let mut seen = HashSet::new();
+    pairs
+        .iter()
+        .filter(|(from, to)| !moving.contains(to) && !case_only_change(from, to) && to.exists())
+        .filter(|(_, to)| seen.insert(to))
+        .map(|(_, to)| to.clone())
+        .collect()
+}
+
+/// Move files a batch is about to replace to hidden names next to them, so they can be put
+/// back when the batch doesn't go through. Returns (original, aside) pairs; when one can't be
+/// moved, the ones before it are put back and the error returned.
+pub fn set_aside(files: &[PathBuf]) -> std::io::Result<Vec<(PathBuf, PathBuf)>> {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let mut aside = Vec::new();
+    for (i, file) in files.iter().enumerate() {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        let tmp = file.with_file_name(format!(".replaced-{}-{}-{}", nanos, i, name));
+        if let Err(e) = fs::rename(extended_path(file), extended_path(&tmp)) {
+            put_back(&aside);
+            return Err(e);
+        }
+        aside.push((file.clone(), tmp));
+    }
+    Ok(aside)
+}
+
+/// Move files from `set_aside` back to their names. One whose name is taken again stays
+/// aside; the aside paths left are returned.
+pub fn put_back(aside: &[(PathBuf, PathBuf)]) -> Vec<PathBuf> {
+    aside
+        .iter()
+        .filter(|(orig, tmp)| orig.exists() || fs::rename(extended_path(tmp), extended_path(orig)).is_err())
+        .map(|(_, tmp)| tmp.clone())
+        .collect()
+}
+
+/// A case-only change finds the file itself on case-insensitive filesystems.
+fn case_only_change(orig: &Path, path: &Path) -> bool {
+    let folded = |p: &Path| p.file_name().map(|n| n.to_string_lossy().to_lowercase());
+    orig.parent() == path.parent() && folded(orig) == folded(path)
+}
+
+fn resolve_with_reserved(
+    collision: &[CollisionStrategy],
+    dedup_delete_source: bool,
+    origs: &[PathBuf],
+    final_paths: &[PathBuf],
+    reserved: &HashSet<PathBuf>,
+    sources_stay: bool,
+) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    // claimed by members that move
+    let mut used = HashSet::new();
+    let mut resolved_paths = Vec::new();
+    let mut duplicates = Vec::new();
+    let batch_origs: HashSet<&PathBuf> = origs.iter().collect();
+    let taken = |used: &HashSet<PathBuf>, p: &PathBuf| {
+        used.contains(p) || reserved.contains(p) || (sources_stay && batch_origs.contains(p))
+    };
+
+    for ((orig, path), &collision) in origs.iter().zip(final_paths).zip(collision) {
+        if reserved.contains(orig) {
+            resolved_paths.push(orig.clone());
+            continue;
+        }
+        // files outside the batch; names of members that move away are freed by the batch itself
+        let on_disk = |p: &PathBuf| {
+            p != orig
+                && !case_only_change(orig, p)
+                && p.exists()
+                && (sources_stay || !batch_origs.contains(p) || reserved.contains(p))
+        };
+
+        match collision {
+            CollisionStrategy::Overwrite => {
+                if sources_stay && batch_origs.contains(path) {
+                    resolved_paths.push(orig.clone());
+                } else {
+                    resolved_paths.push(path.clone());
+                }
+            }
+
+            CollisionStrategy::Skip | CollisionStrategy::Ask => {
+                if taken(&used, path) || on_disk(path) {
+                    resolved_paths.push(orig.clone());
+                } else {
+                    used.insert(path.clone());
+                    resolved_paths.push(path.clone());
+                }
+            }
+
+            CollisionStrategy::Suffix => {
+                let numbered = if orig.is_dir() { make_numbered_dir_path } else { make_numbered_path };
+                let mut candidate = path.clone();
+                let mut n = 1;
+
+                while taken(&used, &candidate) || on_disk(&candidate) {
+                    candidate = numbered(path, n);
+                    n += 1;
+                }
+
+                used.insert(candidate.clone());
+                resolved_paths.push(candidate);
+            }
+
+            CollisionStrategy::Dedup => {
+                let numbered = if orig.is_dir() { make_numbered_dir_path } else { make_numbered_path };
+                let mut candidate = path.clone();
+                let mut n = 1;
+                // an identical file under the name or one of its numbers (left by an earlier run): nothing to do
+                let mut identical = false;
+                while taken(&used, &candidate) || on_disk(&candidate) {
+                    if !used.contains(&candidate) && on_disk(&candidate) && same_content(orig, &candidate) {
+                        identical = true;
+                        break;
+                    }
+                    candidate = numbered(path, n);
+                    n += 1;
+                }
+                if identical {
+                    if dedup_delete_source {
+                        duplicates.push(orig.clone());
+                    }
+                    resolved_paths.push(orig.clone());
+                    continue;
+                }
+                used.insert(candidate.clone());
+                resolved_paths.push(candidate);
+            }
+        }
+    }
+    (resolved_paths, duplicates)
+}
+
+/// Groups of members that trade names (A → B → A), for reporting.
+pub fn find_cycles(origs: &[PathBuf], resolved: &[PathBuf]) -> Vec<Vec<PathBuf>> {
+    let moves: HashMap<&PathBuf, &PathBuf> = origs
+        .iter()
+        .zip(resolved)
+        .filter(|(orig, r)| orig != r)
+        .collect();
+    let mut seen: HashSet<&PathBuf> = HashSet::new();
+    let mut cycles = Vec::new();
+    for start in origs {
+        if seen.contains(start) || !moves.contains_key(start) {
+            continue;
+        }
+        let mut chain = vec![start.clone()];
+        let mut cur = moves[start];
+        seen.insert(start);
+        while let Some((&member, &target)) = moves.get_key_value(cur) {
+            if member == start {
+                cycles.push(chain);
+                break;
+            }
+            if !seen.insert(member) {
+                break;
+            }
+            chain.push(member.clone());
+            cur = target;
+        }
+    }
+    cycles
+}
+
+#[derive(Debug)]
+pub enum RenameFailure {
+    /// orig → tmp failed for `file`; everything was moved back except
+    /// `not_restored` (tmp, orig).
+    Staging {
+        error: std::io::Error,
+        file: PathBuf,
+        not_restored: Vec<(PathBuf, PathBuf)>,
+    },
+    /// tmp → final failed for `file`; `stranded` files are left under temporary
+    /// names (tmp, orig, final), the ones before it are already renamed.
+    Final {
+        error: std::io::Error,
+        file: PathBuf,
+        stranded: Vec<(PathBuf, PathBuf, PathBuf)>,
+    },
+    /// Copying `file` failed; the copies made before it were deleted except `not_removed`
+    /// (also the ones that replaced a file that was there before).
+    Copy {
+        error: std::io::Error,
+        file: PathBuf,
+        not_removed: Vec<PathBuf>,
+    },
+    /// Stopped through `Progress::cancel`; everything was put back except `not_restored`
+    /// (where it is now, original path; for copies: the copy, its source).
+    Cancelled { not_restored: Vec<(PathBuf, PathBuf)> },
+}
+
+impl RenameFailure {
+    pub fn message(&self) -> String {
+        match self {
+            RenameFailure::Staging { error, file, .. } => format!("Failed: {:?}: {}", file, error),
+            RenameFailure::Final { error, file, .. } => {
+                format!("Failed final rename: {:?}: {}", file, error)
+            }
+            RenameFailure::Copy { error, file, .. } => format!("Failed to copy {:?}: {}", file, error),
+            RenameFailure::Cancelled { not_restored } if not_restored.is_empty() => {
+                "Cancelled, nothing was changed.".to_string()
+            }
+            RenameFailure::Cancelled { not_restored } => {
+                format!("Cancelled, but {} file(s) could not be put back.", not_restored.len())
+            }
+        }
+    }
+
+    /// Original paths of the files the failure is about: the one that failed and those left
+    /// where they shouldn't be.
+    pub fn sources(&self) -> Vec<PathBuf> {
+        match self {
+            RenameFailure::Staging { file, not_restored, .. } => {
+                std::iter::once(file.clone()).chain(not_restored.iter().map(|(_, orig)| orig.clone())).collect()
+            }
+            // the failed file is the first one stranded
+            RenameFailure::Final { stranded, .. } => stranded.iter().map(|(_, orig, _)| orig.clone()).collect(),
+            RenameFailure::Copy { file, .. } => vec![file.clone()],
+            RenameFailure::Cancelled { not_restored } => not_restored.iter().map(|(_, orig)| orig.clone()).collect(),
+        }
+    }
+}
+
+/// Progress of a batch running on another thread, and the switch to cancel it.
+#[derive(Default)]
+pub struct Progress {
+    done: AtomicUsize,
+    total: AtomicUsize,
+    cancel: AtomicBool,
+}
+
+impl Progress {
+    /// Steps done and the total (a rename takes two steps per file: aside, then to the new name).
+    pub fn counts(&self) -> (usize, usize) {
+        (self.done.load(Ordering::Relaxed), self.total.load(Ordering::Relaxed))
+    }
+
+    /// Ask the batch to stop and put back what it already did.
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+
+    pub fn cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    fn start(&self, total: usize) {
+        self.done.store(0, Ordering::Relaxed);
+        self.total.store(total, Ordering::Relaxed);
+    }
+
+    fn step(&self) {
+        self.done.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// timestamps a copy (or a move to another drive) takes over from its source
+#[derive(PartialEq, Copy, Clone, Debug, Default, Serialize, Deserialize)]
+pub enum KeepTimes {
+    Off,
+    #[default]
+    Modified,
+    // modified and accessed, plus created on Windows and macOS
+    All,
+}
+
+impl KeepTimes {
+    pub const ALL: [KeepTimes; 3] = [KeepTimes::Off, KeepTimes::Modified, KeepTimes::All];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            KeepTimes::Off => "Off",
+            KeepTimes::Modified => "Modified",
+            KeepTimes::All => "All",
+        }
+    }
+}
+
+/// `fs::copy` that also takes over the timestamps `keep` asks for and the metadata `fs::copy`
+/// misses (see `copy_metadata`).
+/// The source's times are read before copying, which may update its access time.
+pub fn copy_with_times(from: &Path, to: &Path, keep: KeepTimes) -> std::io::Result<()> {
+    let meta = fs::metadata(from)?;
+    fs::copy(extended_path(from), extended_path(to))?;
+    copy_metadata(from, to)?;
+    let mtime = filetime::FileTime::from_last_modification_time(&meta);
+    match keep {
+        KeepTimes::Off => Ok(()),
+        KeepTimes::Modified => filetime::set_file_mtime(to, mtime),
+        KeepTimes::All => {
+            filetime::set_file_times(to, filetime::FileTime::from_last_access_time(&meta), mtime)?;
+            // best effort: not every file system keeps a creation time, and Linux can't set one
+            #[cfg(any(windows, target_os = "macos"))]
+            if let Ok(created) = meta.created() {
+                let _ = set_created(to, created);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Carry over what `fs::copy` leaves behind. Linux: extended attributes of files and folders.
+/// macOS (Finder tags, extended attributes, flags such as hidden) and Windows (hidden, read-only,
+/// system): folders only, as `fs::copy` already takes a file's metadata and alternate data streams.
+/// Attributes the target's file system can't hold are skipped.
+#[cfg(target_os = "linux")]
+fn copy_metadata(from: &Path, to: &Path) -> std::io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    let c_path = |p: &Path| std::ffi::CString::new(p.as_os_str().as_bytes()).map_err(std::io::Error::other);
+    let (src, dst) = (c_path(from)?, c_path(to)?);
+    let names = match xattr_buffer(|buf, len| unsafe { libc::llistxattr(src.as_ptr(), buf.cast(), len) }) {
+        Ok(names) => names,
+        Err(e) if e.raw_os_error() == Some(libc::ENOTSUP) => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for name in names.split(|&b| b == 0).filter(|name| !name.is_empty()) {
+        let name = std::ffi::CString::new(name).map_err(std::io::Error::other)?;
+        let value = xattr_buffer(|buf, len| unsafe { libc::lgetxattr(src.as_ptr(), name.as_ptr(), buf, len) })?;
+        let set = unsafe { libc::lsetxattr(dst.as_ptr(), name.as_ptr(), value.as_ptr().cast(), value.len(), 0) };
+        if set != 0 {
+            let e = std::io::Error::last_os_error();
+            // not supported there, or privileged (security.*, trusted.*)
+            if !matches!(e.raw_os_error(), Some(libc::ENOTSUP | libc::EPERM)) {
+                return Err(e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Run an xattr call the way they want it: once for the size, then into a buffer that big.
+#[cfg(target_os = "linux")]
+fn xattr_buffer(call: impl Fn(*mut libc::c_void, usize) -> isize) -> std::io::Result<Vec<u8>> {
+    loop {
+        let size = call(std::ptr::null_mut(), 0);
+        if size < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut buf = vec![0u8; size as usize];
+        let len = call(buf.as_mut_ptr().cast(), buf.len());
+        if len >= 0 {
+            buf.truncate(len as usize);
+            return Ok(buf);
+        }
+        let e = std::io::Error::last_os_error();
+        // grew in between
+        if e.raw_os_error() != Some(libc::ERANGE) {
+            return Err(e);
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn copy_metadata(from: &Path, to: &Path) -> std::io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    if !from.is_dir() {
+        return Ok(());
+    }
+    let c_path = |p: &Path| std::ffi::CString::new(p.as_os_str().as_bytes()).map_err(std::io::Error::other);
+    let (src, dst) = (c_path(from)?, c_path(to)?);
+    let flags = libc::COPYFILE_METADATA;
+    if unsafe { libc::copyfile(src.as_ptr(), dst.as_ptr(), std::ptr::null_mut(), flags) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn copy_metadata(from: &Path, to: &Path) -> std::io::Result<()> {
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::fs::MetadataExt;
+    if !from.is_dir() {
+        return Ok(());
+    }
+    // read-only, hidden, system, archive, offline, not content indexed
+    let attributes = fs::metadata(from)?.file_attributes() & 0x3027;
+    let wide: Vec<u16> = extended_path(to).as_os_str().encode_wide().chain(Some(0)).collect();
+    // FILE_ATTRIBUTE_NORMAL when none is set
+    let attributes = if attributes == 0 { 0x80 } else { attributes };
+    if unsafe { winapi::um::fileapi::SetFileAttributesW(wide.as_ptr(), attributes) } == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn copy_metadata(_from: &Path, _to: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(windows)]
+fn set_created(path: &Path, created: std::time::SystemTime) -> std::io::Result<()> {
+    use std::os::windows::fs::{FileTimesExt, OpenOptionsExt};
+    // FILE_WRITE_ATTRIBUTES only, so read-only files can be stamped too
+    let file = fs::OpenOptions::new().access_mode(0x100).open(path)?;
+    file.set_times(fs::FileTimes::new().set_created(created))
+}
+
+#[cfg(target_os = "macos")]
+fn set_created(path: &Path, created: std::time::SystemTime) -> std::io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes()).map_err(std::io::Error::other)?;
+    let since = created.duration_since(UNIX_EPOCH).map_err(std::io::Error::other)?;
+    let mut attrs = libc::attrlist {
+        bitmapcount: libc::ATTR_BIT_MAP_COUNT,
+        reserved: 0,
+        commonattr: libc::ATTR_CMN_CRTIME,
+        volattr: 0,
+        dirattr: 0,
+        fileattr: 0,
+        forkattr: 0,
+    };
+    let mut time = libc::timespec { tv_sec: since.as_secs() as libc::time_t, tv_nsec: since.subsec_nanos() as _ };
+    let set = unsafe {
+        libc::setattrlist(
+            c_path.as_ptr(),
+            (&mut attrs as *mut libc::attrlist).cast(),
+            (&mut time as *mut libc::timespec).cast(),
+            std::mem::size_of::<libc::timespec>(),
+            libc::FSOPT_NOFOLLOW,
+        )
+    };
+    if set != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[derive(Copy, Clone)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub base_delay_ms: u64,
+    // for files that have to be copied to another drive
+    pub keep_times: KeepTimes,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            base_delay_ms: 200,
+            keep_times: KeepTimes::default(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// `fs::rename` retried with exponential backoff on errors that may be transient (network shares).
+    /// Files moved to another drive are copied and then deleted.
+    pub fn rename(&self, from: &Path, to: &Path) -> std::io::Result<()> {
+        use std::io::ErrorKind;
+        let mut attempt = 0;
+        loop {
+            match fs::rename(extended_path(from), extended_path(to)) {
+                Ok(()) => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+                    return move_across_devices(from, to, self.keep_times)
+                }
+                Err(e) => {
+                    let permanent = matches!(
+                        e.kind(),
+                        ErrorKind::NotFound
+                            | ErrorKind::PermissionDenied
+                            | ErrorKind::AlreadyExists
+                            | ErrorKind::InvalidInput
+                    );
+                    if permanent || attempt >= self.attempts {
+                        return Err(e);
+                    }
+                    let delay = self.base_delay_ms.saturating_mul(1 << attempt.min(16));
+                    thread::sleep(std::time::Duration::from_millis(delay));
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Copy + verify + delete for a file or folder moved between drives. The source only goes once
+/// the copy matches it (size and SHA-256 of every file); the copy is removed again if a step fails.
+fn move_across_devices(from: &Path, to: &Path, keep: KeepTimes) -> std::io::Result<()> {
+    let differs = || std::io::Error::other(format!("the copy at {:?} differs from the source", to));
+    if from.is_dir() {
+        if to.exists() {
+            let msg = format!("{:?} already exists", to);
+            return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, msg));
+        }
+        // only a folder this call created is removed again on failure
+        fs::create_dir(extended_path(to))?;
+        let copied = copy_dir_contents(from, to, keep)
+            .and_then(|_| same_tree(from, to).then_some(()).ok_or_else(differs));
+        if let Err(e) = copied {
+            let _ = fs::remove_dir_all(to);
+            return Err(e);
+        }
+        // a folder may be half deleted: the copy is then the only complete one and stays
+        return fs::remove_dir_all(from).map_err(|e| {
+            let msg = format!("copied to {:?}, but the source could not be removed: {}", to, e);
+            std::io::Error::new(e.kind(), msg)
+        });
+    }
+    let moved = copy_with_times(from, to, keep)
+        .and_then(|_| same_content(from, to).then_some(()).ok_or_else(differs))
+        .and_then(|_| fs::remove_file(from));
+    if moved.is_err() {
+        let _ = fs::remove_file(to);
+    }
+    moved
+}
+
+/// `copy_with_times` for a folder and everything in it.
+fn copy_dir_with_times(from: &Path, to: &Path, keep: KeepTimes) -> std::io::Result<()> {
+    fs::create_dir(extended_path(to))?;
+    copy_dir_contents(from, to, keep)
+}
+
+/// Fills the existing folder `to` with the contents of `from`, then copies the folder's own times.
+fn copy_dir_contents(from: &Path, to: &Path, keep: KeepTimes) -> std::io::Result<()> {
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir_with_times(&entry.path(), &target, keep)?;
+        } else {
+            copy_with_times(&entry.path(), &target, keep)?;
+        }
+    }
+    copy_metadata(from, to)?;
+    if keep != KeepTimes::Off {
+        let mtime = filetime::FileTime::from_last_modification_time(&fs::metadata(from)?);
+        filetime::set_file_mtime(to, mtime)?;
+    }
+    Ok(())
+}
+
+/// Both folders hold the same names, with byte-identical files.
+fn same_tree(a: &Path, b: &Path) -> bool {
+    let names = |dir: &Path| -> Option<Vec<std::ffi::OsString>> {
+        let entries = fs::read_dir(dir).ok()?;
+        let mut names: Vec<_> = entries.map(|e| e.map(|e| e.file_name())).collect::<Result<_, _>>().ok()?;
+        names.sort();
+        Some(names)
+    };
+    let (Some(in_a), Some(in_b)) = (names(a), names(b)) else { return false };
+    in_a == in_b
+        && in_a.iter().all(|name| {
+            let (a, b) = (a.join(name), b.join(name));
+            if a.is_dir() { b.is_dir() && same_tree(&a, &b) } else { same_content(&a, &b) }
+        })
+}
+
+/// Longest path the file APIs take: UTF-16 units on Windows (with the `\\?\` prefix), bytes elsewhere.
+pub const PATH_LIMIT: usize = if cfg!(windows) {
+    32_767
+} else if cfg!(target_os = "macos") {
+    1024
+} else {
+    4096
+};
+
+/// Windows' classic MAX_PATH (including the terminating NUL): longer paths need the `\\?\`
+/// prefix, and Explorer and many programs can't open them.
+pub const WINDOWS_MAX_PATH: usize = 260;
+
+/// Length of `path` in the unit `PATH_LIMIT` counts.
+pub fn path_length(path: &Path) -> usize {
+    if cfg!(windows) {
+        path.to_string_lossy().encode_utf16().count()
+    } else {
+        path.as_os_str().len()
+    }
+}
+
+/// `path` in the extended-length `\\?\` form on Windows once it gets near MAX_PATH
+/// (folders are limited to 248 characters), so long paths don't fail. Unchanged elsewhere.
+pub fn extended_path(path: &Path) -> PathBuf {
+    if cfg!(windows) && path_length(path) >= 248 {
+        if let Some(extended) = extended_form(&path.to_string_lossy()) {
+            return PathBuf::from(extended);
+        }
+    }
+    path.to_path_buf()
+}
+
+/// The `\\?\` form of an absolute Windows path. The prefix turns off the normalization Windows
+/// does otherwise, so separators become backslashes and `.`/`..` are resolved here.
+/// None for relative, device and already prefixed paths.
+fn extended_form(path: &str) -> Option<String> {
+    let path = path.replace('/', "\\");
+    if path.starts_with(r"\\?\") || path.starts_with(r"\\.\") {
+        return None;
+    }
+    // (prefix, the rest, leading parts `..` can't remove: server and share)
+    let (prefix, rest, fixed) = if let Some(unc) = path.strip_prefix(r"\\") {
+        (r"\\?\UNC\".to_string(), unc, 2)
+    } else if path.as_bytes().first().is_some_and(u8::is_ascii_alphabetic) && path[1..].starts_with(":\\") {
+        (format!(r"\\?\{}", &path[..3]), &path[3..], 0)
+    } else {
+        return None;
+    };
+    let mut parts: Vec<&str> = Vec::new();
+    for part in rest.split('\\') {
+        match part {
+            "" | "." => {}
+            ".." if parts.len() > fixed => {
+                parts.pop();
+            }
+            ".." => {}
+            part => parts.push(part),
+        }
+    }
+    Some(prefix + parts.join("\\").as_str())
+}
+
+/// Create the missing folders for `targets`, parents first, and return them in that order.
+/// On failure the folders created so far are removed again.
+pub fn create_target_dirs(targets: &[PathBuf]) -> Result<Vec<PathBuf>, (std::io::Error, PathBuf)> {
+    let mut created: Vec<PathBuf> = Vec::new();
+    for target in targets {
+        let mut missing = Vec::new();
+        let mut dir = target.parent();
+        while let Some(d) = dir.filter(|d| !d.as_os_str().is_empty() && !d.exists()) {
+            missing.push(d.to_path_buf());
+            dir = d.parent();
+        }
+        for d in missing.into_iter().rev() {
+            if let Err(e) = fs::create_dir(extended_path(&d)) {
+                remove_empty_dirs(&created);
+                return Err((e, d));
+            }
+            created.push(d);
+        }
+    }
+    Ok(created)
+}
+
+/// Remove the folders from `create_target_dirs` that are empty (again), deepest first.
+pub fn remove_empty_dirs(dirs: &[PathBuf]) {
+    for d in dirs.iter().rev() {
+        let _ = fs::remove_dir(d);
+    }
+}
+
+/// Fail fast when a folder (e.g. a disconnected share) does not answer.
+pub fn check_dirs_reachable<'a>(dirs: impl IntoIterator<Item = &'a Path>) -> Result<(), String> {
+    const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+    let unique: HashSet<&Path> = dirs.into_iter().collect();
+    for dir in unique {
+        let (tx, rx) = mpsc::channel();
+        let d = dir.to_path_buf();
+        thread::spawn(move || {
+            tx.send(fs::metadata(&d).map(|m| m.is_dir())).ok();
+        });
+        match rx.recv_timeout(TIMEOUT) {
+            Ok(Ok(true)) => {}
+            Ok(Ok(false)) => return Err(format!("Not a folder: {}", dir.display())),
+            Ok(Err(e)) => return Err(format!("Folder unreachable: {} ({})", dir.display(), e)),
+            Err(_) => return Err(format!("Folder not responding: {}", dir.display())),
+        }
+    }
+    Ok(())
+}
+
+/// Audit log a batch leaves in every folder it touched.
+#[derive(PartialEq, Copy, Clone, Debug, Default, Serialize, Deserialize)]
+pub enum FolderLog {
+    #[default]
+    Off,
+    Json,
+    Csv,
+}
+
+impl FolderLog {
+    pub const ALL: [FolderLog; 3] = [FolderLog::Off, FolderLog::Json, FolderLog::Csv];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            FolderLog::Off => "(off)",
+            FolderLog::Json => "JSON",
+            FolderLog::Csv => "CSV",
+        }
+    }
+
+    pub fn file_name(self) -> Option<&'static str> {
+        match self {
+            FolderLog::Off => None,
+            FolderLog::Json => Some(".bulkrename-log.json"),
+            FolderLog::Csv => Some(".bulkrename-log.csv"),
+        }
+    }
+
+    /// One of the log files, which folder walks leave out.
+    pub fn is_log_file(path: &Path) -> bool {
+        let name = path.file_name().and_then(|n| n.to_str());
+        Self::ALL.iter().any(|f| f.file_name().is_some() && f.file_name() == name)
+    }
+}
+
+// one batch in `.bulkrename-log.json`
+#[derive(Serialize, Deserialize)]
+struct FolderLogBatch {
+    time: String,
+    action: String,
+    template: String,
+    files: Vec<FolderLogFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FolderLogFile {
+    old: PathBuf,
+    new: PathBuf,
+}
+
+/// Append a batch to the log in each folder a file left or arrived in.
+/// Returns the folders whose log could not be written, with the reason.
+pub fn write_folder_logs(
+    format: FolderLog,
+    action: &str,
+    template: &str,
+    time: DateTime<Local>,
+    pairs: &[(PathBuf, PathBuf)],
+) -> Vec<(PathBuf, String)> {
+    let Some(file_name) = format.file_name() else {
+        return Vec::new();
+    };
+    let mut by_dir: Vec<(PathBuf, Vec<&(PathBuf, PathBuf)>)> = Vec::new();
+    for pair in pairs {
+        let (old, new) = (pair.0.parent(), pair.1.parent());
+        for dir in [old, new.filter(|&n| Some(n) != old)].into_iter().flatten() {
+            match by_dir.iter_mut().find(|(d, _)| d == dir) {
+                Some((_, list)) => list.push(pair),
+                None => by_dir.push((dir.to_path_buf(), vec![pair])),
+            }
+        }
+    }
+    let mut failed = Vec::new();
+    for (dir, list) in by_dir {
+        let path = dir.join(file_name);
+        let result = match format {
+            FolderLog::Json => append_json_log(&path, action, template, time, &list),
+            _ => append_csv_log(&path, action, template, time, &list),
+        };
+        if let Err(e) = result {
+            failed.push((dir, e));
+        }
+    }
+    failed
+}
+
+fn append_json_log(
+    path: &Path,
+    action: &str,
+    template: &str,
+    time: DateTime<Local>,
+    pairs: &[&(PathBuf, PathBuf)],
+) -> Result<(), String> {
+    // a log that doesn't parse is left alone rather than replaced
+    let mut batches: Vec<FolderLogBatch> = match fs::read_to_string(path) {
+        Ok(text) => serde_json::from_str(&text).map_err(|e| format!("existing log is not readable: {}", e))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e.to_string()),
+    };
+    batches.push(FolderLogBatch {
+        time: time.to_rfc3339(),
+        action: action.to_string(),
+        template: template.to_string(),
+        files: pairs.iter().map(|(old, new)| FolderLogFile { old: old.clone(), new: new.clone() }).collect(),
+    });
+    let json = serde_json::to_string_pretty(&batches).map_err(|e| e.to_string())?;
+    fs::write(path, json).map_err(|e| e.to_string())
+}
+
+fn append_csv_log(
+    path: &Path,
+    action: &str,
+    template: &str,
+    time: DateTime<Local>,
+    pairs: &[&(PathBuf, PathBuf)],
+) -> Result<(), String> {
+    use std::io::Write;
+    let quote = |s: &str| format!("\"{}\"", s.replace('"', "\"\""));
+    let mut text = String::new();
+    if !path.exists() {
+        text.push_str("time,action,template,old,new\n");
+    }
+    let time = time.to_rfc3339();
+    for (old, new) in pairs {
+        let fields = [&time, action, template, &old.to_string_lossy(), &new.to_string_lossy()];
+        text.push_str(&fields.map(quote).join(","));
+        text.push('\n');
+    }
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut f| f.write_all(text.as_bytes()))
+        .map_err(|e| e.to_string())
+}
+
+/// Rename every (orig, final) pair through a temporary name, so swaps and cycles work.
+pub fn two_phase_rename(pairs: &[(PathBuf, PathBuf)], retry: RetryPolicy) -> Result<(), RenameFailure> {
+    two_phase_rename_journaled(pairs, retry, None, None)
+}
+
+/// `two_phase_rename` that first writes a `RenameJournal` to `journal`, so a batch cut
+/// short by a crash can be completed or rolled back later; every rename is then marked in it.
+/// The journal is removed once the batch is done or fully moved back; it stays when files are
+/// left with temporary names.
+/// With `progress`, every step is counted and a cancel moves all files back.
+pub fn two_phase_rename_journaled(
+    pairs: &[(PathBuf, PathBuf)],
+    retry: RetryPolicy,
+    journal: Option<&Path>,
+    progress: Option<&Progress>,
+) -> Result<(), RenameFailure> {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let tmps: Vec<PathBuf> = pairs
+        .iter()
+        .enumerate()
+        .map(|(i, (orig, _))| {
+            let dir = orig.parent().unwrap_or(Path::new("."));
+            dir.join(format!(".tmp-{}-{}.tmp", nanos, i))
+        })
+        .collect();
+    let mut log = None;
+    if let Some(path) = journal {
+        let steps = pairs
+            .iter()
+            .zip(&tmps)
+            .map(|((orig, target), tmp)| JournalStep { orig: orig.clone(), tmp: tmp.clone(), target: target.clone() })
+            .collect();
+        match (RenameJournal { steps, marks: None }).save(path) {
+            Ok(file) => log = Some(JournalLog(std::cell::RefCell::new(file))),
+            Err(error) => {
+                let file = pairs.first().map(|(orig, _)| orig.clone()).unwrap_or_default();
+                return Err(RenameFailure::Staging { error, file, not_restored: Vec::new() });
+            }
+        }
+    }
+    let result = stage_and_rename(pairs, &tmps, retry, progress, log.as_ref());
+    drop(log);
+    let clean = match &result {
+        Ok(()) => true,
+        Err(RenameFailure::Staging { not_restored, .. } | RenameFailure::Cancelled { not_restored }) => {
+            not_restored.is_empty()
+        }
+        Err(_) => false,
+    };
+    if let Some(path) = journal.filter(|_| clean) {
+        let _ = fs::remove_file(path);
+    }
+    result
+}
+
+// the progress lines of an open journal (see `JournalMarks`)
+struct JournalLog(std::cell::RefCell<fs::File>);
+
+impl JournalLog {
+    fn mark(&self, line: &str) {
+        use std::io::Write;
+        let mut file = self.0.borrow_mut();
+        let _ = writeln!(file, "{}", line).and_then(|_| file.sync_data());
+    }
+}
+
+fn stage_and_rename(
+    pairs: &[(PathBuf, PathBuf)],
+    tmps: &[PathBuf],
+    retry: RetryPolicy,
+    progress: Option<&Progress>,
+    log: Option<&JournalLog>,
+) -> Result<(), RenameFailure> {
+    let mark = |line: &str| {
+        if let Some(log) = log {
+            log.mark(line);
+        }
+    };
+    // rename step `j` and mark it `kind` once it's there
+    let step = |j: usize, from: &Path, to: &Path, kind: &str| {
+        retry.rename(from, to)?;
+        mark(&format!("{} {}", kind, j));
+        Ok::<(), std::io::Error>(())
+    };
+    // the first `staged` files back from their temporary names
+    let unstage = |staged: usize| -> Vec<(PathBuf, PathBuf)> {
+        mark("u");
+        (0..staged)
+            .rev()
+            .filter(|&j| step(j, &tmps[j], &pairs[j].0, "o").is_err())
+            .map(|j| (tmps[j].clone(), pairs[j].0.clone()))
+            .collect()
+    };
+    let cancelled = || progress.is_some_and(Progress::cancelled);
+    if let Some(p) = progress {
+        p.start(pairs.len() * 2);
+    }
+
+    // orig → tmp
+    for (i, (orig, _)) in pairs.iter().enumerate() {
+        if cancelled() {
+            return Err(RenameFailure::Cancelled { not_restored: unstage(i) });
+        }
+        if let Err(e) = step(i, orig, &tmps[i], "s") {
+            let not_restored = unstage(i);
+            return Err(RenameFailure::Staging {
+                error: e,
+                file: orig.clone(),
+                not_restored,
+            });
+        }
+        if let Some(p) = progress {
+            p.step();
+        }
+    }
+
+    // tmp → final
+    for (i, (orig, final_path)) in pairs.iter().enumerate() {
+        if cancelled() {
+            // renamed files go aside again first, so swapped names are free
+            mark("u");
+            let mut not_restored: Vec<(PathBuf, PathBuf)> = (0..i)
+                .rev()
+                .filter(|&j| step(j, &pairs[j].1, &tmps[j], "s").is_err())
+                .map(|j| (pairs[j].1.clone(), pairs[j].0.clone()))
+                .collect();
+            let stuck: HashSet<&PathBuf> = not_restored.iter().map(|(_, orig)| orig).collect();
+            let back = (0..pairs.len()).rev().filter(|&j| !stuck.contains(&pairs[j].0));
+            let failed: Vec<usize> = back.filter(|&j| step(j, &tmps[j], &pairs[j].0, "o").is_err()).collect();
+            not_restored.extend(failed.into_iter().map(|j| (tmps[j].clone(), pairs[j].0.clone())));
+            return Err(RenameFailure::Cancelled { not_restored });
+        }
+        if let Err(e) = step(i, &tmps[i], final_path, "d") {
+            let stranded = (i..pairs.len())
+                .map(|j| (tmps[j].clone(), pairs[j].0.clone(), pairs[j].1.clone()))
+                .collect();
+            return Err(RenameFailure::Final {
+                error: e,
+                file: orig.clone(),
+                stranded,
+            });
+        }
+        if let Some(p) = progress {
+            p.step();
+        }
+    }
+    Ok(())
+}
+
+/// Copy every (source, target) pair, keeping the mtime; on failure the copies made so far
+/// are deleted again, also when cancelled through `progress`. Existing targets are replaced;
+/// those are left in place on a rollback and reported with the copies that could not be deleted.
+pub fn copy_files(
+    pairs: &[(PathBuf, PathBuf)],
+    keep: KeepTimes,
+    progress: Option<&Progress>,
+) -> Result<(), RenameFailure> {
+    if let Some(p) = progress {
+        p.start(pairs.len());
+    }
+    // targets that were there before the batch
+    let mut existed = Vec::with_capacity(pairs.len());
+    // the first `done` copies that stay: replaced an earlier file or can't be deleted
+    let remove_copies = |done: usize, existed: &[bool]| -> Vec<usize> {
+        (0..done).filter(|&j| existed[j] || fs::remove_file(&pairs[j].1).is_err()).collect()
+    };
+    for (i, (from, to)) in pairs.iter().enumerate() {
+        if progress.is_some_and(Progress::cancelled) {
+            let not_restored = remove_copies(i, &existed)
+                .into_iter()
+                .map(|j| (pairs[j].1.clone(), pairs[j].0.clone()))
+                .collect();
+            return Err(RenameFailure::Cancelled { not_restored });
+        }
+        existed.push(to.exists());
+        if let Err(error) = copy_with_times(from, to, keep) {
+            let mut not_removed: Vec<PathBuf> =
+                remove_copies(i, &existed).into_iter().map(|j| pairs[j].1.clone()).collect();
+            if existed[i] {
+                not_removed.push(to.clone());
+            } else {
+                let _ = fs::remove_file(to);
+            }
+            return Err(RenameFailure::Copy {
+                error,
+                file: from.clone(),
+                not_removed,
+            });
+        }
+        if let Some(p) = progress {
+            p.step();
+        }
+    }
+    Ok(())
+}
+
+/// One file of a journaled batch: `orig` → `tmp` → `target`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JournalStep {
+    pub orig: PathBuf,
+    pub tmp: PathBuf,
+    pub target: PathBuf,
+}
+
+/// Where a journaled file is now.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum StepState {
+    // still at `orig`
+    Original,
+    // at the temporary name
+    Staged,
+    // at `target`
+    Done,
+    // at none of them
+    Lost,
+}
+
+/// On-disk record of a two-phase rename in progress (see `two_phase_rename_journaled`).
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RenameJournal {
+    pub steps: Vec<JournalStep>,
+    #[serde(skip)]
+    marks: Option<JournalMarks>,
+}
+
+/// The lines after the journal's first: `s 3`, `d 3`, `o 3` once step 3 got its temporary,
+/// target or original name, and `u` when moving the files back started.
+#[derive(Clone, Debug, Default)]
+struct JournalMarks {
+    last: Vec<Option<StepState>>,
+    undo: bool,
+}
+
+impl JournalMarks {
+    fn parse(text: &str, steps: usize) -> JournalMarks {
+        let mut marks = JournalMarks { last: vec![None; steps], undo: false };
+        for line in text.lines() {
+            if line == "u" {
+                marks.undo = true;
+                continue;
+            }
+            // a line cut short by the crash is skipped like a missing one
+            let Some((kind, i)) = line.split_once(' ') else { continue };
+            let state = match kind {
+                "s" => StepState::Staged,
+                "d" => StepState::Done,
+                "o" => StepState::Original,
+                _ => continue,
+            };
+            if let Some(last) = i.parse::<usize>().ok().and_then(|i| marks.last.get_mut(i)) {
+                *last = Some(state);
+            }
+        }
+        marks
+    }
+}
+
+impl RenameJournal {
+    /// Written and flushed before the first file is touched; the file is kept open for the marks.
+    fn save(&self, path: &Path) -> std::io::Result<fs::File> {
+        use std::io::Write;
+        let mut json = serde_json::to_vec(self).map_err(std::io::Error::other)?;
+        json.push(b'\n');
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut f = fs::File::create(path)?;
+        f.write_all(&json)?;
+        f.sync_all()?;
+        Ok(f)
+    }
+
+    pub fn load(path: &Path) -> Option<RenameJournal> {
+        let text = fs::read_to_string(path).ok()?;
+        // journals written before the marks are a single line
+        let (json, marks) = text.split_once('\n').map_or((text.as_str(), None), |(j, m)| (j, Some(m)));
+        let mut journal: RenameJournal = serde_json::from_str(json).ok()?;
+        journal.marks = marks.map(|m| JournalMarks::parse(m, journal.steps.len()));
+        Some(journal)
+    }
+
+    pub fn states(&self) -> Vec<StepState> {
+        let at = |state: StepState, path: &Path| if path.exists() { state } else { StepState::Lost };
+        self.steps
+            .iter()
+            .enumerate()
+            .map(|(i, s)| {
+                if s.tmp.exists() {
+                    return StepState::Staged;
+                }
+                // the original name may hold another step's file (swaps, cycles): go by the marks
+                let Some(marks) = &self.marks else {
+                    return if s.orig.exists() { StepState::Original } else { at(StepState::Done, &s.target) };
+                };
+                match marks.last[i] {
+                    None | Some(StepState::Original) => at(StepState::Original, &s.orig),
+                    // left the temporary name after its last mark: moved back, or on to the target
+                    Some(StepState::Staged) if marks.undo => at(StepState::Original, &s.orig),
+                    _ => at(StepState::Done, &s.target),
+                }
+            })
+            .collect()
+    }
+
+    /// True when some file was left under its temporary name, or the batch stopped halfway.
+    pub fn interrupted(&self) -> bool {
+        let states = self.states();
+        states.contains(&StepState::Staged)
+            || (states.contains(&StepState::Done) && states.contains(&StepState::Original))
+    }
+
+    /// Finish the batch: staged and untouched files get their target names.
+    pub fn complete(&self, retry: RetryPolicy) -> Result<(), RenameFailure> {
+        let pairs: Vec<(PathBuf, PathBuf)> = self
+            .steps
+            .iter()
+            .zip(self.states())
+            .filter_map(|(s, state)| match state {
+                StepState::Staged => Some((s.tmp.clone(), s.target.clone())),
+                StepState::Original if s.orig != s.target => Some((s.orig.clone(), s.target.clone())),
+                _ => None,
+            })
+            .collect();
+        two_phase_rename(&pairs, retry)
+    }
+
+    /// Undo the batch: staged and renamed files go back to their original names.
+    pub fn roll_back(&self, retry: RetryPolicy) -> Result<(), RenameFailure> {
+        let pairs: Vec<(PathBuf, PathBuf)> = self
+            .steps
+            .iter()
+            .zip(self.states())
+            .filter_map(|(s, state)| match state {
+                StepState::Staged => Some((s.tmp.clone(), s.orig.clone())),
+                StepState::Done => Some((s.target.clone(), s.orig.clone())),
+                _ => None,
+            })
+            .collect();
+        two_phase_rename(&pairs, retry)
+    }
+}
+
+/// A checked batch of renames: build it, look at `pairs`, then execute (and undo).
+pub struct Plan {
+    /// (current path, new path) of every file whose name changes.
+    pub pairs: Vec<(PathBuf, PathBuf)>,
+    pub retry: RetryPolicy,
+}
+
+impl Plan {
+    /// New names for `files` under `template`, numbered in the given order, with the
+    /// template's collision strategy applied. Date blocks use the current time.
+    pub fn build<F: NameSource>(files: &[F], template: &Template) -> Plan {
+        let now = Local::now();
+        let origs: Vec<PathBuf> = files.iter().map(|f| f.path().to_path_buf()).collect();
+        let groups = group_positions(&template.blocks, files, |_| now);
+        let final_paths: Vec<PathBuf> = files
+            .iter()
+            .zip(&groups)
+            .enumerate()
+            .map(|(i, (f, groups))| {
+                let name = render_grouped(&template.blocks, i, groups, f, now, &template.extension);
+                f.path().with_file_name(template.trailing_fix.apply(name))
+            })
+            .collect();
+        let (resolved, _) = resolve_targets(template.collision, false, &origs, &final_paths);
+        let pairs = origs.into_iter().zip(resolved).filter(|(orig, new)| orig != new).collect();
+        Plan {
+            pairs,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    pub fn execute(&self) -> Result<(), RenameFailure> {
+        two_phase_rename(&self.pairs, self.retry)
+    }
+
+    /// Move every renamed file back to its old name.
+    pub fn undo(&self) -> Result<(), RenameFailure> {
+        let back: Vec<(PathBuf, PathBuf)> = self.pairs.iter().map(|(orig, new)| (new.clone(), orig.clone())).collect();
+        two_phase_rename(&back, self.retry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    /// Empty scratch folder under the system temp dir.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bulkrename-core-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn number(width: usize, start: i64, step: i64) -> Block {
+        let (locale, style) = (Locale::Default, NumberStyle::Decimal);
+        Block::Number { width, start, step, locale, style, pad: '0', next: None, group: GroupKey::None }
+    }
+
+    #[test]
+    fn render_joins_blocks() {
+        let blocks = vec![
+            Block::Literal("trip_".into()),
+            number(3, 1, 1),
+            Block::Literal("_".into()),
+            Block::Original { mode: OriginalMode::Keep, case: NameCase::Keep },
+            Block::Extension,
+        ];
+        let now = Local::now();
+        let keep = ExtPolicy::Keep;
+        assert_eq!(render_name(&blocks, 0, Path::new("a/IMG.jpg"), now, &keep), "trip_001_IMG.jpg");
+        assert_eq!(render_name(&blocks, 9, Path::new("a/IMG"), now, &keep), "trip_010_IMG");
+        assert_eq!(render_name(&blocks, 0, Path::new("a/IMG.JPG"), now, &ExtPolicy::Lower), "trip_001_IMG.jpg");
+    }
+
+    #[test]
+    fn counters_continue_across_batches() {
+        let mut blocks = vec![number(3, 1, 2), Block::Extension];
+        if let Block::Number { next, .. } = &mut blocks[0] {
+            *next = Some(1);
+        }
+        let now = Local::now();
+        let keep = ExtPolicy::Keep;
+        assert_eq!(render_name(&blocks, 1, Path::new("a.jpg"), now, &keep), "003.jpg");
+        advance_counters(&mut blocks, 4);
+        assert_eq!(render_name(&blocks, 0, Path::new("a.jpg"), now, &keep), "009.jpg");
+        // without a stored counter every batch starts over
+        let mut fresh = vec![number(3, 1, 2)];
+        advance_counters(&mut fresh, 4);
+        assert_eq!(render_name(&fresh, 0, Path::new("a"), now, &keep), "001");
+    }
+
+    #[test]
+    fn numbers_grouped_by_key() {
+        let now = Local::now();
+        let keep = ExtPolicy::Keep;
+        let files = ["a/2024-05-01 x", "a/2024-05-01 y", "b/2024-05-02 z", "a/2024-05-01 w"].map(PathBuf::from);
+        let number_by = |width, key| {
+            let mut block = number(width, 1, 1);
+            if let Block::Number { group, .. } = &mut block {
+                *group = key;
+            }
+            block
+        };
+        let grouped = |key| {
+            let blocks = vec![number_by(2, key)];
+            let groups = group_positions(&blocks, &files, |_| now);
+            let names = files.iter().zip(&groups).enumerate();
+            names.map(|(i, (f, g))| render_grouped(&blocks, i, g, f, now, &keep)).collect::<Vec<_>>()
+        };
+        assert_eq!(grouped(GroupKey::None), ["01", "02", "03", "04"]);
+        assert_eq!(grouped(GroupKey::Folder), ["01", "02", "01", "03"]);
+        assert_eq!(grouped(GroupKey::Pattern(r"^(\d{4}-\d\d-\d\d)".into())), ["01", "02", "01", "03"]);
+        // the key from another block: the date at the start of the name
+        let blocks = vec![
+            Block::OriginalSlice { start: 0, len: 10, from_end: false },
+            number_by(1, GroupKey::Block(0)),
+        ];
+        let groups = group_positions(&blocks, &files, |_| now);
+        assert_eq!(render_grouped(&blocks, 2, &groups[2], &files[2], now, &keep), "2024-05-021");
+        assert_eq!(render_grouped(&blocks, 3, &groups[3], &files[3], now, &keep), "2024-05-013");
+        // blocks moved or removed: the key follows its block or goes away with it
+        let mut moved = vec![
+            Block::Extension,
+            Block::OriginalSlice { start: 0, len: 10, from_end: false },
+            number_by(1, GroupKey::Block(1)),
+            number_by(1, GroupKey::Block(0)),
+        ];
+        moved.swap(0, 1);
+        GroupKey::remap(&mut moved, |i| Some(if i < 2 { 1 - i } else { i }));
+        moved.remove(0);
+        GroupKey::remap(&mut moved, |i| i.checked_sub(1));
+        let keys: Vec<_> = moved[1..]
+            .iter()
+            .map(|b| match b {
+                Block::Number { group, .. } => group.clone(),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(keys, [GroupKey::None, GroupKey::Block(0)]);
+    }
+
+    #[test]
+    fn sidecars_follow_their_group() {
+        let files = ["a/IMG_1.CR2", "a/IMG_2.jpg", "a/IMG_1.jpg", "a/IMG_1.CR2.xmp", "b/IMG_1.jpg", "a/IMG_1"]
+            .map(PathBuf::from);
+        let groups = sidecar_groups(&files);
+        assert_eq!(
+            groups,
+            [None, None, Some((0, ".jpg".into())), Some((0, ".CR2.xmp".into())), None, None]
+        );
+        assert_eq!(sidecar_name("x_001.cr2", "CR2", ".CR2.xmp", &ExtPolicy::Lower), "x_001.cr2.xmp");
+        assert_eq!(sidecar_name("x_001.CR2", "CR2", ".jpg", &ExtPolicy::Keep), "x_001.jpg");
+        // the extension replaced or dropped: the sidecars keep theirs
+        assert_eq!(sidecar_name("x_001.dng", "CR2", ".jpg", &ExtPolicy::Replace("dng".into())), "x_001.jpg");
+        assert_eq!(sidecar_name("x_001", "CR2", ".jpg", &ExtPolicy::Strip), "x_001.jpg");
+    }
+
+    #[test]
+    fn folders_keep_dotted_names() {
+        let dir = scratch_dir("folders");
+        let folder = dir.join("site.v2");
+        fs::create_dir(&folder).unwrap();
+        let blocks = vec![Block::Original { mode: OriginalMode::Keep, case: NameCase::Upper }, Block::Extension];
+        let now = Local::now();
+        assert_eq!(render_name(&blocks, 0, &folder, now, &ExtPolicy::Keep), "SITE.V2");
+        assert_eq!(render_name(&blocks, 0, &folder, now, &ExtPolicy::Replace("txt".into())), "SITE.V2");
+        // a file of the same name still has its extension split off
+        assert_eq!(render_name(&blocks, 0, Path::new("a/site.v2"), now, &ExtPolicy::Keep), "SITE.v2");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn scripts_see_the_file() {
+        let now = Local::now();
+        let keep = ExtPolicy::Keep;
+        let script = |code: &str| vec![Block::Script { code: code.into() }, Block::Extension];
+        let path = Path::new("a/IMG_0042.JPG");
+        let render = |code: &str, idx| render_name(&script(code), idx, path, now, &keep);
+        assert_eq!(render(r#"name.to_lower() + "_" + (index + 1)"#, 2), "img_0042_3.JPG");
+        assert_eq!(render(r#"if ext.to_lower() == "jpg" { "photo" } else { "other" }"#, 0), "photo.JPG");
+        assert_eq!(render(r#"let n = regex_replace(name, "\\D", ""); n"#, 0), "0042.JPG");
+        assert_eq!(render("let n = 0; for i in 0..index { n += 2 } n", 4), "8.JPG");
+        assert_eq!(render("name +", 0), "[SCRIPT_ERROR].JPG");
+        // a loop that never ends stops at the operation limit
+        assert_eq!(render("loop {}", 0), "[SCRIPT_ERROR].JPG");
+        assert!(run_script("unknown_variable", 0, path).is_err());
+    }
+
+    #[test]
+    fn conditional_blocks_follow_the_file() {
+        let now = Local::now();
+        let keep = ExtPolicy::Keep;
+        let photo = Box::new(Block::Literal("photo_".into()));
+        let when = |when| {
+            vec![
+                Block::Conditional { when, block: photo.clone(), otherwise: "side_".into() },
+                Block::Original { mode: OriginalMode::Keep, case: NameCase::Keep },
+                Block::Extension,
+            ]
+        };
+        let ext = when(Condition::ExtIn("jpg, .PNG".into()));
+        assert_eq!(render_name(&ext, 0, Path::new("IMG_1.JPG"), now, &keep), "photo_IMG_1.JPG");
+        assert_eq!(render_name(&ext, 0, Path::new("IMG_1.png"), now, &keep), "photo_IMG_1.png");
+        assert_eq!(render_name(&ext, 0, Path::new("IMG_1.xmp"), now, &keep), "side_IMG_1.xmp");
+        let name = when(Condition::NameMatches(r"^IMG_\d+$".into()));
+        assert_eq!(render_name(&name, 0, Path::new("IMG_1.xmp"), now, &keep), "photo_IMG_1.xmp");
+        assert_eq!(render_name(&name, 0, Path::new("IMG_1a.jpg"), now, &keep), "side_IMG_1a.jpg");
+        let mut tpl = Template::new(when(Condition::NameMatches("(".into())));
+        tpl.name = "t".into();
+        assert_eq!(tpl.problems().len(), 1);
+    }
+
+    #[test]
+    fn case_transforms() {
+        let name = "my holidayPhoto_2024-final";
+        assert_eq!(NameCase::Upper.apply(name), "MY HOLIDAYPHOTO_2024-FINAL");
+        assert_eq!(NameCase::Title.apply("the QUICK fox"), "The Quick Fox");
+        assert_eq!(NameCase::Snake.apply(name), "my_holiday_photo_2024_final");
+        assert_eq!(NameCase::Kebab.apply("IMG 0001  Édité"), "img-0001-édité");
+    }
+
+    #[test]
+    fn slices_count_characters() {
+        assert_eq!(slice_stem("IMG_20240305_1200", 0, 8, false), "IMG_2024");
+        assert_eq!(slice_stem("report_v2_DRAFT", 6, 0, true), "report_v2");
+        assert_eq!(slice_stem("report_v2_DRAFT", 0, 5, true), "DRAFT");
+        assert_eq!(slice_stem("写真が好き", 1, 2, false), "真が");
+        // e + combining acute stays one character
+        assert_eq!(slice_stem("cafe\u{301}s", 3, 1, false), "e\u{301}");
+        assert_eq!(slice_stem("abc", 5, 2, false), "");
+    }
+
+    #[test]
+    fn size_units() {
+        assert_eq!(SizeUnit::Auto.format(845), "845B");
+        assert_eq!(SizeUnit::Auto.format(12 * 1024 + 100), "12KB");
+        assert_eq!(SizeUnit::Auto.format(4_718_592), "4.5MB");
+        assert_eq!(SizeUnit::Auto.format(3 << 30), "3.0GB");
+        assert_eq!(SizeUnit::Kilobytes.format(123_456), "121KB");
+        assert_eq!(SizeUnit::Megabytes.format(123_456), "0.1MB");
+    }
+
+    #[test]
+    fn tag_values() {
+        let tags = AudioTags {
+            artist: Some("AC/DC".into()),
+            track: Some("3/12".into()),
+            ..Default::default()
+        };
+        assert_eq!(TagField::Artist.value(&tags).as_deref(), Some("AC_DC"));
+        assert_eq!(TagField::Track.value(&tags).as_deref(), Some("03"));
+        assert_eq!(TagField::Title.value(&tags), None);
+        let blocks = vec![Block::AudioTag { field: TagField::Title, placeholder: "Unknown".into() }];
+        assert_eq!(render_name(&blocks, 0, Path::new("x.mp3"), Local::now(), &ExtPolicy::Keep), "Unknown");
+    }
+
+    #[test]
+    fn char_diff_runs() {
+        let runs = diff_chars("IMG_0042.JPG", "trip_0042.jpg");
+        let old: String = runs.iter().filter(|r| r.0 != '+').map(|r| r.1.as_str()).collect();
+        let new: String = runs.iter().filter(|r| r.0 != '-').map(|r| r.1.as_str()).collect();
+        assert_eq!((old.as_str(), new.as_str()), ("IMG_0042.JPG", "trip_0042.jpg"));
+        assert!(runs.contains(&(' ', "_0042.".to_string())));
+        assert_eq!(diff_chars("same", "same"), vec![(' ', "same".to_string())]);
+    }
+
+    #[test]
+    fn image_shapes() {
+        assert_eq!(orientation(1080, 1920), "portrait");
+        assert_eq!(orientation(1000, 995), "square");
+        assert_eq!(aspect_ratio(1920, 1080), "16x9");
+        assert_eq!(aspect_ratio(1080, 1920), "9x16");
+        assert_eq!(aspect_ratio(3440, 1440), "21x9");
+        assert_eq!(aspect_ratio(6000, 4000), "3x2");
+        assert_eq!(aspect_ratio(1000, 700), "10x7");
+        assert_eq!(ShapeField::Orientation.value((1280, 800)), "landscape");
+        let dir = scratch_dir("shapes");
+        let tall = dir.join("tall.png");
+        image::RgbImage::new(2, 4).save(&tall).unwrap();
+        assert!(Condition::ShapeIn("square, Portrait".into()).matches(tall.as_path()));
+        assert!(!Condition::ShapeIn("16x9".into()).matches(tall.as_path()));
+        assert!(!Condition::ShapeIn("portrait".into()).matches(dir.join("notes.txt").as_path()));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn camera_values() {
+        let info = CameraInfo {
+            model: Some("Canon EOS R5 ".into()),
+            lens: Some("RF24-105mm F4 L IS USM".into()),
+            iso: Some(200),
+            gps: Some((35.68123, -0.12756)),
+            ..Default::default()
+        };
+        assert_eq!(CameraField::Model.value(&info).as_deref(), Some("Canon EOS R5"));
+        assert_eq!(CameraField::Iso.value(&info).as_deref(), Some("ISO200"));
+        assert_eq!(CameraField::Gps.value(&info).as_deref(), Some("35.681N_0.128W"));
+        assert_eq!(CameraField::Make.value(&info), None);
+        let blocks = vec![Block::Camera { field: CameraField::Model, placeholder: "nocam".into() }];
+        assert_eq!(render_name(&blocks, 0, Path::new("x.jpg"), Local::now(), &ExtPolicy::Keep), "nocam");
+    }
+
+    #[test]
+    fn cleanup_messy_names() {
+        let all = |stem| clean_stem(stem, true, true, true, "");
+        assert_eq!(all("  Movie.Title  [1080p] (copy)  "), "Movie.Title");
+        assert_eq!(all("song__(live [2019])__final"), "song_final");
+        assert_eq!(all("【公式】 曲名 （MV）"), "曲名");
+        assert_eq!(clean_stem("a  b__c", false, false, false, "_"), "a  bc");
+        assert_eq!(clean_stem(" x [y] ", false, false, true, ""), " x  ");
+        assert_eq!(clean_stem("#tag! name", true, true, false, "#!"), "tag name");
+    }
+
+    #[test]
+    fn transliterate_to_ascii() {
+        assert_eq!(transliterate("Café Crème Brûlée", false, "_"), "Cafe Creme Brulee");
+        assert_eq!(transliterate("Straße – Ærø", false, "_"), "Strasse - AEro");
+        assert_eq!(transliterate("ＡＢＣ１２３", false, "_"), "ABC123");
+        assert_eq!(transliterate("しゃしん_きって", true, "_"), "shashin_kitte");
+        assert_eq!(transliterate("コーヒー・マッチャ", true, "-"), "koohii-matcha");
+        assert_eq!(transliterate("ファイル ちゃん", true, ""), "fairu chan");
+        // kanji have no reading here
+        assert_eq!(transliterate("東京タワー", true, "_"), "__tawaa");
+        assert_eq!(transliterate("ガス", false, "_"), "__");
+    }
+
+    #[test]
+    fn find_replace_plain_text() {
+        let fr = |find: &str, replace: &str, case_sensitive, all| FindReplace {
+            find: find.into(),
+            replace: replace.into(),
+            case_sensitive,
+            all,
+        };
+        assert_eq!(fr(" - Copy", "", false, true).apply("report - copy - Copy"), "report");
+        assert_eq!(fr(" - Copy", "", true, true).apply("report - copy - Copy"), "report - copy");
+        assert_eq!(fr("a", "b", false, false).apply("banana"), "bbnana");
+        // no regex syntax, no $ expansion
+        assert_eq!(fr("(1).", "$1", true, true).apply("x(1).y"), "x$1y");
+        assert_eq!(FindReplace::default().apply("same"), "same");
+    }
+
+    #[test]
+    fn regex_replace_with_groups() {
+        assert_eq!(regex_replace("IMG_1234_edited", r"^IMG_(\d+)_.*$", "$1"), "1234");
+        assert_eq!(regex_replace("2024-03-05 trip", r"(?P<y>\d{4})-(?P<m>\d\d)-\d\d", "${m}.${y}"), "03.2024 trip");
+        assert_eq!(regex_replace("a_b_c", "_", "-"), "a-b-c");
+        assert_eq!(regex_replace("keep", "(", "x"), "keep");
+    }
+
+    #[test]
+    fn compile_caches_stay_bounded() {
+        let mut cache = HashMap::new();
+        for i in 0..CACHE_LIMIT * 3 {
+            assert_eq!(*cached(&mut cache, i, || i * 2), i * 2);
+            assert!(cache.len() <= CACHE_LIMIT);
+        }
+        // a hit never empties the cache
+        let last = CACHE_LIMIT * 3 - 1;
+        let len = cache.len();
+        assert_eq!(*cached(&mut cache, last, || 0), last * 2);
+        assert_eq!(cache.len(), len);
+    }
+
+    #[test]
+    fn number_width_step_and_grouping() {
+        let dec = NumberStyle::Decimal;
+        assert_eq!(format_number(2, 4, 10, 5, dec, '0', Locale::Default), "0020");
+        assert_eq!(format_number(0, 0, -3, 1, dec, '0', Locale::Default), "-3");
+        assert_eq!(format_number(0, 3, -3, 1, dec, '0', Locale::Default), "-03");
+        assert_eq!(format_number(0, 0, 1234567, 1, dec, '0', Locale::En), "1,234,567");
+        assert_eq!(format_number(0, 0, -1234, 1, dec, '0', Locale::De), "-1.234");
+        // counting down past zero keeps the width
+        assert_eq!(format_number(3, 3, 2, -1, dec, '0', Locale::Default), "-01");
+        assert_eq!(format_number(3, 4, 2, -1, dec, ' ', Locale::Default), "  -1");
+        assert_eq!(format_number(0, 7, 1234, 1, dec, '_', Locale::En), "__1,234");
+    }
+
+    #[test]
+    fn number_styles() {
+        let fmt = |val: i64, style| format_number(0, 0, val, 1, style, '0', Locale::En);
+        assert_eq!(format_number(0, 4, 255, 1, NumberStyle::Hex, '0', Locale::En), "00ff");
+        assert_eq!(format_number(0, 6, 5, 1, NumberStyle::Binary, '0', Locale::En), "000101");
+        assert_eq!(format_number(0, 4, -2, 1, NumberStyle::Binary, ' ', Locale::En), " -10");
+        assert_eq!(fmt(1, NumberStyle::Letters), "a");
+        assert_eq!(fmt(26, NumberStyle::Letters), "z");
+        assert_eq!(fmt(27, NumberStyle::Letters), "aa");
+        assert_eq!(fmt(703, NumberStyle::Letters), "aaa");
+        assert_eq!(fmt(1994, NumberStyle::Roman), "MCMXCIV");
+        assert_eq!(fmt(4, NumberStyle::Roman), "IV");
+        // out of range: plain decimal
+        assert_eq!(fmt(0, NumberStyle::Roman), "0");
+        assert_eq!(fmt(4000, NumberStyle::Roman), "4,000");
+    }
+
+    #[test]
+    fn original_modes() {
+        let range = OriginalMode::RemoveRange { start: 0, end: 4 };
+        assert_eq!(process_original("IMG_0001", &range), "0001");
+        let tail = OriginalMode::RemoveRange { start: -4, end: -1 };
+        assert_eq!(process_original("IMG_0001", &tail), "IMG_");
+        let sub = OriginalMode::RemoveSubstring { pattern: "copy/ _ ".into(), case_sensitive: false };
+        assert_eq!(process_original("report_Copy", &sub), "report");
+    }
+
+    #[test]
+    fn localized_dates_and_era() {
+        let date = Local.with_ymd_and_hms(2024, 3, 5, 12, 0, 0).unwrap();
+        assert_eq!(date.format(&Locale::Ja.localize_format("%EY%B", &date)).to_string(), "令和6年3月");
+        assert_eq!(date.format(&Locale::De.localize_format("%A %%B", &date)).to_string(), "Dienstag %B");
+        let first = Local.with_ymd_and_hms(2019, 5, 1, 0, 0, 0).unwrap();
+        assert_eq!(japanese_era(&first), Some(("令和", 'R', 1)));
+    }
+
+    #[test]
+    fn date_zones_and_bad_formats() {
+        let date = chrono::Utc.with_ymd_and_hms(2024, 3, 5, 22, 30, 0).unwrap().with_timezone(&Local);
+        let fmt = |zone: DateZone, format| format_date(&zone.convert(date), format, Locale::Default);
+        assert_eq!(fmt(DateZone::Utc, "%Y%m%d_%H%M%z").unwrap(), "20240305_2230+0000");
+        assert_eq!(fmt(DateZone::Offset(9 * 60), "%Y-%m-%d_%H%M").unwrap(), "2024-03-06_0730");
+        assert_eq!(fmt(DateZone::Offset(-330), "%H:%M").unwrap(), "17:00");
+        assert_eq!(DateZone::Offset(-330).label(), "UTC-05:30");
+        assert!(fmt(DateZone::Utc, "%Y%Q").is_none());
+        let mut tpl = Template::new(vec![Block::ExifDate { format: "%Y-%".into(), locale: Locale::Default }]);
+        tpl.name = "t".into();
+        assert_eq!(tpl.problems().len(), 1);
+    }
+
+    #[test]
+    fn date_sources() {
+        let dir = scratch_dir("dates");
+        let file = dir.join("a.txt");
+        fs::write(&file, "a").unwrap();
+        let mtime = Local.with_ymd_and_hms(2020, 5, 17, 8, 30, 0).unwrap();
+        filetime::set_file_mtime(&file, filetime::FileTime::from_unix_time(mtime.timestamp(), 0)).unwrap();
+        let now = Local::now();
+        let date = |source| Block::Date {
+            format: "%Y%m%d".into(),
+            locale: Locale::Default,
+            source,
+            zone: DateZone::Local,
+        };
+        let render = |b: Block| render_name(&[b], 0, &file, now, &ExtPolicy::Keep);
+        assert_eq!(render(date(DateSource::Modified)), "20200517");
+        assert_eq!(render(date(DateSource::Now)), now.format("%Y%m%d").to_string());
+        assert_eq!(DateSource::Modified.date(Path::new("missing/x"), now), now);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn trailing_fix() {
+        assert_eq!(TrailingFix::Trim.apply("name. ".into()), "name");
+        assert_eq!(TrailingFix::Replace.apply("name..".into()), "name__");
+        assert_eq!(TrailingFix::Warn.apply("name.".into()), "name.");
+    }
+
+    #[test]
+    fn natural_order() {
+        let mut names = vec!["img10.jpg", "IMG2.jpg", "img1.jpg", "img02.jpg", "a.jpg"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(names, ["a.jpg", "img1.jpg", "IMG2.jpg", "img02.jpg", "img10.jpg"]);
+    }
+
+    #[test]
+    fn portable_names() {
+        assert_eq!(portable_name("a<b>:c?.txt").0, "a_b__c_.txt");
+        assert_eq!(portable_name("notes. ").0, "notes");
+        assert_eq!(portable_name("con.txt").0, "con_.txt");
+        assert_eq!(portable_name("NUL").0, "NUL_");
+        assert_eq!(portable_name("console.txt"), ("console.txt".to_string(), Vec::new()));
+        assert_eq!(portable_name("a|b|c").1, vec!["'|' → '_'".to_string()]);
+    }
+
+    #[test]
+    fn template_files_round_trip_and_validate() {
+        let mut tpl = Template::new(vec![Block::Literal("a".into()), number(2, 1, 1)]);
+        tpl.name = "shared".into();
+        let text = export_templates(std::slice::from_ref(&tpl));
+        let Ok(back) = import_templates(&text) else { panic!("round trip failed") };
+        assert_eq!(back[0].name, "shared");
+        assert_eq!(back[0].diff(&tpl).iter().filter(|(k, _)| *k != ' ').count(), 0);
+
+        let newer = text.replace("\"version\": 1", "\"version\": 99");
+        assert!(import_templates(&newer).is_err_and(|e| e[0].contains("newer")));
+        tpl.blocks.push(Block::RegexReplace { pattern: "(".into(), replacement: String::new() });
+        assert!(import_templates(&serde_json::to_string(&tpl).unwrap()).is_err_and(|e| e.len() == 1));
+        assert!(import_templates("{}").is_err());
+    }
+
+    #[test]
+    fn extension_policies() {
+        assert_eq!(ExtPolicy::Lower.apply("JPG"), "jpg");
+        assert_eq!(ExtPolicy::Upper.apply("png"), "PNG");
+        assert_eq!(ExtPolicy::Replace(".zip".into()).apply("cbz"), "zip");
+        assert_eq!(ExtPolicy::Replace("txt".into()).apply(""), "txt");
+        assert_eq!(ExtPolicy::Strip.apply("jpg"), "");
+    }
+
+    #[test]
+    fn dates_from_names() {
+        let parse = |name: &str, pattern: &str| DatePattern::new(pattern).unwrap().parse(name);
+        let date = parse("IMG_20240305_142233.jpg", "IMG_%Y%m%d_%H%M%S").unwrap();
+        assert_eq!(date.format("%Y-%m-%d %H:%M:%S").to_string(), "2024-03-05 14:22:33");
+        // found anywhere in the name, missing time is midnight
+        let date = parse("IMG-20240305-WA0012.jpg", "%Y%m%d").unwrap();
+        assert_eq!(date.format("%Y-%m-%d %H:%M").to_string(), "2024-03-05 00:00");
+        assert_eq!(parse("scan 05.03.24", "%d.%m.%y").unwrap().format("%F").to_string(), "2024-03-05");
+        assert!(parse("IMG_20241345.jpg", "%Y%m%d").is_none());
+        assert!(parse("notes.txt", "%Y%m%d").is_none());
+        assert!(DatePattern::new("IMG_%Q").is_none() && DatePattern::new("plain").is_none());
+        assert!(DatePattern::new("100%%_%Y").is_some());
+    }
+
+    #[test]
+    fn name_date_blocks() {
+        let block = |pattern: &str| Block::NameDate {
+            pattern: pattern.into(),
+            format: "%Y%m%d".into(),
+            locale: Locale::Default,
+            missing: "nodate".into(),
+        };
+        let render = |b: Block, name: &str| render_name(&[b], 0, Path::new(name), Local::now(), &ExtPolicy::Keep);
+        assert_eq!(render(block(""), "2023-07-01 report.pdf"), "20230701");
+        assert_eq!(render(block(""), "IMG_20240305_142233.jpg"), "20240305");
+        assert_eq!(render(block(""), "scan 2024.03.05.png"), "20240305");
+        assert_eq!(render(block("%d.%m.%y"), "scan 05.03.24.png"), "20240305");
+        assert_eq!(render(block(""), "notes.txt"), "nodate");
+        let stamp = Local.with_ymd_and_hms(2023, 7, 1, 12, 0, 0).unwrap().timestamp();
+        assert_eq!(render(block(""), &format!("export_{}.csv", stamp)), "20230701");
+        assert_eq!(render(block(""), &format!("export_{}123.csv", stamp)), "20230701");
+        // a timestamp whose digits also read as a date comes out as a timestamp
+        let expected = Local.timestamp_opt(1_710_101_010, 0).unwrap().format("%Y%m%d").to_string();
+        assert!(expected.starts_with("202403"));
+        assert_eq!(render(block(""), "export_1710101010"), expected);
+        // a guessed layout needs non-digits around it
+        assert_eq!(render(block(""), "scan_120240305.png"), "nodate");
+        assert_eq!(render(block(""), "v2_20240305.png"), "20240305");
+        // an impossible first match doesn't hide a later one
+        assert_eq!(render(block(""), "20241399_20240305.png"), "20240305");
+        assert_eq!(render(block("%Y-%m-%d"), "2024-13-2024-03-05"), "20240305");
+    }
+
+    #[test]
+    fn duplicates_are_grouped_by_content() {
+        let dir = scratch_dir("dups");
+        let names = ["a.txt", "b.txt", "c.txt", "d.txt", "e.txt"];
+        for (name, text) in names.iter().zip(["same", "diff", "same", "same", "four"]) {
+            fs::write(dir.join(name), text).unwrap();
+        }
+        let mut paths: Vec<PathBuf> = names.iter().map(|n| dir.join(n)).collect();
+        paths.push(dir.join("missing.txt"));
+        let groups = find_duplicates(&paths);
+        assert_eq!(groups, vec![vec![paths[0].clone(), paths[2].clone(), paths[3].clone()]]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn numbered_path_replaces_suffix() {
+        assert_eq!(make_numbered_path(Path::new("d/a.jpg"), 1), Path::new("d/a (1).jpg"));
+        assert_eq!(make_numbered_path(Path::new("d/a (4).jpg"), 2), Path::new("d/a (2).jpg"));
+        assert_eq!(make_numbered_path(Path::new("d/README"), 3), Path::new("d/README (3)"));
+        assert_eq!(make_numbered_dir_path(Path::new("d/site.v2 (1)"), 2), Path::new("d/site.v2 (2)"));
+    }
+
+    #[test]
+    fn collisions_within_batch() {
+        let origs = vec![PathBuf::from("d/x.jpg"), PathBuf::from("d/y.jpg")];
+        let same = vec![PathBuf::from("d/a.jpg"), PathBuf::from("d/a.jpg")];
+        let (suffixed, _) = resolve_targets(CollisionStrategy::Suffix, false, &origs, &same);
+        assert_eq!(suffixed, vec![PathBuf::from("d/a.jpg"), PathBuf::from("d/a (1).jpg")]);
+        let (skipped, _) = resolve_targets(CollisionStrategy::Skip, false, &origs, &same);
+        assert_eq!(skipped, vec![PathBuf::from("d/a.jpg"), PathBuf::from("d/y.jpg")]);
+    }
+
+    #[test]
+    fn extended_length_paths() {
+        assert_eq!(extended_form(r"C:\photos\.\2024\..\a.jpg").as_deref(), Some(r"\\?\C:\photos\a.jpg"));
+        assert_eq!(extended_form("d:/x/y").as_deref(), Some(r"\\?\d:\x\y"));
+        assert_eq!(extended_form(r"\\nas\share\..\a").as_deref(), Some(r"\\?\UNC\nas\share\a"));
+        assert_eq!(extended_form(r"\\?\C:\a"), None);
+        assert_eq!(extended_form(r"photos\a.jpg"), None);
+        assert_eq!(path_length(Path::new("abc/é")), if cfg!(windows) { 5 } else { 6 });
+    }
+
+    #[test]
+    fn dedup_finds_earlier_copies() {
+        let dir = scratch_dir("dedup");
+        for (name, content) in [("x.txt", "x"), ("y.txt", "y"), ("a.txt", "other"), ("a (1).txt", "x")] {
+            fs::write(dir.join(name), content).unwrap();
+        }
+        let origs = vec![dir.join("x.txt"), dir.join("y.txt")];
+        let finals = vec![dir.join("a.txt"), dir.join("a.txt")];
+        let (resolved, duplicates) = resolve_targets(CollisionStrategy::Dedup, true, &origs, &finals);
+        // x is already there as "a (1)"; y differs from both and takes the next number
+        assert_eq!(resolved, vec![dir.join("x.txt"), dir.join("a (2).txt")]);
+        assert_eq!(duplicates, vec![dir.join("x.txt")]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn collisions_answered_per_file() {
+        let dir = scratch_dir("ask");
+        let names = ["x.txt", "y.txt", "z.txt", "taken.txt"];
+        let [x, y, z, taken] = names.map(|n| dir.join(n));
+        for f in [&x, &y, &z, &taken] {
+            fs::write(f, "x").unwrap();
+        }
+        let a = dir.join("a.txt");
+        let origs = vec![x.clone(), y.clone(), z.clone()];
+        let finals = vec![a.clone(), a.clone(), taken.clone()];
+        assert_eq!(find_collisions(&origs, &finals, false), vec![(1, Some(0)), (2, None)]);
+        // files outside the batch are skipped or numbered too
+        let (skipped, _) = resolve_targets(CollisionStrategy::Ask, false, &origs, &finals);
+        assert_eq!(skipped, vec![a.clone(), y.clone(), z.clone()]);
+        let answers = [CollisionStrategy::Ask, CollisionStrategy::Suffix, CollisionStrategy::Overwrite];
+        let (answered, _) = resolve_targets_each(&answers, false, &origs, &finals);
+        assert_eq!(answered, vec![a, dir.join("a (1).txt"), taken]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn copies_keep_sources() {
+        let origs = vec![PathBuf::from("d/a.jpg"), PathBuf::from("d/b.jpg")];
+        let finals = vec![PathBuf::from("d/b.jpg"), PathBuf::from("d/b.jpg")];
+        let suffixed = resolve_copy_targets(CollisionStrategy::Suffix, &origs, &finals);
+        assert_eq!(suffixed, vec![PathBuf::from("d/b (1).jpg"), PathBuf::from("d/b (2).jpg")]);
+        let overwrite = resolve_copy_targets(CollisionStrategy::Overwrite, &origs, &finals);
+        assert_eq!(overwrite, origs);
+
+        let dir = scratch_dir("copy");
+        let (a, b) = (dir.join("a.txt"), dir.join("b.txt"));
+        fs::write(&a, "a").unwrap();
+        copy_files(&[(a.clone(), b.clone())], KeepTimes::Modified, None).unwrap();
+        assert_eq!(fs::read_to_string(&a).unwrap(), "a");
+        assert_eq!(fs::read_to_string(&b).unwrap(), "a");
+        let pairs = [(a.clone(), dir.join("c.txt")), (dir.join("missing"), dir.join("d.txt"))];
+        let failed = copy_files(&pairs, KeepTimes::Modified, None);
+        assert!(matches!(failed, Err(RenameFailure::Copy { ref not_removed, .. }) if not_removed.is_empty()));
+        assert_eq!(failed.unwrap_err().sources(), vec![dir.join("missing")]);
+        assert!(!dir.join("c.txt").exists());
+
+        // a target that was there before the batch is never deleted by the rollback
+        fs::write(dir.join("c.txt"), "old").unwrap();
+        let pairs = [
+            (a.clone(), dir.join("c.txt")),
+            (a.clone(), dir.join("e.txt")),
+            (dir.join("missing"), dir.join("d.txt")),
+        ];
+        let failed = copy_files(&pairs, KeepTimes::Modified, None);
+        let kept = vec![dir.join("c.txt")];
+        assert!(matches!(failed, Err(RenameFailure::Copy { ref not_removed, .. }) if *not_removed == kept));
+        assert!(dir.join("c.txt").exists());
+        assert!(!dir.join("e.txt").exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn copies_keep_the_chosen_times() {
+        let dir = scratch_dir("times");
+        let from = dir.join("a.txt");
+        fs::write(&from, "a").unwrap();
+        let atime = filetime::FileTime::from_unix_time(1_500_000_000, 0);
+        let mtime = filetime::FileTime::from_unix_time(1_600_000_000, 0);
+        let times = |p: &Path| {
+            let meta = fs::metadata(p).unwrap();
+            let at = filetime::FileTime::from_last_access_time(&meta).unix_seconds();
+            (at, filetime::FileTime::from_last_modification_time(&meta).unix_seconds())
+        };
+        for (keep, name) in [(KeepTimes::Off, "off.txt"), (KeepTimes::Modified, "m.txt"), (KeepTimes::All, "all.txt")] {
+            filetime::set_file_times(&from, atime, mtime).unwrap();
+            copy_with_times(&from, &dir.join(name), keep).unwrap();
+        }
+        assert_ne!(times(&dir.join("off.txt")).1, 1_600_000_000);
+        assert_eq!(times(&dir.join("m.txt")).1, 1_600_000_000);
+        assert_eq!(times(&dir.join("all.txt")), (1_500_000_000, 1_600_000_000));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn copies_keep_creation_time_and_permissions() {
+        let dir = scratch_dir("created");
+        let from = dir.join("a.txt");
+        fs::write(&from, "a").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&from, fs::Permissions::from_mode(0o640)).unwrap();
+            copy_with_times(&from, &dir.join("off.txt"), KeepTimes::Off).unwrap();
+            assert_eq!(fs::metadata(dir.join("off.txt")).unwrap().permissions().mode() & 0o777, 0o640);
+        }
+        #[cfg(any(windows, target_os = "macos"))]
+        {
+            let created = UNIX_EPOCH + std::time::Duration::from_secs(1_400_000_000);
+            set_created(&from, created).unwrap();
+            copy_with_times(&from, &dir.join("all.txt"), KeepTimes::All).unwrap();
+            assert_eq!(fs::metadata(dir.join("all.txt")).unwrap().created().unwrap(), created);
+        }
+        // where the creation time can't be set, All still keeps the rest
+        copy_with_times(&from, &dir.join("all2.txt"), KeepTimes::All).unwrap();
+        assert_eq!(
+            fs::metadata(dir.join("all2.txt")).unwrap().modified().unwrap(),
+            fs::metadata(&from).unwrap().modified().unwrap()
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn swap_is_a_cycle() {
+        let origs = vec![PathBuf::from("d/a"), PathBuf::from("d/b")];
+        let swapped = vec![PathBuf::from("d/b"), PathBuf::from("d/a")];
+        let (resolved, _) = resolve_targets(CollisionStrategy::Suffix, false, &origs, &swapped);
+        assert_eq!(resolved, swapped);
+        assert_eq!(find_cycles(&origs, &resolved).len(), 1);
+    }
+
+    #[test]
+    fn plan_execute_and_undo() {
+        let dir = scratch_dir("plan");
+        let files: Vec<PathBuf> = ["b.txt", "a.txt", "note"].iter().map(|n| dir.join(n)).collect();
+        for f in &files {
+            fs::write(f, f.file_name().unwrap().to_string_lossy().as_bytes()).unwrap();
+        }
+        let template = Template::new(vec![Block::Literal("f".into()), number(1, 1, 1), Block::Extension]);
+        let plan = Plan::build(&files, &template);
+        let new_names: Vec<String> = plan
+            .pairs
+            .iter()
+            .map(|(_, new)| new.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(new_names, ["f1.txt", "f2.txt", "f3"]);
+
+        plan.execute().unwrap();
+        assert_eq!(fs::read_to_string(dir.join("f1.txt")).unwrap(), "b.txt");
+        assert!(!files[0].exists());
+
+        plan.undo().unwrap();
+        for f in &files {
+            assert_eq!(fs::read_to_string(f).unwrap(), f.file_name().unwrap().to_string_lossy());
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cross_device_move_keeps_mtime() {
+        let dir = scratch_dir("move");
+        let (from, to) = (dir.join("a.txt"), dir.join("b.txt"));
+        fs::write(&from, "a").unwrap();
+        filetime::set_file_mtime(&from, filetime::FileTime::from_unix_time(1_600_000_000, 0)).unwrap();
+        move_across_devices(&from, &to, KeepTimes::Modified).unwrap();
+        assert!(!from.exists());
+        assert_eq!(fs::read_to_string(&to).unwrap(), "a");
+        let mtime = filetime::FileTime::from_last_modification_time(&fs::metadata(&to).unwrap());
+        assert_eq!(mtime.unix_seconds(), 1_600_000_000);
+        assert!(move_across_devices(&dir.join("missing"), &dir.join("c.txt"), KeepTimes::Modified).is_err());
+        assert!(!dir.join("c.txt").exists());
+
+        let folder = dir.join("folder");
+        fs::create_dir_all(folder.join("sub")).unwrap();
+        fs::write(folder.join("sub/x.txt"), "x").unwrap();
+        move_across_devices(&folder, &dir.join("moved"), KeepTimes::Modified).unwrap();
+        assert!(!folder.exists());
+        assert_eq!(fs::read_to_string(dir.join("moved/sub/x.txt")).unwrap(), "x");
+
+        // an existing folder in the way is left alone, not replaced or cleaned up
+        let other = dir.join("other");
+        fs::create_dir(&other).unwrap();
+        fs::write(other.join("y.txt"), "y").unwrap();
+        let err = move_across_devices(&dir.join("moved"), &other, KeepTimes::Modified).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(other.join("y.txt")).unwrap(), "y");
+        assert_eq!(fs::read_to_string(dir.join("moved/sub/x.txt")).unwrap(), "x");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn copies_keep_extended_attributes() {
+        use std::os::unix::ffi::OsStrExt;
+        let dir = scratch_dir("xattr");
+        let c_path = |p: &Path| std::ffi::CString::new(p.as_os_str().as_bytes()).unwrap();
+        let name = std::ffi::CString::new("user.bulkrename.test").unwrap();
+        let folder = dir.join("folder");
+        fs::create_dir(&folder).unwrap();
+        fs::write(folder.join("a.txt"), "a").unwrap();
+        for p in [folder.clone(), folder.join("a.txt")] {
+            let set = unsafe { libc::setxattr(c_path(&p).as_ptr(), name.as_ptr(), b"tag".as_ptr().cast(), 3, 0) };
+            if set != 0 {
+                // no user xattrs on this file system
+                let _ = fs::remove_dir_all(&dir);
+                return;
+            }
+        }
+        move_across_devices(&folder, &dir.join("moved"), KeepTimes::Off).unwrap();
+        for p in [dir.join("moved"), dir.join("moved/a.txt")] {
+            let mut value = [0u8; 8];
+            let len = unsafe { libc::getxattr(c_path(&p).as_ptr(), name.as_ptr(), value.as_mut_ptr().cast(), 8) };
+            assert_eq!(&value[..len.max(0) as usize], b"tag", "{:?}", p);
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn target_dirs_created_and_removed() {
+        let dir = scratch_dir("dirs");
+        let targets = vec![dir.join("2024/05/a.jpg"), dir.join("2024/06/b.jpg"), dir.join("c.jpg")];
+        let created = create_target_dirs(&targets).unwrap();
+        assert_eq!(created, vec![dir.join("2024"), dir.join("2024/05"), dir.join("2024/06")]);
+        fs::write(dir.join("2024/06/b.jpg"), "b").unwrap();
+        remove_empty_dirs(&created);
+        assert!(!dir.join("2024/05").exists());
+        assert!(dir.join("2024/06/b.jpg").exists());
+        // a file in the way: nothing is left behind
+        fs::write(dir.join("file"), "").unwrap();
+        assert!(create_target_dirs(&[dir.join("new/x"), dir.join("file/y/z")]).is_err());
+        assert!(!dir.join("new").exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn journal_completes_and_rolls_back() {
+        let dir = scratch_dir("journal");
+        let journal_path = dir.join("journal.json");
+        let (a, b, c) = (dir.join("a"), dir.join("b"), dir.join("c"));
+        fs::write(&a, "a").unwrap();
+        fs::write(&b, "b").unwrap();
+        // a swap cut short: a is staged, b already took a's name
+        let journal = RenameJournal {
+            marks: None,
+            steps: vec![
+                JournalStep { orig: a.clone(), tmp: dir.join(".tmp-a"), target: b.clone() },
+                JournalStep { orig: b.clone(), tmp: dir.join(".tmp-b"), target: a.clone() },
+                JournalStep { orig: c.clone(), tmp: dir.join(".tmp-c"), target: dir.join("d") },
+            ],
+        };
+        fs::rename(&a, dir.join(".tmp-a")).unwrap();
+        fs::rename(&b, &a).unwrap();
+        assert_eq!(journal.states(), vec![StepState::Staged, StepState::Done, StepState::Lost]);
+        assert!(journal.interrupted());
+        journal.roll_back(RetryPolicy::default()).unwrap();
+        assert_eq!(fs::read_to_string(&a).unwrap(), "a");
+        assert_eq!(fs::read_to_string(&b).unwrap(), "b");
+        journal.complete(RetryPolicy::default()).unwrap();
+        assert_eq!(fs::read_to_string(&a).unwrap(), "b");
+        assert!(!journal.interrupted());
+
+        two_phase_rename_journaled(&[(a.clone(), c.clone())], RetryPolicy::default(), Some(&journal_path), None)
+            .unwrap();
+        assert!(c.exists() && !journal_path.exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn journal_recovers_an_interrupted_cycle() {
+        use std::io::Write;
+        for complete in [true, false] {
+            let dir = scratch_dir(if complete { "cycle-complete" } else { "cycle-roll-back" });
+            let path = dir.join("journal.json");
+            let [a, b, c] = ["a", "b", "c"].map(|n| dir.join(n));
+            for f in [&a, &b, &c] {
+                fs::write(f, f.file_name().unwrap().as_encoded_bytes()).unwrap();
+            }
+            // a → b → c → a, cut short after b reached c but before that was marked
+            let tmp = |n: &str| dir.join(format!(".tmp-{}", n));
+            let steps = vec![
+                JournalStep { orig: a.clone(), tmp: tmp("a"), target: b.clone() },
+                JournalStep { orig: b.clone(), tmp: tmp("b"), target: c.clone() },
+                JournalStep { orig: c.clone(), tmp: tmp("c"), target: a.clone() },
+            ];
+            let mut file = (RenameJournal { steps, marks: None }).save(&path).unwrap();
+            for n in ["a", "b", "c"] {
+                fs::rename(dir.join(n), tmp(n)).unwrap();
+            }
+            fs::rename(tmp("a"), &b).unwrap();
+            fs::rename(tmp("b"), &c).unwrap();
+            file.write_all(b"s 0\ns 1\ns 2\nd 0\n").unwrap();
+
+            let journal = RenameJournal::load(&path).unwrap();
+            assert_eq!(journal.states(), vec![StepState::Done, StepState::Done, StepState::Staged]);
+            assert!(journal.interrupted());
+            let expected = if complete {
+                journal.complete(RetryPolicy::default()).unwrap();
+                ["c", "a", "b"]
+            } else {
+                journal.roll_back(RetryPolicy::default()).unwrap();
+                ["a", "b", "c"]
+            };
+            for (f, content) in [&a, &b, &c].into_iter().zip(expected) {
+                assert_eq!(fs::read_to_string(f).unwrap(), content);
+            }
+            assert_eq!(fs::read_dir(&dir).unwrap().count(), 4);
+            let _ = fs::remove_dir_all(&dir);
+        }
+    }
+
+    #[test]
+    fn progress_counts_and_cancels() {
+        let dir = scratch_dir("progress");
+        let (a, b) = (dir.join("a"), dir.join("b"));
+        fs::write(&a, "a").unwrap();
+        let progress = Progress::default();
+        two_phase_rename_journaled(&[(a.clone(), b.clone())], RetryPolicy::default(), None, Some(&progress)).unwrap();
+        assert_eq!(progress.counts(), (2, 2));
+
+        progress.cancel();
+        let cancelled =
+            two_phase_rename_journaled(&[(b.clone(), a.clone())], RetryPolicy::default(), None, Some(&progress));
+        assert!(matches!(cancelled, Err(RenameFailure::Cancelled { ref not_restored }) if not_restored.is_empty()));
+        let copied = copy_files(&[(b.clone(), a.clone())], KeepTimes::Modified, Some(&progress));
+        assert!(matches!(copied, Err(RenameFailure::Cancelled { .. })));
+        assert!(b.exists() && !a.exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn plan_swaps_names() {
+        let dir = scratch_dir("swap");
+        let (a, b) = (dir.join("a"), dir.join("b"));
+        fs::write(&a, "A").unwrap();
+        fs::write(&b, "B").unwrap();
+        let template = Template::new(vec![Block::Literal("x".into())]);
+        let plan = Plan {
+            pairs: vec![(a.clone(), b.clone()), (b.clone(), a.clone())],
+            retry: RetryPolicy::default(),
+        };
+        plan.execute().unwrap();
+        assert_eq!(fs::read_to_string(&a).unwrap(), "B");
+        assert_eq!(fs::read_to_string(&b).unwrap(), "A");
+        // all targets equal: the second one is numbered
+        let plan = Plan::build(&[a.clone(), b.clone()], &template);
+        assert_eq!(plan.pairs, vec![(a, dir.join("x")), (b, dir.join("x (1)"))]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn replaced_files_skip_freed_names() {
+        let dir = scratch_dir("replaced");
+        let (a, b, c) = (dir.join("a"), dir.join("b"), dir.join("c"));
+        for p in [&a, &b, &c] {
+            fs::write(p, "x").unwrap();
+        }
+        // a swap frees both names; c is outside the batch
+        let swap = [(a.clone(), b.clone()), (b.clone(), a.clone())];
+        assert!(replaced_files(&swap, false).is_empty());
+        assert_eq!(replaced_files(&[(a.clone(), c.clone()), (b.clone(), c.clone())], false), vec![c.clone()]);
+        assert_eq!(replaced_files(&[(a.clone(), dir.join("d"))], false), Vec::<PathBuf>::new());
+        // copies leave their sources in place
+        assert_eq!(replaced_files(&swap, true), vec![b, a]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn set_aside_files_come_back_unless_taken() {
+        let dir = scratch_dir("aside");
+        let (a, b) = (dir.join("a.txt"), dir.join("b.txt"));
+        fs::write(&a, "a").unwrap();
+        fs::write(&b, "b").unwrap();
+        let aside = set_aside(&[a.clone(), b.clone()]).unwrap();
+        assert!(!a.exists() && !b.exists());
+        // the batch got as far as b
+        fs::write(&b, "new").unwrap();
+        let left = put_back(&aside);
+        assert_eq!(fs::read_to_string(&a).unwrap(), "a");
+        assert_eq!(fs::read_to_string(&b).unwrap(), "new");
+        assert_eq!(left, vec![aside[1].1.clone()]);
+        assert_eq!(fs::read_to_string(&left[0]).unwrap(), "b");
+        assert!(set_aside(&[dir.join("missing")]).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn folder_logs_append_per_folder() {
+        let dir = scratch_dir("folder_log");
+        let sub = dir.join("sub");
+        fs::create_dir_all(&sub).unwrap();
+        let time = Local.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        let pairs = [(dir.join("a.jpg"), dir.join("b.jpg")), (dir.join("c \"1\".jpg"), sub.join("c.jpg"))];
+        assert!(write_folder_logs(FolderLog::Json, "rename", "Photos", time, &pairs).is_empty());
+        assert!(write_folder_logs(FolderLog::Json, "undo", "", time, &pairs[..1]).is_empty());
+        let log: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(dir.join(".bulkrename-log.json")).unwrap()).unwrap();
+        assert_eq!(log.as_array().unwrap().len(), 2);
+        assert_eq!(log[0]["template"], "Photos");
+        assert_eq!(log[0]["files"].as_array().unwrap().len(), 2);
+        // the moved file is logged where it arrived too
+        let sub_log = fs::read_to_string(sub.join(".bulkrename-log.json")).unwrap();
+        assert!(sub_log.contains("c.jpg") && !sub_log.contains("b.jpg"));
+
+        write_folder_logs(FolderLog::Csv, "rename", "Photos", time, &pairs[1..]);
+        let csv = fs::read_to_string(sub.join(".bulkrename-log.csv")).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "time,action,template,old,new");
+        assert!(lines[1].contains("\"rename\",\"Photos\"") && lines[1].contains("c \"\"1\"\".jpg"));
+        assert!(FolderLog::is_log_file(&sub.join(".bulkrename-log.csv")));
+
+        // a damaged log is not overwritten
+        fs::write(dir.join(".bulkrename-log.json"), "{").unwrap();
+        assert_eq!(write_folder_logs(FolderLog::Json, "rename", "", time, &pairs[..1]).len(), 1);
+        assert_eq!(fs::read_to_string(dir.join(".bulkrename-log.json")).unwrap(), "{");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // (tag, type, count, value bytes in the fixture's byte order)
+    type TiffEntry = (u16, u16, u32, Vec<u8>);
+
+    /// TIFF bytes: IFD0 at 8, then the EXIF IFD (when `exif` isn't empty, with its pointer
+    /// added to IFD0), then the values over 4 bytes.
+    fn tiff_fixture(le: bool, mut ifd0: Vec<TiffEntry>, exif: Vec<TiffEntry>) -> Vec<u8> {
+        let w16 = |v: u16| if le { v.to_le_bytes() } else { v.to_be_bytes() };
+        let w32 = |v: u32| if le { v.to_le_bytes() } else { v.to_be_bytes() };
+        let ifd_len = |n: usize| 2 + 12 * n + 4;
+        if !exif.is_empty() {
+            ifd0.push((0x8769, 4, 1, Vec::new()));
+        }
+        let exif_at = 8 + ifd_len(ifd0.len());
+        let data_at = exif_at + if exif.is_empty() { 0 } else { ifd_len(exif.len()) };
+        let mut out = if le { b"II*\0".to_vec() } else { b"MM\0*".to_vec() };
+        out.extend(w32(8));
+        let mut data = Vec::new();
+        for ifd in [ifd0, exif].iter().filter(|ifd| !ifd.is_empty()) {
+            out.extend(w16(ifd.len() as u16));
+            for (tag, kind, count, value) in ifd {
+                out.extend(w16(*tag));
+                out.extend(w16(*kind));
+                out.extend(w32(*count));
+                let mut value = if *tag == 0x8769 { w32(exif_at as u32).to_vec() } else { value.clone() };
+                if value.len() > 4 {
+                    out.extend(w32((data_at + data.len()) as u32));
+                    data.append(&mut value);
+                } else {
+                    value.resize(4, 0);
+                    out.extend(value);
+                }
+            }
+            out.extend([0; 4]);
+        }
+        out.extend(data);
+        out
+    }
+
+    fn ascii(text: &str) -> (u32, Vec<u8>) {
+        let mut bytes = text.as_bytes().to_vec();
+        bytes.push(0);
+        (bytes.len() as u32, bytes)
+    }
+
+    /// A JPEG holding `tiff` in an APP1 "Exif" segment.
+    fn jpeg_with_exif(tiff: &[u8]) -> Vec<u8> {
+        let mut out = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        out.extend(((tiff.len() + 8) as u16).to_be_bytes());
+        out.extend(b"Exif\0\0");
+        out.extend(tiff);
+        out.extend([0xFF, 0xDA, 0, 2]);
+        out
+    }
+
+    #[test]
+    fn exif_dates_in_both_byte_orders() {
+        let stamp = |text: &str| chrono::NaiveDate::parse_from_str(text, "%Y-%m-%d").unwrap().and_hms_opt(10, 20, 30);
+        for le in [true, false] {
+            let (count, original) = ascii("2024:05:01 10:20:30");
+            let (dcount, digitized) = ascii("2023:01:02 10:20:30");
+            let exif = vec![(0x9004, 2, dcount, digitized.clone()), (0x9003, 2, count, original)];
+            let tiff = tiff_fixture(le, Vec::new(), exif);
+            assert_eq!(exif_date(&tiff), stamp("2024-05-01"), "le = {}", le);
+            assert_eq!(exif_date(&jpeg_with_exif(&tiff)), stamp("2024-05-01"));
+
+            // no DateTimeOriginal: DateTimeDigitized, then IFD0's DateTime
+            let tiff = tiff_fixture(le, Vec::new(), vec![(0x9004, 2, dcount, digitized)]);
+            assert_eq!(exif_date(&tiff), stamp("2023-01-02"));
+            let (count, changed) = ascii("2022:12:31 10:20:30");
+            let tiff = tiff_fixture(le, vec![(0x0132, 2, count, changed)], Vec::new());
+            assert_eq!(exif_date(&tiff), stamp("2022-12-31"));
+        }
+        let (count, junk) = ascii("not a date at all");
+        assert_eq!(exif_date(&tiff_fixture(true, Vec::new(), vec![(0x9003, 2, count, junk)])), None);
+    }
+
+    #[test]
+    fn exif_offsets_past_the_data_are_ignored() {
+        let (count, original) = ascii("2024:05:01 10:20:30");
+        let tiff = tiff_fixture(true, Vec::new(), vec![(0x9003, 2, count, original)]);
+        // every truncation either still parses or gives up, never panics
+        for len in 0..tiff.len() {
+            assert_eq!(exif_date(&tiff[..len]), None, "cut at {}", len);
+            let _ = camera_info(&tiff[..len]);
+            let _ = exif_date(&jpeg_with_exif(&tiff[..len]));
+        }
+        // IFD0 and a value pointing far outside the buffer
+        let mut far = tiff.clone();
+        far[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(exif_date(&far), None);
+        let (count, text) = ascii("a text long enough to be stored elsewhere");
+        let mut far = tiff_fixture(true, vec![(0x010F, 2, count, text)], Vec::new());
+        far[18..22].copy_from_slice(&0xFFFF_FFF0u32.to_le_bytes());
+        assert_eq!(camera_info(&far).unwrap().make, None);
+        assert_eq!(exif_date(b"II*\0"), None);
+        assert_eq!(exif_date(&[0xFF, 0xD8, 0xFF, 0xE1, 0xFF, 0xFF, b'E']), None);
+    }
+
+    #[test]
+    fn camera_info_from_exif() {
+        let (make_len, make) = ascii("Canon");
+        let (model_len, model) = ascii("Canon EOS R5");
+        let tiff = tiff_fixture(
+            false,
+            vec![(0x010F, 2, make_len, make), (0x0110, 2, model_len, model)],
+            vec![(0x8827, 3, 1, 400u16.to_be_bytes().to_vec())],
+        );
+        let info = camera_info(&jpeg_with_exif(&tiff)).unwrap();
+        assert_eq!(info.make.as_deref(), Some("Canon"));
+        assert_eq!(info.model.as_deref(), Some("Canon EOS R5"));
+        assert_eq!(info.iso, Some(400));
+        assert_eq!(info.lens, None);
+        assert!(info.gps.is_none());
+    }
+
+    #[test]
+    fn ratings_from_xmp_and_exif() {
+        assert_eq!(xmp_rating(b"<rdf:Description xmp:Rating=\"4\" xmp:Label=\"Red\"/>"), Some(4));
+        assert_eq!(xmp_rating(b"<rdf:Description xmp:Rating='2'/>"), Some(2));
+        assert_eq!(xmp_rating(b"<xmp:Rating>5</xmp:Rating>"), Some(5));
+        // rejected counts as unrated; out-of-range values are capped
+        assert_eq!(xmp_rating(b"xmp:Rating=\"-1\""), Some(0));
+        assert_eq!(xmp_rating(b"<xmp:Rating>7</xmp:Rating>"), Some(5));
+        assert_eq!(xmp_rating(b"<xmp:Label>Red</xmp:Label>"), None);
+        assert_eq!(xmp_rating(b"xmp:Rating=\""), None);
+        assert_eq!(xmp_rating(b"xmp:Rating"), None);
+
+        for le in [true, false] {
+            let rating = if le { 3u16.to_le_bytes() } else { 3u16.to_be_bytes() };
+            let tiff = tiff_fixture(le, vec![(0x4746, 3, 1, rating.to_vec())], Vec::new());
+            assert_eq!(embedded_rating(&jpeg_with_exif(&tiff)), Some(3));
+        }
+        let tiff = tiff_fixture(true, vec![(0x4746, 3, 1, 9u16.to_le_bytes().to_vec())], Vec::new());
+        assert_eq!(embedded_rating(&jpeg_with_exif(&tiff)), Some(5));
+        assert_eq!(embedded_rating(&jpeg_with_exif(&tiff_fixture(true, Vec::new(), Vec::new()))), None);
+
+        // a sidecar wins over what the file holds, in both naming styles
+        let dir = scratch_dir("rating");
+        let photo = dir.join("IMG_1.jpg");
+        fs::write(&photo, jpeg_with_exif(&tiff)).unwrap();
+        assert_eq!(read_rating(&photo), Some(5));
+        fs::write(dir.join("IMG_1.jpg.xmp"), "<xmp:Rating>1</xmp:Rating>").unwrap();
+        assert_eq!(read_rating(&photo), Some(1));
+        fs::write(dir.join("IMG_1.xmp"), "xmp:Rating=\"2\"").unwrap();
+        assert_eq!(read_rating(&photo), Some(2));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn wav_fixture() -> Vec<u8> {
+        let mut wav = b"RIFF\0\0\0\0WAVEfmt ".to_vec();
+        wav.extend(16u32.to_le_bytes());
+        wav.extend([1, 0, 2, 0]);
+        wav.extend(44_100u32.to_le_bytes());
+        wav.extend(176_400u32.to_le_bytes());
+        wav.extend([4, 0, 16, 0]);
+        wav.extend(b"data");
+        wav.extend(352_800u32.to_le_bytes());
+        wav
+    }
+
+    fn mp4_fixture() -> Vec<u8> {
+        let atom = |kind: &[u8], body: Vec<u8>| {
+            let mut out = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+            out.extend(kind);
+            out.extend(body);
+            out
+        };
+        // version + flags, creation and modification time, timescale, duration
+        let header =
+            |scale: u32, duration: u32| [[0; 12].as_slice(), &scale.to_be_bytes(), &duration.to_be_bytes()].concat();
+        let mdhd = atom(b"mdhd", header(48_000, 96_000));
+        let hdlr = atom(b"hdlr", [[0; 8].as_slice(), b"soun"].concat());
+        let trak = atom(b"trak", [mdhd, hdlr].concat());
+        let moov = atom(b"moov", [atom(b"mvhd", header(1000, 2500)), trak].concat());
+        [atom(b"ftyp", b"M4A \0\0\0\0".to_vec()), moov].concat()
+    }
+
+    #[test]
+    fn audio_info_of_each_format() {
+        let dir = scratch_dir("audio_info");
+        let read = |name: &str, bytes: &[u8]| {
+            let path = dir.join(name);
+            fs::write(&path, bytes).unwrap();
+            read_audio_info(&path)
+        };
+        let wav = read("a.wav", &wav_fixture()).unwrap();
+        assert_eq!((wav.seconds, wav.kbps, wav.sample_rate), (2.0, 1411, 44_100));
+
+        let mut flac = b"fLaC\x80\0\0\x22".to_vec();
+        flac.extend([0; 10]);
+        flac.extend((48_000u64 << 44 | 1 << 41 | 15 << 36 | 96_000).to_be_bytes());
+        flac.extend([0; 16]);
+        let info = read("a.flac", &flac).unwrap();
+        assert_eq!((info.seconds, info.sample_rate), (2.0, 48_000));
+
+        // MPEG-1 Layer III, 128 kbps, 44.1 kHz after an empty ID3v2 tag
+        let mut mp3 = b"ID3\x03\0\0\0\0\0\0".to_vec();
+        mp3.extend([0xFF, 0xFB, 0x90, 0x44]);
+        mp3.extend(vec![0; 15_996]);
+        let info = read("a.mp3", &mp3).unwrap();
+        assert_eq!((info.kbps, info.sample_rate), (128, 44_100));
+        assert!((info.seconds - 1.0).abs() < 0.01);
+        // a Xing header gives the frame count
+        let xing = [b"Xing".as_slice(), &[0, 0, 0, 1], &100u32.to_be_bytes()].concat();
+        mp3[10 + 36..10 + 48].copy_from_slice(&xing);
+        let info = read("a.mp3", &mp3).unwrap();
+        assert!((info.seconds - 100.0 * 1152.0 / 44_100.0).abs() < 0.001);
+
+        let mut ogg = b"OggS".to_vec();
+        ogg.extend([0; 24]);
+        ogg.extend(b"\x01vorbis\0\0\0\0\x02");
+        ogg.extend(44_100u32.to_le_bytes());
+        ogg.extend(0u32.to_le_bytes());
+        ogg.extend(160_000u32.to_le_bytes());
+        ogg.extend(b"OggS\0\x04");
+        ogg.extend(88_200u64.to_le_bytes());
+        ogg.extend([0; 16]);
+        let info = read("a.ogg", &ogg).unwrap();
+        assert_eq!((info.seconds, info.kbps, info.sample_rate), (2.0, 160, 44_100));
+
+        let info = read("a.m4a", &mp4_fixture()).unwrap();
+        assert_eq!((info.seconds, info.sample_rate), (2.5, 48_000));
+
+        // cut short anywhere: no panic
+        for bytes in [wav_fixture(), flac, mp3, ogg, mp4_fixture()] {
+            for len in 0..bytes.len().min(120) {
+                let _ = read("cut", &bytes[..len]);
+            }
+        }
+        assert!(read("cut.wav", &wav_fixture()[..40]).is_none());
+        assert!(read("cut.flac", b"fLaC\x80\0\0\x22").is_none());
+        assert!(read("cut.m4a", &mp4_fixture()[..30]).is_none());
+        assert!(read("empty.mp3", b"").is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// An ID3v2 tag of `version` (2, 3 or 4) with the given text frames.
+    fn id3v2_fixture(version: u8, frames: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let syncsafe = |n: usize| [21, 14, 7, 0].map(|shift| (n >> shift) as u8 & 0x7F);
+        let mut body = Vec::new();
+        for (id, text) in frames {
+            body.extend(id.as_bytes());
+            match version {
+                2 => body.extend(&(text.len() as u32).to_be_bytes()[1..]),
+                3 => body.extend((text.len() as u32).to_be_bytes()),
+                _ => body.extend(syncsafe(text.len())),
+            }
+            if version > 2 {
+                body.extend([0, 0]);
+            }
+            body.extend(text);
+        }
+        let mut tag = vec![b'I', b'D', b'3', version, 0, 0];
+        tag.extend(syncsafe(body.len()));
+        tag.extend(body);
+        tag
+    }
+
+    fn vorbis_comments(entries: &[&str]) -> Vec<u8> {
+        let mut out = 3u32.to_le_bytes().to_vec();
+        out.extend(b"lib");
+        out.extend((entries.len() as u32).to_le_bytes());
+        for entry in entries {
+            out.extend((entry.len() as u32).to_le_bytes());
+            out.extend(entry.as_bytes());
+        }
+        out
+    }
+
+    #[test]
+    fn audio_tags_of_each_format() {
+        let dir = scratch_dir("audio_tags");
+        let read = |name: &str, bytes: &[u8]| {
+            let path = dir.join(name);
+            fs::write(&path, bytes).unwrap();
+            read_audio_tags(&path)
+        };
+        let latin1 = |text: &str| [&[0u8][..], text.as_bytes()].concat();
+        let utf16 = |text: &str| {
+            let units: Vec<u8> = text.encode_utf16().flat_map(u16::to_le_bytes).collect();
+            [&[1u8, 0xFF, 0xFE][..], &units].concat()
+        };
+
+        let frames = [("TPE1", latin1("Artist")), ("TIT2", utf16("タイトル")), ("TRCK", latin1("3/12"))];
+        let mut mp3 = id3v2_fixture(3, &frames);
+        mp3.extend([0xFF, 0xFB, 0x90, 0x44]);
+        // ID3v1 at the end fills what v2 lacks
+        let mut v1 = [0u8; 128];
+        v1[..3].copy_from_slice(b"TAG");
+        v1[3..8].copy_from_slice(b"Other");
+        v1[63..68].copy_from_slice(b"Album");
+        v1[126] = 7;
+        mp3.extend(v1);
+        let tags = read("a.mp3", &mp3);
+        assert_eq!(tags.artist.as_deref(), Some("Artist"));
+        assert_eq!(tags.title.as_deref(), Some("タイトル"));
+        assert_eq!(tags.album.as_deref(), Some("Album"));
+        assert_eq!(tags.track.as_deref(), Some("3/12"));
+
+        // v2.4: syncsafe frame sizes, UTF-8; v2.2: three-letter ids
+        let tags = read("b.mp3", &id3v2_fixture(4, &[("TIT2", [&[3u8][..], "Ünïcode\0second".as_bytes()].concat())]));
+        assert_eq!(tags.title.as_deref(), Some("Ünïcode"));
+        let tags = read("c.mp3", &id3v2_fixture(2, &[("TP1", latin1("Old"))]));
+        assert_eq!(tags.artist.as_deref(), Some("Old"));
+
+        let comments = vorbis_comments(&["ARTIST=Band", "title=Song", "TRACKNUMBER=05", "junk"]);
+        let mut flac = b"fLaC".to_vec();
+        flac.extend([0, 0, 0, 2, 0, 0]);
+        flac.push(0x84);
+        flac.extend(&(comments.len() as u32).to_be_bytes()[1..]);
+        flac.extend(&comments);
+        let tags = read("a.flac", &flac);
+        assert_eq!((tags.artist.as_deref(), tags.title.as_deref()), (Some("Band"), Some("Song")));
+        assert_eq!(tags.track.as_deref(), Some("05"));
+
+        let ogg = [b"OggS".as_slice(), &[0; 24], b"OpusTags", &vorbis_comments(&["ALBUM=Record"])].concat();
+        assert_eq!(read("a.opus", &ogg).album.as_deref(), Some("Record"));
+
+        // MP4: moov > udta > meta > ilst, items holding a "data" atom
+        let atom = |kind: &[u8], body: &[u8]| [&((body.len() + 8) as u32).to_be_bytes()[..], kind, body].concat();
+        let data = |value: &[u8]| atom(b"data", &[&[0u8, 0, 0, 1, 0, 0, 0, 0][..], value].concat());
+        let items = [atom(b"\xA9ART", &data(b"Singer")), atom(b"trkn", &data(&[0, 0, 0, 9, 0, 12]))];
+        let ilst = atom(b"ilst", &items.concat());
+        let moov = atom(b"moov", &atom(b"udta", &atom(b"meta", &[&[0u8; 4][..], &ilst].concat())));
+        let m4a = [atom(b"ftyp", b"M4A \0\0\0\0"), moov].concat();
+        let tags = read("a.m4a", &m4a);
+        assert_eq!((tags.artist.as_deref(), tags.track.as_deref()), (Some("Singer"), Some("9")));
+        // "ilst" right at the start of the atom data: no size before it
+        assert!(mp4_tags(b"ilst\0\0").artist.is_none());
+        assert!(mp4_tags(b"ilst").artist.is_none());
+
+        // cut short or garbled anywhere: no panic
+        for bytes in [mp3, flac, ogg, m4a] {
+            for len in 0..bytes.len() {
+                let _ = read("cut", &bytes[..len]);
+                let mut garbled = bytes.clone();
+                garbled[len] = 0xFF;
+                let _ = read("garbled", &garbled);
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A small JPEG header: a DHT segment, then the frame header, then the end.
+    fn jpeg_fixture(sof: u8, width: u16, height: u16) -> Vec<u8> {
+        let mut out = vec![0xFF, 0xD8, 0xFF, 0xC4, 0, 4, 0, 0, 0xFF, sof, 0, 11, 8];
+        out.extend(height.to_be_bytes());
+        out.extend(width.to_be_bytes());
+        out.extend([1, 1, 0x11, 0, 0xFF, 0xD9]);
+        out
+    }
+
+    #[test]
+    fn jpeg_size_skips_tables_before_the_frame() {
+        assert_eq!(jpeg_size(&jpeg_fixture(0xC0, 640, 480)), Some((640, 480)));
+        assert_eq!(jpeg_size(&jpeg_fixture(0xC2, 320, 200)), Some((320, 200)));
+        // lossless (RAW sensor data) and arithmetic frames are not previews
+        assert_eq!(jpeg_size(&jpeg_fixture(0xC3, 640, 480)), None);
+        assert_eq!(jpeg_size(&jpeg_fixture(0xC9, 640, 480)), None);
+        // DAC and JPG segments are skipped like DHT
+        let mut dac = jpeg_fixture(0xC0, 640, 480);
+        dac[3] = 0xCC;
+        assert_eq!(jpeg_size(&dac), Some((640, 480)));
+        let jpeg = jpeg_fixture(0xC0, 640, 480);
+        for len in 0..jpeg.len() - 10 {
+            assert_eq!(jpeg_size(&jpeg[..len]), None);
+        }
+    }
+
+    #[test]
+    fn raw_previews_found_through_the_ifds() {
+        let (small, big) = (jpeg_fixture(0xC0, 160, 120), jpeg_fixture(0xC0, 1620, 1080));
+        let long = |v: u32| v.to_le_bytes().to_vec();
+        // IFD0: the thumbnail; a sub-IFD: a JPEG-compressed strip (offsets filled in below)
+        let ifd0 = vec![(0x0201, 4, 1, long(0)), (0x0202, 4, 1, long(small.len() as u32))];
+        let compression = 6u16.to_le_bytes().to_vec();
+        let sub = vec![(0x0103, 3, 1, compression), (0x0111, 4, 1, long(0)), (0x0117, 4, 1, long(big.len() as u32))];
+        let mut raw = tiff_fixture(true, ifd0, sub);
+        // the pointer the fixture adds as EXIF IFD becomes a SubIFDs entry
+        raw[34..36].copy_from_slice(&0x014Au16.to_le_bytes());
+        let (small_at, big_at) = (raw.len(), raw.len() + small.len());
+        raw[18..22].copy_from_slice(&long(small_at as u32));
+        raw[72..76].copy_from_slice(&long(big_at as u32));
+        raw.extend(&small);
+        raw.extend(&big);
+        // an unreferenced JPEG is not picked up
+        raw.extend(jpeg_fixture(0xC0, 4000, 3000));
+
+        assert_eq!(embedded_jpeg(&raw, (100, 100)), Some(small.as_slice()));
+        assert_eq!(embedded_jpeg(&raw, (1000, 1000)), Some(big.as_slice()));
+        assert_eq!(embedded_jpeg(&raw, (8000, 8000)), Some(big.as_slice()));
+        for len in 0..raw.len() {
+            let _ = embedded_jpeg(&raw[..len], (100, 100));
+        }
+        // IFDs pointing at each other
+        let mut looped = raw.clone();
+        looped[46..50].copy_from_slice(&long(8));
+        assert!(embedded_jpeg(&looped, (100, 100)).is_some());
+
+        let cr3 = [b"\0\0\0\x20PRVW\0\0\0\0".as_slice(), &[0; 12], &big].concat();
+        assert_eq!(embedded_jpeg(&cr3, (100, 100)), Some(big.as_slice()));
+    }
+
+    #[test]
+    fn cli_patterns_become_blocks() {
+        let blocks = parse_pattern("{date:%Y%m%d}_{n:3:10:5}{{x}}{ext}").unwrap();
+        assert!(matches!(&blocks[0], Block::Date { format, source: DateSource::Now, .. } if format == "%Y%m%d"));
+        assert!(matches!(&blocks[1], Block::Literal(text) if text == "_"));
+        assert!(matches!(blocks[2], Block::Number { width: 3, start: 10, step: 5, .. }));
+        assert!(matches!(&blocks[3], Block::Literal(text) if text == "{x}"));
+        assert!(matches!(blocks[4], Block::Extension));
+        let keep = ExtPolicy::Keep;
+        let named = parse_pattern("{name}-{n:2}{ext}").unwrap();
+        assert_eq!(render_name(&named, 0, Path::new("a/photo.jpg"), Local::now(), &keep), "photo-01.jpg");
+        assert_eq!(parse_pattern("{name").unwrap_err(), "unterminated {");
+        assert_eq!(parse_pattern("img_{n:3").unwrap_err(), "unterminated {");
+        assert!(parse_pattern("name}").is_err());
+        assert!(parse_pattern("{size}").is_err());
+        assert!(parse_pattern("{n:1:2:3:4}").is_err());
+        assert!(parse_pattern("{n:x}").is_err());
+        assert!(parse_pattern("").is_err());
+    }
+
+    #[test]
+    fn cli_paths_expand_folders_and_wildcards() {
+        let dir = scratch_dir("cli-paths");
+        fs::create_dir(dir.join("sub")).unwrap();
+        for name in ["b.jpg", "a.jpg", "c.png", "sub/d.jpg", ".bulkrename-log.csv"] {
+            fs::write(dir.join(name), b"x").unwrap();
+        }
+        let arg = |p: &Path| p.to_string_lossy().to_string();
+        let names = |paths: Vec<PathBuf>| {
+            paths.iter().map(|p| p.strip_prefix(&dir).unwrap().to_string_lossy().replace('\\', "/")).collect::<Vec<_>>()
+        };
+        // the audit log is never picked up
+        let all = expand_cli_paths(&[arg(&dir.join("*"))], false).unwrap();
+        assert_eq!(names(all), ["a.jpg", "b.jpg", "c.png"]);
+        let jpgs = expand_cli_paths(&[arg(&dir.join("*.jpg"))], false).unwrap();
+        assert_eq!(names(jpgs), ["a.jpg", "b.jpg"]);
+        let flat = expand_cli_paths(&[arg(&dir)], false).unwrap();
+        assert_eq!(names(flat), ["a.jpg", "b.jpg", "c.png"]);
+        let deep = expand_cli_paths(&[arg(&dir)], true).unwrap();
+        assert_eq!(names(deep), ["a.jpg", "b.jpg", "c.png", "sub/d.jpg"]);
+        let single = expand_cli_paths(&[arg(&dir.join("c.png")), arg(&dir.join("?.jpg"))], false).unwrap();
+        assert_eq!(names(single), ["c.png", "a.jpg", "b.jpg"]);
+        assert!(expand_cli_paths(&[arg(&dir.join("missing.jpg"))], false).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn csv_rows_and_mappings() {
+        let text = "\u{feff}Old_Name, NEW_NAME\r\n\"a, b.jpg\",\"say \"\"hi\"\".jpg\"\r\n\r\nc.jpg,\"two\nlines\"\r\n";
+        let rows = parse_csv(text);
+        assert_eq!(rows[0], ["Old_Name", " NEW_NAME"]);
+        assert_eq!(rows[1], ["a, b.jpg", "say \"hi\".jpg"]);
+        assert_eq!(rows[2], ["c.jpg", "two\nlines"]);
+        assert_eq!(rows.len(), 3);
+        assert_eq!(parse_csv("a;b\nc;d"), [["a", "b"], ["c", "d"]]);
+        assert_eq!(parse_csv("a\tb c\nd\te"), [["a", "b c"], ["d", "e"]]);
+
+        let files = ["p/a, b.jpg", "p/c.jpg", "p/e.jpg", "q/e.jpg"].map(PathBuf::from);
+        let plan = mapping_to_plan(&rows[..2], &files).unwrap();
+        assert_eq!((plan.len(), &plan[0].from, &plan[0].to), (1, &files[0], &PathBuf::from("say \"hi\".jpg")));
+        // absolute old names need not be in the list
+        let outside = std::env::temp_dir().join("x.jpg");
+        let rows = vec![vec![outside.to_string_lossy().to_string(), "y.jpg".to_string()]];
+        assert_eq!(mapping_to_plan(&rows, &files).unwrap()[0].from, outside);
+        // only a literal header is skipped: a first row naming no file is reported
+        let typo = parse_csv("cc.jpg,x.jpg\nc.jpg,y.jpg");
+        assert_eq!(mapping_to_plan(&typo, &files).unwrap_err(), ["line 1: \"cc.jpg\" is not in the list"]);
+        let problems = mapping_to_plan(&parse_csv("old_name,new_name\ne.jpg,f.jpg\nc.jpg"), &files).unwrap_err();
+        assert_eq!(problems, ["line 2: \"e.jpg\" matches 2 files, use the full path", "line 3: needs two columns"]);
+        assert_eq!(mapping_to_plan(&parse_csv("old_name,new_name\n"), &files).unwrap_err(), ["no rows"]);
+    }
+}
@@ -2,1219 +2,10369 @@
 
 #![windows_subsystem = "windows"]
 
-use chrono::{DateTime, Local};
+use bulkrename_core::{
+    advance_counters, check_dirs_reachable, check_script, collect_files, copy_files, copy_with_times,
+    create_target_dirs, date_in_name, diff_chars, embedded_jpeg, expand_cli_paths, export_templates, file_sha256,
+    find_collisions, find_cycles, find_duplicates, format_date, format_offset, group_positions, import_templates,
+    make_numbered_dir_path, make_numbered_path, mapping_to_plan, natural_cmp, parse_csv, parse_pattern, path_length,
+    portable_name, put_back, read_audio_info, read_audio_tags, read_camera_info, read_exif_date, read_rating,
+    remove_empty_dirs, render_grouped, render_name, replaced_files, resolve_copy_targets_each, resolve_targets,
+    resolve_targets_each, run_script, set_aside, sidecar_groups, sidecar_name, split_name, title_case, two_phase_rename,
+    two_phase_rename_journaled, wildcard_match, write_folder_logs, AudioField, AudioInfo, AudioTags, Block, CameraField,
+    CameraInfo, CollisionStrategy, Condition, DatePattern, DateSource, DateZone, ExtPolicy, FindReplace, FolderLog,
+    GroupKey, KeepTimes, Locale, NameCase, NameSource, NumberStyle, OriginalMode, PATH_LIMIT, PlanEntry, Progress,
+    RatingStyle, RenameFailure, RenameJournal, RetryPolicy, ShapeField, SizeUnit, StepState, TagField, Template,
+    TrailingFix, WINDOWS_MAX_PATH,
+};
+use chrono::{DateTime, FixedOffset, Local, Timelike};
 use directories::ProjectDirs;
 use eframe::{egui, egui::RichText};
 use egui::{ComboBox, DragValue}; // ,Layout};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::net::{TcpListener, TcpStream};
 use std::path::{Path, PathBuf};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use eframe::egui::ViewportBuilder;
 use image::GenericImageView;
-use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
 use std::thread;
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
-enum Block {
-    Literal(String),
-    Number { width: usize, start: i64, step: i64 },
-    Date { format: String },
-    Original { mode: OriginalMode, },
-    Extension,
-}
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
-enum OriginalMode {
-    Keep,
-    RemoveRange { start: i32, end: i32, },
-    RemoveSubstring { pattern: String, case_sensitive: bool, },
-}
+use notify::{EventKind, RecursiveMode, Watcher};
 
 #[derive(Clone)]
 struct FileEntry {
     path: PathBuf,
+    category: FileCategory,
+    // (mtime, size) when the file was added
+    snapshot: Option<(SystemTime, u64)>,
+    changed: bool,
+    // moved, renamed or deleted outside the app (set by the watcher)
+    missing: bool,
+    // part of the multi-selection
+    selected: bool,
+    // kept in the list but never renamed
+    excluded: bool,
+    // typed in the preview; wins over the template until the file is renamed
+    manual_name: Option<String>,
+    // free text for the Tag block
+    tag: String,
+    // star rating, read on first use
+    rating: std::cell::OnceCell<u8>,
+    // audio header, read on first use
+    audio: std::cell::OnceCell<Option<AudioInfo>>,
+    // artist/title/album/track, read on first use
+    audio_tags: std::cell::OnceCell<AudioTags>,
+    // EXIF capture date, read on first use
+    exif_date: std::cell::OnceCell<Option<DateTime<Local>>>,
+    // EXIF camera, lens, ISO and GPS, read on first use
+    camera: std::cell::OnceCell<Option<CameraInfo>>,
+    // image width and height, read on first use
+    dimensions: std::cell::OnceCell<Option<(u32, u32)>>,
 }
 
-#[derive(PartialEq, Copy, Clone, Serialize, Deserialize)]
-enum CollisionStrategy {
-    Overwrite,
-    Skip,
-    Suffix,
-}
-
-#[derive(Serialize, Deserialize)]
-struct Template {
-    name: String,
-    blocks: Vec<Block>,
-    collision: CollisionStrategy,
-    use_mtime_for_date: bool,
-}
+impl FileEntry {
+    fn new(path: PathBuf, category: FileCategory) -> Self {
+        let snapshot = Self::read_snapshot(&path);
+        Self {
+            path,
+            category,
+            snapshot,
+            changed: false,
+            missing: false,
+            selected: false,
+            excluded: false,
+            manual_name: None,
+            tag: String::new(),
+            rating: Default::default(),
+            audio: Default::default(),
+            audio_tags: Default::default(),
+            exif_date: Default::default(),
+            camera: Default::default(),
+            dimensions: Default::default(),
+        }
+    }
 
-enum ThumbnailState {
-    Loading,
-    Loaded(egui::TextureHandle, egui::Vec2),
-    Failed,
-}
+    /// Star rating 0-5 (0 = unrated).
+    fn rating(&self) -> u8 {
+        *self.rating.get_or_init(|| read_rating(&self.path).unwrap_or(0))
+    }
 
-#[derive(PartialEq)]
-enum LoadingPhase {
-    None,
-    AddingFiles,
-    LoadingThumbs,
-}
+    fn audio(&self) -> Option<&AudioInfo> {
+        self.audio.get_or_init(|| read_audio_info(&self.path)).as_ref()
+    }
 
-//sort
-#[derive(PartialEq, Copy, Clone)]
-enum SortKey {
-    Name,
-    Modified,
-    Created,
-    Size,
-}
-#[derive(PartialEq, Copy, Clone)]
-enum SortOrder {
-    Asc,
-    Desc,
-}
+    fn dimensions(&self) -> Option<(u32, u32)> {
+        *self.dimensions.get_or_init(|| image::image_dimensions(&self.path).ok())
+    }
 
-struct BulkRename {
-    files: Vec<FileEntry>,
-    selected_idx: Option<usize>,
-    blocks: Vec<Block>,
-    collision: CollisionStrategy,
-    use_mtime_for_date: bool,
-    last_actions: Vec<HashMap<PathBuf, PathBuf>>,
-    messages: Vec<String>,
-    dragging_idx: Option<usize>,
-    // thumbnail cache: key = path → state
-    thumbnails: HashMap<String, ThumbnailState>,
-    thumb_max_size: (usize, usize),
-    thumb_tx: Option<SyncSender<(String, Result<(image::RgbaImage, (usize, usize)), String>)>>,
-    thumb_rx: Option<Receiver<(String, Result<(image::RgbaImage, (usize, usize)), String>)>>,
-    show_thumbnails: bool,
-    // persistence
-    saved_templates: Vec<Template>,
-    current_template_name: String,
-    //loading
-    loading_phase: LoadingPhase,
-    loader_rx: Option<Receiver<PathBuf>>,
-    loading_count: usize,
-    //sort
-    sort_key: Option<SortKey>,
-    sort_order: SortOrder,
-    //error
-    show_delete_error: bool,
-}
+    fn audio_tags(&self) -> &AudioTags {
+        self.audio_tags.get_or_init(|| read_audio_tags(&self.path))
+    }
 
-impl Default for BulkRename {
-    fn default() -> Self {
-        Self {
-            files: Vec::new(),
-            selected_idx: None,
-            blocks: vec![
-                Block::Original { mode: OriginalMode::Keep },
-                Block::Extension,
-            ],
-            collision: CollisionStrategy::Suffix,
-            use_mtime_for_date: true,
-            last_actions: Vec::new(),
-            messages: Vec::new(),
-            dragging_idx: None,
-            thumbnails: HashMap::new(),
-            thumb_max_size: (160, 120),
-            thumb_tx: None,
-            thumb_rx: None,
-            show_thumbnails: true,
-            saved_templates: Vec::new(),
-            current_template_name: String::new(),
-            //loading
-            loading_phase: LoadingPhase::None,
-            loader_rx: None,
-            loading_count: 0,
-            //sort
-            sort_key: None,
-            sort_order: SortOrder::Asc,
-            //error
-            show_delete_error: false,
-        }
+    fn camera(&self) -> Option<&CameraInfo> {
+        self.camera.get_or_init(|| read_camera_info(&self.path)).as_ref()
     }
-}
 
-impl BulkRename {
-    /// Path to `templates.json` in user config directory.
-    fn config_path() -> PathBuf {
-        let proj = ProjectDirs::from("jp", "mi3zuk", "BulkReName")
-            .expect("failed to get project directory");
-        let dir = proj.config_dir();
-        let _ = fs::create_dir_all(dir);
-        dir.join("templates.json")
+    /// Capture date from EXIF, else the file's mtime.
+    fn capture_date(&self) -> Option<DateTime<Local>> {
+        let exif = *self.exif_date.get_or_init(|| read_exif_date(&self.path));
+        exif.or_else(|| Some(fs::metadata(&self.path).ok()?.modified().ok()?.into()))
     }
 
-    fn load_templates(&mut self) {
-        if let Ok(text) = fs::read_to_string(Self::config_path()) {
-            if let Ok(list) = serde_json::from_str::<Vec<Template>>(&text) {
-                self.saved_templates = list;
-            }
+    /// Modified time, from the snapshot unless the file changed since.
+    fn modified(&self) -> Option<DateTime<Local>> {
+        match self.snapshot {
+            Some((mtime, _)) if !self.changed => Some(mtime.into()),
+            _ => Some(fs::metadata(&self.path).ok()?.modified().ok()?.into()),
         }
     }
 
-    fn save_templates(&self) {
-        if let Ok(json) = serde_json::to_string_pretty(&self.saved_templates) {
-            let _ = fs::write(Self::config_path(), json);
+    fn size(&self) -> Option<u64> {
+        match self.snapshot {
+            Some((_, len)) if !self.changed => Some(len),
+            _ => fs::metadata(&self.path).ok().map(|m| m.len()),
         }
     }
 
-    fn add_files(&mut self, paths: Vec<PathBuf>) {
-        for p in paths {
-            if p.is_file() {
-                self.files.push(FileEntry { path: p });
-            }
+    /// Lowercase extension; folders and files without one give "".
+    fn extension(&self) -> String {
+        if self.category == FileCategory::Folder {
+            return String::new();
         }
+        self.path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default()
     }
 
-    fn process_original(name: &str, mode: &OriginalMode) -> String {
-        match mode {
-            OriginalMode::Keep => name.to_string(),
+    /// Take in a fresh look at the file; `snapshot` as `read_snapshot` gives it now.
+    /// Metadata read before the file changed is dropped. Returns true when it newly changed.
+    fn update_state(&mut self, exists: bool, snapshot: Option<(SystemTime, u64)>) -> bool {
+        self.missing = !exists;
+        let changed = exists && snapshot != self.snapshot;
+        let newly = changed && !self.changed;
+        self.changed = changed;
+        if newly {
+            self.rating = Default::default();
+            self.audio = Default::default();
+            self.audio_tags = Default::default();
+            self.exif_date = Default::default();
+            self.dimensions = Default::default();
+        }
+        newly
+    }
 
-            OriginalMode::RemoveSubstring { pattern, case_sensitive } => {
-                let mut result = name.to_string();
+    fn read_snapshot(path: &Path) -> Option<(SystemTime, u64)> {
+        let meta = fs::metadata(path).ok()?;
+        Some((meta.modified().ok()?, meta.len()))
+    }
+}
 
-                for p in pattern.split('/') {
-                    let p = p.trim();
+// a list entry as the blocks see it, with its hash from the app's cache
+// and its stem after Find/Replace
+struct ListFile<'a> {
+    fe: &'a FileEntry,
+    phash: Option<Option<u64>>,
+    stem: Option<String>,
+}
 
-                    // 空要素無視
-                    if p.is_empty() {
-                        continue;
-                    }
+impl NameSource for ListFile<'_> {
+    fn path(&self) -> &Path {
+        &self.fe.path
+    }
 
-                    if *case_sensitive {
-                        result = result.replace(p, "");
-                    } else {
-                        // case-insensitive replace
-                        let mut tmp = String::new();
-                        let mut rest = result.as_str();
+    fn tag(&self) -> &str {
+        &self.fe.tag
+    }
 
-                        let p_lower = p.to_lowercase();
+    fn rating(&self) -> u8 {
+        self.fe.rating()
+    }
 
-                        while let Some(pos) = rest.to_lowercase().find(&p_lower) {
-                            tmp.push_str(&rest[..pos]);
-                            rest = &rest[pos + p.len()..];
-                        }
-                        tmp.push_str(rest);
-                        result = tmp;
-                    }
-                }
-                result
-            }
+    fn audio(&self) -> Option<&AudioInfo> {
+        self.fe.audio()
+    }
 
-            OriginalMode::RemoveRange { start, end } => {
-                let chars: Vec<char> = name.chars().collect();
-                let len = chars.len() as i32;
-                let s = if *start < 0 { len + *start } else { *start };
-                let e = if *end < 0 { len + *end + 1 } else { *end };
-                let s = s.clamp(0, len);
-                let e = e.clamp(0, len);
-                if s >= e {
-                    return name.to_string();
-                }
-                chars[..s as usize]
-                    .iter()
-                    .chain(chars[e as usize..].iter())
-                    .collect()
-            }
-        }
+    fn capture_date(&self) -> Option<DateTime<Local>> {
+        self.fe.capture_date()
     }
 
-    fn sort_files(&mut self, key: SortKey) {
-        if self.sort_key == Some(key) {
-            self.sort_order = match self.sort_order {
-                SortOrder::Asc => SortOrder::Desc,
-                SortOrder::Desc => SortOrder::Asc,
-            };
-        } else {
-            self.sort_key = Some(key);
-            self.sort_order = SortOrder::Asc;
-        }
-        let asc = self.sort_order == SortOrder::Asc;
-        match key {
-            SortKey::Name => {
-                self.files.sort_by(|a, b| {
-                    let ord = a.path.file_name().cmp(&b.path.file_name());
-                    if asc { ord } else { ord.reverse() }
-                });
-            }
-            SortKey::Modified => {
-                self.files.sort_by(|a, b| {
-                    let a_m = fs::metadata(&a.path).and_then(|m| m.modified()).ok();
-                    let b_m = fs::metadata(&b.path).and_then(|m| m.modified()).ok();
-                    let ord = a_m.cmp(&b_m);
-                    if asc { ord } else { ord.reverse() }
-                });
-            }
-            SortKey::Created => {
-                self.files.sort_by(|a, b| {
-                    let a_c = fs::metadata(&a.path).and_then(|m| m.created()).ok();
-                    let b_c = fs::metadata(&b.path).and_then(|m| m.created()).ok();
-                    let ord = a_c.cmp(&b_c);
-                    if asc { ord } else { ord.reverse() }
-                });
-            }
-            SortKey::Size => {
-                self.files.sort_by(|a, b| {
-                    let a_s = fs::metadata(&a.path).map(|m| m.len()).ok();
-                    let b_s = fs::metadata(&b.path).map(|m| m.len()).ok();
-                    let ord = a_s.cmp(&b_s);
-                    if asc { ord } else { ord.reverse() }
-                });
-            }
-        }
+    fn modified(&self) -> Option<DateTime<Local>> {
+        self.fe.modified()
     }
 
-    fn collect_files_recursively(dir: &Path, out: &mut Vec<PathBuf>) {
-        if let Ok(read_dir) = fs::read_dir(dir) {
-            for entry in read_dir.flatten() {
-                let path = entry.path();
-                if path.is_file() {
-                    out.push(path);
-                } else if path.is_dir() {
-                    Self::collect_files_recursively(&path, out);
-                }
-            }
-        }
+    fn audio_tags(&self) -> Option<&AudioTags> {
+        Some(self.fe.audio_tags())
     }
 
-    fn move_up(&mut self) {
-        if let Some(i) = self.selected_idx {
-            if i > 0 {
-                self.files.swap(i, i - 1);
-                self.selected_idx = Some(i - 1);
-            }
-        }
+    fn edited_stem(&self) -> Option<&str> {
+        self.stem.as_deref()
     }
 
-    fn move_down(&mut self) {
-        if let Some(i) = self.selected_idx {
-            if i + 1 < self.files.len() {
-                self.files.swap(i, i + 1);
-                self.selected_idx = Some(i + 1);
-            }
-        }
+    fn camera(&self) -> Option<&CameraInfo> {
+        self.fe.camera()
     }
 
-    fn remove_selected(&mut self) {
-        if let Some(i) = self.selected_idx {
-            if let Some(p) = self.files.get(i) {
-                let key = p.path.to_string_lossy().to_string();
-                self.thumbnails.remove(&key);
-            }
-            self.files.remove(i);
-            self.selected_idx = None;
-        }
+    fn size(&self) -> Option<u64> {
+        self.fe.size()
     }
 
-    fn format_number(&self, idx: usize, width: usize, start: i64, step: i64) -> String {
-        let val = start + (idx as i64) * step;
-        let s = format!("{}", val);
-        if width > 0 && s.len() < width {
-            format!("{:0width$}", val, width = width)
-        } else {
-            s
-        }
+    fn dimensions(&self) -> Option<(u32, u32)> {
+        self.fe.dimensions()
     }
 
-    fn generate_targets(&self) -> Vec<String> {
-        let mut res = Vec::new();
-        for (idx, fe) in self.files.iter().enumerate() {
-            let file_name = fe
-                .path
-                .file_stem()
-                .and_then(|s| s.to_str())
-                .unwrap_or("")
-                .to_string();
-            let ext = fe
-                .path
-                .extension()
-                .and_then(|s| s.to_str())
-                .unwrap_or("")
-                .to_string();
-
-            let now: DateTime<Local> = Local::now();
-            let mut parts = Vec::new();
-            for b in &self.blocks {
-                match b {
-                    Block::Literal(s) => parts.push(s.clone()),
-                    Block::Number { width, start, step } => {
-                        parts.push(self.format_number(idx, *width, *start, *step))
-                    }
-                    Block::Date { format } => {
-                        let s = std::panic::catch_unwind(|| {
-                            now.format(format).to_string()
-                        })
-                        .unwrap_or_else(|_| "[INVALID_DATE]".to_string());
-                        parts.push(s);
-                    }
-                    Block::Original { mode } => {
-                        parts.push(Self::process_original(&file_name, mode));
-                    }
-                    Block::Extension => {
-                        if !ext.is_empty() {
-                            parts.push(format!(".{}", ext));
-                        }
-                    }
-                }
-            }
-            res.push(parts.join(""));
-        }
-        res
+    fn phash(&self) -> Option<Option<u64>> {
+        self.phash
     }
 
-    fn preview_table(&self) -> Vec<(String, String)> {
-        let targets = self.generate_targets();
-        self.files
-            .iter()
-            .zip(targets.iter())
-            .map(|(f, t)| {
-                (
-                    f.path
-                        .file_name()
-                        .and_then(|s| s.to_str())
-                        .unwrap_or("")
-                        .to_string(),
-                    t.clone(),
-                )
-            })
-            .collect()
+    fn is_dir(&self) -> bool {
+        self.fe.category == FileCategory::Folder
     }
+}
 
-    fn ensure_thumbnail(&mut self, _ctx: &egui::Context, path: &Path) {
-        let key = path.to_string_lossy().to_string();
-        if self.thumbnails.contains_key(&key) {
-            return;
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
+enum FileCategory {
+    Image,
+    Video,
+    Audio,
+    Document,
+    Other,
+    // a folder added as an entry of its own
+    Folder,
+}
+
+impl FileCategory {
+    const ALL: [FileCategory; 6] = [
+        FileCategory::Image,
+        FileCategory::Video,
+        FileCategory::Audio,
+        FileCategory::Document,
+        FileCategory::Other,
+        FileCategory::Folder,
+    ];
+
+    /// Name used by the scope expression (`type == "image"`).
+    fn keyword(self) -> &'static str {
+        match self {
+            FileCategory::Image => "image",
+            FileCategory::Video => "video",
+            FileCategory::Audio => "audio",
+            FileCategory::Document => "document",
+            FileCategory::Other => "other",
+            FileCategory::Folder => "folder",
         }
-        if let Some(ext) = path.extension().and_then(|s| s.to_str()).map(|s| s.to_lowercase()) {
-            let supported = ["png", "jpg", "jpeg", "webp", "gif", "bmp", "ico"];
-            if !supported.contains(&ext.as_str()) {
-                return;
-            }
-        } else {
-            return;
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            FileCategory::Image => "Images",
+            FileCategory::Video => "Video",
+            FileCategory::Audio => "Audio",
+            FileCategory::Document => "Docs",
+            FileCategory::Other => "Other",
+            FileCategory::Folder => "Folders",
         }
-        self.thumbnails.insert(key.clone(), ThumbnailState::Loading);
+    }
 
-        // Initialize channel if not already done
-        if self.thumb_tx.is_none() {
-            let (tx, rx) = mpsc::sync_channel(4); // Limit to 4 concurrent thumbnail threads
-            self.thumb_tx = Some(tx);
-            self.thumb_rx = Some(rx);
+    fn from_extension(ext: &str) -> Self {
+        match ext.to_lowercase().as_str() {
+            "png" | "jpg" | "jpeg" | "webp" | "gif" | "bmp" | "ico" | "tif" | "tiff" | "heic"
+            | "heif" | "svg" | "cr2" | "cr3" | "nef" | "arw" | "dng" => FileCategory::Image,
+            "mp4" | "mov" | "mkv" | "avi" | "webm" | "wmv" | "flv" | "m4v" | "mpg" | "mpeg"
+            | "ts" | "3gp" => FileCategory::Video,
+            "mp3" | "wav" | "flac" | "ogg" | "m4a" | "aac" | "wma" | "opus" | "aiff" => {
+                FileCategory::Audio
+            }
+            "pdf" | "doc" | "docx" | "xls" | "xlsx" | "ppt" | "pptx" | "txt" | "md" | "rtf"
+            | "odt" | "ods" | "odp" | "csv" | "epub" => FileCategory::Document,
+            _ => FileCategory::Other,
         }
+    }
 
-        let path_clone = path.to_path_buf();
-        let max_size = self.thumb_max_size;
-        if let Some(tx) = &self.thumb_tx {
-            let tx_clone = tx.clone();
-            thread::spawn(move || {
-                let result = std::panic::catch_unwind(|| {
-                    match image::open(&path_clone) {
-                        Ok(img) => {
-                            let (max_w, max_h) = max_size;
-                            let thumb = img.thumbnail(max_w as u32, max_h as u32).into_rgba8();
-                            let (w, h) = (thumb.width() as usize, thumb.height() as usize);
-                            Ok((thumb, (w, h)))
-                        }
-                        Err(e) => Err(format!("{:?}", e)),
-                    }
-                }).unwrap_or_else(|_| Err("Panic in image processing".to_string()));
-                tx_clone.send((key, result)).ok();
-            });
+    /// Guess the category from the first bytes of the file.
+    fn sniff(path: &Path) -> Option<Self> {
+        use std::io::Read;
+        let mut head = [0u8; 16];
+        let n = fs::File::open(path).and_then(|mut f| f.read(&mut head)).ok()?;
+        let head = &head[..n];
+        let riff_kind = if head.starts_with(b"RIFF") && n >= 12 { &head[8..12] } else { &[][..] };
+        if head.starts_with(&[0xFF, 0xD8, 0xFF])
+            || head.starts_with(b"\x89PNG")
+            || head.starts_with(b"GIF8")
+            || head.starts_with(b"BM")
+            || head.starts_with(b"II*\0")
+            || head.starts_with(b"MM\0*")
+            || riff_kind == b"WEBP"
+        {
+            Some(FileCategory::Image)
+        } else if head.starts_with(b"ID3")
+            || head.starts_with(b"fLaC")
+            || head.starts_with(b"OggS")
+            || riff_kind == b"WAVE"
+        {
+            Some(FileCategory::Audio)
+        } else if n >= 12 && &head[4..8] == b"ftyp" {
+            // mp4 container: audio-only brands are M4A / M4B
+            if &head[8..11] == b"M4A" || &head[8..11] == b"M4B" {
+                Some(FileCategory::Audio)
+            } else {
+                Some(FileCategory::Video)
+            }
+        } else if head.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) || riff_kind == b"AVI " {
+            Some(FileCategory::Video)
+        } else if head.starts_with(b"%PDF") {
+            Some(FileCategory::Document)
+        } else {
+            None
         }
     }
 
-    fn split_stem_and_number(stem: &str) -> (String, Option<u32>) {
-        if let Some(idx) = stem.rfind('(') {
-            if stem.ends_with(')') {
-                let num_part = &stem[idx + 1..stem.len() - 1];
-                if let Ok(n) = num_part.parse::<u32>() {
-                    let base = stem[..idx].trim_end().to_string();
-                    return (base, Some(n));
-                }
+    fn detect(path: &Path, sniff: bool) -> Self {
+        if path.is_dir() {
+            return FileCategory::Folder;
+        }
+        if sniff {
+            if let Some(c) = Self::sniff(path) {
+                return c;
             }
         }
-        (stem.to_string(), None)
+        let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("");
+        Self::from_extension(ext)
     }
+}
 
-    fn make_numbered_path(path: &PathBuf, n: u32) -> PathBuf {
-        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
-        let ext = path.extension().and_then(|s| s.to_str());
-        let dir = path.parent().unwrap_or(Path::new("."));
+// import filter (applied while files are collected)
+#[derive(Clone)]
+struct ImportFilter {
+    use_min_size: bool,
+    min_width: u32,
+    min_height: u32,
+    // categories to import; all enabled by default
+    categories: HashSet<FileCategory>,
+    sniff_magic: bool,
+    // only add names matching this regex (empty = all)
+    name_pattern: String,
+    // only add files rated at least this (0 = off)
+    min_rating: u8,
+    // wildcard patterns separated by ';', e.g. "*.jpg;*.png" (empty = all)
+    glob: String,
+    // descend into subfolders of added folders, at most max_depth levels (0 = unlimited)
+    recursive: bool,
+    max_depth: usize,
+    // added folders become entries themselves instead of being walked
+    folders_as_entries: bool,
+}
 
-        let (base, _) = Self::split_stem_and_number(stem);
-        let new_stem = format!("{} ({})", base, n);
+impl Default for ImportFilter {
+    fn default() -> Self {
+        Self {
+            use_min_size: false,
+            min_width: 1920,
+            min_height: 1080,
+            categories: FileCategory::ALL.into_iter().collect(),
+            sniff_magic: false,
+            name_pattern: String::new(),
+            min_rating: 0,
+            glob: String::new(),
+            recursive: true,
+            max_depth: 0,
+            folders_as_entries: false,
+        }
+    }
+}
 
-        if let Some(e) = ext {
-            dir.join(format!("{}.{}", new_stem, e))
+impl ImportFilter {
+    fn name_regex(&self) -> Result<Option<Regex>, regex::Error> {
+        if self.name_pattern.is_empty() {
+            Ok(None)
         } else {
-            dir.join(new_stem)
+            Regex::new(&self.name_pattern).map(Some)
         }
     }
 
-    fn execute_rename(&mut self) {
-        let targets = self.generate_targets();
-        if targets.len() != self.files.len() {
-            return;
+    fn matches_glob(&self, name: &str) -> bool {
+        let mut patterns = self.glob.split(';').map(str::trim).filter(|p| !p.is_empty()).peekable();
+        if patterns.peek().is_none() {
+            return true;
         }
+        let name: Vec<char> = name.to_lowercase().chars().collect();
+        patterns.any(|p| wildcard_match(&p.to_lowercase().chars().collect::<Vec<_>>(), &name))
+    }
 
-        // final_paths creation
-        let mut final_paths = Vec::new();
-        for (fe, tname) in self.files.iter().zip(targets.iter()) {
-            let mut p = fe.path.clone();
-            p.set_file_name(tname);
-            final_paths.push(p);
+    fn accepts(&self, path: &Path, name_re: Option<&Regex>) -> bool {
+        let name = path.file_name().map(|s| s.to_string_lossy()).unwrap_or_default();
+        if let Some(re) = name_re {
+            if !re.is_match(&name) {
+                return false;
+            }
         }
-
-        // Duplicate detection (between final entries)
-        use std::collections::{HashMap, HashSet};
-
-        let mut used = HashSet::new();
-        let mut resolved_paths = Vec::new();
-
-        for (i, path) in final_paths.iter().enumerate() {
-            let orig = &self.files[i].path;
-
-            match self.collision {
-                CollisionStrategy::Overwrite => {
-                    resolved_paths.push(path.clone());
+        if !self.matches_glob(&name) {
+            return false;
+        }
+        if self.categories.len() < FileCategory::ALL.len() {
+            let category = FileCategory::detect(path, self.sniff_magic);
+            if !self.categories.contains(&category) {
+                return false;
+            }
+        }
+        if self.min_rating > 0 && read_rating(path).unwrap_or(0) < self.min_rating {
+            return false;
+        }
+        if self.use_min_size {
+            // only the image header is read here, not the whole file
+            if let Ok((w, h)) = image::image_dimensions(path) {
+                if w < self.min_width || h < self.min_height {
+                    return false;
                 }
+            }
+        }
+        true
+    }
+}
 
-                CollisionStrategy::Skip => {
-                    if used.contains(path) {
-                        resolved_paths.push(orig.clone());
-                    } else {
-                        used.insert(path.clone());
-                        resolved_paths.push(path.clone());
-                    }
-                }
+// checksum records written after a rename
+#[derive(PartialEq, Copy, Clone, Debug)]
+enum Manifest {
+    Off,
+    // one `SHA256SUMS` per folder (sha256sum format)
+    Sha256Sums,
+    // `<name>.sha256` next to each file
+    PerFile,
+}
 
-                CollisionStrategy::Suffix => {
-                    let mut candidate = path.clone();
-                    let mut n = 1;
+impl Manifest {
+    const ALL: [Manifest; 3] = [Manifest::Off, Manifest::Sha256Sums, Manifest::PerFile];
 
-                    while used.contains(&candidate) {
-                        candidate = Self::make_numbered_path(path, n);
-                        n += 1;
-                    }
+    fn label(self) -> &'static str {
+        match self {
+            Manifest::Off => "(off)",
+            Manifest::Sha256Sums => "SHA256SUMS",
+            Manifest::PerFile => "<name>.sha256",
+        }
+    }
+}
 
-                    used.insert(candidate.clone());
-                    resolved_paths.push(candidate);
-                }
+/// Hash the renamed files and write their checksum records.
+/// In an existing `SHA256SUMS`, lines for other files are kept.
+/// Returns the number of files hashed, or the problems.
+fn write_manifests(mode: Manifest, pairs: &[(PathBuf, PathBuf)]) -> Result<usize, Vec<String>> {
+    let name = |p: &Path| p.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let mut problems = Vec::new();
+    let mut by_dir: HashMap<PathBuf, Vec<(String, String)>> = HashMap::new();
+    let mut old_names: HashMap<PathBuf, HashSet<String>> = HashMap::new();
+    let mut hashed = 0;
+    for (orig, path) in pairs.iter().filter(|(_, path)| !path.is_dir()) {
+        let hash = match file_sha256(path) {
+            Ok(hash) => hash.iter().map(|b| format!("{:02x}", b)).collect::<String>(),
+            Err(e) => {
+                problems.push(format!("{:?}: {}", path, e));
+                continue;
             }
+        };
+        hashed += 1;
+        let line = format!("{}  {}\n", hash, name(path));
+        if mode == Manifest::PerFile {
+            let mut sidecar = path.clone().into_os_string();
+            sidecar.push(".sha256");
+            if let Err(e) = fs::write(&sidecar, line) {
+                problems.push(format!("{:?}: {}", sidecar, e));
+            }
+            continue;
+        }
+        let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        old_names.entry(dir.clone()).or_default().insert(name(orig));
+        by_dir.entry(dir).or_default().push((name(path), line));
+    }
+    for (dir, entries) in by_dir {
+        let sums = dir.join("SHA256SUMS");
+        let stale = &old_names[&dir];
+        let mut out: String = fs::read_to_string(&sums)
+            .unwrap_or_default()
+            .lines()
+            .filter(|l| {
+                // "<hash>  <name>" or "<hash> *<name>" (binary mode)
+                let listed = l.get(66..).unwrap_or("");
+                !stale.contains(listed) && !entries.iter().any(|(n, _)| n == listed)
+            })
+            .map(|l| format!("{}\n", l))
+            .collect();
+        for (_, line) in entries {
+            out.push_str(&line);
+        }
+        if let Err(e) = fs::write(&sums, out) {
+            problems.push(format!("{:?}: {}", sums, e));
+        }
+    }
+    if problems.is_empty() {
+        Ok(hashed)
+    } else {
+        Err(problems)
+    }
+}
+
+/// Name as case- and normalization-insensitive filesystems compare it.
+fn fold_name(name: &str) -> String {
+    use unicode_normalization::UnicodeNormalization;
+    name.nfc().collect::<String>().to_lowercase()
+}
+
+/// Difference hash: 9×8 grayscale, one bit per horizontal neighbour comparison.
+fn dhash(path: &Path) -> Option<u64> {
+    let small = image::open(path).ok()?.resize_exact(9, 8, image::imageops::FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash = (hash << 1) | (small.get_pixel(x, y)[0] < small.get_pixel(x + 1, y)[0]) as u64;
         }
+    }
+    Some(hash)
+}
 
+// scope filter expression, e.g. `ext == "jpg" && size > 1MB`
+#[derive(Debug)]
+enum FilterExpr {
+    And(Box<FilterExpr>, Box<FilterExpr>),
+    Or(Box<FilterExpr>, Box<FilterExpr>),
+    Not(Box<FilterExpr>),
+    Cmp(FilterField, CmpOp, FilterValue),
+}
 
-        // orig -> tmp -> final
-        let mut robust_map = Vec::new();
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum FilterField {
+    Ext,
+    Name,
+    Size,
+    Type,
+    Rating,
+    // of images; empty for other files
+    Orientation,
+    Aspect,
+}
 
-        for (i, fe) in self.files.iter().enumerate() {
-            let orig = fe.path.clone();
-            let desired = resolved_paths[i].clone();
+impl FilterField {
+    fn numeric(self) -> bool {
+        matches!(self, FilterField::Size | FilterField::Rating)
+    }
+}
 
-            if orig == desired {
-                continue;
-            }
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum CmpOp {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Match,
+}
 
-            let dir = orig.parent().unwrap_or(Path::new("."));
-            let nanos = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_nanos();
+#[derive(Debug)]
+enum FilterValue {
+    Text(String),
+    Number(u64),
+    Pattern(Regex),
+}
 
-            let tmp = dir.join(format!(".tmp-{}-{}.tmp", nanos, i));
-            robust_map.push((orig, tmp, desired));
-        }
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Text(String),
+    Number(u64),
+    Op(&'static str),
+    LParen,
+    RParen,
+}
 
-        if robust_map.is_empty() {
-            self.messages.push("No files to rename.".into());
-            return;
+impl FilterExpr {
+    fn parse(src: &str) -> Result<FilterExpr, String> {
+        let tokens = Self::tokenize(src)?;
+        let mut pos = 0;
+        let expr = Self::parse_or(&tokens, &mut pos)?;
+        if pos < tokens.len() {
+            return Err(format!("unexpected {:?}", tokens[pos]));
         }
+        Ok(expr)
+    }
 
-        // orig → tmp
-        let mut temps_created = Vec::new();
-        for (orig, tmp, _) in &robust_map {
-            if let Err(e) = fs::rename(orig, tmp) {
-                self.messages.push(format!("Failed: {}", e));
-                for (t, o) in temps_created.iter().rev() {
-                    let _ = fs::rename(t, o);
+    fn tokenize(src: &str) -> Result<Vec<Token>, String> {
+        let chars: Vec<char> = src.chars().collect();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            if c.is_whitespace() {
+                i += 1;
+            } else if c == '(' || c == ')' {
+                tokens.push(if c == '(' { Token::LParen } else { Token::RParen });
+                i += 1;
+            } else if c == '"' {
+                let mut text = String::new();
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    text.push(chars[i]);
+                    i += 1;
                 }
-                return;
+                if i == chars.len() {
+                    return Err("unterminated string".into());
+                }
+                tokens.push(Token::Text(text));
+                i += 1;
+            } else if c.is_ascii_digit() {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                let num: f64 = chars[start..i]
+                    .iter()
+                    .collect::<String>()
+                    .parse()
+                    .map_err(|_| "bad number".to_string())?;
+                let unit_start = i;
+                while i < chars.len() && chars[i].is_ascii_alphabetic() {
+                    i += 1;
+                }
+                let unit: String = chars[unit_start..i].iter().collect();
+                let mult: u64 = match unit.to_uppercase().as_str() {
+                    "" | "B" => 1,
+                    "KB" => 1 << 10,
+                    "MB" => 1 << 20,
+                    "GB" => 1 << 30,
+                    _ => return Err(format!("unknown unit '{}'", unit)),
+                };
+                tokens.push(Token::Number((num * mult as f64) as u64));
+            } else if c.is_alphabetic() {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                tokens.push(Token::Ident(chars[start..i].iter().collect()));
+            } else {
+                let two: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+                let op = ["&&", "||", "==", "!=", ">=", "<="]
+                    .into_iter()
+                    .find(|op| two == *op)
+                    .or_else(|| ["!", ">", "<", "~"].into_iter().find(|op| op.starts_with(c)))
+                    .ok_or_else(|| format!("unexpected '{}'", c))?;
+                i += op.len();
+                tokens.push(Token::Op(op));
             }
-            temps_created.push((tmp.clone(), orig.clone()));
         }
+        Ok(tokens)
+    }
 
-        // tmp → final
-        for (_orig, tmp, final_path) in &robust_map {
-            if let Err(e) = fs::rename(tmp, final_path) {
-                self.messages.push(format!("Failed final rename: {}", e));
-                return;
-            }
+    fn parse_or(tokens: &[Token], pos: &mut usize) -> Result<FilterExpr, String> {
+        let mut lhs = Self::parse_and(tokens, pos)?;
+        while tokens.get(*pos) == Some(&Token::Op("||")) {
+            *pos += 1;
+            let rhs = Self::parse_and(tokens, pos)?;
+            lhs = FilterExpr::Or(Box::new(lhs), Box::new(rhs));
         }
+        Ok(lhs)
+    }
 
-        // undo
-        let mut undo_map = HashMap::new();
-        for (orig, _tmp, final_path) in robust_map {
-            undo_map.insert(orig, final_path);
+    fn parse_and(tokens: &[Token], pos: &mut usize) -> Result<FilterExpr, String> {
+        let mut lhs = Self::parse_unary(tokens, pos)?;
+        while tokens.get(*pos) == Some(&Token::Op("&&")) {
+            *pos += 1;
+            let rhs = Self::parse_unary(tokens, pos)?;
+            lhs = FilterExpr::And(Box::new(lhs), Box::new(rhs));
         }
-        self.last_actions.push(undo_map);
-
-        self.messages.push("Rename completed.".into());
+        Ok(lhs)
     }
 
-    fn undo(&mut self) {
-        if let Some(mapping) = self.last_actions.pop() {
-            for (orig, final_path) in mapping {
-                if final_path.exists() {
-                    if let Err(e) = fs::rename(&final_path, &orig) {
-                        self.messages.push(format!(
-                            "Failed to undo {:?} -> {:?}: {}",
-                            final_path, orig, e
-                        ));
-                    }
-                } else {
-                    self.messages.push(format!(
-                        "Cannot undo, final file missing: {:?}",
-                        final_path
-                    ));
+    fn parse_unary(tokens: &[Token], pos: &mut usize) -> Result<FilterExpr, String> {
+        match tokens.get(*pos) {
+            Some(Token::Op("!")) => {
+                *pos += 1;
+                Ok(FilterExpr::Not(Box::new(Self::parse_unary(tokens, pos)?)))
+            }
+            Some(Token::LParen) => {
+                *pos += 1;
+                let inner = Self::parse_or(tokens, pos)?;
+                if tokens.get(*pos) != Some(&Token::RParen) {
+                    return Err("missing ')'".into());
                 }
+                *pos += 1;
+                Ok(inner)
             }
-            self.messages.push("Undo attempted.".into());
-        } else {
-            self.messages.push("No actions to undo.".into());
+            _ => Self::parse_cmp(tokens, pos),
         }
     }
-}
 
-impl eframe::App for BulkRename {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        // Process thumbnail loading results
-        if let Some(rx) = &self.thumb_rx {
-            while let Ok((key, result)) = rx.try_recv() {
-                match result {
-                    Ok((rgba, (w, h))) => {
-                        let color_image = egui::ColorImage::from_rgba_unmultiplied([w, h], &rgba.into_vec());
-                        let tex = ctx.load_texture(key.clone(), color_image, egui::TextureOptions::NEAREST);
-                        let orig_size = egui::Vec2::new(w as f32, h as f32);
-                        self.thumbnails.insert(key, ThumbnailState::Loaded(tex, orig_size));
-                    }
-                    Err(e) => {
-                        self.thumbnails.insert(key, ThumbnailState::Failed);
-                        self.messages.push(format!("Thumbnail load failed: {}", e));
-                    }
+    fn parse_cmp(tokens: &[Token], pos: &mut usize) -> Result<FilterExpr, String> {
+        let field = match tokens.get(*pos) {
+            Some(Token::Ident(name)) => match name.as_str() {
+                "ext" => FilterField::Ext,
+                "name" => FilterField::Name,
+                "size" => FilterField::Size,
+                "type" => FilterField::Type,
+                "rating" => FilterField::Rating,
+                "orientation" => FilterField::Orientation,
+                "aspect" => FilterField::Aspect,
+                _ => {
+                    return Err(format!(
+                        "unknown field '{}' (ext, name, size, type, rating, orientation, aspect)",
+                        name
+                    ))
                 }
+            },
+            other => return Err(format!("expected a field, found {:?}", other)),
+        };
+        let op = match tokens.get(*pos + 1) {
+            Some(Token::Op("==")) => CmpOp::Eq,
+            Some(Token::Op("!=")) => CmpOp::Ne,
+            Some(Token::Op(">")) => CmpOp::Gt,
+            Some(Token::Op(">=")) => CmpOp::Ge,
+            Some(Token::Op("<")) => CmpOp::Lt,
+            Some(Token::Op("<=")) => CmpOp::Le,
+            Some(Token::Op("~")) => CmpOp::Match,
+            other => return Err(format!("expected an operator, found {:?}", other)),
+        };
+        let value = match (tokens.get(*pos + 2), field, op) {
+            (Some(Token::Number(n)), _, _) if field.numeric() => FilterValue::Number(*n),
+            (Some(Token::Text(t)), _, CmpOp::Match) if !field.numeric() => {
+                FilterValue::Pattern(Regex::new(t).map_err(|e| e.to_string())?)
             }
-        }
+            (Some(Token::Text(t)), _, CmpOp::Eq | CmpOp::Ne) if !field.numeric() => {
+                FilterValue::Text(t.clone())
+            }
+            (other, _, _) => {
+                return Err(format!("invalid value {:?} for {:?} {:?}", other, field, op))
+            }
+        };
+        *pos += 3;
+        Ok(FilterExpr::Cmp(field, op, value))
+    }
 
-        match self.loading_phase {
-            LoadingPhase::AddingFiles => {
-                if self.loading_phase == LoadingPhase::AddingFiles {
-                    let mut finished = false;
-                    if let Some(rx) = self.loader_rx.take() {
-                        use std::sync::mpsc::TryRecvError;
-                        loop {
-                            match rx.try_recv() {
-                                Ok(path) => {
-                                    self.add_files(vec![path]);
-                                    self.loading_count += 1;
-                                }
-                                Err(TryRecvError::Empty) => {
-                                    self.loader_rx = Some(rx);
-                                    break;
-                                }
-                                Err(TryRecvError::Disconnected) => {
-                                    finished = true;
-                                    break;
-                                }
-                            }
+    fn matches(&self, fe: &FileEntry) -> bool {
+        match self {
+            FilterExpr::And(a, b) => a.matches(fe) && b.matches(fe),
+            FilterExpr::Or(a, b) => a.matches(fe) || b.matches(fe),
+            FilterExpr::Not(a) => !a.matches(fe),
+            FilterExpr::Cmp(field, op, value) => {
+                let text = match field {
+                    FilterField::Ext if fe.category == FileCategory::Folder => String::new(),
+                    FilterField::Ext => fe
+                        .path
+                        .extension()
+                        .map(|s| s.to_string_lossy().to_lowercase())
+                        .unwrap_or_default(),
+                    FilterField::Name => fe
+                        .path
+                        .file_name()
+                        .map(|s| s.to_string_lossy().to_string())
+                        .unwrap_or_default(),
+                    FilterField::Type => fe.category.keyword().to_string(),
+                    FilterField::Orientation | FilterField::Aspect => {
+                        let shape = match field {
+                            FilterField::Orientation => ShapeField::Orientation,
+                            _ => ShapeField::Aspect,
+                        };
+                        fe.dimensions().map(|size| shape.value(size)).unwrap_or_default()
+                    }
+                    FilterField::Size | FilterField::Rating => String::new(),
+                };
+                match value {
+                    FilterValue::Number(n) => {
+                        let size = match field {
+                            FilterField::Rating => fe.rating() as u64,
+                            _ => match fe.snapshot {
+                                Some((_, size)) => size,
+                                None => return false,
+                            },
+                        };
+                        match op {
+                            CmpOp::Eq => size == *n,
+                            CmpOp::Ne => size != *n,
+                            CmpOp::Gt => size > *n,
+                            CmpOp::Ge => size >= *n,
+                            CmpOp::Lt => size < *n,
+                            CmpOp::Le => size <= *n,
+                            CmpOp::Match => false,
                         }
                     }
-                    if finished {
-                        self.loading_phase = LoadingPhase::LoadingThumbs;
-                        self.loading_count = 0;
+                    FilterValue::Text(t) => {
+                        let eq = if *field == FilterField::Name {
+                            text == *t
+                        } else {
+                            text.eq_ignore_ascii_case(t.trim_start_matches('.'))
+                        };
+                        (*op == CmpOp::Eq) == eq
                     }
-                    ctx.request_repaint();
-                }
-                   
-            }
-            LoadingPhase::LoadingThumbs => {
-                // Check if any thumbnails are still loading
-                let any_loading = self.thumbnails.values().any(|s| matches!(s, ThumbnailState::Loading));
-                if !any_loading {
-                    self.loading_phase = LoadingPhase::None;
+                    FilterValue::Pattern(re) => re.is_match(&text),
                 }
-                ctx.request_repaint();
             }
-            LoadingPhase::None => {}
         }
+    }
+}
 
-        let dropped = ctx.input_mut(|i| {
-            if i.raw.dropped_files.is_empty() {
-                None
-            } else {
-                Some(std::mem::take(&mut i.raw.dropped_files))
-            }
-        });
+// column the preview table is sorted by
+#[derive(PartialEq, Copy, Clone)]
+enum PreviewSort {
+    Old,
+    New,
+    Status,
+}
 
-        if let Some(dropped_files) = dropped {
-            let (tx, rx) = mpsc::channel::<PathBuf>();
-            self.loader_rx = Some(rx);
-            self.loading_phase = LoadingPhase::AddingFiles;
-            thread::spawn(move || {
-                let mut collected = Vec::new();
-                for f in dropped_files {
-                    if let Some(path) = f.path {
-                        if path.is_file() {
-                            collected.push(path);
-                        } else if path.is_dir() {
-                            Self::collect_files_recursively(&path, &mut collected);
-                        }
-                    }
-                }
-                for path in collected {
-                    tx.send(path).ok();
-                }
-            });
-            ctx.request_repaint();
-        }
+// one row of the preview panel
+struct PreviewRow {
+    // index into the file list
+    row: usize,
+    // the new name was typed by hand
+    manual: bool,
+    old: String,
+    new: String,
+    // fatal: the rename is refused
+    issues: Vec<String>,
+    // handled by the collision setting, or nothing to do
+    warnings: Vec<String>,
+    // what "Portable names" changed
+    sanitized: Vec<String>,
+    // excluded by the scope expression: left untouched
+    in_scope: bool,
+    // gone from disk, see `check_stale`
+    missing: bool,
+}
 
-        if self.loading_phase == LoadingPhase::None
-            && ctx.input(|i| !i.raw.hovered_files.is_empty())
-        {
-            egui::Area::new("drop_overlay".into())
-                .order(egui::Order::Foreground)
-                .interactable(false)
-                .show(ctx, |ui| {
-                    let rect = ctx.available_rect();
-                    ui.painter().rect_filled(
-                        rect,
-                        0.0,
-                        egui::Color32::from_rgba_unmultiplied(50, 100, 200, 80),
-                    );
-                    ui.centered_and_justified(|ui| {
-                        ui.label(
-                            egui::RichText::new("Drop files to add")
-                                .size(32.0)
-                                .color(egui::Color32::WHITE),
-                        );
-                    });
-                }
-            );
-        }
+// the preview rows and how many of them block the rename
+struct PreviewTable {
+    rows: Vec<PreviewRow>,
+    blocked: usize,
+}
 
-        egui::CentralPanel::default().show(ctx, |ui| {
-            ui.heading("BulkReName v2");
+impl PreviewRow {
+    /// Status column order: conflicts, warnings, renamed, unchanged, out of scope.
+    fn status_rank(&self) -> u8 {
+        if !self.issues.is_empty() {
+            0
+        } else if !self.warnings.is_empty() {
+            1
+        } else if !self.in_scope {
+            4
+        } else if self.old == self.new {
+            3
+        } else {
+            2
+        }
+    }
+}
 
-            // top buttons
-            ui.horizontal(|ui| {
-                if ui.button("Add files...").clicked() {
-                    if let Some(paths) = rfd::FileDialog::new().pick_files() {
-                        self.add_files(paths);
-                    }
-                }
-                if ui.button("Clear files").clicked() {
-                    self.files.clear();
-                    self.selected_idx = None;
-                }
-                if ui.button("ReName").clicked() {
-                    self.execute_rename();
-                }
-                if ui.button("Undo").clicked() {
-                    self.undo();
-                }
-            });
+// target filesystem naming rules
+#[derive(PartialEq, Copy, Clone, Debug)]
+enum FsProfile {
+    Off,
+    // per file, from the filesystem its folder is on
+    Auto,
+    Ntfs,
+    Fat32,
+    ExFat,
+    Ext4,
+}
 
-            ui.separator();
+impl FsProfile {
+    const ALL: [FsProfile; 6] = [
+        FsProfile::Off,
+        FsProfile::Auto,
+        FsProfile::Ntfs,
+        FsProfile::Fat32,
+        FsProfile::ExFat,
+        FsProfile::Ext4,
+    ];
 
-            ui.horizontal(|ui| {
-                ui.label("Sort: ");
-                let namelabel = match (self.sort_key, self.sort_order) {
-                    (Some(SortKey::Name), SortOrder::Asc) => "Name ↓",
-                    (Some(SortKey::Name), SortOrder::Desc) => "Name ↑",
-                    _ => "Name",
-                };
-                let modifiedlabel = match (self.sort_key, self.sort_order) {
-                    (Some(SortKey::Modified), SortOrder::Asc) => "Modified ↓",
-                    (Some(SortKey::Modified), SortOrder::Desc) => "Modified ↑",
-                    _ => "Modified",
-                };
-                let createdlabel = match (self.sort_key, self.sort_order) {
-                    (Some(SortKey::Created), SortOrder::Asc) => "Created ↓",
-                    (Some(SortKey::Created), SortOrder::Desc) => "Created ↑",
-                    _ => "Created",
-                };
-                let sizelabel = match (self.sort_key, self.sort_order) {
-                    (Some(SortKey::Size), SortOrder::Asc) => "Size ↓",
-                    (Some(SortKey::Size), SortOrder::Desc) => "Size ↑",
-                    _ => "Size",
-                };
-                if ui.button(namelabel).clicked() {
-                    self.sort_files(SortKey::Name);
-                }
-                if ui.button(modifiedlabel).clicked() {
-                    self.sort_files(SortKey::Modified);
-                }
-                if ui.button(createdlabel).clicked() {
-                    self.sort_files(SortKey::Created);
-                }
-                if ui.button(sizelabel).clicked() {
-                    self.sort_files(SortKey::Size);
-                }
-            });
+    fn label(self) -> &'static str {
+        match self {
+            FsProfile::Off => "(no check)",
+            FsProfile::Auto => "Auto-detect",
+            FsProfile::Ntfs => "NTFS",
+            FsProfile::Fat32 => "FAT32",
+            FsProfile::ExFat => "exFAT",
+            FsProfile::Ext4 => "ext4",
+        }
+    }
 
-            ui.separator();
+    fn is_invalid_char(self, c: char) -> bool {
+        match self {
+            FsProfile::Off | FsProfile::Auto => false,
+            FsProfile::Ext4 => c == '/' || c == '\0',
+            FsProfile::Ntfs | FsProfile::Fat32 | FsProfile::ExFat => {
+                c.is_control() || matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*')
+            }
+        }
+    }
 
-            ui.columns(2, |cols| {
-                // Left panel: file list
-                let left = &mut cols[0];
-                left.label(RichText::new("Files (select then move)").strong());
-                left.checkbox(&mut self.show_thumbnails, "show thumbnail");
+    /// (length of `name` in the unit the filesystem counts, limit, unit name)
+    fn name_length(self, name: &str) -> (usize, usize, &'static str) {
+        match self {
+            FsProfile::Ext4 => (name.len(), 255, "bytes"),
+            _ => (name.encode_utf16().count(), 255, "UTF-16 units"),
+        }
+    }
 
-                egui::ScrollArea::vertical()
-                    .max_height(800.0)
-                    .auto_shrink([false, false])
-                    .id_source("file_list")
-                    .show(left, |ui| {
-                        let mut to_delete = None;
-                        let pointer_y = ui.input(|i| i.pointer.hover_pos().map(|p| p.y));
-                        let mut insert_index = None;
+    /// Profile for a filesystem type name as reported by the OS (e.g. "NTFS", "vfat").
+    fn from_fs_name(name: &str) -> Self {
+        match name.to_lowercase().as_str() {
+            "ntfs" | "ntfs3" | "fuseblk" | "refs" => FsProfile::Ntfs,
+            "fat" | "fat12" | "fat16" | "fat32" | "vfat" | "msdos" => FsProfile::Fat32,
+            "exfat" => FsProfile::ExFat,
+            "ext2" | "ext3" | "ext4" | "btrfs" | "xfs" | "zfs" | "f2fs" => FsProfile::Ext4,
+            _ => FsProfile::Off,
+        }
+    }
 
-                        let mut row_tops = Vec::new();
+    fn case_insensitive(self) -> bool {
+        matches!(self, FsProfile::Ntfs | FsProfile::Fat32 | FsProfile::ExFat)
+    }
 
-                        for i in 0..self.files.len() {
-                            let (rect, resp) = ui.push_id(i, |ui| {
-                                let content_ui = ui.horizontal(|ui| {
-                                    ui.set_min_height(40.0);
+    /// Problems with a single name under this profile.
+    fn check_name(self, name: &str) -> Vec<String> {
+        let mut issues = Vec::new();
+        if matches!(self, FsProfile::Off | FsProfile::Auto) {
+            return issues;
+        }
+        let mut bad: Vec<char> = name.chars().filter(|&c| self.is_invalid_char(c)).collect();
+        bad.dedup();
+        if !bad.is_empty() {
+            let shown: String = bad.iter().map(|c| format!("{:?} ", c)).collect();
+            issues.push(format!("invalid on {}: {}", self.label(), shown.trim_end()));
+        }
+        let (len, limit, unit) = self.name_length(name);
+        if len > limit {
+            issues.push(format!("too long for {}: {} / {} {}", self.label(), len, limit, unit));
+        }
+        if self.case_insensitive() && name.ends_with(['.', ' ']) {
+            issues.push(format!("ends with a dot or space (not allowed on {})", self.label()));
+        }
+        issues
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug, Default)]
+enum QuickCase {
+    #[default]
+    Keep,
+    Lower,
+    Title,
+}
+
+// one-off transforms applied to every generated name, after the blocks
+#[derive(PartialEq, Copy, Clone, Debug, Default)]
+struct QuickActions {
+    case: QuickCase,
+    underscores: bool,
+    strip_diacritics: bool,
+}
+
+impl QuickActions {
+    fn is_active(&self) -> bool {
+        *self != Self::default()
+    }
+
+    /// Title Case and spaces → underscores leave the extension alone.
+    fn apply(&self, name: String) -> String {
+        if !self.is_active() {
+            return name;
+        }
+        let mut name = name;
+        if self.strip_diacritics {
+            use unicode_normalization::UnicodeNormalization;
+            // only Latin accents, so Japanese dakuten survive the round trip
+            let stripped: String = name.nfd().filter(|c| !('\u{300}'..='\u{36f}').contains(c)).collect();
+            name = stripped.nfc().collect();
+        }
+        let (stem, ext) = match name.rfind('.') {
+            Some(dot) if dot > 0 => (name[..dot].to_string(), name[dot..].to_string()),
+            _ => (name, String::new()),
+        };
+        let mut stem = if self.underscores { stem.replace(' ', "_") } else { stem };
+        let mut ext = ext;
+        match self.case {
+            QuickCase::Keep => {}
+            QuickCase::Lower => {
+                stem = stem.to_lowercase();
+                ext = ext.to_lowercase();
+            }
+            QuickCase::Title => stem = title_case(&stem),
+        }
+        stem + ext.as_str()
+    }
+}
+
+/// Filesystem type name of the volume `dir` is on.
+#[cfg(windows)]
+fn detect_fs_name(dir: &Path) -> Option<String> {
+    use std::os::windows::ffi::OsStrExt;
+    use winapi::um::fileapi::{GetVolumeInformationW, GetVolumePathNameW};
+
+    let wide: Vec<u16> = dir.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut root = [0u16; 261];
+    if unsafe { GetVolumePathNameW(wide.as_ptr(), root.as_mut_ptr(), root.len() as u32) } == 0 {
+        return None;
+    }
+    let mut fs_name = [0u16; 64];
+    let ok = unsafe {
+        GetVolumeInformationW(
+            root.as_ptr(),
+            std::ptr::null_mut(),
+            0,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            fs_name.as_mut_ptr(),
+            fs_name.len() as u32,
+        )
+    };
+    if ok == 0 {
+        return None;
+    }
+    let len = fs_name.iter().position(|&c| c == 0).unwrap_or(fs_name.len());
+    Some(String::from_utf16_lossy(&fs_name[..len]))
+}
+
+#[cfg(target_os = "linux")]
+fn detect_fs_name(dir: &Path) -> Option<String> {
+    let dir = fs::canonicalize(dir).ok()?;
+    let mounts = fs::read_to_string("/proc/self/mounts").ok()?;
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut cols = line.split_whitespace();
+            let _dev = cols.next()?;
+            // spaces in mount points are written as \040
+            let mount_point = cols.next()?.replace("\\040", " ");
+            let fs_type = cols.next()?;
+            Some((PathBuf::from(mount_point), fs_type.to_string()))
+        })
+        .filter(|(mount_point, _)| dir.starts_with(mount_point))
+        .max_by_key(|(mount_point, _)| mount_point.as_os_str().len())
+        .map(|(_, fs_type)| fs_type)
+}
+
+#[cfg(target_os = "macos")]
+fn detect_fs_name(dir: &Path) -> Option<String> {
+    use std::os::unix::ffi::OsStrExt;
+    let c_path = std::ffi::CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut st: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(c_path.as_ptr(), &mut st) } != 0 {
+        return None;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(st.f_fstypename.as_ptr()) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+#[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+fn detect_fs_name(_dir: &Path) -> Option<String> {
+    None
+}
+
+/// App preferences kept in `settings.json` (templates stay in templates.json).
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+struct Settings {
+    // opt-in: ask GitHub for a newer release at startup
+    check_updates: bool,
+    // off: a second launch hands its files to the running window
+    allow_multiple: bool,
+    language: Language,
+    theme: Theme,
+    // zoom of the whole UI (1.0 = 100%)
+    ui_scale: f32,
+    // width of list thumbnails in px; the height is 3/4 of it
+    thumb_size: usize,
+    // collision handling at startup, until a template sets one
+    collision: CollisionStrategy,
+    // watch folder mode: folder, saved template, and whether it starts with the app
+    watch_dir: Option<PathBuf>,
+    watch_template: String,
+    watch_at_start: bool,
+    // files replaced by Overwrite or deleted by Dedup go to the trash
+    trash_replaced: bool,
+    // audit log written into the folders of each batch
+    folder_log: FolderLog,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            check_updates: false,
+            allow_multiple: false,
+            language: Language::default(),
+            theme: Theme::default(),
+            ui_scale: 1.0,
+            thumb_size: 160,
+            collision: CollisionStrategy::Suffix,
+            watch_dir: None,
+            watch_template: String::new(),
+            watch_at_start: false,
+            trash_replaced: true,
+            folder_log: FolderLog::Off,
+        }
+    }
+}
+
+// hover text of the Script block
+const SCRIPT_HELP: &str = "rhai script; the value of the last line is the text to insert. Variables: name, ext, \
+    index, size, modified, created, tag, rating, width, height. Also let, if/else, for and loops, name.to_upper(), \
+    name.to_lower(), name.sub_string(from, len), regex_replace(s, re, with); + joins text";
+
+// formats offered next to the Date block's format field; the last is ISO 8601 (basic, no colons)
+const DATE_PRESETS: [&str; 5] = ["%Y%m%d", "%Y-%m-%d", "%Y%m%d_%H%M%S", "%Y-%m-%d_%H%M%S", "%Y%m%dT%H%M%S%z"];
+
+/// Drop-down setting `format` to one of DATE_PRESETS, each shown with `sample` in it.
+fn date_preset_menu(ui: &mut egui::Ui, id: String, format: &mut String, sample: &DateTime<FixedOffset>) {
+    egui::ComboBox::from_id_source(id)
+        .width(20.0)
+        .selected_text("")
+        .show_ui(ui, |ui| {
+            for preset in DATE_PRESETS {
+                let example = format_date(sample, preset, Locale::Default);
+                let label = format!("{}  →  {}", preset, example.unwrap_or_default());
+                ui.selectable_value(format, preset.to_string(), label);
+            }
+        })
+        .response
+        .on_hover_text(tr("Common formats"));
+}
+
+// hover text of the Name date block's pattern
+const NAME_DATE_HELP: &str = "Date layout in the original name, e.g. IMG_%Y%m%d (%Y %y %m %d %H %M %S); \
+    empty = guess unix time, 20240305, 2024-03-05";
+
+// zoom steps offered in Settings
+const UI_SCALES: [f32; 7] = [0.8, 0.9, 1.0, 1.1, 1.25, 1.5, 2.0];
+
+const COLLISION_LABELS: [(CollisionStrategy, &str); 5] = [
+    (CollisionStrategy::Overwrite, "Overwrite"),
+    (CollisionStrategy::Skip, "Skip"),
+    (CollisionStrategy::Suffix, "Suffix (1)"),
+    (CollisionStrategy::Dedup, "Dedup"),
+    (CollisionStrategy::Ask, "Ask"),
+];
+
+/// Window layout kept in eframe's storage between runs.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+struct Layout {
+    column_split: f32,
+    show_thumbnails: bool,
+    columns: Vec<ListColumn>,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self { column_split: 0.5, show_thumbnails: true, columns: Vec::new() }
+    }
+}
+
+const LAYOUT_KEY: &str = "layout";
+
+/// File list and template, kept in `session.json` so a crash doesn't lose a hand-ordered list.
+#[derive(Serialize, Deserialize)]
+struct Session {
+    template: Template,
+    #[serde(default)]
+    sanitize: bool,
+    files: Vec<SessionFile>,
+}
+
+// one list entry with what was set by hand
+#[derive(Serialize, Deserialize)]
+struct SessionFile {
+    path: PathBuf,
+    #[serde(default)]
+    selected: bool,
+    #[serde(default)]
+    excluded: bool,
+    #[serde(default)]
+    manual_name: Option<String>,
+    #[serde(default)]
+    tag: String,
+}
+
+const RELEASES_API: &str = "https://api.github.com/repos/mi3zuk/BulkReName/releases/latest";
+
+// the part of the GitHub release JSON we use
+#[derive(Clone, Deserialize)]
+struct ReleaseInfo {
+    tag_name: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    body: Option<String>,
+    html_url: String,
+}
+
+/// "v1.2.10" → [1, 2, 10]; non-numeric parts count as 0.
+fn version_parts(v: &str) -> Vec<u64> {
+    v.trim_start_matches(['v', 'V'])
+        .split(['.', '-', '+'])
+        .take(3)
+        .map(|p| p.parse().unwrap_or(0))
+        .collect()
+}
+
+/// Latest release, if it is newer than this build.
+fn fetch_newer_release() -> Result<Option<ReleaseInfo>, String> {
+    let release: ReleaseInfo = ureq::get(RELEASES_API)
+        .set("Accept", "application/vnd.github+json")
+        .set("User-Agent", concat!("BulkReName/", env!("CARGO_PKG_VERSION")))
+        .timeout(std::time::Duration::from_secs(15))
+        .call()
+        .map_err(|e| e.to_string())?
+        .into_string()
+        .map_err(|e| e.to_string())
+        .and_then(|text| serde_json::from_str(&text).map_err(|e| e.to_string()))?;
+    let newer = version_parts(&release.tag_name) > version_parts(env!("CARGO_PKG_VERSION"));
+    Ok(newer.then_some(release))
+}
+
+/// Where "Refresh" in the template gallery downloads the list from.
+const GALLERY_URL: &str = "https://raw.githubusercontent.com/mi3zuk/BulkReName/main/gallery.json";
+
+#[derive(Clone, Serialize, Deserialize)]
+struct GalleryItem {
+    description: String,
+    template: Template,
+}
+
+// one line of history.jsonl
+#[derive(Clone, Serialize, Deserialize)]
+struct HistoryRecord {
+    time: String,
+    batch: String,
+    // "rename", "undo" or "revert"
+    action: String,
+    old: PathBuf,
+    new: PathBuf,
+}
+
+enum ThumbnailState {
+    Loading,
+    Loaded(egui::TextureHandle, egui::Vec2),
+    Failed,
+}
+
+// (cache key, image, largest width and height)
+type ThumbJob = (String, PathBuf, (usize, usize));
+type ThumbResult = Result<(image::RgbaImage, (usize, usize)), String>;
+
+// (path, exists, snapshot) from a look at a listed file
+type FileState = (PathBuf, bool, Option<(SystemTime, u64)>);
+
+// camera RAW formats: thumbnails come from the JPEG preview the camera embeds
+const RAW_EXTENSIONS: [&str; 5] = ["cr2", "cr3", "nef", "arw", "dng"];
+
+/// `ffmpeg` without a console window popping up on Windows.
+fn ffmpeg_command() -> std::process::Command {
+    let mut cmd = std::process::Command::new("ffmpeg");
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+        cmd.creation_flags(CREATE_NO_WINDOW);
+    }
+    cmd.stdin(std::process::Stdio::null()).stderr(std::process::Stdio::null());
+    cmd
+}
+
+/// Whether `ffmpeg` is on the PATH (checked once); video thumbnails need it.
+fn ffmpeg_available() -> bool {
+    static FOUND: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+    *FOUND.get_or_init(|| ffmpeg_command().arg("-version").output().is_ok_and(|o| o.status.success()))
+}
+
+/// A frame one second in (the first one for shorter clips), scaled by ffmpeg.
+fn video_frame(path: &Path, (max_w, max_h): (usize, usize)) -> Option<image::DynamicImage> {
+    let scale = format!("scale={}:{}:force_original_aspect_ratio=decrease", max_w, max_h);
+    for seek in ["1", "0"] {
+        let out = ffmpeg_command()
+            .args(["-v", "error", "-ss", seek, "-i"])
+            .arg(path)
+            .args(["-frames:v", "1", "-vf", &scale, "-f", "image2pipe", "-vcodec", "png", "-"])
+            .output()
+            .ok()?;
+        if out.status.success() && !out.stdout.is_empty() {
+            return image::load_from_memory_with_format(&out.stdout, image::ImageFormat::Png).ok();
+        }
+    }
+    None
+}
+
+/// Scaled-down RGBA copy of the image at `path`.
+fn decode_thumbnail(path: &Path, (max_w, max_h): (usize, usize)) -> ThumbResult {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    if FileCategory::from_extension(&ext) == FileCategory::Video {
+        return match video_frame(path, (max_w, max_h)) {
+            Some(frame) => {
+                let thumb = frame.thumbnail(max_w as u32, max_h as u32).into_rgba8();
+                let (w, h) = (thumb.width() as usize, thumb.height() as usize);
+                Ok((thumb, (w, h)))
+            }
+            None => Err("ffmpeg could not read a frame".to_string()),
+        };
+    }
+    let open = || -> image::ImageResult<image::DynamicImage> {
+        if !RAW_EXTENSIONS.contains(&ext.as_str()) {
+            return image::open(path);
+        }
+        let data = fs::read(path)?;
+        let jpeg = embedded_jpeg(&data, (max_w, max_h)).ok_or_else(|| {
+            image::ImageError::Unsupported(image::error::UnsupportedError::from_format_and_kind(
+                image::error::ImageFormatHint::PathExtension(path.to_path_buf()),
+                image::error::UnsupportedErrorKind::GenericFeature("no embedded preview".into()),
+            ))
+        })?;
+        image::load_from_memory_with_format(jpeg, image::ImageFormat::Jpeg)
+    };
+    std::panic::catch_unwind(|| match open() {
+        Ok(img) => {
+            let thumb = img.thumbnail(max_w as u32, max_h as u32).into_rgba8();
+            let (w, h) = (thumb.width() as usize, thumb.height() as usize);
+            Ok((thumb, (w, h)))
+        }
+        Err(e) => Err(format!("{:?}", e)),
+    })
+    .unwrap_or_else(|_| Err("Panic in image processing".to_string()))
+}
+
+// the on-disk thumbnail cache is trimmed to this size, oldest first
+const THUMB_CACHE_LIMIT: u64 = 200 * 1024 * 1024;
+
+/// Cache file for the thumbnail of `path` as it is now (path, mtime, size and thumbnail size).
+fn thumb_cache_file(cache: &Path, path: &Path, max_size: (usize, usize)) -> Option<PathBuf> {
+    use sha2::{Digest, Sha256};
+    let meta = fs::metadata(path).ok()?;
+    let mtime = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    let key = format!("{}|{}|{}|{}x{}", path.display(), mtime.as_nanos(), meta.len(), max_size.0, max_size.1);
+    let hex: String = Sha256::digest(key.as_bytes()).iter().take(16).map(|b| format!("{:02x}", b)).collect();
+    Some(cache.join(format!("{}.png", hex)))
+}
+
+/// Thumbnail from the cache, else decoded and written to the cache.
+fn cached_thumbnail(cache: &Path, path: &Path, max_size: (usize, usize)) -> ThumbResult {
+    let file = thumb_cache_file(cache, path, max_size);
+    if let Some((file, img)) = file.as_ref().and_then(|f| Some((f, image::open(f).ok()?))) {
+        // recently used entries survive the trimming
+        filetime::set_file_mtime(file, filetime::FileTime::now()).ok();
+        let thumb = img.into_rgba8();
+        let (w, h) = (thumb.width() as usize, thumb.height() as usize);
+        return Ok((thumb, (w, h)));
+    }
+    let result = decode_thumbnail(path, max_size);
+    if let (Ok((thumb, _)), Some(file)) = (&result, file) {
+        if fs::create_dir_all(cache).is_ok() {
+            thumb.save(file).ok();
+        }
+    }
+    result
+}
+
+/// Delete the least recently used cache files until the cache fits in `limit` bytes.
+fn trim_thumb_cache(cache: &Path, limit: u64) {
+    let Ok(entries) = fs::read_dir(cache) else { return };
+    let mut files: Vec<(SystemTime, u64, PathBuf)> = entries
+        .flatten()
+        .filter_map(|e| {
+            let meta = e.metadata().ok()?;
+            Some((meta.modified().ok()?, meta.len(), e.path()))
+        })
+        .collect();
+    files.sort_by_key(|f| std::cmp::Reverse(f.0));
+    let mut total = 0;
+    for (_, len, path) in files {
+        total += len;
+        if total > limit {
+            fs::remove_file(path).ok();
+        }
+    }
+}
+
+/// Total size of the files in the thumbnail cache.
+fn thumb_cache_size(cache: &Path) -> u64 {
+    fs::read_dir(cache)
+        .map(|entries| entries.flatten().filter_map(|e| e.metadata().ok()).map(|m| m.len()).sum())
+        .unwrap_or(0)
+}
+
+/// A few threads decoding thumbnails off the UI thread; each result wakes the UI.
+fn spawn_thumb_workers(ctx: &egui::Context, cache: PathBuf) -> (Sender<ThumbJob>, Receiver<(String, ThumbResult)>) {
+    let (job_tx, job_rx) = mpsc::channel::<ThumbJob>();
+    let (tx, rx) = mpsc::channel();
+    let job_rx = Arc::new(Mutex::new(job_rx));
+    let workers = thread::available_parallelism().map_or(2, |n| n.get()).clamp(1, 4);
+    {
+        let cache = cache.clone();
+        thread::spawn(move || trim_thumb_cache(&cache, THUMB_CACHE_LIMIT));
+    }
+    for _ in 0..workers {
+        let (job_rx, tx, ctx, cache) = (job_rx.clone(), tx.clone(), ctx.clone(), cache.clone());
+        thread::spawn(move || {
+            // the lock is held only while waiting for the next job
+            while let Ok((key, path, max_size)) = job_rx.lock().unwrap().recv() {
+                if tx.send((key, cached_thumbnail(&cache, &path, max_size))).is_err() {
+                    break;
+                }
+                ctx.request_repaint();
+            }
+        });
+    }
+    (job_tx, rx)
+}
+
+/// One topic of the in-app help: examples (copyable) with a short explanation.
+struct HelpSection {
+    title: &'static str,
+    intro: &'static str,
+    rows: &'static [(&'static str, &'static str)],
+}
+
+const HELP: &[HelpSection] = &[
+    HelpSection {
+        title: "Blocks",
+        intro: "The new name is the blocks joined top to bottom.",
+        rows: &[
+            ("photo_", "Literal: fixed text"),
+            ("min digits 3, init 1, gain 1", "Number: 001, 002, 003 … (counts files in scope)"),
+            ("  7 / __7 / -07", "Number / pad: 0, space or any character up to min digits; a negative gain counts down (the minus sign counts as a digit)"),
+            ("a, b, … aa / I, II, III", "Number styles: hex, binary, letters (27 = aa) and Roman numerals (1-3999); values they can't show stay decimal"),
+            ("%Y-%m-%d", "Date fmt: date/time in strftime format (see Date format); the menu next to it has common formats"),
+            ("local / UTC / UTC±", "Date fmt: time zone the time is written in; %z adds the offset (+0900)"),
+            ("modified / created / now", "Date fmt: each file's modification or creation time (mtime where not recorded), or the current time"),
+            ("2023-07-01 report → 20230701", "Name date: the date in the original name (pattern like %Y-%m-%d, or empty to guess) in a new format"),
+            ("%Y%m%d_%H%M%S", "EXIF date: capture date of photos (JPEG, HEIC, TIFF, RAW); the file's modified time if missing"),
+            ("Keep", "Orig: the original name without extension"),
+            ("Range 0 .. 4", "Orig / Del Range: remove characters 0-3; negative numbers count from the end (-1 = last)"),
+            ("copy/ (1)", "Orig / Del Substr.: remove each \"/\"-separated text; Aa = case sensitive"),
+            ("start 0, length 8", "Orig slice: part of the original name; \"from end\" with start 4, length 0 drops the last 4 characters"),
+            ("snake_case", "Orig / case: UPPERCASE, lowercase, Title Case, snake_case or kebab-case (after the mode)"),
+            ("^IMG_(\\d+)_.*$ → $1", "Regex: replace matches in the original name; $1 or ${name} inserts a group (${1}_ when text follows)"),
+            (".jpg", "Extension: the original extension including the dot, as set under Extension (Keep, lower, UPPER, Strip, Replace with)"),
+            ("4.5MB", "Size: file size; auto picks B, KB, MB or GB (1KB = 1024 bytes)"),
+            ("1920x1080", "Dimensions: width x height of images; empty for other files"),
+            ("portrait / 16x9", "Shape: orientation (portrait, landscape, square) or aspect ratio of images; empty for other files"),
+            ("beach", "Tag: the text typed in the tag field next to each file (empty if none)"),
+            ("★★★ / r3", "Rating: star rating from XMP (embedded or .xmp sidecar) or EXIF; unrated = nothing / r0"),
+            ("a3f09c", "Image hash: perceptual hash (dHash) prefix; similar-looking images share it; empty for non-images"),
+            ("Canon EOS R5 / ISO200 / 35.681N_139.767E", "Camera: make, model, lens, ISO or GPS position (3 decimals) from EXIF; the \"if missing\" text otherwise"),
+            ("01 / Artist / Title / Album", "Audio tag: from ID3, Vorbis comments (FLAC, Ogg, Opus) or MP4; the \"if missing\" text otherwise"),
+            ("03m21s / 320kbps / 44.1kHz", "Audio: duration, bitrate or sample rate (WAV, MP3, FLAC, Ogg, Opus, M4A); empty for other files"),
+            ("if ext in jpg, png → … else:", "if: the block only for files with these extensions (or names matching a regex); the else text for the rest"),
+        ],
+    },
+    HelpSection {
+        title: "Date format (strftime)",
+        intro: "Specifiers for the Date fmt block. Anything else is copied as is.",
+        rows: &[
+            ("%Y", "year, 2024"),
+            ("%y", "year, 2 digits, 24"),
+            ("%m", "month, 01-12"),
+            ("%d", "day, 01-31"),
+            ("%H", "hour, 00-23"),
+            ("%M", "minute, 00-59"),
+            ("%S", "second, 00-60"),
+            ("%j", "day of year, 001-366"),
+            ("%a", "weekday, Mon"),
+            ("%A", "weekday, Monday"),
+            ("%b", "month name, Jan"),
+            ("%B", "month name, January"),
+            ("%%", "a literal %"),
+            ("%EY / %Ey", "Japanese era (locale 日本語): 令和6年 (元年 for the first year) / R6"),
+            ("%Y%m%d_%H%M%S", "20240105_130709"),
+        ],
+    },
+    HelpSection {
+        title: "Scope expressions",
+        intro: "Only files matching the expression are renamed. \
+            Fields: ext, name, size, type, rating, orientation, aspect. Text values are quoted.",
+        rows: &[
+            ("ext == \"jpg\"", "extension is jpg (case-insensitive)"),
+            ("size > 1MB && type == \"image\"", "images over 1 MiB (units B, KB, MB, GB)"),
+            ("name ~ \"^IMG_\\d+\"", "name matches a regex"),
+            ("rating >= 3", "rated 3 stars or more (unrated = 0)"),
+            ("orientation == \"portrait\"", "images taller than wide (portrait, landscape or square; aspect like \"16x9\")"),
+            ("!(ext == \"tmp\") || size < 10KB", "not, or, parentheses"),
+        ],
+    },
+    HelpSection {
+        title: "Keyboard shortcuts",
+        intro: "Cmd instead of Ctrl on macOS. Not active while typing in a text field.",
+        rows: &[
+            ("Ctrl+O", "Add files"),
+            ("Ctrl+V", "Add files copied in a file manager"),
+            ("Ctrl+Enter", "ReName (when the preview has no conflicts)"),
+            ("Ctrl+Shift+Enter", "Rename selected"),
+            ("Ctrl+Z", "Undo the last batch"),
+            ("Ctrl+Y / Ctrl+Shift+Z", "Redo the last undone batch"),
+            ("Delete", "Remove the selected files from the list (the focused one if it isn't selected)"),
+            ("Alt+↑ / Alt+↓", "Move the current file, or the whole selection when the current file is selected"),
+            ("Tab / Shift+Tab", "Move between controls; Enter or Space presses the focused one"),
+            ("F1", "Show or hide this help"),
+        ],
+    },
+    HelpSection {
+        title: "Regular expressions",
+        intro: "Used by \"Only names matching regex\" (Import options) and ~ in Scope.",
+        rows: &[
+            ("^IMG_", "starts with IMG_"),
+            ("\\.jpe?g$", "ends with .jpg or .jpeg"),
+            ("\\d{4}", "four digits"),
+            ("(?i)holiday", "case-insensitive"),
+            ("[A-Z]+", "one or more capital letters"),
+            ("cat|dog", "either cat or dog"),
+            (".*", "anything"),
+        ],
+    },
+];
+
+/// Pages of the first-run tour: (title, text).
+const TOUR: &[(&str, &str)] = &[
+    (
+        "1. Add files",
+        "Click \"Add files...\" or drop files and folders onto the window. \
+         Reorder them with ▲▼ or by dragging; the order decides the numbering.",
+    ),
+    (
+        "2. Build the name",
+        "On the right, combine blocks (Literal, Number, Date, Orig, Extension) into the new name, \
+         or choose a template under \"Load:\" and press Apply. Presets for common patterns are listed there.",
+    ),
+    (
+        "3. Check the preview",
+        "The preview shows every new name. Problems such as invalid characters or duplicate names are listed in red.",
+    ),
+    (
+        "4. Rename",
+        "Press ReName. If the result is not what you wanted, Undo puts the names back, and History keeps every batch.",
+    ),
+];
+
+/// Language of the UI labels.
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+enum Language {
+    #[default]
+    English,
+    Japanese,
+}
+
+impl Language {
+    const ALL: [Language; 2] = [Language::English, Language::Japanese];
+
+    fn label(self) -> &'static str {
+        match self {
+            Language::English => "English",
+            Language::Japanese => "日本語",
+        }
+    }
+}
+
+// read by `tr` on every label, set from the settings
+static UI_JAPANESE: AtomicBool = AtomicBool::new(false);
+
+fn set_ui_language(language: Language) {
+    UI_JAPANESE.store(language == Language::Japanese, Ordering::Relaxed);
+}
+
+/// `text` in the UI language. The English text is the key; text without an entry stays English.
+fn tr(text: &'static str) -> &'static str {
+    static TABLE: std::sync::OnceLock<HashMap<&str, &str>> = std::sync::OnceLock::new();
+    if !UI_JAPANESE.load(Ordering::Relaxed) {
+        return text;
+    }
+    TABLE.get_or_init(|| JA.iter().copied().collect()).get(text).copied().unwrap_or(text)
+}
+
+// English label → Japanese
+const JA: &[(&str, &str)] = &[
+    ("(1920x1080, images only)", "（1920x1080、画像のみ）"),
+    ("(no date in the first name)", "（1つ目の名前に日付がありません）"),
+    ("(same folder)", "（同じフォルダ）"),
+    ("(tag typed next to each file)", "（各ファイルの横に入力したタグ）"),
+    ("+ Rule", "+ ルール"),
+    (
+        "0 = any; ratings come from XMP (embedded or .xmp sidecar) or EXIF",
+        "0 = すべて。評価はXMP（埋め込みまたは.xmpサイドカー）かEXIFから読みます",
+    ),
+    ("0 = up to the other end", "0 = 反対側の端まで"),
+    ("A number is appended", "末尾に番号を追加します"),
+    ("Access denied", "アクセスが拒否されました"),
+    ("Add ASCII", "ASCIIを追加"),
+    ("Add Audio tag", "Audio tagを追加"),
+    ("Add Audio", "Audioを追加"),
+    ("Add Camera", "Cameraを追加"),
+    ("Add Cleanup", "Cleanupを追加"),
+    ("Add Date", "Dateを追加"),
+    ("Add Dimensions", "Dimensionsを追加"),
+    ("Add EXIF date", "EXIF dateを追加"),
+    ("Add Extension", "Extensionを追加"),
+    ("Add Image hash", "Image hashを追加"),
+    ("Add Literal", "Literalを追加"),
+    ("Add Name date", "Name dateを追加"),
+    ("Add Number", "Numberを追加"),
+    ("Add Orig slice", "Orig sliceを追加"),
+    ("Add Original", "Originalを追加"),
+    ("Add Rating", "Ratingを追加"),
+    ("Add Regex", "Regexを追加"),
+    ("Add Script", "Scriptを追加"),
+    ("Add Shape", "Shapeを追加"),
+    ("Add Size", "Sizeを追加"),
+    ("Add Tag", "Tagを追加"),
+    ("Add files to preview this template.", "ファイルを追加すると、このテンプレートのプレビューを表示します。"),
+    ("Add files...", "ファイルを追加..."),
+    ("Add folder...", "フォルダを追加..."),
+    ("Add folders as entries", "フォルダ自体を項目として追加"),
+    ("Add templates from a shared file", "共有されたファイルからテンプレートを追加"),
+    (
+        "Add the files in a folder, using Subfolders and Only names matching from Import options",
+        "フォルダ内のファイルを追加します（Import optionsのSubfoldersと名前の条件を使用）",
+    ),
+    (
+        "Added or dropped folders are renamed themselves instead of adding the files inside",
+        "追加・ドロップしたフォルダの中のファイルではなく、フォルダ自体の名前を変更します",
+    ),
+    ("All", "すべて"),
+    ("Allow multiple windows", "複数のウィンドウを許可"),
+    ("Also add files in subfolders of added folders", "追加したフォルダのサブフォルダ内のファイルも追加"),
+    ("Another file of the batch has this name", "一括変更する別のファイルがこの名前になります"),
+    (
+        "Answer the administrator prompt, or cancel if it was dismissed.",
+        "管理者権限の確認に応答してください。閉じてしまった場合はキャンセルします。",
+    ),
+    ("Applied to every new name after the blocks", "ブロックの後にすべての新しい名前に適用"),
+    (
+        "Apply this block only to files with some extensions or names",
+        "このブロックを特定の拡張子・名前のファイルにだけ適用します",
+    ),
+    ("Apply to all remaining conflicts", "残りの衝突すべてに適用"),
+    ("Apply", "適用"),
+    ("Ask again at the next start", "次回の起動時にもう一度確認"),
+    ("Back to the name from the template", "テンプレートによる名前に戻す"),
+    ("Back", "戻る"),
+    ("Cancel", "キャンセル"),
+    ("Cancelling, putting the files back...", "キャンセル中、ファイルを元に戻しています..."),
+    ("Capture date (mtime when missing); off = modification time", "撮影日時（無ければ更新日時）。オフ = 更新日時"),
+    ("Capture date; files without one use the modified time", "撮影日時。無いファイルは更新日時を使います"),
+    ("Case of the original name", "元の名前の大文字・小文字"),
+    ("Case sensitive", "大文字・小文字を区別"),
+    ("Character filling the number up to the min digits", "桁数に満たない部分を埋める文字"),
+    ("Characters to remove", "削除する文字"),
+    ("Check for updates at startup", "起動時に更新を確認"),
+    ("Check for updates", "更新を確認"),
+    (
+        "Check the listed files again and mark the ones changed outside the app",
+        "リストのファイルを確認し直し、アプリの外で変更されたものに印を付けます",
+    ),
+    ("Checksums:", "チェックサム:"),
+    ("Choose folder...", "フォルダを選択..."),
+    ("Choose two saved templates.", "保存済みのテンプレートを2つ選んでください。"),
+    ("Clean list", "リストを整理"),
+    ("Clear files", "ファイルをクリア"),
+    ("Clear", "クリア"),
+    ("Click to type a name for this file", "クリックしてこのファイルの名前を入力"),
+    ("Close", "閉じる"),
+    ("Collision strategy:", "衝突時の処理:"),
+    ("Columns", "列"),
+    ("Common formats", "よく使う形式"),
+    ("Compare templates", "テンプレートの比較"),
+    ("Compare...", "比較..."),
+    ("Complete", "完了させる"),
+    ("Confirm rename", "リネームの確認"),
+    ("Copied", "コピー"),
+    ("Copies keep the modified, access and creation times of the originals", "コピーに元のファイルの更新日時・アクセス日時・作成日時を引き継ぎます"),
+    ("Copy details", "詳細をコピー"),
+    ("Copy failed", "コピー失敗"),
+    ("Copy finished", "コピー完了"),
+    ("Copy the entries shown", "表示中の項目をコピー"),
+    (
+        "Copy the files (the selected ones, if any) to a temp folder and rename the copies",
+        "ファイル（選択があれば選択中のもの）を一時フォルダにコピーし、コピーをリネームします",
+    ),
+    ("Copy with new names", "新しい名前でコピー"),
+    (
+        "Count from init again for each value of the key, e.g. per shooting day",
+        "キーの値ごとに初期値から数え直します（例: 撮影日ごと）",
+    ),
+    ("Count start and length back from the end of the name", "開始位置と長さを名前の末尾から数える"),
+    (
+        "Ctrl+click / Shift+click file names to select. Ctrl+Shift+Enter",
+        "Ctrl+クリック / Shift+クリックでファイル名を選択。Ctrl+Shift+Enter",
+    ),
+    ("Date in file name", "ファイル名の日付"),
+    ("Delete copies", "コピーを削除"),
+    ("Delete source when identical (cannot be undone)", "内容が同じなら元のファイルを削除（元に戻せません）"),
+    ("Delete", "削除"),
+    ("Depth", "深さ"),
+    ("Detect by content", "内容で判定"),
+    ("Dimensions", "画素数"),
+    ("Discard", "破棄"),
+    ("Done", "完了"),
+    ("Double-click to rename", "ダブルクリックで名前を変更"),
+    ("Drop files to add", "ドロップしてファイルを追加"),
+    ("Duplicate", "複製"),
+    ("Duplicates", "重複ファイル"),
+    ("EXIF date", "EXIF日時"),
+    (
+        "Each batch adds its old and new names to a log file in the folders",
+        "実行のたびに、変更前・変更後の名前をフォルダ内のログファイルに追記します",
+    ),
+    (
+        "Each file's modification or creation time, or the time of the rename",
+        "各ファイルの更新日時・作成日時、またはリネームした日時",
+    ),
+    ("Enable local API", "ローカルAPIを有効にする"),
+    ("Errors", "エラー"),
+    ("Exclude duplicates", "重複を除外"),
+    ("Excluded files stay in the list but keep their names", "除外したファイルはリストに残り、名前は変わりません"),
+    ("Export all...", "すべて書き出し..."),
+    ("Export preview...", "プレビューを書き出し..."),
+    ("Export revert script...", "元に戻すスクリプトを書き出し..."),
+    ("Export...", "書き出し..."),
+    ("Extension:", "拡張子:"),
+    ("Failed", "失敗"),
+    ("Files (select then move)", "ファイル（選択して移動）"),
+    (
+        "Files replaced by Overwrite or deleted by Dedup can be restored",
+        "Overwriteで置き換えたファイルやDedupで削除したファイルをゴミ箱から戻せます",
+    ),
+    (
+        "Files with one name and different extensions (RAW+JPEG, video+.srt, .xmp) are renamed together, one number",
+        "名前が同じで拡張子だけ違うファイル（RAW+JPEG、動画+.srt、.xmp）をまとめてリネームし、連番を1つだけ使います",
+    ),
+    (
+        "Files with a date in their name; the others keep their mtime",
+        "名前に日付があるファイルの数。それ以外のファイルの更新日時は変わりません",
+    ),
+    ("Find duplicates", "重複を検索"),
+    ("Find:", "検索:"),
+    ("Folder tree", "フォルダツリー"),
+    ("Folder tree...", "フォルダツリー..."),
+    ("Gallery...", "ギャラリー..."),
+    ("Give every file its new name", "すべてのファイルを新しい名前にする"),
+    ("Give every file its old name back", "すべてのファイルを元の名前に戻す"),
+    (
+        "Hash the listed files and group the ones with identical content",
+        "リストのファイルのハッシュを計算し、内容が同じものをまとめます",
+    ),
+    ("Hashing files...", "ハッシュを計算中..."),
+    ("Help (F1)", "ヘルプ (F1)"),
+    ("Help", "ヘルプ"),
+    ("Hepburn, e.g. しゃしん → shashin", "ヘボン式（例: しゃしん → shashin）"),
+    ("History", "履歴"),
+    ("How many levels of subfolders to descend into (0 = all)", "サブフォルダをたどる階層数（0 = すべて）"),
+    ("I understand the replaced files will be lost", "置き換えられるファイルが失われることを理解しました"),
+    (
+        "If the target or a numbered copy has the same content: skipped; otherwise numbered",
+        "変更後の名前（または番号付きの名前）に内容が同じファイルがある場合はスキップ、無ければ連番を付けます",
+    ),
+    ("Images at least", "画像の最小サイズ"),
+    ("Import mapping...", "対応表を読み込み..."),
+    ("Import options", "読み込みオプション"),
+    ("Import plan...", "プランを読み込み..."),
+    ("Import...", "読み込み..."),
+    ("Install", "インストール"),
+    ("Interrupted rename", "中断されたリネーム"),
+    ("Keep files in their folders", "ファイルを元のフォルダに置く"),
+    ("Keep sidecars together", "サイドカーをまとめる"),
+    ("Keep them in the list but never rename them", "リストには残しますがリネームしません"),
+    ("Keep timestamps:", "タイムスタンプを保持:"),
+    ("Later", "後で"),
+    ("Load its files and template", "ファイルとテンプレートを読み込む"),
+    ("Load session...", "セッションを読み込み..."),
+    ("Load:", "読み込み:"),
+    ("Log in each folder", "フォルダごとのログ"),
+    ("Manage...", "管理..."),
+    ("Modified since added", "追加後に変更されました"),
+    ("Modified", "更新日時"),
+    ("Move replaced files to the trash", "置き換えるファイルをゴミ箱へ移動"),
+    ("Move to:", "移動先:"),
+    ("Moved, renamed or deleted outside BulkReName", "BulkReNameの外で移動・リネーム・削除されました"),
+    ("Name conflict", "名前の衝突"),
+    ("Name:", "名前:"),
+    (
+        "New files in the folder are renamed with the template once they stop growing.",
+        "フォルダに追加されたファイルを、サイズの変化が止まったらテンプレートでリネームします。",
+    ),
+    ("New name:", "新しい名前:"),
+    ("New", "新しい名前"),
+    ("Next", "次へ"),
+    ("No duplicates found.", "重複は見つかりませんでした。"),
+    ("No folder chosen.", "フォルダが選ばれていません。"),
+    ("No history yet.", "履歴はまだありません。"),
+    ("No saved templates.", "保存済みのテンプレートはありません。"),
+    ("Number near-duplicates", "似た名前に連番"),
+    (
+        "Number the selection from the start value instead of by list position",
+        "リスト上の位置ではなく開始値から選択範囲に番号を振る",
+    ),
+    ("Numbered", "番号付き"),
+    ("Off", "オフ"),
+    (
+        "Off: launching again adds files to this window (after a restart)",
+        "オフ：もう一度起動するとこのウィンドウにファイルを追加します（再起動後に有効）",
+    ),
+    ("Off: only the first occurrence", "オフ: 最初の1か所のみ"),
+    ("Old", "元の名前"),
+    ("Only names matching regex:", "正規表現に一致する名前のみ:"),
+    ("Only names matching:", "一致する名前のみ:"),
+    ("Open download page", "ダウンロードページを開く"),
+    ("Open folder", "フォルダを開く"),
+    ("Original", "元の名前"),
+    ("Overwrite template?", "テンプレートを上書きしますか？"),
+    ("Overwrite", "上書き"),
+    ("Pattern:", "パターン:"),
+    (
+        "Plain text, applied to the original name before the blocks",
+        "通常の文字列として、ブロックより前に元のファイル名へ適用します",
+    ),
+    ("Port", "ポート"),
+    ("Portable names", "どのOSでも使える名前"),
+    ("Preserve timestamps", "タイムスタンプを保持"),
+    ("Presets can't be deleted", "プリセットは削除できません"),
+    ("Presets", "プリセット"),
+    ("Preview", "プレビュー"),
+    (
+        "Put in for characters without an ASCII form (e.g. kanji)",
+        "ASCIIに変換できない文字（漢字など）の代わりに入れる文字",
+    ),
+    ("Quick:", "クイック:"),
+    ("Rated at least:", "評価の下限:"),
+    (
+        "Read the first bytes of each file instead of trusting the extension",
+        "拡張子ではなくファイルの先頭のバイトで種類を判定",
+    ),
+    ("Redo", "やり直し"),
+    ("Refresh", "更新"),
+    ("Reload", "再読み込み"),
+    ("Remove [..], (..), {..}, 【..】 and （..） segments", "[..]、(..)、{..}、【..】、（..）の部分を削除"),
+    ("Remove from the list (files stay on disk)", "リストから外す（ファイルは削除されません）"),
+    (
+        "Remove missing files and flag files changed since they were added",
+        "存在しないファイルを外し、追加後に変更されたファイルに印を付けます",
+    ),
+    ("Rename a folder tree with rules per depth", "深さごとのルールでフォルダツリーをリネーム"),
+    ("Rename failed", "リネーム失敗"),
+    ("Rename finished", "リネーム完了"),
+    ("Rename list files by a CSV of old_name,new_name rows", "old_name,new_name のCSVでリストのファイルをリネーム"),
+    ("Rename manually", "名前を入力"),
+    ("Rename tree", "ツリーをリネーム"),
+    ("Rename", "リネーム"),
+    ("Rename, duplicate, reorder or delete", "名前の変更・複製・並べ替え・削除"),
+    ("Renamed", "リネーム"),
+    ("Repeated spaces or _ → one", "連続したスペースや_を1つに"),
+    ("Replace with:", "置換後:"),
+    ("Rescan", "再スキャン"),
+    ("Reset counter", "カウンターをリセット"),
+    ("Restore last session?", "前回のセッションを復元しますか？"),
+    ("Restore", "復元"),
+    ("Retries:", "再試行:"),
+    ("Retry as administrator", "管理者として再試行"),
+    (
+        "Retry failed renames with growing delays (useful on network shares)",
+        "失敗したリネームを間隔を延ばしながら再試行（ネットワーク共有で有効）",
+    ),
+    ("Retry this batch with administrator rights?", "管理者権限でこのバッチを再試行しますか？"),
+    ("Revert", "取り消す"),
+    ("Roll back", "ロールバック"),
+    (
+        "Rules (depth 1 = entries directly in the folder; the first matching rule wins):",
+        "ルール（深さ1 = フォルダ直下の項目。最初に一致したルールを使用）:",
+    ),
+    ("Save / Load Template", "テンプレートの保存・読み込み"),
+    ("Save an editable copy of this preset", "このプリセットを編集できるコピーとして保存"),
+    (
+        "Save every old → new name, with collision handling and skip reasons, as CSV or JSON",
+        "すべての変更前→変更後の名前を、衝突処理とスキップ理由付きでCSVまたはJSONに保存",
+    ),
+    ("Save log...", "ログを保存..."),
+    ("Save session...", "セッションを保存..."),
+    ("Save the entries shown as text", "表示中の項目をテキストで保存"),
+    ("Save the file list and the template", "ファイルリストとテンプレートを保存"),
+    ("Save the template chosen in Load as a file to share", "読み込みで選んだテンプレートを共有用のファイルに保存"),
+    (
+        "Save the template under this name to keep the counter after a restart",
+        "再起動後もカウンターを続けるには、この名前でテンプレートを保存してください",
+    ),
+    ("Save", "保存"),
+    ("Scope:", "対象:"),
+    ("Search", "検索"),
+    ("Select duplicates", "重複を選択"),
+    ("Set mtime from:", "更新日時の設定元:"),
+    ("Set now", "今すぐ設定"),
+    ("Set the modification times without renaming", "リネームせずに更新日時を設定"),
+    ("Show failed files", "失敗したファイルを表示"),
+    ("Show skipped files", "スキップしたファイルを表示"),
+    ("Show tour", "ツアーを表示"),
+    ("Size", "サイズ"),
+    ("Skip", "スキップ"),
+    ("Skipped files", "スキップしたファイル"),
+    ("Skipped", "スキップ"),
+    (
+        "Sort the preview (list order and numbering stay)",
+        "プレビューを並べ替えます（リストの順番と番号は変わりません）",
+    ),
+    ("Sort: ", "並べ替え: "),
+    ("Spaces and _ at both ends", "前後のスペースと_"),
+    (
+        "Start the next batch after the last number used; kept with the template",
+        "次のバッチは前回使った番号の続きから数えます（テンプレートに保存されます）",
+    ),
+    ("Start watching when the app starts", "アプリの起動時に監視を開始"),
+    ("Start", "開始"),
+    ("Status", "状態"),
+    ("Stop", "停止"),
+    ("Subfolders by date:", "日付のサブフォルダ:"),
+    ("Subfolders", "サブフォルダ"),
+    ("Suffix", "番号を付ける"),
+    ("Tag duplicates:", "重複にタグ付け:"),
+    ("Target filesystem:", "変更先のファイルシステム:"),
+    ("Template Blocks", "テンプレートのブロック"),
+    ("Template gallery", "テンプレートギャラリー"),
+    ("Template:", "テンプレート:"),
+    ("Templates", "テンプレート"),
+    ("Test on copies", "コピーで試す"),
+    ("The existing file is replaced (cannot be undone)", "既にあるファイルを上書きします（元に戻せません）"),
+    (
+        "The first capture group (or the whole match) is the key",
+        "最初のキャプチャグループ（無ければ一致した部分全体）がキーになります",
+    ),
+    (
+        "The first file of each group is kept; the others count as duplicates.",
+        "各グループの最初のファイルを残し、それ以外を重複として扱います。",
+    ),
+    ("Theme", "テーマ"),
+    ("This file keeps its name", "このファイルの名前は変更しません"),
+    ("Time zone the date and time are written in", "日時を書き出すタイムゾーン"),
+    ("Time", "時間"),
+    (
+        "Times copies and files moved to another drive take over; All adds access and creation time (Windows, macOS)",
+        "コピーや別のドライブへ移動したファイルに引き継ぐ日時。Allではアクセス日時と作成日時（Windows・macOS）も引き継ぎます",
+    ),
+    ("Trailing dot/space:", "末尾のドット・スペース:"),
+    ("Type", "種類"),
+    ("Types:", "種類:"),
+    ("Unchanged", "変更なし"),
+    ("Undo", "元に戻す"),
+    ("Unknown % specifier (see Help → Date format)", "不明な%指定子です（ヘルプ → Date format を参照）"),
+    ("Update available", "更新があります"),
+    ("Waiting for administrator rights", "管理者権限を待っています"),
+    ("Warnings and errors", "警告とエラー"),
+    ("Watch folder", "フォルダの監視"),
+    ("Watch folder...", "フォルダを監視..."),
+    ("Welcome to BulkReName", "BulkReNameへようこそ"),
+    (
+        "Where the date is in the name: %Y %y %m %d %H %M %S; other text must match as typed",
+        "名前の中の日付の書き方：%Y %y %m %d %H %M %S。それ以外の文字はそのまま一致する必要があります",
+    ),
+    (
+        "Wildcards separated by ';' (* = anything, ? = one character), case-insensitive",
+        "';'で区切ったワイルドカード（* = 任意の文字列、? = 任意の1文字）。大文字・小文字は区別しません",
+    ),
+    (
+        "Write SHA-256 checksums of the renamed files after each rename",
+        "リネームのたびにリネームしたファイルのSHA-256チェックサムを書き出す",
+    ),
+    (
+        "Write this text into their tag, for a Tag block in the template",
+        "テンプレートのTagブロック用に、このテキストをタグに書き込みます",
+    ),
+    ("all files", "全ファイル通し"),
+    ("backoff (ms):", "待ち時間 (ms):"),
+    ("brackets", "括弧を削除"),
+    ("case sensitive", "大文字小文字を区別"),
+    ("collapse", "連続をまとめる"),
+    ("continue", "続きから"),
+    ("else:", "それ以外:"),
+    ("error", "エラー"),
+    ("identical file exists", "同じ内容のファイルあり"),
+    ("identical file exists, source deleted", "同じ内容のファイルあり、元ファイルを削除"),
+    ("info", "情報"),
+    ("kana → romaji", "かな → ローマ字"),
+    ("missing", "見つかりません"),
+    ("name taken", "名前が使用済み"),
+    (
+        NAME_DATE_HELP,
+        "元の名前の日付の並び。例：IMG_%Y%m%d（%Y %y %m %d %H %M %S）。\
+         空欄ならUNIX時間・20240305・2024-03-05などを自動で探します",
+    ),
+    ("next:", "次:"),
+    ("not saved", "未保存"),
+    ("ok", "OK"),
+    ("other:", "その他:"),
+    ("out of scope", "対象外"),
+    ("per folder", "フォルダごと"),
+    ("per regex", "正規表現ごと"),
+    ("per", "ごと:"),
+    (
+        "portrait, landscape, square or an aspect ratio like 16x9",
+        "portrait・landscape・square、または16x9のような縦横比",
+    ),
+    ("replace all occurrences", "すべて置換"),
+    (
+        SCRIPT_HELP,
+        "rhaiのスクリプト。最後の行の値を挿入します。変数：name, ext, index, size, modified, created, tag, rating, \
+         width, height。let・if/else・for・loopのほか、name.to_upper(), name.to_lower(), name.sub_string(開始, 長さ), \
+         regex_replace(s, 正規表現, 置換) が使えます。+ で文字列をつなげます",
+    ),
+    ("excluded", "除外"),
+    ("from end", "末尾から"),
+    ("if missing:", "無い場合:"),
+    ("invalid", "無効"),
+    ("length:", "長さ:"),
+    ("levels", "階層"),
+    ("min digits:", "最小桁数:"),
+    ("pattern:", "パターン:"),
+    ("range:", "範囲:"),
+    ("restart numbering", "番号を振り直す"),
+    ("show thumbnail", "サムネイルを表示"),
+    ("strftime pattern; \"/\" makes nested folders. Empty = off", "strftimeの書式。\"/\"で階層になります。空 = オフ"),
+    ("strip:", "削除する文字:"),
+    ("trim", "前後を削除"),
+    ("unchanged", "変更なし"),
+    ("warning", "警告"),
+    ("⚠ missing", "⚠ 見つかりません"),
+    ("✎ edited", "✎ 編集済み"),
+    ("Clear cache", "キャッシュを削除"),
+    ("Copy token", "トークンをコピー"),
+    ("Copy", "コピー"),
+    ("Remove", "外す"),
+    ("Select all", "すべて選択"),
+    ("To bottom", "末尾へ"),
+    ("To top", "先頭へ"),
+    ("Language", "言語"),
+    ("Settings", "設定"),
+    ("Settings...", "設定..."),
+    ("UI scale", "表示倍率"),
+    ("Thumbnail width", "サムネイルの幅"),
+    ("Collision at startup", "起動時の衝突処理"),
+    ("Adds \"Rename with BulkReName\" to the right-click menu", "右クリックメニューに\"Rename with BulkReName\"を追加"),
+    ("Add to Explorer menu", "エクスプローラーのメニューに追加"),
+    ("Remove from Explorer menu", "エクスプローラーのメニューから削除"),
+    ("Dark", "ダーク"),
+    ("Light", "ライト"),
+    ("High contrast", "ハイコントラスト"),
+];
+
+/// Visual preset.
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+enum Theme {
+    #[default]
+    Default,
+    Light,
+    HighContrast,
+}
+
+/// Colours for status text, chosen per theme.
+struct Palette {
+    // new names in the preview / differing names
+    accent: egui::Color32,
+    // out of scope, unchanged
+    dim: egui::Color32,
+    warn: egui::Color32,
+    error: egui::Color32,
+    added: egui::Color32,
+}
+
+impl Theme {
+    const ALL: [Theme; 3] = [Theme::Default, Theme::Light, Theme::HighContrast];
+
+    fn label(self) -> &'static str {
+        match self {
+            Theme::Default => "Dark",
+            Theme::Light => "Light",
+            Theme::HighContrast => "High contrast",
+        }
+    }
+
+    fn palette(self) -> Palette {
+        use egui::Color32;
+        match self {
+            Theme::Default => Palette {
+                accent: Color32::BLUE,
+                dim: Color32::GRAY,
+                warn: Color32::YELLOW,
+                error: Color32::RED,
+                added: Color32::GREEN,
+            },
+            Theme::Light => Palette {
+                accent: Color32::from_rgb(0, 70, 200),
+                dim: Color32::GRAY,
+                warn: Color32::from_rgb(170, 110, 0),
+                error: Color32::from_rgb(200, 0, 0),
+                added: Color32::from_rgb(0, 130, 0),
+            },
+            Theme::HighContrast => Palette {
+                accent: Color32::from_rgb(0, 255, 255),
+                dim: Color32::from_gray(200),
+                warn: Color32::YELLOW,
+                error: Color32::from_rgb(255, 110, 110),
+                added: Color32::from_rgb(120, 255, 120),
+            },
+        }
+    }
+
+    /// White on black, thick outlines, bigger text and hit targets.
+    fn high_contrast_style(base: &egui::Style) -> egui::Style {
+        use egui::{Color32, Stroke};
+        let mut style = base.clone();
+        let mut v = egui::Visuals::dark();
+        v.override_text_color = Some(Color32::WHITE);
+        v.panel_fill = Color32::BLACK;
+        v.window_fill = Color32::BLACK;
+        v.extreme_bg_color = Color32::BLACK;
+        v.faint_bg_color = Color32::from_gray(30);
+        v.window_stroke = Stroke::new(2.0, Color32::WHITE);
+        v.hyperlink_color = Color32::from_rgb(0, 255, 255);
+        v.selection.bg_fill = Color32::from_rgb(0, 90, 160);
+        v.selection.stroke = Stroke::new(2.0, Color32::WHITE);
+        for w in [
+            &mut v.widgets.noninteractive,
+            &mut v.widgets.inactive,
+            &mut v.widgets.hovered,
+            &mut v.widgets.active,
+            &mut v.widgets.open,
+        ] {
+            w.bg_stroke = Stroke::new(2.0, Color32::WHITE);
+            w.fg_stroke = Stroke::new(2.0, Color32::WHITE);
+        }
+        v.widgets.noninteractive.bg_stroke = Stroke::new(1.0, Color32::from_gray(160));
+        v.widgets.inactive.bg_fill = Color32::BLACK;
+        v.widgets.inactive.weak_bg_fill = Color32::BLACK;
+        v.widgets.hovered.bg_fill = Color32::from_gray(60);
+        v.widgets.hovered.weak_bg_fill = Color32::from_gray(60);
+        v.widgets.hovered.bg_stroke = Stroke::new(3.0, Color32::YELLOW);
+        v.widgets.active.bg_stroke = Stroke::new(3.0, Color32::YELLOW);
+        style.visuals = v;
+        for font in style.text_styles.values_mut() {
+            font.size *= 1.25;
+        }
+        style.spacing.interact_size = egui::vec2(48.0, 30.0);
+        style.spacing.button_padding = egui::vec2(10.0, 6.0);
+        style.spacing.item_spacing = egui::vec2(10.0, 6.0);
+        style.spacing.icon_width = 20.0;
+        style
+    }
+}
+
+#[derive(PartialEq)]
+enum LoadingPhase {
+    None,
+    AddingFiles,
+    LoadingThumbs,
+}
+
+//sort
+#[derive(PartialEq, Copy, Clone)]
+enum SortKey {
+    Name,
+    Modified,
+    Created,
+    Size,
+    // EXIF capture date, else mtime
+    ExifDate,
+    // extension, then name
+    Type,
+    // pixel count; files that are not images first
+    Dimensions,
+}
+
+/// Optional metadata columns of the file list.
+#[derive(PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+enum ListColumn {
+    Size,
+    Modified,
+    Type,
+    Dimensions,
+}
+
+impl ListColumn {
+    const ALL: [ListColumn; 4] = [ListColumn::Size, ListColumn::Modified, ListColumn::Type, ListColumn::Dimensions];
+
+    fn label(self) -> &'static str {
+        match self {
+            ListColumn::Size => "Size",
+            ListColumn::Modified => "Modified",
+            ListColumn::Type => "Type",
+            ListColumn::Dimensions => "Dimensions",
+        }
+    }
+
+    fn width(self) -> f32 {
+        match self {
+            ListColumn::Size => 60.0,
+            ListColumn::Modified => 110.0,
+            ListColumn::Type => 50.0,
+            ListColumn::Dimensions => 80.0,
+        }
+    }
+
+    fn sort_key(self) -> SortKey {
+        match self {
+            ListColumn::Size => SortKey::Size,
+            ListColumn::Modified => SortKey::Modified,
+            ListColumn::Type => SortKey::Type,
+            ListColumn::Dimensions => SortKey::Dimensions,
+        }
+    }
+
+    fn value(self, fe: &FileEntry) -> String {
+        let folder = fe.category == FileCategory::Folder;
+        match self {
+            ListColumn::Size if folder => String::new(),
+            ListColumn::Size => fe.size().map(|s| SizeUnit::Auto.format(s)).unwrap_or_default(),
+            ListColumn::Modified => fe.modified().map(|d| d.format("%Y-%m-%d %H:%M").to_string()).unwrap_or_default(),
+            ListColumn::Type if folder => "folder".to_string(),
+            ListColumn::Type => fe.extension(),
+            // only images are opened for their header
+            ListColumn::Dimensions if fe.category != FileCategory::Image => String::new(),
+            ListColumn::Dimensions => fe.dimensions().map(|(w, h)| format!("{}×{}", w, h)).unwrap_or_default(),
+        }
+    }
+}
+#[derive(PartialEq, Copy, Clone)]
+enum SortOrder {
+    Asc,
+    Desc,
+}
+
+struct BulkRename {
+    files: Vec<FileEntry>,
+    selected_idx: Option<usize>,
+    blocks: Vec<Block>,
+    collision: CollisionStrategy,
+    use_mtime_for_date: bool,
+    // set each renamed file's mtime to the value of this Date block
+    mtime_block: Option<usize>,
+    // date pattern read from the original names instead (wins over mtime_block)
+    mtime_pattern: Option<String>,
+    retry: RetryPolicy,
+    fs_profile: FsProfile,
+    trailing_fix: TrailingFix,
+    ext_policy: ExtPolicy,
+    // renamed files go here instead of staying in their folders
+    move_to: Option<PathBuf>,
+    // write renamed copies and leave the originals alone
+    copy_mode: bool,
+    // strftime pattern for date subfolders ("%Y/%m"); empty = off
+    subfolder_pattern: String,
+    // take the subfolder date from EXIF (mtime when missing) instead of the mtime
+    subfolder_exif: bool,
+    dedup_delete_source: bool,
+    restart_numbering_in_selection: bool,
+    // files sharing a stem (RAW+JPEG, video+.srt) are renamed as one, see `sidecar_groups`
+    keep_sidecars: bool,
+    // only files matching this expression are renamed
+    scope_expr: String,
+    // folder → detected profile, refreshed when the loaded folders change
+    detected_fs: HashMap<PathBuf, FsProfile>,
+    last_actions: Vec<LastBatch>,
+    // batches taken back by Undo, newest last
+    redo_actions: Vec<LastBatch>,
+    log: Log,
+    dragging_idx: Option<usize>,
+    hidden_categories: HashSet<FileCategory>,
+    // thumbnail cache: key = path → state
+    thumbnails: HashMap<String, ThumbnailState>,
+    thumb_max_size: (usize, usize),
+    // decode jobs for the thumbnail workers, and their results
+    thumb_tx: Option<Sender<ThumbJob>>,
+    thumb_rx: Option<Receiver<(String, ThumbResult)>>,
+    show_thumbnails: bool,
+    // metadata columns shown in the file list, in ListColumn::ALL order
+    list_columns: Vec<ListColumn>,
+    // share of the width taken by the file list
+    column_split: f32,
+    theme: Theme,
+    show_help: bool,
+    show_settings: bool,
+    // Explorer context-menu entry present (Windows; checked when Help opens)
+    shell_menu: Option<bool>,
+    // guided tour page, None when closed
+    tour_step: Option<usize>,
+    // style before switching away from the default theme
+    base_style: Option<std::sync::Arc<egui::Style>>,
+    // persistence
+    saved_templates: Vec<Template>,
+    current_template_name: String,
+    // last session found at startup, waiting for Restore / Discard
+    session_offer: Option<Session>,
+    // what session.json holds now, and when to autosave next (egui time)
+    session_json: String,
+    session_due: f64,
+    //loading
+    loading_phase: LoadingPhase,
+    loader_rx: Option<Receiver<PathBuf>>,
+    loading_count: usize,
+    import_filter: ImportFilter,
+    //watch
+    watcher: Option<notify::RecommendedWatcher>,
+    watch_rx: Option<Receiver<notify::Result<notify::Event>>>,
+    watched_dirs: HashSet<PathBuf>,
+    // loaded folders changed (watches / filesystem detection need a refresh)
+    dirs_dirty: bool,
+    // Alt+↑/↓ presses waiting to move the current file (negative = up)
+    key_move: i32,
+    // row whose name should take keyboard focus next frame
+    focus_row: Option<usize>,
+    // scroll the file list to this row
+    reveal_row: Option<usize>,
+    //sort
+    sort_key: Option<SortKey>,
+    sort_order: SortOrder,
+    //error
+    show_delete_error: bool,
+    // template waiting for overwrite confirmation
+    pending_overwrite: Vec<Template>,
+    // history browser (batches from history.jsonl, oldest first)
+    show_history: bool,
+    history: Vec<Vec<HistoryRecord>>,
+    settings: Settings,
+    // update check: running, result to show
+    update_rx: Option<Receiver<Result<Option<ReleaseInfo>, String>>>,
+    update_available: Option<ReleaseInfo>,
+    // template gallery window
+    show_gallery: bool,
+    gallery: Vec<GalleryItem>,
+    gallery_pick: usize,
+    gallery_rx: Option<Receiver<Result<Vec<GalleryItem>, String>>>,
+    // template manager window; the template being renamed and its new name
+    show_templates: bool,
+    template_rename: Option<(usize, String)>,
+    // duplicates window: groups of identical files (the first of each is kept) and the tag for the others
+    show_duplicates: bool,
+    dup_groups: Vec<Vec<PathBuf>>,
+    dup_rx: Option<Receiver<Vec<Vec<PathBuf>>>>,
+    dup_tag: String,
+    // watch folder window, the running watch and what it did
+    show_watch: bool,
+    watch_folder: Option<WatchFolder>,
+    watch_log: Vec<String>,
+    // template comparison window
+    show_compare: bool,
+    compare_a: String,
+    compare_b: String,
+    // critical failure shown in a modal dialog
+    error_report: Option<ErrorReport>,
+    // number names colliding only by case / Unicode normalization
+    deconflict: bool,
+    // make every new name valid on Windows, whatever the OS
+    sanitize: bool,
+    // quick-action toolbar
+    quick: QuickActions,
+    // plain find/replace on the original names, before the blocks
+    find_replace: FindReplace,
+    // preview shown in its own viewport
+    preview_detached: bool,
+    // preview table column and direction (true = ascending)
+    preview_sort: Option<(PreviewSort, bool)>,
+    // preview row being edited by hand, with the text so far
+    editing_name: Option<(usize, String)>,
+    // the last preview table and the `preview_key` it was built for
+    preview_cache: std::cell::RefCell<Option<(u64, std::rc::Rc<PreviewTable>)>>,
+    // perceptual hashes for the image hash block (None = not an image)
+    phashes: HashMap<PathBuf, Option<u64>>,
+    phash_rx: Option<Receiver<(PathBuf, Option<u64>)>>,
+    // checksum records after a rename
+    manifest: Manifest,
+    manifest_rx: Option<Receiver<Result<usize, Vec<String>>>>,
+    // folder tree dialog
+    show_tree: bool,
+    tree_root: Option<PathBuf>,
+    tree_rules: Vec<DepthRule>,
+    tree_entries: Vec<TreeEntry>,
+    // folder tree rename waiting for its current depth to finish
+    tree_run: Option<TreeRun>,
+    // "Test on copies" result window
+    sandbox: Option<Sandbox>,
+    // risky batch waiting for confirmation
+    pending_batch: Option<PendingBatch>,
+    // summary of the last batch, shown until closed
+    batch_summary: Option<BatchSummary>,
+    // batch paused by the Ask collision strategy, and the answers it is submitted with
+    collision_prompt: Option<CollisionPrompt>,
+    collision_answers: HashMap<PathBuf, CollisionStrategy>,
+    // why the name typed for a conflict was refused
+    collision_error: Option<String>,
+    // batch renaming on a worker thread
+    running: Option<RunningBatch>,
+    // background look for stale entries, see `check_stale`
+    stale_rx: Option<Receiver<Vec<FileState>>>,
+    stale_checked: Instant,
+    confirm_ack: bool,
+    // batch that failed with "access denied", offered for elevated retry
+    elevation_offer: Option<Vec<(PathBuf, PathBuf)>>,
+    // rename journals left by a batch that didn't finish (crash, power loss)
+    interrupted: Vec<(PathBuf, RenameJournal)>,
+    pending_elevation: Option<PendingElevation>,
+    //automation api
+    api_enabled: bool,
+    api_port: u16,
+    api_token: String,
+    api_rx: Option<Receiver<ApiRequest>>,
+    api_stop: Option<Arc<AtomicBool>>,
+    // reply of an /execute call and where its log messages start, sent once the batch is done
+    api_waiting: Option<(mpsc::Sender<(u16, String)>, usize)>,
+}
+
+impl Default for BulkRename {
+    fn default() -> Self {
+        Self {
+            files: Vec::new(),
+            selected_idx: None,
+            blocks: vec![
+                Block::Original { mode: OriginalMode::Keep, case: NameCase::Keep },
+                Block::Extension,
+            ],
+            collision: CollisionStrategy::Suffix,
+            use_mtime_for_date: true,
+            mtime_block: None,
+            mtime_pattern: None,
+            retry: RetryPolicy::default(),
+            fs_profile: FsProfile::Off,
+            trailing_fix: TrailingFix::Warn,
+            ext_policy: ExtPolicy::Keep,
+            move_to: None,
+            copy_mode: false,
+            subfolder_pattern: String::new(),
+            subfolder_exif: true,
+            dedup_delete_source: false,
+            restart_numbering_in_selection: false,
+            keep_sidecars: false,
+            scope_expr: String::new(),
+            detected_fs: HashMap::new(),
+            last_actions: Vec::new(),
+            redo_actions: Vec::new(),
+            log: Log::default(),
+            dragging_idx: None,
+            hidden_categories: HashSet::new(),
+            thumbnails: HashMap::new(),
+            thumb_max_size: (160, 120),
+            thumb_tx: None,
+            thumb_rx: None,
+            show_thumbnails: true,
+            list_columns: Vec::new(),
+            column_split: 0.5,
+            theme: Theme::Default,
+            show_help: false,
+            show_settings: false,
+            shell_menu: None,
+            tour_step: None,
+            base_style: None,
+            saved_templates: Vec::new(),
+            current_template_name: String::new(),
+            session_offer: None,
+            session_json: String::new(),
+            session_due: 0.0,
+            //loading
+            loading_phase: LoadingPhase::None,
+            loader_rx: None,
+            loading_count: 0,
+            import_filter: ImportFilter::default(),
+            //watch
+            watcher: None,
+            watch_rx: None,
+            watched_dirs: HashSet::new(),
+            dirs_dirty: false,
+            key_move: 0,
+            focus_row: None,
+            reveal_row: None,
+            //sort
+            sort_key: None,
+            sort_order: SortOrder::Asc,
+            //error
+            show_delete_error: false,
+            pending_overwrite: Vec::new(),
+            show_history: false,
+            history: Vec::new(),
+            settings: Settings::default(),
+            update_rx: None,
+            update_available: None,
+            show_gallery: false,
+            gallery: Vec::new(),
+            gallery_pick: 0,
+            gallery_rx: None,
+            show_templates: false,
+            template_rename: None,
+            show_duplicates: false,
+            dup_groups: Vec::new(),
+            dup_rx: None,
+            dup_tag: "_dup".into(),
+            show_watch: false,
+            watch_folder: None,
+            watch_log: Vec::new(),
+            show_compare: false,
+            compare_a: String::new(),
+            compare_b: String::new(),
+            error_report: None,
+            deconflict: false,
+            sanitize: false,
+            quick: QuickActions::default(),
+            find_replace: FindReplace::default(),
+            preview_detached: false,
+            preview_sort: None,
+            editing_name: None,
+            preview_cache: Default::default(),
+            phashes: HashMap::new(),
+            phash_rx: None,
+            manifest: Manifest::Off,
+            manifest_rx: None,
+            show_tree: false,
+            tree_root: None,
+            tree_rules: vec![DepthRule { depth: 1, target: TreeTarget::Files, template: String::new() }],
+            tree_entries: Vec::new(),
+            tree_run: None,
+            sandbox: None,
+            pending_batch: None,
+            batch_summary: None,
+            collision_prompt: None,
+            collision_answers: HashMap::new(),
+            collision_error: None,
+            running: None,
+            stale_rx: None,
+            stale_checked: Instant::now(),
+            confirm_ack: false,
+            elevation_offer: None,
+            interrupted: Vec::new(),
+            pending_elevation: None,
+            //automation api
+            api_enabled: false,
+            api_port: API_DEFAULT_PORT,
+            api_token: String::new(),
+            api_rx: None,
+            api_stop: None,
+            api_waiting: None,
+        }
+    }
+}
+
+impl BulkRename {
+    /// User config directory (created if missing).
+    fn config_dir() -> PathBuf {
+        let proj = ProjectDirs::from("jp", "mi3zuk", "BulkReName")
+            .expect("failed to get project directory");
+        let dir = proj.config_dir();
+        let _ = fs::create_dir_all(dir);
+        dir.to_path_buf()
+    }
+
+    /// Where thumbnails are cached between sessions.
+    fn thumb_cache_dir() -> PathBuf {
+        let proj = ProjectDirs::from("jp", "mi3zuk", "BulkReName")
+            .expect("failed to get project directory");
+        proj.cache_dir().join("thumbnails")
+    }
+
+    /// Path to `templates.json` in user config directory.
+    fn config_path() -> PathBuf {
+        Self::config_dir().join("templates.json")
+    }
+
+    /// Path to the Undo/Redo journal `undo.json`.
+    fn undo_journal_path() -> PathBuf {
+        Self::config_dir().join("undo.json")
+    }
+
+    /// Load the Undo/Redo stacks of earlier sessions, dropping batches none of whose
+    /// files are still where the batch left them.
+    fn load_undo_journal(&mut self) {
+        let Ok(text) = fs::read_to_string(Self::undo_journal_path()) else { return };
+        let Ok(journal) = serde_json::from_str::<UndoJournal>(&text) else { return };
+        let before = journal.undo.len() + journal.redo.len();
+        self.last_actions = journal.undo.into_iter().filter(|b| b.mapping.values().any(|p| p.exists())).collect();
+        self.redo_actions = journal.redo.into_iter().filter(|b| b.mapping.keys().any(|p| p.exists())).collect();
+        let dropped = before - self.last_actions.len() - self.redo_actions.len();
+        if dropped > 0 {
+            self.log.warn(format!("{} undo journal batch(es) dropped, their files are gone.", dropped));
+        }
+    }
+
+    fn save_undo_journal(&mut self) {
+        let keep = |stack: &mut Vec<LastBatch>| {
+            let excess = stack.len().saturating_sub(UNDO_JOURNAL_LIMIT);
+            stack.drain(..excess);
+        };
+        keep(&mut self.last_actions);
+        keep(&mut self.redo_actions);
+        let journal = serde_json::json!({ "undo": &self.last_actions, "redo": &self.redo_actions });
+        if let Err(e) = fs::write(Self::undo_journal_path(), journal.to_string()) {
+            self.log.error(format!("Failed to save undo journal: {}", e));
+        }
+    }
+
+    /// Unique path for the journal of one batch, under `transactions/`.
+    fn transaction_path() -> PathBuf {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+        Self::config_dir()
+            .join("transactions")
+            .join(format!("{}-{}.json", nanos, std::process::id()))
+    }
+
+    /// Two-phase rename with a journal on disk for crash recovery.
+    fn rename_journaled(&self, pairs: &[(PathBuf, PathBuf)]) -> Result<(), RenameFailure> {
+        two_phase_rename_journaled(pairs, self.retry, Some(&Self::transaction_path()), None)
+    }
+
+    /// Find batches cut short by a crash; journals of batches that didn't get to
+    /// touch any file (or did finish) are just removed.
+    fn check_interrupted(&mut self) {
+        let Ok(entries) = fs::read_dir(Self::config_dir().join("transactions")) else { return };
+        for path in entries.flatten().map(|e| e.path()) {
+            match RenameJournal::load(&path) {
+                Some(journal) if journal.interrupted() => self.interrupted.push((path, journal)),
+                _ => {
+                    let _ = fs::remove_file(&path);
+                }
+            }
+        }
+    }
+
+    /// Complete or roll back an interrupted batch; its journal goes once that worked.
+    fn resolve_interrupted(&mut self, k: usize, complete: bool) {
+        let (path, journal) = self.interrupted.remove(k);
+        let (what, result) = if complete {
+            ("Completing interrupted rename", journal.complete(self.retry))
+        } else {
+            ("Rolling back interrupted rename", journal.roll_back(self.retry))
+        };
+        match result {
+            Ok(()) => {
+                let _ = fs::remove_file(&path);
+                let pairs: Vec<(PathBuf, PathBuf)> = journal
+                    .steps
+                    .iter()
+                    .map(|s| (s.orig.clone(), s.target.clone()))
+                    .map(|(orig, target)| if complete { (orig, target) } else { (target, orig) })
+                    .collect();
+                self.append_history(if complete { "recover" } else { "rollback" }, &pairs);
+                self.dirs_dirty = true;
+                self.log.info(format!("{}: done ({} file(s)).", what, journal.steps.len()));
+            }
+            Err(failure) => {
+                self.log.error(format!("{}: {}", what, failure.message()));
+                self.error_report = Some(ErrorReport::from_failure(&failure, what));
+                self.interrupted.insert(k, (path, journal));
+            }
+        }
+    }
+
+    /// Path to the append-only rename log `history.jsonl`.
+    fn history_path() -> PathBuf {
+        Self::config_dir().join("history.jsonl")
+    }
+
+    /// Read the history log grouped by batch. Unparsable lines are skipped.
+    fn load_history() -> Vec<Vec<HistoryRecord>> {
+        let text = fs::read_to_string(Self::history_path()).unwrap_or_default();
+        let mut batches: Vec<Vec<HistoryRecord>> = Vec::new();
+        for rec in text.lines().filter_map(|l| serde_json::from_str::<HistoryRecord>(l).ok()) {
+            match batches.last_mut() {
+                Some(last) if last[0].batch == rec.batch => last.push(rec),
+                _ => batches.push(vec![rec]),
+            }
+        }
+        batches
+    }
+
+    /// Append one line per rename to the history log.
+    fn append_history(&mut self, action: &str, pairs: &[(PathBuf, PathBuf)]) {
+        use std::io::Write;
+        if pairs.is_empty() {
+            return;
+        }
+        let now = Local::now();
+        let batch = now.format("%Y%m%d-%H%M%S-%f").to_string();
+        let mut lines = String::new();
+        for (old, new) in pairs {
+            let record = HistoryRecord {
+                time: now.to_rfc3339(),
+                batch: batch.clone(),
+                action: action.to_string(),
+                old: old.clone(),
+                new: new.clone(),
+            };
+            if let Ok(json) = serde_json::to_string(&record) {
+                lines.push_str(&json);
+                lines.push('\n');
+            }
+        }
+        let result = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(Self::history_path())
+            .and_then(|mut f| f.write_all(lines.as_bytes()));
+        if let Err(e) = result {
+            self.log.error(format!("Failed to write history log: {}", e));
+        }
+        let template = if matches!(action, "rename" | "copy") { self.current_template_name.as_str() } else { "" };
+        for (dir, e) in write_folder_logs(self.settings.folder_log, action, template, now, pairs) {
+            self.log.error_at(&dir, format!("Failed to write the folder log: {}", e));
+        }
+    }
+
+    /// Load saved templates. On first launch (no templates.json yet) open the tour.
+    fn load_templates(&mut self) {
+        if !Self::config_path().exists() {
+            self.save_templates();
+            self.tour_step = Some(0);
+            return;
+        }
+        if let Some(list) = Self::read_templates() {
+            self.saved_templates = list;
+        }
+    }
+
+    /// The saved templates as they are on disk; nothing is created or written.
+    fn read_templates() -> Option<Vec<Template>> {
+        let text = fs::read_to_string(Self::config_path()).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    fn settings_path() -> PathBuf {
+        Self::config_dir().join("settings.json")
+    }
+
+    fn read_settings() -> Settings {
+        fs::read_to_string(Self::settings_path())
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    fn load_settings(&mut self) {
+        self.settings = Self::read_settings();
+        set_ui_language(self.settings.language);
+    }
+
+    fn save_settings(&mut self) {
+        let result = serde_json::to_string_pretty(&self.settings)
+            .map_err(|e| e.to_string())
+            .and_then(|json| fs::write(Self::settings_path(), json).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            self.log.error(format!("Failed to save settings: {}", e));
+        }
+    }
+
+    /// Query the GitHub releases API on a worker thread. `quiet` hides "up to date" and errors.
+    fn check_for_updates(&mut self, ctx: &egui::Context, quiet: bool) {
+        let (tx, rx) = mpsc::channel();
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            let result = fetch_newer_release();
+            if !quiet || matches!(result, Ok(Some(_))) {
+                tx.send(result).ok();
+            }
+            ctx.request_repaint();
+        });
+        self.update_rx = Some(rx);
+    }
+
+    /// Gallery downloaded by the last refresh, or the bundled one.
+    fn load_gallery() -> Vec<GalleryItem> {
+        fs::read_to_string(Self::config_dir().join("gallery.json"))
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_else(|| serde_json::from_str(include_str!("../gallery.json")).unwrap_or_default())
+    }
+
+    /// Download the gallery from `GALLERY_URL` on a worker thread.
+    fn refresh_gallery(&mut self, ctx: &egui::Context) {
+        let (tx, rx) = mpsc::channel();
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            let result = ureq::get(GALLERY_URL)
+                .timeout(std::time::Duration::from_secs(15))
+                .call()
+                .map_err(|e| e.to_string())
+                .and_then(|resp| resp.into_string().map_err(|e| e.to_string()))
+                .and_then(|text| {
+                    let items = serde_json::from_str::<Vec<GalleryItem>>(&text).map_err(|e| e.to_string())?;
+                    let _ = fs::write(BulkRename::config_dir().join("gallery.json"), &text);
+                    Ok(items)
+                });
+            tx.send(result).ok();
+            ctx.request_repaint();
+        });
+        self.gallery_rx = Some(rx);
+    }
+
+    /// Add a gallery template to the saved ones (same name: confirm with a diff first).
+    fn install_template(&mut self, tpl: Template) {
+        match self.saved_templates.iter().find(|t| t.name == tpl.name) {
+            Some(old) if old.diff(&tpl).iter().any(|(k, _)| *k != ' ') => {
+                self.pending_overwrite.push(tpl);
+            }
+            Some(_) => self.log.warn(format!("\"{}\" is already installed.", tpl.name)),
+            None => {
+                self.log.info(format!("Installed template \"{}\".", tpl.name));
+                self.saved_templates.push(tpl);
+                self.save_templates();
+            }
+        }
+    }
+
+    /// Write templates to a JSON file others can import.
+    fn export_templates(&mut self, templates: &[Template]) {
+        let file_name = match templates {
+            [one] => format!("{}.json", portable_name(&one.name).0),
+            _ => "templates.json".to_string(),
+        };
+        let Some(path) = rfd::FileDialog::new().add_filter("JSON", &["json"]).set_file_name(file_name).save_file()
+        else {
+            return;
+        };
+        match fs::write(&path, export_templates(templates)) {
+            Ok(()) => self.log.info(format!("Exported {} template(s) to {:?}", templates.len(), path)),
+            Err(e) => self.log.error(format!("Failed to export templates {:?}: {}", path, e)),
+        }
+    }
+
+    /// Add the templates of a shared file; a name that exists with other contents asks first.
+    fn import_templates(&mut self) {
+        let Some(path) = rfd::FileDialog::new().add_filter("JSON", &["json"]).pick_file() else {
+            return;
+        };
+        let result = fs::read_to_string(&path)
+            .map_err(|e| vec![e.to_string()])
+            .and_then(|text| import_templates(&text));
+        match result {
+            Ok(templates) => {
+                for tpl in templates {
+                    self.install_template(tpl);
+                }
+            }
+            Err(problems) => {
+                self.log.error(format!("Templates not imported from {:?}:", path));
+                self.log.errors(problems);
+            }
+        }
+    }
+
+    /// Give saved template `k` a new name; refused when empty or taken.
+    fn rename_template(&mut self, k: usize, name: String) {
+        let name = name.trim().to_string();
+        let old = self.saved_templates[k].name.clone();
+        if name == old {
+            return;
+        }
+        if name.is_empty() || self.saved_templates.iter().any(|t| t.name == name) {
+            self.log.warn(format!("Template not renamed: \"{}\" is empty or already used.", name));
+            return;
+        }
+        if self.current_template_name == old {
+            self.current_template_name = name.clone();
+        }
+        self.saved_templates[k].name = name;
+        self.save_templates();
+    }
+
+    /// Insert a copy of saved template `k` right after it, as "name copy" (or "name copy 2", ...).
+    fn duplicate_template(&mut self, k: usize) {
+        let mut tpl = self.saved_templates[k].clone();
+        let base = format!("{} copy", tpl.name);
+        tpl.name = (1..)
+            .map(|n| if n == 1 { base.clone() } else { format!("{} {}", base, n) })
+            .find(|name| self.saved_templates.iter().all(|t| &t.name != name))
+            .unwrap_or(base);
+        self.saved_templates.insert(k + 1, tpl);
+        self.save_templates();
+    }
+
+    /// Built-in templates listed under Load after the saved ones. They can't be changed
+    /// or deleted; "Duplicate" saves an editable copy.
+    fn preset_templates() -> &'static [Template] {
+        static PRESETS: std::sync::OnceLock<Vec<Template>> = std::sync::OnceLock::new();
+        PRESETS.get_or_init(|| {
+            let template = |name: &str, blocks: Vec<Block>, trailing_fix| Template {
+                name: name.to_string(),
+                blocks,
+                collision: CollisionStrategy::Suffix,
+                use_mtime_for_date: true,
+                mtime_block: None,
+                mtime_pattern: None,
+                trailing_fix,
+                extension: ExtPolicy::Keep,
+            };
+            let number = |width| Block::Number {
+                width,
+                start: 1,
+                step: 1,
+                locale: Locale::Default,
+                style: NumberStyle::Decimal,
+                pad: '0',
+                next: None,
+                group: GroupKey::None,
+            };
+            let tag = |field| Block::AudioTag { field, placeholder: "Unknown".into() };
+            vec![
+                template(
+                    "Photo import: YYYYMMDD_####",
+                    vec![
+                        Block::ExifDate { format: "%Y%m%d".into(), locale: Locale::Default },
+                        Block::Literal("_".into()),
+                        number(4),
+                        Block::Extension,
+                    ],
+                    TrailingFix::Warn,
+                ),
+                template(
+                    "Music: Track - Title",
+                    vec![tag(TagField::Track), Block::Literal(" - ".into()), tag(TagField::Title), Block::Extension],
+                    TrailingFix::Trim,
+                ),
+                template(
+                    "Document: YYYY-MM-DD name",
+                    vec![
+                        Block::Date {
+                            format: "%Y-%m-%d".into(),
+                            locale: Locale::Default,
+                            source: DateSource::Modified,
+                            zone: DateZone::Local,
+                        },
+                        Block::Literal(" ".into()),
+                        Block::Original { mode: OriginalMode::Keep, case: NameCase::Keep },
+                        Block::Extension,
+                    ],
+                    TrailingFix::Warn,
+                ),
+                template(
+                    "Sequence: name_001",
+                    vec![
+                        Block::Original { mode: OriginalMode::Keep, case: NameCase::Keep },
+                        Block::Literal("_".into()),
+                        number(3),
+                        Block::Extension,
+                    ],
+                    TrailingFix::Warn,
+                ),
+                template(
+                    "Cleanup: copy markers",
+                    vec![
+                        Block::Original {
+                            mode: OriginalMode::RemoveSubstring {
+                                pattern: "- Copy/- コピー/Copy of/(1)/(2)/(3)".into(),
+                                case_sensitive: false,
+                            },
+                            case: NameCase::Keep,
+                        },
+                        Block::Extension,
+                    ],
+                    TrailingFix::Trim,
+                ),
+            ]
+        })
+    }
+
+    /// Saved templates, then the presets whose name no saved template took.
+    fn templates(saved: &[Template]) -> impl Iterator<Item = &Template> {
+        let presets = Self::preset_templates().iter().filter(|p| saved.iter().all(|t| t.name != p.name));
+        saved.iter().chain(presets)
+    }
+
+    /// The saved template or preset named `name`.
+    fn find_template<'a>(saved: &'a [Template], name: &str) -> Option<&'a Template> {
+        Self::templates(saved).find(|t| t.name == name)
+    }
+
+    /// A preset no saved template hides.
+    fn is_preset(&self, name: &str) -> bool {
+        !self.saved_templates.iter().any(|t| t.name == name) && Self::preset_templates().iter().any(|t| t.name == name)
+    }
+
+    /// Save an editable copy of the preset `name` and load it.
+    fn copy_preset(&mut self, name: &str) {
+        let Some(preset) = Self::preset_templates().iter().find(|t| t.name == name) else {
+            return;
+        };
+        let mut tpl = preset.clone();
+        let base = format!("{} copy", name);
+        tpl.name = (1..)
+            .map(|n| if n == 1 { base.clone() } else { format!("{} {}", base, n) })
+            .find(|name| self.saved_templates.iter().all(|t| &t.name != name))
+            .unwrap_or(base);
+        self.apply_template(&tpl);
+        self.current_template_name = tpl.name.clone();
+        self.log.info(format!("Copied preset \"{}\" to \"{}\".", name, tpl.name));
+        self.saved_templates.push(tpl);
+        self.save_templates();
+    }
+
+    /// Load a template's blocks and settings into the editor.
+    fn apply_template(&mut self, tpl: &Template) {
+        self.blocks = tpl.blocks.clone();
+        self.collision = tpl.collision;
+        self.use_mtime_for_date = tpl.use_mtime_for_date;
+        self.mtime_block = tpl.mtime_block;
+        self.mtime_pattern = tpl.mtime_pattern.clone();
+        self.trailing_fix = tpl.trailing_fix;
+        self.ext_policy = tpl.extension.clone();
+    }
+
+    /// The editor's blocks and settings as a template named after the Name field.
+    fn current_template(&self) -> Template {
+        Template {
+            name: self.current_template_name.clone(),
+            blocks: self.blocks.clone(),
+            collision: self.collision,
+            use_mtime_for_date: self.use_mtime_for_date,
+            mtime_block: self.mtime_block,
+            mtime_pattern: self.mtime_pattern.clone(),
+            trailing_fix: self.trailing_fix,
+            extension: self.ext_policy.clone(),
+        }
+    }
+
+    fn session_path() -> PathBuf {
+        Self::config_dir().join("session.json")
+    }
+
+    fn session(&self) -> Session {
+        Session {
+            template: self.current_template(),
+            sanitize: self.sanitize,
+            files: self
+                .files
+                .iter()
+                .map(|fe| SessionFile {
+                    path: fe.path.clone(),
+                    selected: fe.selected,
+                    excluded: fe.excluded,
+                    manual_name: fe.manual_name.clone(),
+                    tag: fe.tag.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Load a session's template and append its files; files that are gone are skipped.
+    fn restore_session(&mut self, session: Session) {
+        self.apply_template(&session.template);
+        self.current_template_name = session.template.name.clone();
+        self.sanitize = session.sanitize;
+        let mut gone = 0;
+        for f in session.files {
+            if !f.path.exists() {
+                gone += 1;
+                continue;
+            }
+            let category = FileCategory::detect(&f.path, self.import_filter.sniff_magic);
+            let mut fe = FileEntry::new(f.path, category);
+            fe.selected = f.selected;
+            fe.excluded = f.excluded;
+            fe.manual_name = f.manual_name;
+            fe.tag = f.tag;
+            self.files.push(fe);
+        }
+        self.dirs_dirty = true;
+        self.log.info(format!("Session restored: {} file(s).", self.files.len()));
+        if gone > 0 {
+            self.log.warn(format!("{} file(s) of the session no longer exist.", gone));
+        }
+    }
+
+    fn read_session(path: &Path) -> Result<Session, String> {
+        fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| serde_json::from_str(&text).map_err(|e| e.to_string()))
+    }
+
+    /// Offer the session of the last run, if it had any files.
+    fn check_session(&mut self) {
+        self.session_offer = Self::read_session(&Self::session_path()).ok().filter(|s| !s.files.is_empty());
+    }
+
+    /// Keep session.json current; runs every 30 s and on exit.
+    fn autosave_session(&mut self) {
+        // don't overwrite the session that is still on offer
+        if self.session_offer.is_some() {
+            return;
+        }
+        if self.files.is_empty() {
+            if !self.session_json.is_empty() {
+                let _ = fs::remove_file(Self::session_path());
+                self.session_json.clear();
+            }
+            return;
+        }
+        let Ok(json) = serde_json::to_string_pretty(&self.session()) else { return };
+        if json != self.session_json && fs::write(Self::session_path(), &json).is_ok() {
+            self.session_json = json;
+        }
+    }
+
+    fn save_session_as(&mut self) {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("JSON", &["json"])
+            .set_file_name("session.json")
+            .save_file()
+        else {
+            return;
+        };
+        let result = serde_json::to_string_pretty(&self.session())
+            .map_err(|e| e.to_string())
+            .and_then(|json| fs::write(&path, json).map_err(|e| e.to_string()));
+        match result {
+            Ok(()) => self.log.info(format!("Session saved: {:?}", path)),
+            Err(e) => self.log.error(format!("Failed to save session {:?}: {}", path, e)),
+        }
+    }
+
+    fn load_session(&mut self) {
+        let Some(path) = rfd::FileDialog::new().add_filter("JSON", &["json"]).pick_file() else {
+            return;
+        };
+        match Self::read_session(&path) {
+            Ok(session) => self.restore_session(session),
+            Err(e) => self.log.error(format!("Failed to read session {:?}: {}", path, e)),
+        }
+    }
+
+    /// Continue the Number blocks of the editor, and of the saved template of the same name, after a batch.
+    /// The numbers before are kept with the batch for Undo.
+    fn advance_counters(&mut self, used: usize) {
+        let next = |blocks: &[Block]| -> Vec<Option<i64>> {
+            blocks.iter().map(|b| if let Block::Number { next, .. } = b.inner() { *next } else { None }).collect()
+        };
+        let mut counters = Counters { used, editor: next(&self.blocks), template: None };
+        advance_counters(&mut self.blocks, used);
+        let name = &self.current_template_name;
+        if let Some(tpl) = self.saved_templates.iter_mut().find(|t| &t.name == name) {
+            counters.template = Some((tpl.name.clone(), next(&tpl.blocks)));
+            advance_counters(&mut tpl.blocks, used);
+            self.save_templates();
+        }
+        if let Some(last) = self.last_actions.last_mut() {
+            last.counters = Some(counters);
+            self.save_undo_journal();
+        }
+    }
+
+    /// Put the counters back to where they were before an undone batch.
+    fn restore_counters(&mut self, counters: &Counters) {
+        let restore = |blocks: &mut [Block], before: &[Option<i64>]| {
+            for (block, before) in blocks.iter_mut().zip(before) {
+                if let (Block::Number { next: Some(next), .. }, Some(before)) = (block.inner_mut(), before) {
+                    *next = *before;
+                }
+            }
+        };
+        restore(&mut self.blocks, &counters.editor);
+        if let Some((name, before)) = &counters.template {
+            if let Some(tpl) = self.saved_templates.iter_mut().find(|t| &t.name == name) {
+                restore(&mut tpl.blocks, before);
+                self.save_templates();
+            }
+        }
+    }
+
+    fn save_templates(&self) {
+        if let Ok(json) = serde_json::to_string_pretty(&self.saved_templates) {
+            let _ = fs::write(Self::config_path(), json);
+        }
+    }
+
+    fn add_files(&mut self, paths: Vec<PathBuf>) {
+        for p in paths {
+            if p.is_file() || p.is_dir() {
+                let category = FileCategory::detect(&p, self.import_filter.sniff_magic);
+                self.files.push(FileEntry::new(p, category));
+            }
+        }
+    }
+
+    /// Collect files (directories are walked) on a worker thread and stream them into the list.
+    fn start_loading(&mut self, paths: Vec<PathBuf>) {
+        let name_re = match self.import_filter.name_regex() {
+            Ok(re) => re,
+            Err(e) => {
+                self.log.error(format!("Invalid import regex: {}", e));
+                return;
+            }
+        };
+        let (tx, rx) = mpsc::channel::<PathBuf>();
+        self.loader_rx = Some(rx);
+        self.loading_phase = LoadingPhase::AddingFiles;
+        let filter = self.import_filter.clone();
+        thread::spawn(move || {
+            let mut collected = Vec::new();
+            for path in paths {
+                if path.is_file() || path.is_dir() && filter.folders_as_entries {
+                    collected.push(path);
+                } else if path.is_dir() {
+                    let depth = if !filter.recursive {
+                        Some(0)
+                    } else if filter.max_depth == 0 {
+                        None
+                    } else {
+                        Some(filter.max_depth)
+                    };
+                    collect_files(&path, depth, &mut collected);
+                }
+            }
+            for path in collected {
+                if filter.accepts(&path, name_re.as_ref()) {
+                    tx.send(path).ok();
+                }
+            }
+        });
+    }
+
+    /// Drop entries whose file no longer exists and flag the ones modified since they were added.
+    fn clean_list(&mut self) {
+        let before = self.files.len();
+        let thumbnails = &mut self.thumbnails;
+        self.files.retain(|f| {
+            let exists = f.path.exists();
+            if !exists {
+                thumbnails.remove(&f.path.to_string_lossy().to_string());
+            }
+            exists
+        });
+        let removed = before - self.files.len();
+        let (_, changed) = self.refresh_states();
+        self.selected_idx = None;
+        self.dirs_dirty = true;
+        self.log.info(format!(
+            "Clean list: {} missing removed, {} changed since added.",
+            removed, changed
+        ));
+    }
+
+    /// Hash the listed files on a worker thread and open the duplicates window.
+    fn start_duplicate_scan(&mut self, ctx: &egui::Context) {
+        let paths: Vec<PathBuf> = self.files.iter().map(|fe| fe.path.clone()).collect();
+        let (tx, rx) = mpsc::channel();
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            tx.send(find_duplicates(&paths)).ok();
+            ctx.request_repaint();
+        });
+        self.dup_rx = Some(rx);
+        self.dup_groups.clear();
+        self.show_duplicates = true;
+    }
+
+    /// Apply `mark` to the listed files found to duplicate an earlier one; returns how many there were.
+    fn mark_duplicates(&mut self, mark: impl Fn(&mut FileEntry)) -> usize {
+        let dups: HashSet<&PathBuf> = self.dup_groups.iter().flat_map(|g| &g[1..]).collect();
+        let mut marked = 0;
+        for fe in self.files.iter_mut().filter(|fe| dups.contains(&fe.path)) {
+            mark(fe);
+            marked += 1;
+        }
+        marked
+    }
+
+    /// Start renaming the files that arrive in the watch folder with its saved template.
+    fn start_watch_folder(&mut self, ctx: &egui::Context) {
+        let Some(dir) = self.settings.watch_dir.clone() else {
+            return;
+        };
+        let found = Self::find_template(&self.saved_templates, &self.settings.watch_template).cloned();
+        let Some(mut template) = found else {
+            self.log.warn(format!("Watch folder: no saved template \"{}\".", self.settings.watch_template));
+            return;
+        };
+        // numbers go on from file to file, and across restarts through the saved template
+        for block in &mut template.blocks {
+            if let Block::Number { start, next, .. } = block.inner_mut() {
+                next.get_or_insert(*start);
+            }
+        }
+        let (tx, rx) = mpsc::channel();
+        let repaint = ctx.clone();
+        let handler = move |res: notify::Result<notify::Event>| {
+            tx.send(res).ok();
+            repaint.request_repaint();
+        };
+        let watcher = notify::recommended_watcher(handler)
+            .and_then(|mut w| w.watch(&dir, RecursiveMode::NonRecursive).map(|_| w));
+        match watcher {
+            Ok(watcher) => {
+                self.watch_log.push(format!(
+                    "{} Watching {} with \"{}\".",
+                    Local::now().format("%H:%M:%S"),
+                    dir.display(),
+                    template.name
+                ));
+                self.watch_folder = Some(WatchFolder {
+                    template,
+                    _watcher: watcher,
+                    rx,
+                    pending: HashMap::new(),
+                    produced: HashSet::new(),
+                    ready: Vec::new(),
+                    current: None,
+                    renamed: 0,
+                });
+            }
+            Err(e) => self.log.error(format!("Cannot watch {:?}: {}", dir, e)),
+        }
+    }
+
+    /// Note new files in the watch folder and rename the ones whose size has stopped changing.
+    fn poll_watch_folder(&mut self) {
+        let Some(watch) = &mut self.watch_folder else { return };
+        let now = Instant::now();
+        while let Ok(res) = watch.rx.try_recv() {
+            let Ok(event) = res else { continue };
+            if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                continue;
+            }
+            for path in event.paths {
+                let ours = watch.produced.contains(&path) || FolderLog::is_log_file(&path);
+                if !ours && !is_partial_file(&path) && path.is_file() {
+                    watch.pending.entry(path).or_insert((u64::MAX, now));
+                }
+            }
+        }
+        let ready = &mut watch.ready;
+        watch.pending.retain(|path, (size, since)| match fs::metadata(path) {
+            Ok(meta) if meta.len() != *size => {
+                (*size, *since) = (meta.len(), now);
+                true
+            }
+            Ok(_) if now.duration_since(*since) >= WATCH_SETTLE => {
+                if !ready.contains(path) {
+                    ready.push(path.clone());
+                }
+                false
+            }
+            Ok(_) => true,
+            Err(_) => false,
+        });
+        // one arrival per batch, never next to another rename
+        if self.batch_busy() {
+            return;
+        }
+        self.finish_arrival(false);
+        let next = self.watch_folder.as_mut().and_then(|w| (!w.ready.is_empty()).then(|| w.ready.remove(0)));
+        if let Some(path) = next {
+            self.rename_arrival(path);
+        }
+    }
+
+    /// Rename one file that arrived in the watch folder as a one-file batch, so it gets history,
+    /// Undo and the journal like any other; `finish_arrival` logs it.
+    fn rename_arrival(&mut self, path: PathBuf) {
+        let Some(watch) = &mut self.watch_folder else { return };
+        let tpl = &watch.template;
+        let old = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let time = Local::now().format("%H:%M:%S");
+        if !path.is_file() {
+            return;
+        }
+        let name = tpl.trailing_fix.apply(render_name(&tpl.blocks, 0, &path, Local::now(), &tpl.extension));
+        if name.trim().is_empty() {
+            self.watch_log.push(format!("{} {}: empty name, skipped", time, old));
+            return;
+        }
+        let origs = [path.clone()];
+        let final_paths = [path.with_file_name(&name)];
+        let (resolved, duplicates) = resolve_targets(tpl.collision, false, &origs, &final_paths);
+        let target = match resolved.first() {
+            Some(target) if *target != path => target.clone(),
+            _ => {
+                self.watch_log.push(format!("{} {}: unchanged", time, old));
+                return;
+            }
+        };
+        watch.produced.insert(target.clone());
+        watch.current = Some((path.clone(), target.clone()));
+        let summary = self.summarize_batch(&origs, &final_paths, &resolved, &duplicates);
+        let mut batch = PendingBatch { summary, ..PendingBatch::new(vec![(path, target)], duplicates) };
+        batch.done = format!("Watch folder: {} renamed.", old);
+        self.queue_batch(batch);
+    }
+
+    /// Log the arrival whose batch just ended and move the watch's numbers on; `ok` is false
+    /// when it failed or was cancelled.
+    fn finish_arrival(&mut self, ok: bool) {
+        let Some(watch) = &mut self.watch_folder else { return };
+        let Some((path, target)) = watch.current.take() else { return };
+        let time = Local::now().format("%H:%M:%S");
+        let old = path.file_name().unwrap_or_default().to_string_lossy();
+        if !ok {
+            self.watch_log.push(format!("{} {}: not renamed", time, old));
+            watch.produced.remove(&target);
+            return;
+        }
+        let new = target.file_name().unwrap_or_default().to_string_lossy();
+        self.watch_log.push(format!("{} {} → {}", time, old, new));
+        watch.renamed += 1;
+        advance_counters(&mut watch.template.blocks, 1);
+        if let Some(tpl) = self.saved_templates.iter_mut().find(|t| t.name == watch.template.name) {
+            for (block, watched) in tpl.blocks.iter_mut().zip(&watch.template.blocks) {
+                if let (Block::Number { next, .. }, Block::Number { next: now, .. }) =
+                    (block.inner_mut(), watched.inner())
+                {
+                    *next = *now;
+                }
+            }
+            self.save_templates();
+        }
+    }
+
+    /// Watch the parent directories of all loaded files (non-recursive).
+    fn sync_watches(&mut self, ctx: &egui::Context) {
+        if self.watcher.is_none() {
+            let (tx, rx) = mpsc::channel();
+            let ctx = ctx.clone();
+            let handler = move |res: notify::Result<notify::Event>| {
+                tx.send(res).ok();
+                ctx.request_repaint();
+            };
+            match notify::recommended_watcher(handler) {
+                Ok(w) => {
+                    self.watcher = Some(w);
+                    self.watch_rx = Some(rx);
+                }
+                Err(e) => {
+                    self.log.error(format!("File watching unavailable: {}", e));
+                    return;
+                }
+            }
+        }
+        let Some(watcher) = self.watcher.as_mut() else { return };
+
+        let wanted: HashSet<PathBuf> = self
+            .files
+            .iter()
+            .filter_map(|f| f.path.parent().map(Path::to_path_buf))
+            .collect();
+        for dir in self.watched_dirs.difference(&wanted) {
+            let _ = watcher.unwatch(dir);
+        }
+        for dir in wanted.difference(&self.watched_dirs) {
+            let _ = watcher.watch(dir, RecursiveMode::NonRecursive);
+        }
+        self.watched_dirs = wanted;
+    }
+
+    fn set_theme(&mut self, ctx: &egui::Context, theme: Theme) {
+        let base = self.base_style.get_or_insert_with(|| ctx.style()).clone();
+        match theme {
+            Theme::Default => ctx.set_style(base),
+            Theme::Light => ctx.set_style(egui::Style { visuals: egui::Visuals::light(), ..(*base).clone() }),
+            Theme::HighContrast => ctx.set_style(Theme::high_contrast_style(&base)),
+        }
+        self.theme = theme;
+    }
+
+    /// Put the saved settings into effect (at startup and after each change in Settings).
+    fn apply_settings(&mut self, ctx: &egui::Context) {
+        if self.theme != self.settings.theme {
+            self.set_theme(ctx, self.settings.theme);
+        }
+        ctx.set_zoom_factor(self.settings.ui_scale);
+        set_ui_language(self.settings.language);
+        let size = (self.settings.thumb_size, self.settings.thumb_size * 3 / 4);
+        if size != self.thumb_max_size {
+            self.thumb_max_size = size;
+            self.thumbnails.clear();
+        }
+    }
+
+    /// Start or stop the local automation API.
+    fn set_api_enabled(&mut self, ctx: &egui::Context, on: bool) {
+        if let Some(stop) = self.api_stop.take() {
+            stop.store(true, Ordering::Relaxed);
+        }
+        self.api_rx = None;
+        self.api_enabled = false;
+        if !on {
+            return;
+        }
+        let listener = match TcpListener::bind(("127.0.0.1", self.api_port)) {
+            Ok(l) => l,
+            Err(e) => {
+                self.log.error(format!("Automation API failed to start: {}", e));
+                return;
+            }
+        };
+        let (tx, rx) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        self.api_token = new_api_token();
+        let (token, flag, ctx) = (self.api_token.clone(), stop.clone(), ctx.clone());
+        thread::spawn(move || serve_api(listener, token, tx, flag, ctx));
+        self.api_rx = Some(rx);
+        self.api_stop = Some(stop);
+        self.api_enabled = true;
+        self.log.info(format!("Automation API listening on 127.0.0.1:{}", self.api_port));
+    }
+
+    /// Answer requests that arrived through the automation API.
+    fn handle_api_requests(&mut self) {
+        let Some(rx) = &self.api_rx else { return };
+        let requests: Vec<ApiRequest> = rx.try_iter().collect();
+        for req in requests {
+            let start = self.log.entries.len();
+            let parsed = || {
+                let body = req.body.trim_start();
+                let parsed = if body.starts_with('{') {
+                    serde_json::from_str::<ApiExecute>(body).map(|b| (b.plan, b.force))
+                } else {
+                    serde_json::from_str::<Vec<PlanEntry>>(body).map(|plan| (plan, false))
+                };
+                let (plan, force) = parsed.map_err(|e| vec![format!("Invalid plan: {}", e)])?;
+                check_plan(plan).map(|(origs, final_paths)| (origs, final_paths, force))
+            };
+            let busy = req.endpoint != "/plan" && self.batch_busy();
+            let (status, ok, plan) = match req.endpoint.as_str() {
+                "/plan" | "/execute" | "/undo" if busy => {
+                    self.log.error("API: another rename is running or waiting for an answer.");
+                    (409, false, None)
+                }
+                "/plan" | "/execute" => match parsed() {
+                    Err(problems) => {
+                        self.log.errors(problems);
+                        (400, false, None)
+                    }
+                    Ok((origs, final_paths, _)) if req.endpoint == "/plan" => {
+                        let (resolved, _) = self.resolve_targets(&origs, &final_paths);
+                        let steps: Vec<_> = origs
+                            .iter()
+                            .zip(&resolved)
+                            .map(|(from, to)| serde_json::json!({ "from": from, "to": to }))
+                            .collect();
+                        (200, true, Some(steps))
+                    }
+                    Ok((origs, final_paths, force)) => match self.prepare_batch(&origs, &final_paths) {
+                        None => (200, false, None),
+                        Some(batch) if batch.risky() && !force => {
+                            self.log.error(format!(
+                                "API: not run, the batch needs confirmation ({} file(s), {} replaced, {} to another \
+                                 folder); send \"force\": true to run it.",
+                                batch.pairs.len(),
+                                batch.overwrites,
+                                batch.cross_dir
+                            ));
+                            (409, false, None)
+                        }
+                        Some(mut batch) => {
+                            batch.done = format!("API: {} file(s) renamed.", batch.pairs.len());
+                            self.start_batch(batch);
+                            if self.running.is_some() {
+                                // answered by `poll_running`
+                                self.api_waiting = Some((req.reply, start));
+                                continue;
+                            }
+                            (200, false, None)
+                        }
+                    },
+                },
+                "/undo" => {
+                    let ok = !self.last_actions.is_empty();
+                    self.undo();
+                    (200, ok, None)
+                }
+                _ => (404, false, None),
+            };
+            req.reply.send((status, self.api_reply(start, ok, plan))).ok();
+        }
+    }
+
+    /// JSON body of an API reply, with the log messages from `start` on.
+    fn api_reply(&self, start: usize, ok: bool, plan: Option<Vec<serde_json::Value>>) -> String {
+        let messages: Vec<String> =
+            self.log.entries.get(start..).unwrap_or_default().iter().map(LogEntry::line).collect();
+        serde_json::json!({ "ok": ok, "messages": messages, "plan": plan }).to_string()
+    }
+
+    /// A batch is renaming, waiting for confirmation or a collision answer, or part of a tree run.
+    fn batch_busy(&self) -> bool {
+        self.running.is_some()
+            || self.pending_batch.is_some()
+            || self.collision_prompt.is_some()
+            || self.tree_run.is_some()
+            || self.pending_elevation.is_some()
+    }
+
+    fn detect_filesystems(&mut self) {
+        for f in &self.files {
+            if let Some(dir) = f.path.parent() {
+                if !self.detected_fs.contains_key(dir) {
+                    let profile = detect_fs_name(dir)
+                        .map(|name| FsProfile::from_fs_name(&name))
+                        .unwrap_or(FsProfile::Off);
+                    self.detected_fs.insert(dir.to_path_buf(), profile);
+                }
+            }
+        }
+    }
+
+    /// Whether names in `dir` that differ only by case or Unicode normalization collide.
+    /// Undetected filesystems (e.g. APFS) are taken as folding on Windows and macOS.
+    fn folds_names(&self, dir: Option<&Path>) -> bool {
+        let profile = self.profile_for(dir);
+        profile.case_insensitive()
+            || (self.fs_profile == FsProfile::Auto
+                && profile == FsProfile::Off
+                && cfg!(any(windows, target_os = "macos")))
+    }
+
+    /// Profile used to validate names in `dir`.
+    fn profile_for(&self, dir: Option<&Path>) -> FsProfile {
+        match self.fs_profile {
+            FsProfile::Auto => dir
+                .and_then(|d| self.detected_fs.get(d).copied())
+                .unwrap_or(FsProfile::Off),
+            p => p,
+        }
+    }
+
+    /// Re-check entries in directories touched by external changes.
+    fn handle_watch_events(&mut self) {
+        let Some(rx) = &self.watch_rx else { return };
+        let mut touched = HashSet::new();
+        while let Ok(res) = rx.try_recv() {
+            if let Ok(event) = res {
+                if matches!(
+                    event.kind,
+                    EventKind::Create(_) | EventKind::Remove(_) | EventKind::Modify(_)
+                ) {
+                    touched.extend(event.paths.iter().filter_map(|p| p.parent().map(Path::to_path_buf)));
+                }
+            }
+        }
+        if touched.is_empty() {
+            return;
+        }
+        for f in &mut self.files {
+            if f.path.parent().is_some_and(|d| touched.contains(d)) {
+                f.missing = !f.path.exists();
+            }
+        }
+    }
+
+    fn sort_files(&mut self, key: SortKey) {
+        if self.sort_key == Some(key) {
+            self.sort_order = match self.sort_order {
+                SortOrder::Asc => SortOrder::Desc,
+                SortOrder::Desc => SortOrder::Asc,
+            };
+        } else {
+            self.sort_key = Some(key);
+            self.sort_order = SortOrder::Asc;
+        }
+        let asc = self.sort_order == SortOrder::Asc;
+        match key {
+            SortKey::Name => {
+                self.files.sort_by(|a, b| {
+                    let ord = natural_cmp(
+                        &a.path.file_name().unwrap_or_default().to_string_lossy(),
+                        &b.path.file_name().unwrap_or_default().to_string_lossy(),
+                    );
+                    if asc { ord } else { ord.reverse() }
+                });
+            }
+            SortKey::Modified => {
+                self.files.sort_by(|a, b| {
+                    let a_m = fs::metadata(&a.path).and_then(|m| m.modified()).ok();
+                    let b_m = fs::metadata(&b.path).and_then(|m| m.modified()).ok();
+                    let ord = a_m.cmp(&b_m);
+                    if asc { ord } else { ord.reverse() }
+                });
+            }
+            SortKey::Created => {
+                self.files.sort_by(|a, b| {
+                    let a_c = fs::metadata(&a.path).and_then(|m| m.created()).ok();
+                    let b_c = fs::metadata(&b.path).and_then(|m| m.created()).ok();
+                    let ord = a_c.cmp(&b_c);
+                    if asc { ord } else { ord.reverse() }
+                });
+            }
+            SortKey::Size => {
+                self.files.sort_by(|a, b| {
+                    let a_s = fs::metadata(&a.path).map(|m| m.len()).ok();
+                    let b_s = fs::metadata(&b.path).map(|m| m.len()).ok();
+                    let ord = a_s.cmp(&b_s);
+                    if asc { ord } else { ord.reverse() }
+                });
+            }
+            SortKey::ExifDate => {
+                self.files.sort_by(|a, b| {
+                    let ord = a.capture_date().cmp(&b.capture_date());
+                    if asc { ord } else { ord.reverse() }
+                });
+            }
+            SortKey::Type => {
+                self.files.sort_by(|a, b| {
+                    let ord = a.extension().cmp(&b.extension()).then_with(|| {
+                        natural_cmp(
+                            &a.path.file_name().unwrap_or_default().to_string_lossy(),
+                            &b.path.file_name().unwrap_or_default().to_string_lossy(),
+                        )
+                    });
+                    if asc { ord } else { ord.reverse() }
+                });
+            }
+            SortKey::Dimensions => {
+                let pixels = |f: &FileEntry| {
+                    (f.category == FileCategory::Image)
+                        .then(|| f.dimensions())
+                        .flatten()
+                        .map(|(w, h)| w as u64 * h as u64)
+                };
+                self.files.sort_by(|a, b| {
+                    let ord = pixels(a).cmp(&pixels(b));
+                    if asc { ord } else { ord.reverse() }
+                });
+            }
+        }
+    }
+
+    /// Mark the entries moved, renamed or deleted outside the app and the ones changed since
+    /// they were added. Returns the number of each.
+    fn refresh_states(&mut self) -> (usize, usize) {
+        let states = self
+            .files
+            .iter()
+            .map(|f| (f.path.clone(), f.path.exists(), FileEntry::read_snapshot(&f.path)))
+            .collect();
+        self.apply_states(states)
+    }
+
+    /// `refresh_states` with the (path, exists, snapshot) a worker read.
+    fn apply_states(&mut self, states: Vec<FileState>) -> (usize, usize) {
+        let states: HashMap<PathBuf, (bool, Option<(SystemTime, u64)>)> =
+            states.into_iter().map(|(path, exists, snapshot)| (path, (exists, snapshot))).collect();
+        for f in &mut self.files {
+            // entries renamed since the look was taken wait for the next one
+            let Some(&(exists, snapshot)) = states.get(&f.path) else { continue };
+            if f.update_state(exists, snapshot) {
+                self.phashes.remove(&f.path);
+            }
+        }
+        let missing = self.files.iter().filter(|f| f.missing).count();
+        (missing, self.files.iter().filter(|f| f.changed).count())
+    }
+
+    /// Every STALE_CHECK_INTERVAL, look at the listed files on a worker thread: the watcher
+    /// misses changes on some drives (network shares) and when events overflow.
+    fn check_stale(&mut self, ctx: &egui::Context) {
+        if let Some(rx) = &self.stale_rx {
+            let states = match rx.try_recv() {
+                Ok(states) => states,
+                Err(mpsc::TryRecvError::Empty) => return,
+                Err(mpsc::TryRecvError::Disconnected) => Vec::new(),
+            };
+            self.stale_rx = None;
+            let before = self.files.iter().filter(|f| f.missing).count();
+            let (missing, _) = self.apply_states(states);
+            if missing > before {
+                self.log.warn(format!(
+                    "{} listed file(s) were moved, renamed or deleted outside BulkReName \
+                     and are left out of renames.",
+                    missing - before
+                ));
+            }
+        }
+        if self.files.is_empty() || self.running.is_some() {
+            return;
+        }
+        let waited = self.stale_checked.elapsed();
+        if waited < STALE_CHECK_INTERVAL {
+            ctx.request_repaint_after(STALE_CHECK_INTERVAL - waited);
+            return;
+        }
+        self.stale_checked = Instant::now();
+        let paths: Vec<PathBuf> = self.files.iter().map(|f| f.path.clone()).collect();
+        let (tx, rx) = mpsc::channel();
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            let states = paths
+                .into_iter()
+                .map(|path| {
+                    let (exists, snapshot) = (path.exists(), FileEntry::read_snapshot(&path));
+                    (path, exists, snapshot)
+                })
+                .collect();
+            tx.send(states).ok();
+            ctx.request_repaint();
+        });
+        self.stale_rx = Some(rx);
+    }
+
+    /// "Refresh": check every entry now and report the stale ones.
+    fn refresh_list(&mut self) {
+        let (missing, changed) = self.refresh_states();
+        self.stale_checked = Instant::now();
+        self.log.info(format!(
+            "Refresh: {} missing (left out of renames), {} changed since added.",
+            missing, changed
+        ));
+    }
+
+    fn move_up(&mut self) {
+        if self.current_in_selection() {
+            return self.shift_selection(true);
+        }
+        if let Some(i) = self.selected_idx {
+            if i > 0 {
+                self.files.swap(i, i - 1);
+                self.selected_idx = Some(i - 1);
+            }
+        }
+    }
+
+    fn move_down(&mut self) {
+        if self.current_in_selection() {
+            return self.shift_selection(false);
+        }
+        if let Some(i) = self.selected_idx {
+            if i + 1 < self.files.len() {
+                self.files.swap(i, i + 1);
+                self.selected_idx = Some(i + 1);
+            }
+        }
+    }
+
+    fn current_in_selection(&self) -> bool {
+        self.selected_idx.is_some_and(|i| self.files.get(i).is_some_and(|f| f.selected))
+    }
+
+    /// Move every selected entry one row up (or down), past the unselected neighbour.
+    fn shift_selection(&mut self, up: bool) {
+        let n = self.files.len();
+        let swap = |files: &mut Vec<FileEntry>, a: usize, current: &mut Option<usize>| {
+            files.swap(a, a + 1);
+            if *current == Some(a) {
+                *current = Some(a + 1);
+            } else if *current == Some(a + 1) {
+                *current = Some(a);
+            }
+        };
+        if up {
+            for i in 1..n {
+                if self.files[i].selected && !self.files[i - 1].selected {
+                    swap(&mut self.files, i - 1, &mut self.selected_idx);
+                }
+            }
+        } else {
+            for i in (0..n.saturating_sub(1)).rev() {
+                if self.files[i].selected && !self.files[i + 1].selected {
+                    swap(&mut self.files, i, &mut self.selected_idx);
+                }
+            }
+        }
+    }
+
+    fn add_files_dialog(&mut self) {
+        if let Some(paths) = rfd::FileDialog::new().pick_files() {
+            self.start_loading(paths);
+        }
+    }
+
+    /// Click on a row: plain = select only it, `toggle` = Ctrl/Cmd, `range` = Shift from the anchor.
+    fn click_select(&mut self, i: usize, toggle: bool, range: bool) {
+        match (range, self.selected_idx) {
+            (true, Some(anchor)) => {
+                let (lo, hi) = (anchor.min(i), anchor.max(i).min(self.files.len() - 1));
+                for f in &mut self.files[lo..=hi] {
+                    f.selected = true;
+                }
+                return;
+            }
+            _ if toggle => {
+                self.files[i].selected = !self.files[i].selected;
+            }
+            _ => {
+                for f in &mut self.files {
+                    f.selected = false;
+                }
+                self.files[i].selected = true;
+            }
+        }
+        self.selected_idx = Some(i);
+    }
+
+    fn remove_selected(&mut self) {
+        if let Some(i) = self.selected_idx {
+            if let Some(p) = self.files.get(i) {
+                let key = p.path.to_string_lossy().to_string();
+                self.thumbnails.remove(&key);
+            }
+            self.files.remove(i);
+            self.selected_idx = None;
+        }
+    }
+
+    /// Drop every selected entry from the list.
+    fn remove_selection(&mut self) {
+        for fe in self.files.iter().filter(|f| f.selected) {
+            self.thumbnails.remove(&fe.path.to_string_lossy().to_string());
+        }
+        self.files.retain(|f| !f.selected);
+        self.selected_idx = None;
+    }
+
+    /// Move the selected entries, in their order, to the top or the bottom of the list.
+    fn move_selection(&mut self, to_top: bool) {
+        self.move_selection_to(if to_top { 0 } else { self.files.len() });
+    }
+
+    /// Move the selected entries, in their order, to before row `target` (a row index
+    /// from before the move; `files.len()` = the end).
+    fn move_selection_to(&mut self, target: usize) {
+        let before = self.files[..target.min(self.files.len())].iter().filter(|f| !f.selected).count();
+        let (selected, mut rest): (Vec<FileEntry>, Vec<FileEntry>) =
+            std::mem::take(&mut self.files).into_iter().partition(|f| f.selected);
+        rest.splice(before..before, selected);
+        self.files = rest;
+        self.selected_idx = None;
+    }
+
+    /// Date value used by Date blocks for this file.
+    fn file_date(&self, _fe: &FileEntry) -> DateTime<Local> {
+        Local::now()
+    }
+
+    /// Date a Date or EXIF date block shows for this file.
+    fn block_date(&self, block: &Block, fe: &FileEntry) -> DateTime<Local> {
+        match block {
+            Block::ExifDate { .. } => fe.capture_date().unwrap_or_else(|| self.file_date(fe)),
+            Block::Date { source, .. } => source.date(&ListFile { fe, phash: None, stem: None }, self.file_date(fe)),
+            _ => self.file_date(fe),
+        }
+    }
+
+    /// Index of the date block chosen for the mtime post-step, if still valid.
+    fn mtime_date_block(&self) -> Option<usize> {
+        self.mtime_block
+            .filter(|&i| matches!(self.blocks.get(i), Some(Block::Date { .. } | Block::ExifDate { .. })))
+    }
+
+    /// Rows of `rows` the mtime post-step applies to, with their dates: the chosen block's date,
+    /// or the date found in the original name (rows without one are left out).
+    fn mtime_dates(&self, rows: &[usize]) -> Option<(Vec<usize>, Vec<DateTime<Local>>)> {
+        if let Some(pattern) = &self.mtime_pattern {
+            let pattern = DatePattern::new(pattern)?;
+            return Some(
+                rows.iter()
+                    .filter_map(|&row| {
+                        let name = self.files[row].path.file_name()?.to_string_lossy();
+                        Some((row, pattern.parse(&name)?))
+                    })
+                    .unzip(),
+            );
+        }
+        let block = &self.blocks[self.mtime_date_block()?];
+        Some((rows.to_vec(), rows.iter().map(|&row| self.block_date(block, &self.files[row])).collect()))
+    }
+
+    /// The mtime post-step on its own: the files in scope get their new mtime but keep their names.
+    fn set_mtimes_now(&mut self) {
+        let Some((rows, dates)) = self.mtime_dates(&self.scope_rows()) else {
+            return;
+        };
+        let mut set = 0;
+        for (row, date) in rows.into_iter().zip(dates) {
+            let fe = &mut self.files[row];
+            match filetime::set_file_mtime(&fe.path, filetime::FileTime::from_system_time(SystemTime::from(date))) {
+                Ok(()) => set += 1,
+                Err(e) => self.log.error_at(&fe.path, format!("Failed to set mtime: {}", e)),
+            }
+            fe.snapshot = FileEntry::read_snapshot(&fe.path);
+        }
+        self.log.info(format!("Modification time set on {} file(s).", set));
+    }
+
+    /// Parsed scope expression; `None` when the field is empty.
+    fn scope_filter(&self) -> Result<Option<FilterExpr>, String> {
+        if self.scope_expr.trim().is_empty() {
+            Ok(None)
+        } else {
+            FilterExpr::parse(&self.scope_expr).map(Some)
+        }
+    }
+
+    /// Rows the template applies to (all rows when there is no valid scope expression),
+    /// except the excluded and missing ones.
+    fn scope_rows(&self) -> Vec<usize> {
+        let included = (0..self.files.len()).filter(|&i| !self.files[i].excluded && !self.files[i].missing);
+        match self.scope_filter() {
+            Ok(Some(filter)) => included.filter(|&i| filter.matches(&self.files[i])).collect(),
+            _ => included.collect(),
+        }
+    }
+
+    /// Target name per row; rows outside the scope keep their current name.
+    /// Numbering counts only the rows in scope.
+    fn generate_targets(&self) -> Vec<String> {
+        let mut res: Vec<String> = self
+            .files
+            .iter()
+            .map(|fe| fe.path.file_name().unwrap_or_default().to_string_lossy().to_string())
+            .collect();
+        let scope = self.scope_rows();
+        let names = self.numbered_names(&scope, &self.blocks, &self.ext_policy, |pos, groups, fe| {
+            self.target_name(pos, groups, fe)
+        });
+        for (&row, name) in scope.iter().zip(names) {
+            res[row] = name;
+        }
+        if self.deconflict {
+            self.deconflict_targets(&mut res, &scope);
+        }
+        res
+    }
+
+    /// Number the targets of `rows` that would collide only by case or Unicode
+    /// normalization with another name in their folder: "a.jpg", "A (1).jpg".
+    /// Other rows keep their names.
+    fn deconflict_targets(&self, targets: &mut [String], rows: &[usize]) {
+        let renamed: HashSet<usize> = rows.iter().copied().collect();
+        // folded name -> exact name holding it
+        let mut taken: HashMap<(Option<&Path>, String), String> = HashMap::new();
+        for (i, fe) in self.files.iter().enumerate() {
+            let dir = fe.path.parent();
+            if !renamed.contains(&i) && self.folds_names(dir) {
+                taken.entry((dir, fold_name(&targets[i]))).or_insert_with(|| targets[i].clone());
+            }
+        }
+        for &i in rows {
+            let dir = self.files[i].path.parent();
+            if !self.folds_names(dir) {
+                continue;
+            }
+            let numbered = if self.files[i].category == FileCategory::Folder {
+                make_numbered_dir_path
+            } else {
+                make_numbered_path
+            };
+            let mut name = targets[i].clone();
+            let mut n = 0;
+            while taken.get(&(dir, fold_name(&name))).is_some_and(|exact| *exact != name) {
+                n += 1;
+                name = numbered(Path::new(&targets[i]), n)
+                    .to_string_lossy()
+                    .to_string();
+            }
+            taken.entry((dir, fold_name(&name))).or_insert_with(|| name.clone());
+            targets[i] = name;
+        }
+    }
+
+    /// New name for `fe`; `idx` is its position for Number blocks, `groups` for grouped ones
+    /// (see `number_groups`).
+    fn target_name(&self, idx: usize, groups: &[Option<usize>], fe: &FileEntry) -> String {
+        if let Some(name) = &fe.manual_name {
+            return name.clone();
+        }
+        self.target_name_with(idx, groups, fe, &self.blocks, self.trailing_fix, &self.ext_policy)
+    }
+
+    fn target_name_with(
+        &self,
+        idx: usize,
+        groups: &[Option<usize>],
+        fe: &FileEntry,
+        blocks: &[Block],
+        trailing_fix: TrailingFix,
+        ext_policy: &ExtPolicy,
+    ) -> String {
+        let name = self.rendered_name(idx, groups, fe, blocks, trailing_fix, ext_policy);
+        if self.sanitize {
+            portable_name(&name).0
+        } else {
+            name
+        }
+    }
+
+    /// The name before sanitizing.
+    fn rendered_name(
+        &self,
+        idx: usize,
+        groups: &[Option<usize>],
+        fe: &FileEntry,
+        blocks: &[Block],
+        trailing_fix: TrailingFix,
+        ext_policy: &ExtPolicy,
+    ) -> String {
+        let file = self.list_file(fe);
+        let name = render_grouped(blocks, idx, groups, &file, self.file_date(fe), ext_policy);
+        trailing_fix.apply(self.quick.apply(name))
+    }
+
+    /// `fe` as the blocks see it.
+    fn list_file<'a>(&self, fe: &'a FileEntry) -> ListFile<'a> {
+        let mut file = ListFile { fe, phash: self.phashes.get(&fe.path).copied(), stem: None };
+        if self.find_replace.is_active() {
+            file.stem = Some(self.find_replace.apply(&split_name(&file).0));
+        }
+        file
+    }
+
+    /// Positions of `rows` (in this order) for the grouped Number blocks among `blocks`.
+    fn number_groups(&self, blocks: &[Block], rows: &[usize]) -> Vec<Vec<Option<usize>>> {
+        let files: Vec<ListFile> = rows.iter().map(|&row| self.list_file(&self.files[row])).collect();
+        group_positions(blocks, &files, |file| self.file_date(file.fe))
+    }
+
+    /// Sidecar groups of `rows` (see `sidecar_groups`); none unless "Keep sidecars together" is on.
+    fn sidecars(&self, rows: &[usize]) -> Vec<Option<(usize, String)>> {
+        if !self.keep_sidecars {
+            return vec![None; rows.len()];
+        }
+        let files: Vec<ListFile> = rows.iter().map(|&row| self.list_file(&self.files[row])).collect();
+        sidecar_groups(&files)
+    }
+
+    /// Position of each of `rows` for Number blocks: a sidecar group counts once.
+    fn unit_positions(&self, rows: &[usize]) -> Vec<usize> {
+        let mut positions: Vec<usize> = Vec::with_capacity(rows.len());
+        let mut next = 0;
+        for group in self.sidecars(rows) {
+            match group {
+                Some((leader, _)) => positions.push(positions[leader]),
+                None => {
+                    positions.push(next);
+                    next += 1;
+                }
+            }
+        }
+        positions
+    }
+
+    /// Names of `rows`, numbered in this order. `name` gives the name of a file from its
+    /// position and groups; the sidecars of a group follow the name of its first file.
+    fn numbered_names(
+        &self,
+        rows: &[usize],
+        blocks: &[Block],
+        ext_policy: &ExtPolicy,
+        name: impl Fn(usize, &[Option<usize>], &FileEntry) -> String,
+    ) -> Vec<String> {
+        let sidecars = self.sidecars(rows);
+        let units: Vec<usize> = rows.iter().zip(&sidecars).filter(|(_, s)| s.is_none()).map(|(&r, _)| r).collect();
+        let groups = self.number_groups(blocks, &units);
+        let mut names: Vec<String> = Vec::with_capacity(rows.len());
+        let mut pos = 0;
+        for (&row, sidecar) in rows.iter().zip(sidecars) {
+            let fe = &self.files[row];
+            let next = match sidecar {
+                Some((leader, suffix)) => {
+                    let ext = self.files[rows[leader]].path.extension().unwrap_or_default().to_string_lossy();
+                    sidecar_name(&names[leader], &ext, &suffix, ext_policy)
+                }
+                None => {
+                    pos += 1;
+                    name(pos - 1, &groups[pos - 1], fe)
+                }
+            };
+            names.push(next);
+        }
+        names
+    }
+
+    /// Target names a saved template would produce for the current list (same scope rules).
+    fn targets_for_template(&self, tpl: &Template) -> Vec<String> {
+        let mut res: Vec<String> = self
+            .files
+            .iter()
+            .map(|fe| fe.path.file_name().unwrap_or_default().to_string_lossy().to_string())
+            .collect();
+        let scope = self.scope_rows();
+        let names = self.numbered_names(&scope, &tpl.blocks, &tpl.extension, |pos, groups, fe| {
+            self.target_name_with(pos, groups, fe, &tpl.blocks, tpl.trailing_fix, &tpl.extension)
+        });
+        for (&row, name) in scope.iter().zip(names) {
+            res[row] = name;
+        }
+        res
+    }
+
+    /// Per-row problems of the generated names under the selected filesystem profile.
+    fn validate_targets(&self, targets: &[String]) -> Vec<Vec<String>> {
+        let mut issues: Vec<Vec<String>> = self
+            .files
+            .iter()
+            .zip(targets)
+            .map(|(fe, t)| {
+                let dotfile = fe.path.file_name().is_some_and(|n| n.to_string_lossy().starts_with('.'));
+                if t.trim().is_empty() || t.starts_with('.') && !dotfile {
+                    vec!["empty name".to_string()]
+                } else {
+                    self.profile_for(fe.path.parent()).check_name(t)
+                }
+            })
+            .collect();
+
+        // names differing only by case or normalization collide on case-insensitive filesystems
+        let mut seen: HashMap<(Option<&Path>, String), usize> = HashMap::new();
+        for (i, (fe, t)) in self.files.iter().zip(targets).enumerate() {
+            let dir = fe.path.parent();
+            if !self.folds_names(dir) {
+                continue;
+            }
+            let key = (dir, fold_name(t));
+            if let Some(&j) = seen.get(&key) {
+                if targets[j] != *t {
+                    issues[i].push(format!("differs from [{}] only by case or Unicode normalization", j));
+                }
+            } else {
+                seen.insert(key, i);
+            }
+        }
+        issues
+    }
+
+    /// Conflicts between the targets of `rows`: (fatal, warnings) per file.
+    /// Two files on one name are fatal with Overwrite; otherwise the collision setting handles them.
+    fn batch_conflicts(&self, targets: &[String], rows: &[usize]) -> (Vec<Vec<String>>, Vec<Vec<String>>) {
+        let mut fatal = vec![Vec::new(); self.files.len()];
+        let mut warnings = vec![Vec::new(); self.files.len()];
+        let outcome = match self.collision {
+            CollisionStrategy::Overwrite => "it will be replaced",
+            CollisionStrategy::Skip => "this file will be skipped",
+            CollisionStrategy::Suffix => "a number will be appended",
+            CollisionStrategy::Dedup => "skipped if identical (also to a numbered copy), numbered otherwise",
+            CollisionStrategy::Ask => "you will be asked",
+        };
+        // sources that move away free their names (not when copying)
+        let leaving: HashSet<&Path> = if self.copy_mode {
+            HashSet::new()
+        } else {
+            rows.iter().map(|&row| self.files[row].path.as_path()).collect()
+        };
+        // folders renamed by this batch: entries inside them would lose their path
+        let moving_folders: Vec<(usize, &Path)> = rows
+            .iter()
+            .map(|&row| (row, &self.files[row]))
+            .filter(|(row, fe)| {
+                fe.category == FileCategory::Folder
+                    && self.target_path(fe, &targets[*row]).is_ok_and(|p| p != fe.path)
+            })
+            .map(|(row, fe)| (row, fe.path.as_path()))
+            .collect();
+        let mut claimed: HashMap<PathBuf, usize> = HashMap::new();
+        for &row in rows {
+            let fe = &self.files[row];
+            if self.copy_mode && fe.category == FileCategory::Folder {
+                fatal[row].push("folders can't be copied".into());
+                continue;
+            }
+            if let Some((folder, _)) = moving_folders.iter().find(|(j, dir)| *j != row && fe.path.starts_with(dir)) {
+                fatal[row].push(format!("inside folder [{}], which is renamed too", folder));
+                continue;
+            }
+            let path = match self.target_path(fe, &targets[row]) {
+                Ok(p) => p,
+                Err(e) => {
+                    fatal[row].push(e);
+                    continue;
+                }
+            };
+            if path == fe.path {
+                warnings[row].push("unchanged".into());
+                continue;
+            }
+            let length = path_length(&path);
+            if length > PATH_LIMIT {
+                fatal[row].push(format!("path too long: {} / {}", length, PATH_LIMIT));
+                continue;
+            }
+            if cfg!(windows) && length >= WINDOWS_MAX_PATH {
+                warnings[row].push(format!(
+                    "path has {} characters: Explorer and many programs can't open paths of {} or more",
+                    length, WINDOWS_MAX_PATH
+                ));
+            }
+            let key = if self.folds_names(path.parent()) {
+                path.with_file_name(fold_name(&path.file_name().unwrap_or_default().to_string_lossy()))
+            } else {
+                path.clone()
+            };
+            if let Some(&first) = claimed.get(&key) {
+                let msg = format!("same name as [{}]", first);
+                if self.collision == CollisionStrategy::Overwrite {
+                    fatal[row].push(format!("{}: one of them would be lost", msg));
+                } else {
+                    warnings[row].push(format!("{}: {}", msg, outcome));
+                }
+                continue;
+            }
+            claimed.insert(key, row);
+            // a case-only change finds the file itself on case-insensitive filesystems
+            let itself = path.parent() == fe.path.parent()
+                && self.folds_names(path.parent())
+                && fold_name(&targets[row]) == fold_name(&fe.path.file_name().unwrap_or_default().to_string_lossy());
+            if !itself && !leaving.contains(path.as_path()) && path.exists() {
+                warnings[row].push(format!("a file with this name exists: {}", outcome));
+            }
+        }
+        (fatal, warnings)
+    }
+
+    /// The log: filter by level or text, and copy or save the entries shown.
+    fn log_panel(&mut self, ui: &mut egui::Ui) {
+        let palette = self.theme.palette();
+        ui.horizontal(|ui| {
+            let filter_label = |level| match level {
+                LogLevel::Info => "All",
+                LogLevel::Warning => "Warnings and errors",
+                LogLevel::Error => "Errors",
+            };
+            ComboBox::from_id_source("log_level")
+                .selected_text(tr(filter_label(self.log.level)))
+                .show_ui(ui, |ui| {
+                    for level in LogLevel::ALL {
+                        ui.selectable_value(&mut self.log.level, level, tr(filter_label(level)));
+                    }
+                });
+            ui.add(egui::TextEdit::singleline(&mut self.log.search).hint_text(tr("Search")).desired_width(160.0));
+            let shown = self.log.entries.iter().filter(|e| e.matches(self.log.level, &self.log.search)).count();
+            ui.label(format!("{} / {}", shown, self.log.entries.len()));
+            if ui.button(tr("Copy")).on_hover_text(tr("Copy the entries shown")).clicked() {
+                ui.output_mut(|o| o.copied_text = self.log.filtered_text());
+            }
+            if ui.button(tr("Save log...")).on_hover_text(tr("Save the entries shown as text")).clicked() {
+                if let Some(path) = rfd::FileDialog::new()
+                    .add_filter("Log", &["log", "txt"])
+                    .set_file_name("bulkrename.log")
+                    .save_file()
+                {
+                    match fs::write(&path, self.log.filtered_text()) {
+                        Ok(()) => self.log.info(format!("Log saved: {:?}", path)),
+                        Err(e) => self.log.error(format!("Failed to write {:?}: {}", path, e)),
+                    }
+                }
+            }
+            if ui.button(tr("Clear")).clicked() {
+                self.log.entries.clear();
+            }
+        });
+        egui::ScrollArea::vertical()
+            .id_source("log")
+            .max_height(120.0)
+            .auto_shrink([false, true])
+            .stick_to_bottom(true)
+            .show(ui, |ui| {
+                for entry in self.log.entries.iter().filter(|e| e.matches(self.log.level, &self.log.search)) {
+                    ui.horizontal_wrapped(|ui| {
+                        ui.label(RichText::new(entry.time.format("%H:%M:%S").to_string()).weak().monospace());
+                        match entry.level {
+                            LogLevel::Info => ui.label(" "),
+                            LogLevel::Warning => ui.label(RichText::new("⚠").color(palette.warn)),
+                            LogLevel::Error => ui.label(RichText::new("✖").color(palette.error)),
+                        }
+                        .on_hover_text(tr(entry.level.label()));
+                        if let Some(file) = &entry.file {
+                            let name = file.file_name().unwrap_or_default().to_string_lossy();
+                            ui.label(RichText::new(name).strong()).on_hover_text(file.display().to_string());
+                        }
+                        let text = RichText::new(&entry.text);
+                        ui.label(if entry.level == LogLevel::Error { text.color(palette.error) } else { text });
+                    });
+                }
+            });
+    }
+
+    /// Old → new names with their warnings, for the preview pane or window.
+    /// Click a new name to type one for that file; Enter or clicking away keeps it, Escape cancels.
+    /// The preview table: old and new names with what changed highlighted, and each row's status.
+    /// Sorting only reorders the table; the list order (and numbering) stays.
+    fn preview_rows(&mut self, ui: &mut egui::Ui, id: &str, max_height: f32) {
+        use egui_extras::{Column, TableBuilder};
+        let palette = self.theme.palette();
+        let preview = self.preview_table();
+        let mut table: Vec<&PreviewRow> = preview.rows.iter().collect();
+        let fatal = preview.blocked;
+        let warned = table.iter().filter(|r| !r.warnings.is_empty()).count();
+        if fatal + warned > 0 {
+            ui.horizontal_wrapped(|ui| {
+                if fatal > 0 {
+                    ui.label(RichText::new(format!("✖ {} conflict(s) block the rename", fatal)).color(palette.error));
+                }
+                if warned > 0 {
+                    ui.label(RichText::new(format!("⚠ {} warning(s)", warned)).color(palette.warn));
+                }
+            });
+            ui.separator();
+        }
+        if let Some((column, ascending)) = self.preview_sort {
+            table.sort_by(|a, b| {
+                let order = match column {
+                    PreviewSort::Old => natural_cmp(&a.old, &b.old),
+                    PreviewSort::New => natural_cmp(&a.new, &b.new),
+                    PreviewSort::Status => a.status_rank().cmp(&b.status_rank()),
+                };
+                if ascending {
+                    order
+                } else {
+                    order.reverse()
+                }
+            });
+        }
+        let font = egui::TextStyle::Body.resolve(ui.style());
+        // `keep` runs in `base`, `mark` runs highlighted; the other side's runs are left out
+        let highlighted = |runs: &[(char, String)], mark: char, base: egui::Color32, highlight: egui::Color32| {
+            let mut job = egui::text::LayoutJob::default();
+            for (kind, text) in runs {
+                let format = match *kind {
+                    ' ' => egui::TextFormat::simple(font.clone(), base),
+                    k if k == mark => egui::TextFormat {
+                        font_id: font.clone(),
+                        color: highlight,
+                        background: highlight.gamma_multiply(0.2),
+                        ..Default::default()
+                    },
+                    _ => continue,
+                };
+                job.append(text, 0.0, format);
+            }
+            job
+        };
+        let row_height = ui.spacing().interact_size.y;
+        ui.push_id(id, |ui| {
+            egui::ScrollArea::horizontal().auto_shrink([false, true]).show(ui, |ui| {
+                TableBuilder::new(ui)
+                    .striped(true)
+                    .max_scroll_height(max_height)
+                    .auto_shrink([false, false])
+                    .column(Column::auto().at_least(120.0).resizable(true))
+                    .column(Column::auto().at_least(160.0).resizable(true))
+                    .column(Column::remainder().at_least(140.0))
+                    .header(row_height, |mut header| {
+                        let columns = [PreviewSort::Old, PreviewSort::New, PreviewSort::Status];
+                        for (column, title) in columns.into_iter().zip(["Old", "New", "Status"]) {
+                            header.col(|ui| {
+                                let arrow = match self.preview_sort {
+                                    Some((c, true)) if c == column => " ⏶",
+                                    Some((c, false)) if c == column => " ⏷",
+                                    _ => "",
+                                };
+                                let title = RichText::new(format!("{}{}", tr(title), arrow)).strong();
+                                let button = ui.add(egui::Button::new(title).frame(false));
+                                let hint = tr("Sort the preview (list order and numbering stay)");
+                                if button.on_hover_text(hint).clicked() {
+                                    // ascending, descending, unsorted
+                                    self.preview_sort = match self.preview_sort {
+                                        Some((c, true)) if c == column => Some((column, false)),
+                                        Some((c, false)) if c == column => None,
+                                        _ => Some((column, true)),
+                                    };
+                                }
+                            });
+                        }
+                    })
+                    .body(|body| {
+                        body.rows(row_height, table.len(), |mut cells| {
+                            let row = &table[cells.index()];
+                            let runs = if row.in_scope {
+                                diff_chars(&row.old, &row.new)
+                            } else {
+                                vec![(' ', row.old.clone())]
+                            };
+                            let color = if !row.issues.is_empty() {
+                                palette.error
+                            } else if !row.warnings.is_empty() {
+                                palette.warn
+                            } else {
+                                palette.accent
+                            };
+                            cells.col(|ui| {
+                                let old = highlighted(&runs, '-', ui.visuals().text_color(), palette.error);
+                                ui.add(egui::Label::new(old).wrap(false))
+                                    .on_hover_text(self.files[row.row].path.display().to_string());
+                            });
+                            cells.col(|ui| {
+                                if let Some((_, text)) = self.editing_name.as_mut().filter(|(r, _)| *r == row.row) {
+                                    let edit = ui.add(egui::TextEdit::singleline(text).desired_width(f32::INFINITY));
+                                    if !edit.has_focus() && !edit.lost_focus() {
+                                        edit.request_focus();
+                                    }
+                                    if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                                        self.editing_name = None;
+                                    } else if edit.lost_focus() {
+                                        let text = text.trim().to_string();
+                                        if text != row.new {
+                                            self.files[row.row].manual_name = (!text.is_empty()).then_some(text);
+                                        }
+                                        self.editing_name = None;
+                                    }
+                                    return;
+                                }
+                                let new_name = if row.in_scope {
+                                    egui::WidgetText::from(highlighted(&runs, '+', color, palette.added))
+                                } else {
+                                    RichText::new(&row.new).color(palette.dim).into()
+                                };
+                                let name = ui.add(egui::Label::new(new_name).wrap(false).sense(egui::Sense::click()));
+                                let hint = tr("Click to type a name for this file");
+                                if row.in_scope && name.on_hover_text(hint).clicked() {
+                                    self.editing_name = Some((row.row, row.new.clone()));
+                                }
+                                if row.manual {
+                                    ui.label(RichText::new(tr("✎ edited")).color(palette.added));
+                                    let back = ui.small_button("↺");
+                                    if back.on_hover_text(tr("Back to the name from the template")).clicked() {
+                                        self.files[row.row].manual_name = None;
+                                    }
+                                }
+                            });
+                            cells.col(|ui| {
+                                let mut lines: Vec<(egui::Color32, String)> = Vec::new();
+                                lines.extend(row.issues.iter().map(|i| (palette.error, format!("✖ {}", i))));
+                                lines.extend(row.warnings.iter().map(|w| (palette.warn, format!("⚠ {}", w))));
+                                if !row.sanitized.is_empty() {
+                                    lines.push((palette.dim, format!("✎ sanitized: {}", row.sanitized.join(", "))));
+                                }
+                                if lines.is_empty() {
+                                    let text = if row.missing {
+                                        "missing"
+                                    } else if !row.in_scope {
+                                        "out of scope"
+                                    } else if row.old == row.new {
+                                        "unchanged"
+                                    } else {
+                                        "ok"
+                                    };
+                                    ui.label(RichText::new(tr(text)).color(palette.dim));
+                                    return;
+                                }
+                                let (color, first) = &lines[0];
+                                let text = match lines.len() {
+                                    1 => first.clone(),
+                                    n => format!("{}  (+{})", first, n - 1),
+                                };
+                                let all = lines.iter().map(|(_, l)| l.as_str()).collect::<Vec<_>>().join("\n");
+                                let label = egui::Label::new(RichText::new(text).color(*color)).wrap(false);
+                                ui.add(label).on_hover_text(all);
+                            });
+                        });
+                    });
+            });
+        });
+    }
+
+    /// What the preview is made of, hashed. The second is part of it, for dates of "now" and
+    /// targets appearing on disk.
+    fn preview_key(&self) -> u64 {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        let template = (&self.blocks, self.collision, self.trailing_fix, &self.ext_policy);
+        let dates = (self.use_mtime_for_date, self.mtime_block, &self.mtime_pattern);
+        let names = (&self.find_replace, &self.quick, self.sanitize, self.deconflict, &self.scope_expr);
+        let targets = (&self.move_to, self.copy_mode, &self.subfolder_pattern, self.subfolder_exif, self.keep_sidecars);
+        let filesystem = (self.fs_profile, self.detected_fs.len(), self.phashes.len());
+        format!("{:?}", (template, dates, names, targets, filesystem)).hash(&mut hasher);
+        for fe in &self.files {
+            (&fe.path, fe.excluded, fe.missing, &fe.manual_name, &fe.tag, fe.snapshot).hash(&mut hasher);
+        }
+        // the current time only as the blocks render it, whole seconds at most, so a preview
+        // without a date of "now" isn't rebuilt as the clock runs
+        let now = Local::now();
+        let now = now.with_nanosecond(0).unwrap_or(now);
+        for block in self.blocks.iter().map(Block::inner) {
+            let shown = match block {
+                Block::Date { format, locale, source: DateSource::Now, zone } => {
+                    format_date(&zone.convert(now), format, *locale)
+                }
+                // files without a capture date show the current time
+                Block::ExifDate { format, locale } => format_date(&now, format, *locale),
+                _ => continue,
+            };
+            shown.hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    /// The preview table, built again only when `preview_key` changes.
+    fn preview_table(&self) -> std::rc::Rc<PreviewTable> {
+        let key = self.preview_key();
+        let mut cache = self.preview_cache.borrow_mut();
+        match &*cache {
+            Some((k, table)) if *k == key => table.clone(),
+            _ => {
+                let rows = self.build_preview_table();
+                let blocked = rows.iter().filter(|r| !r.issues.is_empty()).count();
+                cache.insert((key, std::rc::Rc::new(PreviewTable { rows, blocked }))).1.clone()
+            }
+        }
+    }
+
+    fn build_preview_table(&self) -> Vec<PreviewRow> {
+        let targets = self.generate_targets();
+        let mut issues = self.validate_targets(&targets);
+        let scope = self.scope_rows();
+        let (fatal, warnings) = self.batch_conflicts(&targets, &scope);
+        for (issues, fatal) in issues.iter_mut().zip(fatal) {
+            issues.extend(fatal);
+        }
+        let mut sanitized = vec![Vec::new(); self.files.len()];
+        if self.sanitize {
+            let names = self.numbered_names(&scope, &self.blocks, &self.ext_policy, |pos, groups, fe| {
+                self.rendered_name(pos, groups, fe, &self.blocks, self.trailing_fix, &self.ext_policy)
+            });
+            for (&row, name) in scope.iter().zip(names) {
+                if self.files[row].manual_name.is_none() {
+                    sanitized[row] = portable_name(&name).1;
+                }
+            }
+        }
+        let mut in_scope = vec![false; self.files.len()];
+        for row in scope {
+            in_scope[row] = true;
+        }
+        self.files
+            .iter()
+            .zip(targets)
+            .zip(issues.into_iter().zip(warnings))
+            .zip(in_scope.into_iter().zip(sanitized))
+            .enumerate()
+            .map(|(row, (((f, t), (issues, warnings)), (in_scope, sanitized)))| PreviewRow {
+                row,
+                manual: f.manual_name.is_some(),
+                old: f
+                    .path
+                    .file_name()
+                    .and_then(|s| s.to_str())
+                    .unwrap_or("")
+                    .to_string(),
+                new: t,
+                issues: if in_scope { issues } else { Vec::new() },
+                warnings: if in_scope { warnings } else { Vec::new() },
+                sanitized,
+                in_scope,
+                missing: f.missing,
+            })
+            .collect()
+    }
+
+    /// Queue `path` for the thumbnail workers; the list shows a spinner until it arrives.
+    fn ensure_thumbnail(&mut self, ctx: &egui::Context, path: &Path) {
+        let key = path.to_string_lossy().to_string();
+        if self.thumbnails.contains_key(&key) {
+            return;
+        }
+        if let Some(ext) = path.extension().and_then(|s| s.to_str()).map(|s| s.to_lowercase()) {
+            let supported = ["png", "jpg", "jpeg", "webp", "gif", "bmp", "ico"];
+            let video = FileCategory::from_extension(&ext) == FileCategory::Video;
+            let image = supported.contains(&ext.as_str()) || RAW_EXTENSIONS.contains(&ext.as_str());
+            if !(image || video && ffmpeg_available()) {
+                return;
+            }
+        } else {
+            return;
+        }
+        self.thumbnails.insert(key.clone(), ThumbnailState::Loading);
+
+        if self.thumb_tx.is_none() {
+            let (tx, rx) = spawn_thumb_workers(ctx, Self::thumb_cache_dir());
+            self.thumb_tx = Some(tx);
+            self.thumb_rx = Some(rx);
+        }
+        if let Some(tx) = &self.thumb_tx {
+            tx.send((key, path.to_path_buf(), self.thumb_max_size)).ok();
+        }
+    }
+
+    /// Rename the whole list, or only the selected rows.
+    fn execute_rename(&mut self, selected_only: bool) {
+        let Some((rows, origs, final_paths)) = self.plan_rename(selected_only) else {
+            return;
+        };
+
+        // dates for the mtime post-step, taken before anything moves
+        let mtime = self.mtime_dates(&rows);
+
+        // numbers taken by the batch, for the counters continuing in the next one
+        let counted = if selected_only && !self.restart_numbering_in_selection {
+            let scope = self.scope_rows();
+            let positions = self.unit_positions(&scope);
+            rows.iter()
+                .filter_map(|r| scope.iter().position(|s| s == r))
+                .map(|k| positions[k])
+                .max()
+                .map_or(0, |pos| pos + 1)
+        } else {
+            self.unit_positions(&rows).into_iter().max().map_or(0, |pos| pos + 1)
+        };
+
+        let done = if self.copy_mode { "Copy completed." } else { "Rename completed." };
+        self.submit_batch(&origs, &final_paths, mtime, counted, done.into());
+    }
+
+    /// Hash the listed files on a worker thread while an image hash block is in use.
+    fn update_phashes(&mut self, ctx: &egui::Context) {
+        if let Some(rx) = &self.phash_rx {
+            loop {
+                match rx.try_recv() {
+                    Ok((path, hash)) => {
+                        self.phashes.insert(path, hash);
+                    }
+                    Err(mpsc::TryRecvError::Empty) => return,
+                    Err(mpsc::TryRecvError::Disconnected) => break,
+                }
+            }
+            self.phash_rx = None;
+        }
+        if !self.blocks.iter().any(|b| matches!(b.inner(), Block::PHash { .. })) {
+            return;
+        }
+        let missing: Vec<(PathBuf, bool)> = self
+            .files
+            .iter()
+            .filter(|fe| !self.phashes.contains_key(&fe.path))
+            .map(|fe| (fe.path.clone(), fe.category == FileCategory::Image))
+            .collect();
+        if missing.is_empty() {
+            return;
+        }
+        let (tx, rx) = mpsc::channel();
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            for (path, is_image) in missing {
+                let hash = if is_image { dhash(&path) } else { None };
+                if tx.send((path, hash)).is_err() {
+                    return;
+                }
+                ctx.request_repaint();
+            }
+        });
+        self.phash_rx = Some(rx);
+    }
+
+    /// Check the rows to rename and build their target paths.
+    /// Returns the rows, their current paths and the target paths.
+    fn plan_rename(&mut self, selected_only: bool) -> Option<(Vec<usize>, Vec<PathBuf>, Vec<PathBuf>)> {
+        if let Err(e) = self.scope_filter() {
+            self.log.error(format!("Invalid scope expression: {}", e));
+            return None;
+        }
+        // files gone since the last look are left out rather than failing the batch
+        let gone: Vec<usize> = (0..self.files.len())
+            .filter(|&i| !self.files[i].missing && !self.files[i].path.exists())
+            .collect();
+        for &i in &gone {
+            self.files[i].missing = true;
+        }
+        if !gone.is_empty() {
+            self.log.warn(format!("{} file(s) no longer exist and are left out.", gone.len()));
+        }
+        let scope = self.scope_rows();
+        let mut rows: Vec<usize> = if selected_only {
+            scope.iter().copied().filter(|&i| self.files[i].selected).collect()
+        } else {
+            scope.clone()
+        };
+        if selected_only && self.keep_sidecars {
+            // a selected file brings the rest of its sidecar group
+            let sidecars = self.sidecars(&scope);
+            let leader = |k: usize| sidecars[k].as_ref().map_or(k, |(l, _)| *l);
+            let chosen: HashSet<usize> =
+                (0..scope.len()).filter(|&k| self.files[scope[k]].selected).map(leader).collect();
+            rows = (0..scope.len()).filter(|&k| chosen.contains(&leader(k))).map(|k| scope[k]).collect();
+        }
+        if rows.is_empty() {
+            self.log.warn(if selected_only {
+                "No files selected (in scope)."
+            } else {
+                "No files in scope."
+            });
+            return None;
+        }
+        if self.blocks.iter().any(|b| matches!(b.inner(), Block::PHash { .. }))
+            && rows.iter().any(|&i| !self.phashes.contains_key(&self.files[i].path))
+        {
+            self.log.warn("Image hashes are still being computed. Try again in a moment.");
+            return None;
+        }
+
+        // numbering either follows the position in scope or restarts within the selection
+        let mut all_targets = self.generate_targets();
+        let mut targets: Vec<String> = if selected_only {
+            let numbered = if self.restart_numbering_in_selection { &rows } else { &scope };
+            let names = self.numbered_names(numbered, &self.blocks, &self.ext_policy, |pos, groups, fe| {
+                self.target_name(pos, groups, fe)
+            });
+            let name_of: HashMap<usize, String> = numbered.iter().copied().zip(names).collect();
+            rows.iter().map(|row| name_of[row].clone()).collect()
+        } else {
+            rows.iter().map(|&row| all_targets[row].clone()).collect()
+        };
+        if selected_only {
+            // unselected files keep their names
+            for (fe, t) in self.files.iter().zip(all_targets.iter_mut()) {
+                *t = fe.path.file_name().unwrap_or_default().to_string_lossy().to_string();
+            }
+        }
+        for (&row, t) in rows.iter().zip(&targets) {
+            all_targets[row] = t.clone();
+        }
+        if selected_only && self.deconflict {
+            self.deconflict_targets(&mut all_targets, &rows);
+            targets = rows.iter().map(|&row| all_targets[row].clone()).collect();
+        }
+        let issues = self.validate_targets(&all_targets);
+        let invalid = rows.iter().filter(|&&row| !issues[row].is_empty()).count();
+        if invalid > 0 {
+            self.log.error(format!(
+                "{} name(s) are not valid on the target filesystem. See the preview.",
+                invalid
+            ));
+            return None;
+        }
+        let (fatal, _) = self.batch_conflicts(&all_targets, &rows);
+        let conflicts = rows.iter().filter(|&&row| !fatal[row].is_empty()).count();
+        if conflicts > 0 {
+            self.log.error(format!("{} name conflict(s) block the rename. See the preview.", conflicts));
+            return None;
+        }
+
+        // final_paths creation
+        let origs: Vec<PathBuf> = rows.iter().map(|&row| self.files[row].path.clone()).collect();
+        let mut final_paths = Vec::new();
+        for (tname, &row) in targets.iter().zip(&rows) {
+            match self.target_path(&self.files[row], tname) {
+                Ok(p) => final_paths.push(p),
+                Err(e) => {
+                    self.log.error(e);
+                    return None;
+                }
+            }
+        }
+        Some((rows, origs, final_paths))
+    }
+
+    /// Where `fe` goes under the new name: its folder or "Move to", plus the date subfolder.
+    fn target_path(&self, fe: &FileEntry, name: &str) -> Result<PathBuf, String> {
+        let dir = match &self.move_to {
+            Some(dir) => dir.clone(),
+            None => fe.path.parent().unwrap_or(Path::new("")).to_path_buf(),
+        };
+        Ok(dir.join(self.date_subfolder(fe)?).join(name))
+    }
+
+    /// Save the dry-run mapping of the whole list, with collisions resolved and the
+    /// reason for every entry that stays, as CSV or JSON (by the chosen extension).
+    fn export_preview(&mut self) {
+        if let Err(e) = self.scope_filter() {
+            self.log.error(format!("Invalid scope expression: {}", e));
+            return;
+        }
+        let scope: HashSet<usize> = self.scope_rows().into_iter().collect();
+        let targets = self.generate_targets();
+        let issues = self.validate_targets(&targets);
+        let mut entries = Vec::with_capacity(self.files.len());
+        // entries that would be renamed, before collision handling
+        let mut pending = Vec::new();
+        for (row, fe) in self.files.iter().enumerate() {
+            let mut entry = PreviewEntry {
+                from: fe.path.clone(),
+                proposed: fe.path.clone(),
+                to: fe.path.clone(),
+                status: String::new(),
+            };
+            if !scope.contains(&row) {
+                entry.status = "out of scope".into();
+            } else if !fe.path.exists() {
+                entry.status = "missing".into();
+            } else if !issues[row].is_empty() {
+                entry.status = format!("invalid: {}", issues[row].join("; "));
+            } else {
+                match self.target_path(fe, &targets[row]) {
+                    Ok(p) if p == fe.path => entry.status = "unchanged".into(),
+                    Ok(p) => {
+                        entry.proposed = p;
+                        pending.push(row);
+                    }
+                    Err(e) => entry.status = e,
+                }
+            }
+            entries.push(entry);
+        }
+        let origs: Vec<PathBuf> = pending.iter().map(|&row| entries[row].from.clone()).collect();
+        let proposed: Vec<PathBuf> = pending.iter().map(|&row| entries[row].proposed.clone()).collect();
+        let (resolved, duplicates) = self.resolve_targets(&origs, &proposed);
+        let batch: HashSet<&PathBuf> = origs.iter().collect();
+        let action = if self.copy_mode { "copy" } else { "rename" };
+        for (&row, to) in pending.iter().zip(resolved) {
+            let entry = &mut entries[row];
+            entry.status = if to == entry.from && duplicates.contains(&entry.from) {
+                "identical to the target, source deleted".into()
+            } else if to == entry.from {
+                "skipped: name taken".into()
+            } else if to != entry.proposed {
+                format!("{}, numbered (name taken)", action)
+            } else if to.exists() && !batch.contains(&to) {
+                format!("{}, replaces existing file", action)
+            } else {
+                action.into()
+            };
+            entry.to = to;
+        }
+
+        let Some(path) = rfd::FileDialog::new()
+            .set_file_name("preview.csv")
+            .add_filter("CSV", &["csv"])
+            .add_filter("JSON", &["json"])
+            .save_file()
+        else {
+            return;
+        };
+        let json = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("json"));
+        let text = if json {
+            serde_json::to_string_pretty(&entries).unwrap_or_default()
+        } else {
+            let mut out = String::from("from,proposed,to,status\n");
+            for e in &entries {
+                let fields = [
+                    e.from.display().to_string(),
+                    e.proposed.display().to_string(),
+                    e.to.display().to_string(),
+                    e.status.clone(),
+                ];
+                out.push_str(&fields.iter().map(|f| csv_field(f)).collect::<Vec<_>>().join(","));
+                out.push('\n');
+            }
+            out
+        };
+        match fs::write(&path, text) {
+            Ok(()) => self.log.info(format!("Preview exported: {:?} ({} entries)", path, entries.len())),
+            Err(e) => self.log.error(format!("Failed to write {:?}: {}", path, e)),
+        }
+    }
+
+    /// Date subfolder for `fe` from `subfolder_pattern`, e.g. `2024/05`; empty when off.
+    fn date_subfolder(&self, fe: &FileEntry) -> Result<PathBuf, String> {
+        if self.subfolder_pattern.trim().is_empty() {
+            return Ok(PathBuf::new());
+        }
+        let file = ListFile { fe, phash: None, stem: None };
+        let date = if self.subfolder_exif { file.capture_date() } else { file.modified() };
+        let date = date.unwrap_or_else(|| self.file_date(fe));
+        let pattern = &self.subfolder_pattern;
+        let text = std::panic::catch_unwind(|| date.format(pattern).to_string())
+            .map_err(|_| format!("Invalid subfolder pattern: {}", pattern))?;
+        Ok(text
+            .split(['/', '\\'])
+            .map(str::trim)
+            .filter(|part| !part.is_empty() && *part != "." && *part != "..")
+            .collect())
+    }
+
+    /// Copy the files to a temp folder and run the whole rename on the copies.
+    fn test_on_copies(&mut self, selected_only: bool) {
+        let Some((rows, origs, final_paths)) = self.plan_rename(selected_only) else {
+            return;
+        };
+        let dir = std::env::temp_dir().join(format!("BulkReName-test-{}", Local::now().format("%Y%m%d-%H%M%S")));
+
+        // one subfolder per source folder, so equal names from different folders stay apart
+        let mut folders: Vec<&Path> = Vec::new();
+        for orig in &origs {
+            let parent = orig.parent().unwrap_or(Path::new(""));
+            if !folders.contains(&parent) {
+                folders.push(parent);
+            }
+        }
+        let sub = |orig: &Path| -> PathBuf {
+            if folders.len() == 1 {
+                return dir.clone();
+            }
+            let parent = orig.parent().unwrap_or(Path::new(""));
+            let k = folders.iter().position(|&f| f == parent).unwrap_or(0);
+            let name = parent.file_name().unwrap_or_default().to_string_lossy();
+            dir.join(format!("{}-{}", k + 1, name))
+        };
+        let copies: Vec<PathBuf> = origs.iter().map(|o| sub(o).join(o.file_name().unwrap_or_default())).collect();
+        let targets: Vec<PathBuf> = origs
+            .iter()
+            .zip(&final_paths)
+            .map(|(o, t)| sub(o).join(t.file_name().unwrap_or_default()))
+            .collect();
+
+        for (orig, copy) in origs.iter().zip(&copies) {
+            let copied = copy
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| copy_with_times(orig, copy, self.retry.keep_times));
+            if let Err(e) = copied {
+                self.log.error_at(orig, format!("Failed to copy for testing: {}", e));
+                let _ = fs::remove_dir_all(&dir);
+                return;
+            }
+        }
+
+        let (resolved, duplicates) = self.resolve_targets(&copies, &targets);
+        let pairs: Vec<(PathBuf, PathBuf)> = copies
+            .iter()
+            .zip(&resolved)
+            .filter(|(c, r)| c != r)
+            .map(|(c, r)| (c.clone(), r.clone()))
+            .collect();
+        if let Err(failure) = two_phase_rename(&pairs, self.retry) {
+            self.log.error(format!("Test on copies: {}", failure.message()));
+        }
+        for p in &duplicates {
+            let _ = fs::remove_file(p);
+        }
+        if let Some((mtime_rows, dates)) = self.mtime_dates(&rows) {
+            for (row, date) in mtime_rows.iter().zip(dates) {
+                let Some(k) = rows.iter().position(|r| r == row) else {
+                    continue;
+                };
+                let ft = filetime::FileTime::from_system_time(SystemTime::from(date));
+                let _ = filetime::set_file_mtime(&resolved[k], ft);
+            }
+        }
+
+        let results = origs
+            .iter()
+            .zip(&resolved)
+            .map(|(orig, r)| {
+                let result = if duplicates.contains(r) {
+                    "(deleted duplicate)".to_string()
+                } else if r.exists() {
+                    r.strip_prefix(&dir).unwrap_or(r).display().to_string()
+                } else {
+                    "(missing)".to_string()
+                };
+                (orig.file_name().unwrap_or_default().to_string_lossy().to_string(), result)
+            })
+            .collect();
+        self.log.info(format!("Test copies renamed in {:?}", dir));
+        self.sandbox = Some(Sandbox { dir, results });
+    }
+
+    /// Resolve a batch and run it, asking for confirmation first when it is risky.
+    fn submit_batch(
+        &mut self,
+        origs: &[PathBuf],
+        final_paths: &[PathBuf],
+        mtime: Option<(Vec<usize>, Vec<DateTime<Local>>)>,
+        counted: usize,
+        done: String,
+    ) {
+        if self.running.is_some() {
+            return;
+        }
+        if self.collision == CollisionStrategy::Ask && self.collision_answers.is_empty() {
+            let conflicts = find_collisions(origs, final_paths, self.copy_mode);
+            if !conflicts.is_empty() {
+                self.collision_prompt = Some(CollisionPrompt {
+                    origs: origs.to_vec(),
+                    final_paths: final_paths.to_vec(),
+                    mtime,
+                    counted,
+                    done,
+                    answers: vec![None; conflicts.len()],
+                    conflicts,
+                    apply_to_all: false,
+                    manual: None,
+                });
+                return;
+            }
+        }
+        let Some(mut batch) = self.prepare_batch(origs, final_paths) else {
+            return;
+        };
+        batch.mtime = mtime;
+        batch.counted = counted;
+        batch.done = done;
+        self.queue_batch(batch);
+    }
+
+    /// Start a prepared batch, or hold it for confirmation when it is risky.
+    fn queue_batch(&mut self, batch: PendingBatch) {
+        if batch.risky() {
+            self.confirm_ack = false;
+            self.pending_batch = Some(batch);
+        } else {
+            self.start_batch(batch);
+        }
+    }
+
+    /// Rename a prepared batch, then run its post-steps. Returns false when the rename failed.
+    fn commit_batch(&mut self, mut batch: PendingBatch) -> bool {
+        let Some(prepared) = self.prepare_target_dirs(&batch.pairs) else {
+            return false;
+        };
+        let started = Instant::now();
+        let result = if self.copy_mode {
+            copy_files(&batch.pairs, self.retry.keep_times, None)
+        } else {
+            self.rename_journaled(&batch.pairs)
+        };
+        batch.summary.elapsed = started.elapsed();
+        self.settle_commit(batch, prepared, result)
+    }
+
+    /// `commit_batch` on a worker thread; `poll_running` picks up the result.
+    fn start_batch(&mut self, batch: PendingBatch) {
+        let Some(prepared) = self.prepare_target_dirs(&batch.pairs) else {
+            return;
+        };
+        let progress = Arc::new(Progress::default());
+        let (tx, rx) = mpsc::channel();
+        let (pairs, copy, retry, worker) = (batch.pairs.clone(), self.copy_mode, self.retry, progress.clone());
+        thread::spawn(move || {
+            let result = if copy {
+                copy_files(&pairs, retry.keep_times, Some(&worker))
+            } else {
+                two_phase_rename_journaled(&pairs, retry, Some(&Self::transaction_path()), Some(&worker))
+            };
+            tx.send(result).ok();
+        });
+        self.running = Some(RunningBatch { batch, started: Instant::now(), prepared, progress, rx });
+    }
+
+    fn poll_running(&mut self) {
+        let Some(run) = &self.running else { return };
+        let result = match run.rx.try_recv() {
+            Ok(result) => result,
+            Err(mpsc::TryRecvError::Empty) => return,
+            Err(mpsc::TryRecvError::Disconnected) => {
+                self.log.error("The rename stopped unexpectedly; restart to recover the interrupted batch.");
+                self.running = None;
+                self.answer_api(false);
+                return;
+            }
+        };
+        if let Some(mut run) = self.running.take() {
+            run.batch.summary.elapsed = run.started.elapsed();
+            let ok = self.settle_commit(run.batch, run.prepared, result);
+            self.answer_api(ok);
+            self.finish_arrival(ok);
+            match &mut self.tree_run {
+                Some(tree) if ok => {
+                    tree.done += 1;
+                    self.next_tree_step();
+                }
+                Some(_) => self.stop_tree(),
+                None => {}
+            }
+        }
+    }
+
+    /// Reply to the /execute call that started the finished batch, if any.
+    fn answer_api(&mut self, ok: bool) {
+        if let Some((reply, start)) = self.api_waiting.take() {
+            reply.send((200, self.api_reply(start, ok, None))).ok();
+        }
+    }
+
+    /// Record the outcome of a batch's rename, then run its post-steps.
+    fn settle_commit(
+        &mut self,
+        batch: PendingBatch,
+        prepared: Prepared,
+        result: Result<(), RenameFailure>,
+    ) -> bool {
+        let manifest = (self.manifest != Manifest::Off).then(|| batch.pairs.clone());
+        let copies: HashMap<PathBuf, PathBuf> =
+            if self.copy_mode { batch.pairs.iter().cloned().collect() } else { HashMap::new() };
+        let renamed: HashSet<PathBuf> = batch.pairs.iter().map(|(_, dst)| dst.clone()).collect();
+        let mut summary = batch.summary;
+        if let Err(failure) = &result {
+            summary.error = Some(failure.message());
+            summary.failed = failure.sources();
+        }
+        self.batch_summary = Some(summary);
+        if !self.settle_rename(batch.pairs, &batch.duplicates, prepared, result) {
+            return false;
+        }
+
+        // post-step: file mtime = chosen Date block value, only on files this batch renamed (or copied)
+        if let Some((rows, dates)) = batch.mtime {
+            let mut failed = 0;
+            for (&row, date) in rows.iter().zip(dates) {
+                let fe = &mut self.files[row];
+                let target = match copies.get(&fe.path) {
+                    Some(copy) => copy.clone(),
+                    None if self.copy_mode || !renamed.contains(&fe.path) => continue,
+                    None => fe.path.clone(),
+                };
+                let ft = filetime::FileTime::from_system_time(SystemTime::from(date));
+                if let Err(e) = filetime::set_file_mtime(&target, ft) {
+                    self.log.error_at(&target, format!("Failed to set mtime: {}", e));
+                    failed += 1;
+                }
+                fe.snapshot = FileEntry::read_snapshot(&fe.path);
+            }
+            if failed == 0 {
+                self.log.info("Modification times updated.");
+            }
+        }
+
+        if batch.counted > 0 {
+            self.advance_counters(batch.counted);
+        }
+        self.log.info(batch.done);
+
+        // post-step: checksums, hashed on a worker thread
+        if let Some(pairs) = manifest {
+            let (tx, rx) = mpsc::channel();
+            let mode = self.manifest;
+            thread::spawn(move || {
+                tx.send(write_manifests(mode, &pairs)).ok();
+            });
+            self.manifest_rx = Some(rx);
+            self.log.info("Writing checksums...");
+        }
+        true
+    }
+
+    /// Apply the collision strategy and drop unchanged entries.
+    /// Returns None when there is nothing to rename.
+    fn prepare_batch(&mut self, origs: &[PathBuf], final_paths: &[PathBuf]) -> Option<PendingBatch> {
+        let (resolved_paths, duplicates) = self.resolve_targets(origs, final_paths);
+        // copies never trade names
+        let cycles = if self.copy_mode { Vec::new() } else { find_cycles(origs, &resolved_paths) };
+        for cycle in cycles {
+            let names: Vec<String> = cycle
+                .iter()
+                .chain(cycle.first())
+                .map(|p| p.file_name().unwrap_or_default().to_string_lossy().to_string())
+                .collect();
+            self.log.info(format!("Swap/cycle resolved: {}", names.join(" → ")));
+        }
+
+        let pairs: Vec<(PathBuf, PathBuf)> = origs
+            .iter()
+            .zip(resolved_paths.iter())
+            .filter(|(orig, desired)| orig != desired)
+            .map(|(orig, desired)| (orig.clone(), desired.clone()))
+            .collect();
+
+        if pairs.is_empty() {
+            self.delete_duplicates(&duplicates);
+            self.log.warn(if self.copy_mode { "No files to copy." } else { "No files to rename." });
+            return None;
+        }
+        let summary = self.summarize_batch(origs, final_paths, &resolved_paths, &duplicates);
+        Some(PendingBatch { summary, ..PendingBatch::new(pairs, duplicates) })
+    }
+
+    /// Counts and skip reasons of a resolved batch; `settle_commit` adds the outcome.
+    fn summarize_batch(
+        &self,
+        origs: &[PathBuf],
+        final_paths: &[PathBuf],
+        resolved: &[PathBuf],
+        duplicates: &[PathBuf],
+    ) -> BatchSummary {
+        let mut summary = BatchSummary { copy: self.copy_mode, ..Default::default() };
+        for ((orig, wanted), got) in origs.iter().zip(final_paths).zip(resolved) {
+            if orig == wanted {
+                summary.unchanged += 1;
+            } else if got == wanted {
+                summary.renamed += 1;
+            } else if got != orig {
+                summary.suffixed += 1;
+            } else {
+                let strategy = self.collision_answers.get(orig).copied().unwrap_or(self.collision);
+                let reason = if duplicates.contains(orig) {
+                    "identical file exists, source deleted"
+                } else if strategy == CollisionStrategy::Dedup {
+                    "identical file exists"
+                } else {
+                    "name taken"
+                };
+                summary.skipped.push((orig.clone(), reason));
+            }
+        }
+        summary
+    }
+
+    /// Select exactly the listed files whose path is in `paths` and scroll to the first.
+    fn select_paths(&mut self, paths: &[PathBuf]) {
+        let wanted: HashSet<&PathBuf> = paths.iter().collect();
+        for fe in &mut self.files {
+            fe.selected = wanted.contains(&fe.path);
+        }
+        if let Some(row) = self.files.iter().position(|fe| fe.selected) {
+            self.selected_idx = Some(row);
+            self.reveal_row = Some(row);
+        }
+    }
+
+    /// Create missing target folders, check that every folder answers and set the files the
+    /// batch replaces aside (they go to the trash once it succeeds). None (with a message) when
+    /// the batch can't start.
+    fn prepare_target_dirs(&mut self, pairs: &[(PathBuf, PathBuf)]) -> Option<Prepared> {
+        let targets: Vec<PathBuf> = pairs.iter().map(|(_, desired)| desired.clone()).collect();
+        let created_dirs = match create_target_dirs(&targets) {
+            Ok(created) => created,
+            Err((e, dir)) => {
+                self.log.error(format!("Rename aborted: cannot create folder {:?}: {}", dir, e));
+                return None;
+            }
+        };
+        let dirs = pairs
+            .iter()
+            .flat_map(|(orig, desired)| [orig.parent(), desired.parent()])
+            .flatten();
+        if let Err(e) = check_dirs_reachable(dirs) {
+            self.log.error(format!("Rename aborted: {}", e));
+            remove_empty_dirs(&created_dirs);
+            return None;
+        }
+        let replaced = replaced_files(pairs, self.copy_mode);
+        let (replaced, overwritten) =
+            if self.settings.trash_replaced { (replaced, Vec::new()) } else { (Vec::new(), replaced) };
+        let aside = match set_aside(&replaced) {
+            Ok(aside) => aside,
+            Err(e) => {
+                self.log.error(format!("Rename aborted: cannot move the replaced files aside ({}).", e));
+                remove_empty_dirs(&created_dirs);
+                return None;
+            }
+        };
+        Some(Prepared { created_dirs, aside, overwritten })
+    }
+
+    /// After a successful batch: the files it replaced go from aside to the trash.
+    /// Returns the original paths of the ones now in the trash.
+    fn trash_replaced(&mut self, aside: &[(PathBuf, PathBuf)]) -> Vec<PathBuf> {
+        let mut trashed = Vec::new();
+        for (orig, tmp) in aside {
+            match trash::delete(tmp) {
+                Ok(()) => trashed.push(orig.clone()),
+                Err(e) => self.log.error_at(tmp, format!("Replaced file kept under this name, not trashed: {}", e)),
+            }
+        }
+        if !trashed.is_empty() {
+            self.log.info(format!("{} replaced file(s) moved to the trash.", trashed.len()));
+        }
+        trashed
+    }
+
+    /// Record undo for a finished rename, or report its failure. Returns false when nothing was renamed.
+    fn settle_rename(
+        &mut self,
+        pairs: Vec<(PathBuf, PathBuf)>,
+        duplicates: &[PathBuf],
+        prepared: Prepared,
+        result: Result<(), RenameFailure>,
+    ) -> bool {
+        if let Err(failure) = result {
+            // replaced files come back where the batch didn't take their name after all
+            let left_aside = put_back(&prepared.aside);
+            remove_empty_dirs(&prepared.created_dirs);
+            self.log.error(failure.message());
+            match &failure {
+                RenameFailure::Cancelled { not_restored } if not_restored.is_empty() => {}
+                RenameFailure::Staging { error, not_restored, .. }
+                    if error.kind() == std::io::ErrorKind::PermissionDenied
+                        && not_restored.is_empty()
+                        && cfg!(windows) =>
+                {
+                    self.elevation_offer = Some(pairs);
+                }
+                _ => {
+                    let what = if self.copy_mode { "Copy" } else { "Rename" };
+                    self.error_report = Some(ErrorReport::from_failure(&failure, what));
+                }
+            }
+            if !left_aside.is_empty() {
+                let names: Vec<String> = left_aside.iter().map(|p| p.display().to_string()).collect();
+                self.log.error(format!(
+                    "{} replaced file(s) could not be put back (their name is taken) and keep these names:",
+                    names.len()
+                ));
+                self.log.errors(names.clone());
+                let report = self.error_report.get_or_insert_with(|| ErrorReport {
+                    title: "Replaced files not put back".into(),
+                    summary: String::new(),
+                    files: Vec::new(),
+                });
+                report.files.extend(names.into_iter().map(|n| format!("{} (replaced file, kept aside)", n)));
+            }
+            return false;
+        }
+
+        let replaced = Replaced { trashed: self.trash_replaced(&prepared.aside), overwritten: prepared.overwritten };
+        self.finish_batch(&pairs, self.copy_mode, prepared.created_dirs, replaced);
+        self.delete_duplicates(duplicates);
+        true
+    }
+
+    /// Re-read the folder tree down to the deepest rule.
+    fn scan_tree(&mut self) {
+        let max_depth = self.tree_rules.iter().map(|r| r.depth).max().unwrap_or(1);
+        self.tree_entries = match &self.tree_root {
+            Some(root) => scan_tree(root, max_depth),
+            None => Vec::new(),
+        };
+    }
+
+    /// New name per tree entry (None = no rule or unchanged).
+    /// Numbering restarts in each folder, separately for every rule.
+    fn tree_targets(&self) -> Vec<Option<String>> {
+        let mut counters: HashMap<(Option<&Path>, usize), usize> = HashMap::new();
+        self.tree_entries
+            .iter()
+            .map(|e| {
+                let (k, rule) = self
+                    .tree_rules
+                    .iter()
+                    .enumerate()
+                    .find(|(_, r)| r.depth == e.depth && r.target.matches(e.is_dir))?;
+                let (blocks, trailing_fix, ext_policy) = if rule.template.is_empty() {
+                    (&self.blocks, self.trailing_fix, &self.ext_policy)
+                } else {
+                    let tpl = Self::find_template(&self.saved_templates, &rule.template)?;
+                    (&tpl.blocks, tpl.trailing_fix, &tpl.extension)
+                };
+                let counter = counters.entry((e.path.parent(), k)).or_insert(0);
+                let idx = *counter;
+                *counter += 1;
+                let fe = FileEntry {
+                    path: e.path.clone(),
+                    category: FileCategory::Other,
+                    snapshot: None,
+                    changed: false,
+                    missing: false,
+                    selected: false,
+                    excluded: false,
+                    manual_name: None,
+                    tag: String::new(),
+                    rating: Default::default(),
+                    audio: Default::default(),
+                    audio_tags: Default::default(),
+                    exif_date: Default::default(),
+                    camera: Default::default(),
+                    dimensions: Default::default(),
+                };
+                let name = self.target_name_with(idx, &[], &fe, blocks, trailing_fix, ext_policy);
+                (name != e.path.file_name().unwrap_or_default().to_string_lossy()).then_some(name)
+            })
+            .collect()
+    }
+
+    /// Rename the folder tree one depth at a time, deepest first, so parent paths stay valid.
+    fn rename_tree(&mut self) {
+        if self.running.is_some() || self.tree_run.is_some() {
+            return;
+        }
+        let targets = self.tree_targets();
+        let invalid = self
+            .tree_entries
+            .iter()
+            .zip(&targets)
+            .filter(|(e, t)| t.as_ref().is_some_and(|t| !self.profile_for(e.path.parent()).check_name(t).is_empty()))
+            .count();
+        if invalid > 0 {
+            self.log.error(format!("{} name(s) in the folder tree are not valid. See the preview.", invalid));
+            return;
+        }
+        let max_depth = self.tree_entries.iter().map(|e| e.depth).max().unwrap_or(0);
+        let steps = (1..=max_depth)
+            .filter_map(|depth| {
+                let (origs, final_paths): (Vec<PathBuf>, Vec<PathBuf>) = self
+                    .tree_entries
+                    .iter()
+                    .zip(&targets)
+                    .filter(|(e, _)| e.depth == depth)
+                    .filter_map(|(e, t)| t.as_ref().map(|t| (e.path.clone(), e.path.with_file_name(t))))
+                    .unzip();
+                (!origs.is_empty()).then_some((depth, origs, final_paths))
+            })
+            .collect();
+        self.tree_run = Some(TreeRun { steps, depth: 0, done: 0 });
+        self.next_tree_step();
+    }
+
+    /// Submit the next depth of the folder tree rename; the rest follow as each batch finishes.
+    fn next_tree_step(&mut self) {
+        let Some(run) = &mut self.tree_run else { return };
+        let Some((depth, origs, final_paths)) = run.steps.pop() else {
+            let steps = run.done;
+            self.tree_run = None;
+            if steps > 0 {
+                self.log.info(format!(
+                    "Folder tree renamed in {} step(s), one per depth (each is a separate History entry).",
+                    steps
+                ));
+            }
+            self.scan_tree();
+            return;
+        };
+        run.depth = depth;
+        let done = format!("Folder tree: depth {} renamed.", depth);
+        self.submit_batch(&origs, &final_paths, None, 0, done);
+    }
+
+    /// The current depth failed, was cancelled or didn't start: leave the folders above it as they are.
+    fn stop_tree(&mut self) {
+        if let Some(run) = self.tree_run.take() {
+            self.log.error(format!("Folder tree rename stopped at depth {}.", run.depth));
+            self.scan_tree();
+        }
+    }
+
+    /// Run a JSON plan (`[{"from": ..., "to": ...}]`) made elsewhere.
+    fn import_plan(&mut self) {
+        let Some(path) = rfd::FileDialog::new().add_filter("JSON", &["json"]).pick_file() else {
+            return;
+        };
+        let plan = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| serde_json::from_str::<Vec<PlanEntry>>(&text).map_err(|e| e.to_string()));
+        let plan = match plan {
+            Ok(plan) => plan,
+            Err(e) => {
+                self.log.error(format!("Failed to read plan {:?}: {}", path, e));
+                return;
+            }
+        };
+        let (origs, final_paths) = match check_plan(plan) {
+            Ok(paths) => paths,
+            Err(problems) => {
+                self.log.error(format!("Plan not executed, {} problem(s):", problems.len()));
+                self.log.errors(problems);
+                return;
+            }
+        };
+        self.submit_batch(&origs, &final_paths, None, 0, format!("Plan executed: {:?}", path));
+    }
+
+    /// Rename list entries by a CSV of `old_name,new_name` rows (e.g. from a spreadsheet).
+    fn import_mapping(&mut self) {
+        let Some(path) = rfd::FileDialog::new().add_filter("CSV", &["csv", "tsv", "txt"]).pick_file() else {
+            return;
+        };
+        let rows = match fs::read_to_string(&path) {
+            Ok(text) => parse_csv(&text),
+            Err(e) => {
+                self.log.error(format!("Failed to read mapping {:?}: {}", path, e));
+                return;
+            }
+        };
+        let listed: Vec<&PathBuf> = self.files.iter().map(|f| &f.path).collect();
+        let checked = mapping_to_plan(&rows, &listed).and_then(check_plan);
+        let (origs, final_paths) = match checked {
+            Ok(paths) => paths,
+            Err(problems) => {
+                self.log.error(format!("Mapping not applied, {} problem(s):", problems.len()));
+                self.log.errors(problems);
+                return;
+            }
+        };
+        self.submit_batch(&origs, &final_paths, None, 0, format!("Mapping applied: {:?}", path));
+    }
+
+    /// Apply the collision strategy to the desired paths (see `bulkrename_core::resolve_targets`),
+    /// or the answers given to Ask for the files they were given for.
+    fn resolve_targets(&self, origs: &[PathBuf], final_paths: &[PathBuf]) -> (Vec<PathBuf>, Vec<PathBuf>) {
+        let collision: Vec<CollisionStrategy> = origs
+            .iter()
+            .map(|p| self.collision_answers.get(p).copied().unwrap_or(self.collision))
+            .collect();
+        if self.copy_mode {
+            return (resolve_copy_targets_each(&collision, origs, final_paths), Vec::new());
+        }
+        resolve_targets_each(&collision, self.dedup_delete_source, origs, final_paths)
+    }
+
+    /// Take the answer to the conflict being asked (all remaining ones with "Apply to all").
+    fn answer_collision(&mut self, answer: CollisionStrategy) {
+        let Some(prompt) = &mut self.collision_prompt else { return };
+        let Some(k) = prompt.current() else { return };
+        prompt.answers[k] = Some(answer);
+        if prompt.apply_to_all {
+            for k in k + 1..prompt.conflicts.len() {
+                // two files of the batch on one name: one of them would be lost
+                let in_batch = prompt.conflicts[k].1.is_some();
+                if prompt.answers[k].is_none() && !(answer == CollisionStrategy::Overwrite && in_batch) {
+                    prompt.answers[k] = Some(answer);
+                }
+            }
+        }
+        if prompt.current().is_some() {
+            return;
+        }
+        let Some(prompt) = self.collision_prompt.take() else { return };
+        self.collision_answers = prompt.origs.iter().cloned().zip(prompt.member_answers()).collect();
+        self.submit_batch(&prompt.origs, &prompt.final_paths, prompt.mtime, prompt.counted, prompt.done);
+        self.collision_answers.clear();
+    }
+
+    /// Give the file in conflict the typed name; it has to be valid and free.
+    fn rename_collision_manually(&mut self) -> Result<(), String> {
+        let Some(prompt) = &self.collision_prompt else { return Ok(()) };
+        let (Some(k), Some(name)) = (prompt.current(), &prompt.manual) else { return Ok(()) };
+        let i = prompt.conflicts[k].0;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("empty name".into());
+        }
+        let dir = prompt.final_paths[i].parent().map(Path::to_path_buf).unwrap_or_default();
+        let problems = self.profile_for(Some(dir.as_path())).check_name(name);
+        if !problems.is_empty() {
+            return Err(problems.join("; "));
+        }
+        let mut final_paths = prompt.final_paths.clone();
+        final_paths[i] = dir.join(name);
+        if find_collisions(&prompt.origs, &final_paths, self.copy_mode).iter().any(|&(j, _)| j == i) {
+            return Err("this name is taken too".into());
+        }
+        if let Some(prompt) = &mut self.collision_prompt {
+            prompt.final_paths = final_paths;
+            prompt.manual = None;
+        }
+        // should the name be taken after all, the file stays
+        self.answer_collision(CollisionStrategy::Skip);
+        Ok(())
+    }
+
+    /// Remove sources found identical to their existing target (to the trash, or for good).
+    fn delete_duplicates(&mut self, duplicates: &[PathBuf]) {
+        if duplicates.is_empty() {
+            return;
+        }
+        let mut deleted = 0;
+        for p in duplicates {
+            let result = if self.settings.trash_replaced {
+                trash::delete(p).map_err(|e| e.to_string())
+            } else {
+                fs::remove_file(p).map_err(|e| e.to_string())
+            };
+            match result {
+                Ok(()) => deleted += 1,
+                Err(e) => self.log.error_at(p, format!("Failed to delete duplicate: {}", e)),
+            }
+        }
+        self.files.retain(|f| !duplicates.contains(&f.path));
+        self.selected_idx = None;
+        self.dirs_dirty = true;
+        let verb = if self.settings.trash_replaced { "moved to the trash" } else { "deleted" };
+        self.log.info(format!("{} duplicate source(s) {}.", deleted, verb));
+    }
+
+    /// Record a completed batch for undo and point the list at the new names.
+    /// A new batch clears Redo.
+    fn finish_batch(
+        &mut self,
+        pairs: &[(PathBuf, PathBuf)],
+        copied: bool,
+        created_dirs: Vec<PathBuf>,
+        replaced: Replaced,
+    ) {
+        let mut undo_map = HashMap::new();
+        for (orig, final_path) in pairs {
+            undo_map.insert(orig.clone(), final_path.clone());
+        }
+        let time = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        self.redo_actions.clear();
+        if copied {
+            // the list keeps showing the originals
+            let batch = LastBatch { mapping: undo_map, copied, created_dirs, time, replaced, counters: None };
+            self.last_actions.push(batch);
+            self.save_undo_journal();
+            self.dirs_dirty = true;
+            self.append_history("copy", pairs);
+            return;
+        }
+        for fe in &mut self.files {
+            if let Some(final_path) = undo_map.get(&fe.path) {
+                fe.path = final_path.clone();
+                fe.missing = false;
+                fe.manual_name = None;
+            }
+        }
+        for (orig, final_path) in pairs {
+            if let Some(hash) = self.phashes.remove(orig) {
+                self.phashes.insert(final_path.clone(), hash);
+            }
+        }
+        let batch = LastBatch { mapping: undo_map, copied, created_dirs, time, replaced, counters: None };
+        self.last_actions.push(batch);
+        self.save_undo_journal();
+        self.dirs_dirty = true;
+        self.append_history("rename", pairs);
+    }
+
+    /// Hand a batch that failed with "access denied" to an elevated copy of the app.
+    fn run_elevated(&mut self, pairs: Vec<(PathBuf, PathBuf)>) {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let plan_path = std::env::temp_dir().join(format!("bulkrename-plan-{}.json", nanos));
+        let json = match serde_json::to_string(&pairs) {
+            Ok(json) => json,
+            Err(e) => {
+                self.log.error(format!("Failed to write plan: {}", e));
+                return;
+            }
+        };
+        if let Err(e) = fs::write(&plan_path, json) {
+            self.log.error(format!("Failed to write plan: {}", e));
+            return;
+        }
+        match spawn_elevated(&format!("{} \"{}\"", APPLY_PLAN_ARG, plan_path.display())) {
+            Ok(()) => {
+                self.log.info("Waiting for the elevated helper...");
+                self.pending_elevation = Some(PendingElevation { plan: plan_path, pairs, started: Instant::now() });
+            }
+            Err(e) => {
+                let _ = fs::remove_file(&plan_path);
+                self.log.error(format!("Elevation failed: {}", e));
+            }
+        }
+    }
+
+    /// Pick up the result written by the elevated helper.
+    /// The helper removes the plan once it has read it; a plan still there after `ELEVATION_TIMEOUT`
+    /// means it never started.
+    fn poll_elevation(&mut self) {
+        let Some(pending) = &self.pending_elevation else { return };
+        let result_path = plan_result_path(&pending.plan);
+        let Ok(text) = fs::read_to_string(&result_path) else {
+            if pending.started.elapsed() > ELEVATION_TIMEOUT && fs::remove_file(&pending.plan).is_ok() {
+                self.pending_elevation = None;
+                self.log.error("The elevated helper didn't start (was the prompt dismissed?); nothing was renamed.");
+            }
+            return;
+        };
+        let Ok(result) = serde_json::from_str::<Result<(), String>>(&text) else { return };
+        let PendingElevation { plan, pairs, .. } = self.pending_elevation.take().unwrap();
+        let _ = fs::remove_file(&plan);
+        let _ = fs::remove_file(&result_path);
+        match result {
+            Ok(()) => {
+                self.finish_batch(&pairs, false, Vec::new(), Replaced::default());
+                self.log.info("Rename completed (elevated).");
+            }
+            Err(e) => {
+                self.log.error(format!("Elevated rename failed: {}", e));
+                self.error_report = Some(ErrorReport {
+                    title: "Elevated rename failed".into(),
+                    summary: e,
+                    files: pairs.iter().map(|(orig, _)| orig.display().to_string()).collect(),
+                });
+            }
+        }
+    }
+
+    /// Stop waiting for the elevated helper. Taking its plan away keeps it from renaming anything
+    /// unless it has already started.
+    fn cancel_elevation(&mut self) {
+        let Some(pending) = self.pending_elevation.take() else { return };
+        if fs::remove_file(&pending.plan).is_ok() {
+            self.log.warn("Elevated rename cancelled; nothing was renamed.");
+        } else {
+            self.log.warn(
+                "Stopped waiting for the elevated helper; it had started and may still finish. \
+                 Refresh the list to see the names on disk.",
+            );
+        }
+    }
+
+    /// Revert one file of the most recent batch, leaving the rest renamed.
+    fn undo_single(&mut self, orig: &Path) {
+        let Some(last) = self.last_actions.last_mut() else { return };
+        let Some(final_path) = last.mapping.get(orig).cloned() else { return };
+        if last.copied {
+            if let Err(e) = fs::remove_file(&final_path) {
+                self.log.error_at(&final_path, format!("Failed to delete copy: {}", e));
+                return;
+            }
+            last.mapping.remove(orig);
+            if last.mapping.is_empty() {
+                self.last_actions.pop();
+            }
+            self.save_undo_journal();
+            self.dirs_dirty = true;
+            self.append_history("undo copy", &[(final_path.clone(), orig.to_path_buf())]);
+            let name = final_path.file_name().unwrap_or_default().to_string_lossy();
+            self.log.info(format!("Copy deleted: {}", name));
+            return;
+        }
+        if orig.exists() {
+            self.log.error_at(orig, "Cannot undo, the original name is taken again.");
+            return;
+        }
+        if let Err(e) = self.retry.rename(&final_path, orig) {
+            self.log.error_at(&final_path, format!("Failed to undo to {:?}: {}", orig, e));
+            return;
+        }
+        last.mapping.remove(orig);
+        if last.mapping.is_empty() {
+            self.last_actions.pop();
+        }
+        self.save_undo_journal();
+        if let Some(fe) = self.files.iter_mut().find(|f| f.path == final_path) {
+            fe.path = orig.to_path_buf();
+        }
+        self.dirs_dirty = true;
+        self.append_history("undo", &[(final_path.clone(), orig.to_path_buf())]);
+        self.log.info(format!(
+            "Undone: {} -> {}",
+            final_path.file_name().unwrap_or_default().to_string_lossy(),
+            orig.file_name().unwrap_or_default().to_string_lossy()
+        ));
+    }
+
+    /// Rename every file of a logged batch back to its old path.
+    /// Nothing is touched unless all renamed files are still present and no old path is taken.
+    fn revert_batch(&mut self, records: &[HistoryRecord]) {
+        if records.first().is_some_and(|r| r.action == "copy") {
+            let mut deleted = Vec::new();
+            for r in records {
+                match fs::remove_file(&r.new) {
+                    Ok(()) => deleted.push((r.new.clone(), r.old.clone())),
+                    Err(e) => self.log.error_at(&r.new, format!("Failed to delete copy: {}", e)),
+                }
+            }
+            self.dirs_dirty = true;
+            self.append_history("undo copy", &deleted);
+            self.log.info(format!("Deleted {} copy(ies).", deleted.len()));
+            return;
+        }
+        let news: HashSet<&PathBuf> = records.iter().map(|r| &r.new).collect();
+        let mut conflicts = Vec::new();
+        for r in records {
+            if !r.new.exists() {
+                conflicts.push(format!("missing: {:?}", r.new));
+            } else if r.old.exists() && !news.contains(&r.old) {
+                conflicts.push(format!("already exists: {:?}", r.old));
+            }
+        }
+        if !conflicts.is_empty() {
+            self.log.error(format!("Cannot revert batch, {} conflict(s):", conflicts.len()));
+            self.log.errors(conflicts);
+            return;
+        }
+        let pairs: Vec<(PathBuf, PathBuf)> =
+            records.iter().map(|r| (r.new.clone(), r.old.clone())).collect();
+        if let Err(failure) = self.rename_journaled(&pairs) {
+            self.log.error(format!("Revert: {}", failure.message()));
+            self.error_report = Some(ErrorReport::from_failure(&failure, "Revert"));
+            return;
+        }
+        let moved: HashMap<&PathBuf, &PathBuf> = pairs.iter().map(|(f, t)| (f, t)).collect();
+        for fe in self.files.iter_mut() {
+            if let Some(to) = moved.get(&fe.path) {
+                fe.path = (*to).clone();
+            }
+        }
+        self.dirs_dirty = true;
+        self.append_history("revert", &pairs);
+        self.log.info(format!("Reverted {} file(s).", pairs.len()));
+    }
+
+    /// Save a `.bat`/`.sh` script that reverses the last batch without this app.
+    fn export_revert_script(&mut self) {
+        let Some(last) = self.last_actions.last() else { return };
+        let mut entries: Vec<(PathBuf, PathBuf)> =
+            last.mapping.iter().map(|(o, f)| (o.clone(), f.clone())).collect();
+        entries.sort();
+        let default_name = if cfg!(windows) { "revert.bat" } else { "revert.sh" };
+        let Some(path) = rfd::FileDialog::new()
+            .set_file_name(default_name)
+            .add_filter("Script", &["bat", "cmd", "sh"])
+            .save_file()
+        else {
+            return;
+        };
+        let batch = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| e.eq_ignore_ascii_case("bat") || e.eq_ignore_ascii_case("cmd"));
+        if let Err(e) = fs::write(&path, revert_script(&entries, batch)) {
+            self.log.error(format!("Failed to write {:?}: {}", path, e));
+            return;
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let _ = fs::set_permissions(&path, fs::Permissions::from_mode(0o755));
+        }
+        self.log.info(format!("Revert script saved: {:?}", path));
+    }
+
+    fn undo(&mut self) {
+        if let Some(LastBatch { mapping, copied: true, created_dirs, time, replaced, .. }) = self.last_actions.last() {
+            let mut failed = Vec::new();
+            let mut deleted = Vec::new();
+            for (orig, copy) in mapping {
+                // the copy took the place of an earlier file: deleting it would leave nothing there
+                if replaced.overwritten.contains(copy) {
+                    failed.push(format!("{}: replaced a file that was there before, kept", copy.display()));
+                    continue;
+                }
+                match fs::remove_file(copy) {
+                    Ok(()) => deleted.push((copy.clone(), orig.clone())),
+                    Err(e) => failed.push(format!("{}: {}", copy.display(), e)),
+                }
+            }
+            remove_empty_dirs(created_dirs);
+            let mut redo = LastBatch {
+                mapping: deleted.iter().map(|(copy, orig)| (orig.clone(), copy.clone())).collect(),
+                copied: true,
+                created_dirs: Vec::new(),
+                time: time.clone(),
+                replaced: Replaced::default(),
+                counters: None,
+            };
+            let trashed = replaced.trashed.clone();
+            redo.counters = self.last_actions.pop().and_then(|last| last.counters);
+            if let Some(counters) = &redo.counters {
+                self.restore_counters(counters);
+            }
+            if !redo.mapping.is_empty() {
+                self.redo_actions.push(redo);
+            }
+            self.save_undo_journal();
+            if !failed.is_empty() {
+                self.error_report = Some(ErrorReport {
+                    title: "Undo incomplete".into(),
+                    summary: format!("{} copy(ies) were deleted; these were not:", deleted.len()),
+                    files: failed,
+                });
+            }
+            self.dirs_dirty = true;
+            self.append_history("undo copy", &deleted);
+            self.log.info(format!("Deleted {} copy(ies).", deleted.len()));
+            self.note_still_trashed(&trashed);
+        } else if let Some(batch) = self.last_actions.pop() {
+            // swaps and cycles: every file goes back in one journaled two-phase rename, and
+            // nothing is touched unless all of them are still there and no old name is taken
+            let mut pairs: Vec<(PathBuf, PathBuf)> =
+                batch.mapping.iter().map(|(orig, final_path)| (final_path.clone(), orig.clone())).collect();
+            pairs.sort();
+            let finals: HashSet<&PathBuf> = pairs.iter().map(|(final_path, _)| final_path).collect();
+            let mut conflicts = Vec::new();
+            for (final_path, orig) in &pairs {
+                if !final_path.exists() {
+                    conflicts.push(format!("missing: {:?}", final_path));
+                } else if orig.exists() && !finals.contains(orig) {
+                    conflicts.push(format!("already exists: {:?}", orig));
+                }
+            }
+            if !conflicts.is_empty() {
+                self.log.error(format!("Cannot undo, {} conflict(s):", conflicts.len()));
+                self.log.errors(conflicts);
+                self.last_actions.push(batch);
+                return;
+            }
+            if let Err(failure) = self.rename_journaled(&pairs) {
+                self.log.error(format!("Undo: {}", failure.message()));
+                self.error_report = Some(ErrorReport::from_failure(&failure, "Undo"));
+                self.last_actions.push(batch);
+                self.dirs_dirty = true;
+                return;
+            }
+            let LastBatch { created_dirs, time, replaced, counters, .. } = batch;
+            let moved: HashMap<&PathBuf, &PathBuf> = pairs.iter().map(|(f, t)| (f, t)).collect();
+            for fe in self.files.iter_mut() {
+                if let Some(to) = moved.get(&fe.path) {
+                    fe.path = (*to).clone();
+                }
+            }
+            remove_empty_dirs(&created_dirs);
+            if let Some(counters) = &counters {
+                self.restore_counters(counters);
+            }
+            if !pairs.is_empty() {
+                let mapping = pairs.iter().map(|(final_path, orig)| (orig.clone(), final_path.clone())).collect();
+                let (copied, created_dirs, replaced) = (false, Vec::new(), Replaced::default());
+                self.redo_actions.push(LastBatch { mapping, copied, created_dirs, time, replaced, counters });
+            }
+            self.save_undo_journal();
+            self.dirs_dirty = true;
+            self.append_history("undo", &pairs);
+            self.log.info(format!("Undone {} file(s).", pairs.len()));
+            self.note_still_trashed(&replaced.trashed);
+            if !replaced.overwritten.is_empty() {
+                let lost = replaced.overwritten.len();
+                self.log.warn(format!("{} file(s) overwritten by the undone batch are lost:", lost));
+                for path in &replaced.overwritten {
+                    self.log.push(LogLevel::Warning, Some(path), "overwritten, not restored");
+                }
+            }
+        } else {
+            self.log.warn("No actions to undo.");
+        }
+    }
+
+    /// Undo doesn't take files out of the trash: say which ones the undone batch put there.
+    fn note_still_trashed(&mut self, replaced: &[PathBuf]) {
+        if replaced.is_empty() {
+            return;
+        }
+        self.log.warn(format!(
+            "{} file(s) replaced by the undone batch are still in the trash; restore them from there if needed:",
+            replaced.len()
+        ));
+        for path in replaced {
+            self.log.push(LogLevel::Warning, Some(path), "replaced, in the trash");
+        }
+    }
+
+    /// Run the batch most recently taken back by Undo again. Nothing is touched unless
+    /// every file is still at its old path and no new path is taken.
+    fn redo(&mut self) {
+        let Some(batch) = self.redo_actions.last() else {
+            self.log.warn("Nothing to redo.");
+            return;
+        };
+        let mut pairs: Vec<(PathBuf, PathBuf)> = batch.mapping.iter().map(|(o, f)| (o.clone(), f.clone())).collect();
+        pairs.sort();
+        let copied = batch.copied;
+        let sources: HashSet<&PathBuf> = pairs.iter().map(|(orig, _)| orig).collect();
+        let mut conflicts = Vec::new();
+        for (orig, final_path) in &pairs {
+            if !orig.exists() {
+                conflicts.push(format!("missing: {:?}", orig));
+            } else if final_path.exists() && (copied || !sources.contains(final_path)) {
+                conflicts.push(format!("already exists: {:?}", final_path));
+            }
+        }
+        if !conflicts.is_empty() {
+            self.log.error(format!("Cannot redo, {} conflict(s):", conflicts.len()));
+            self.log.errors(conflicts);
+            return;
+        }
+        let targets: Vec<PathBuf> = pairs.iter().map(|(_, final_path)| final_path.clone()).collect();
+        let created_dirs = match create_target_dirs(&targets) {
+            Ok(created) => created,
+            Err((e, dir)) => {
+                self.log.error(format!("Cannot redo: cannot create folder {:?}: {}", dir, e));
+                return;
+            }
+        };
+        let result = if copied {
+            copy_files(&pairs, self.retry.keep_times, None)
+        } else {
+            self.rename_journaled(&pairs)
+        };
+        if let Err(failure) = result {
+            remove_empty_dirs(&created_dirs);
+            self.log.error(format!("Redo: {}", failure.message()));
+            self.error_report = Some(ErrorReport::from_failure(&failure, "Redo"));
+            return;
+        }
+        // finish_batch clears Redo; keep the older entries
+        let mut redo = std::mem::take(&mut self.redo_actions);
+        let used = redo.pop().and_then(|batch| batch.counters).map_or(0, |c| c.used);
+        self.finish_batch(&pairs, copied, created_dirs, Replaced::default());
+        self.redo_actions = redo;
+        if used > 0 {
+            self.advance_counters(used);
+        }
+        self.save_undo_journal();
+        self.log.info(format!("Redone {} file(s).", pairs.len()));
+    }
+}
+
+// an executed batch for Undo: orig → new path; copies are deleted instead of moved back
+#[derive(Serialize, Deserialize)]
+struct LastBatch {
+    mapping: HashMap<PathBuf, PathBuf>,
+    copied: bool,
+    // folders made for the batch, removed again by Undo when empty
+    #[serde(default)]
+    created_dirs: Vec<PathBuf>,
+    #[serde(default)]
+    time: String,
+    #[serde(default)]
+    replaced: Replaced,
+    // continuing counters as they were before the batch, put back by Undo
+    #[serde(default)]
+    counters: Option<Counters>,
+}
+
+// `next` of each Number block before a batch advanced it by `used` numbers
+#[derive(Serialize, Deserialize)]
+struct Counters {
+    used: usize,
+    editor: Vec<Option<i64>>,
+    // the saved template advanced along with the editor
+    template: Option<(String, Vec<Option<i64>>)>,
+}
+
+// existing files a batch replaced; Undo doesn't bring them back
+#[derive(Default, Serialize, Deserialize)]
+struct Replaced {
+    // moved to the trash
+    trashed: Vec<PathBuf>,
+    // overwritten for good ("Move replaced files to the trash" turned off)
+    overwritten: Vec<PathBuf>,
+}
+
+/// One line of the Undo history list, e.g. "undo  2024-05-01 10:00:00  12 file(s)".
+fn undo_history_label(batch: &LastBatch, kind: &str) -> String {
+    let what = if batch.copied { "copy(ies)" } else { "file(s)" };
+    format!("{}  {}  {} {}", kind, batch.time, batch.mapping.len(), what)
+}
+
+// batches kept in the undo journal
+const UNDO_JOURNAL_LIMIT: usize = 50;
+
+// Undo and Redo stacks as saved in `undo.json`
+#[derive(Deserialize)]
+struct UndoJournal {
+    undo: Vec<LastBatch>,
+    redo: Vec<LastBatch>,
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+enum TreeTarget {
+    Files,
+    Folders,
+    Both,
+}
+
+impl TreeTarget {
+    const ALL: [TreeTarget; 3] = [TreeTarget::Files, TreeTarget::Folders, TreeTarget::Both];
+
+    fn matches(self, is_dir: bool) -> bool {
+        match self {
+            TreeTarget::Files => !is_dir,
+            TreeTarget::Folders => is_dir,
+            TreeTarget::Both => true,
+        }
+    }
+}
+
+// what to do with the entries at one depth of a folder tree
+struct DepthRule {
+    // 1 = direct children of the root
+    depth: usize,
+    target: TreeTarget,
+    // saved template name; empty = the current blocks
+    template: String,
+}
+
+// file or folder found under the tree root
+// a folder tree rename going depth by depth, see `rename_tree`
+struct TreeRun {
+    // (depth, origs, final paths) still to submit, the deepest last
+    steps: Vec<(usize, Vec<PathBuf>, Vec<PathBuf>)>,
+    // depth of the batch submitted last
+    depth: usize,
+    done: usize,
+}
+
+struct TreeEntry {
+    path: PathBuf,
+    depth: usize,
+    is_dir: bool,
+}
+
+/// Entries under `root` down to `max_depth`, parents before children, siblings by name.
+fn scan_tree(root: &Path, max_depth: usize) -> Vec<TreeEntry> {
+    fn walk(dir: &Path, depth: usize, max_depth: usize, out: &mut Vec<TreeEntry>) {
+        if depth > max_depth {
+            return;
+        }
+        let mut children: Vec<PathBuf> = match fs::read_dir(dir) {
+            Ok(rd) => rd.flatten().map(|e| e.path()).filter(|p| !FolderLog::is_log_file(p)).collect(),
+            Err(_) => return,
+        };
+        children.sort();
+        for path in children {
+            let is_dir = path.is_dir();
+            out.push(TreeEntry { path: path.clone(), depth, is_dir });
+            if is_dir {
+                walk(&path, depth + 1, max_depth, out);
+            }
+        }
+    }
+    let mut out = Vec::new();
+    walk(root, 1, max_depth, &mut out);
+    out
+}
+
+// one list entry of an exported preview
+#[derive(Serialize)]
+struct PreviewEntry {
+    from: PathBuf,
+    // the name from the blocks, before collision handling
+    proposed: PathBuf,
+    to: PathBuf,
+    status: String,
+}
+
+/// `s` as a CSV field, quoted when needed.
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Turn plan entries into (origs, final paths), or list what is wrong with them.
+/// A relative `to` is taken as relative to the folder of `from`.
+fn check_plan(plan: Vec<PlanEntry>) -> Result<(Vec<PathBuf>, Vec<PathBuf>), Vec<String>> {
+    let mut origs = Vec::new();
+    let mut final_paths = Vec::new();
+    let mut seen = HashSet::new();
+    let mut problems = Vec::new();
+    for entry in plan {
+        let to = match entry.from.parent() {
+            Some(dir) if entry.to.is_relative() => dir.join(&entry.to),
+            _ => entry.to,
+        };
+        if !entry.from.exists() {
+            problems.push(format!("missing: {:?}", entry.from));
+        } else if to.file_name().is_none() {
+            problems.push(format!("no file name: {:?}", to));
+        } else if to.parent().is_some_and(|d| !d.as_os_str().is_empty() && !d.is_dir()) {
+            problems.push(format!("no such folder: {:?}", to.parent().unwrap()));
+        } else if !seen.insert(entry.from.clone()) {
+            problems.push(format!("listed twice: {:?}", entry.from));
+        }
+        origs.push(entry.from);
+        final_paths.push(to);
+    }
+    if problems.is_empty() {
+        Ok((origs, final_paths))
+    } else {
+        Err(problems)
+    }
+}
+
+const API_DEFAULT_PORT: u16 = 47817;
+const API_TOKEN_HEADER: &str = "x-bulkrename-token";
+
+// a call to the automation API, answered on the UI thread
+// /plan and /execute body when it isn't just the plan; `force` runs a batch that would ask first
+#[derive(Deserialize)]
+struct ApiExecute {
+    plan: Vec<PlanEntry>,
+    #[serde(default)]
+    force: bool,
+}
+
+struct ApiRequest {
+    endpoint: String,
+    body: String,
+    reply: mpsc::Sender<(u16, String)>,
+}
+
+/// Per-session secret from the OS random source; browsers can't send the custom header cross-origin.
+fn new_api_token() -> String {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).expect("no OS random source");
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Accept API connections on `listener` until `stop` is set.
+fn serve_api(
+    listener: TcpListener,
+    token: String,
+    tx: mpsc::Sender<ApiRequest>,
+    stop: Arc<AtomicBool>,
+    ctx: egui::Context,
+) {
+    let _ = listener.set_nonblocking(true);
+    while !stop.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let _ = handle_api_connection(stream, &token, &tx, &ctx);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                thread::sleep(std::time::Duration::from_millis(50));
+            }
+            Err(_) => break,
+        }
+    }
+}
+
+/// Read one HTTP request, hand it to the UI thread and write back its JSON reply.
+fn handle_api_connection(
+    mut stream: TcpStream,
+    token: &str,
+    tx: &mpsc::Sender<ApiRequest>,
+    ctx: &egui::Context,
+) -> std::io::Result<()> {
+    use std::io::{BufRead, BufReader, Read, Write};
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(std::time::Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let endpoint = parts.next().unwrap_or_default().to_string();
+    let mut length = 0usize;
+    let mut authorized = false;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().unwrap_or(0);
+            } else if name.eq_ignore_ascii_case(API_TOKEN_HEADER) {
+                authorized = value.trim() == token;
+            }
+        }
+    }
+    let error = |msg: &str| serde_json::json!({ "ok": false, "messages": [msg] }).to_string();
+    let (status, body) = if !authorized {
+        (401, error("missing or wrong token"))
+    } else if method != "POST" {
+        (405, error("use POST"))
+    } else if length > 16 << 20 {
+        (413, error("plan too large"))
+    } else {
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+        let (reply, rx) = mpsc::channel();
+        let request = ApiRequest {
+            endpoint,
+            body: String::from_utf8_lossy(&body).into_owned(),
+            reply,
+        };
+        if tx.send(request).is_ok() {
+            ctx.request_repaint();
+            rx.recv().unwrap_or_else(|_| (503, error("shutting down")))
+        } else {
+            (503, error("shutting down"))
+        }
+    };
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        _ => "Service Unavailable",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        body
+    )
+}
+
+/// Script that moves each `final` back to `orig`, via temporary names so swaps work.
+/// `batch` selects Windows `.bat` syntax, otherwise POSIX sh.
+fn revert_script(mapping: &[(PathBuf, PathBuf)], batch: bool) -> String {
+    let tmp = |i: usize, p: &Path| p.with_file_name(format!(".revert-{}.tmp", i));
+    let quote = |p: &Path| {
+        let s = p.to_string_lossy();
+        if batch {
+            format!("\"{}\"", s.replace('%', "%%"))
+        } else {
+            format!("'{}'", s.replace('\'', "'\\''"))
+        }
+    };
+    let mut out = String::new();
+    if batch {
+        out.push_str("@echo off\r\nchcp 65001 >nul\r\n");
+    } else {
+        out.push_str("#!/bin/sh\n");
+    }
+    let eol = if batch { "\r\n" } else { "\n" };
+    for (i, (_, final_path)) in mapping.iter().enumerate() {
+        let (from, to) = (quote(final_path), quote(&tmp(i, final_path)));
+        if batch {
+            out.push_str(&format!("move {} {} >nul{}", from, to, eol));
+        } else {
+            out.push_str(&format!("mv -- {} {}{}", from, to, eol));
+        }
+    }
+    for (i, (orig, final_path)) in mapping.iter().enumerate() {
+        let (from, to) = (quote(&tmp(i, final_path)), quote(orig));
+        if batch {
+            out.push_str(&format!("if not exist {to} move {from} {to} >nul{eol}"));
+        } else {
+            out.push_str(&format!("mv -n -- {from} {to}{eol}"));
+        }
+    }
+    out
+}
+
+#[derive(PartialEq, PartialOrd, Copy, Clone)]
+enum LogLevel {
+    Info,
+    Warning,
+    Error,
+}
+
+impl LogLevel {
+    const ALL: [LogLevel; 3] = [LogLevel::Info, LogLevel::Warning, LogLevel::Error];
+
+    fn label(self) -> &'static str {
+        match self {
+            LogLevel::Info => "info",
+            LogLevel::Warning => "warning",
+            LogLevel::Error => "error",
+        }
+    }
+}
+
+/// One message of the log panel, with the file it is about (if any).
+struct LogEntry {
+    time: DateTime<Local>,
+    level: LogLevel,
+    file: Option<PathBuf>,
+    text: String,
+}
+
+impl LogEntry {
+    /// The message with its file, as printed on the command line and returned by the API.
+    fn line(&self) -> String {
+        match &self.file {
+            Some(file) => format!("{}: {}", file.display(), self.text),
+            None => self.text.clone(),
+        }
+    }
+
+    fn matches(&self, level: LogLevel, search: &str) -> bool {
+        self.level >= level && (search.is_empty() || self.line().to_lowercase().contains(&search.to_lowercase()))
+    }
+}
+
+/// Messages shown in the log panel; the filter is the panel's.
+struct Log {
+    entries: Vec<LogEntry>,
+    // lowest level shown
+    level: LogLevel,
+    search: String,
+}
+
+impl Default for Log {
+    fn default() -> Self {
+        Self { entries: Vec::new(), level: LogLevel::Info, search: String::new() }
+    }
+}
+
+impl Log {
+    fn push(&mut self, level: LogLevel, file: Option<&Path>, text: impl Into<String>) {
+        let file = file.map(Path::to_path_buf);
+        self.entries.push(LogEntry { time: Local::now(), level, file, text: text.into() });
+    }
+
+    fn info(&mut self, text: impl Into<String>) {
+        self.push(LogLevel::Info, None, text);
+    }
+
+    fn warn(&mut self, text: impl Into<String>) {
+        self.push(LogLevel::Warning, None, text);
+    }
+
+    fn error(&mut self, text: impl Into<String>) {
+        self.push(LogLevel::Error, None, text);
+    }
+
+    fn error_at(&mut self, file: &Path, text: impl Into<String>) {
+        self.push(LogLevel::Error, Some(file), text);
+    }
+
+    fn errors(&mut self, texts: impl IntoIterator<Item = String>) {
+        for text in texts {
+            self.error(text);
+        }
+    }
+
+    /// The entries passing the filter, one per line with time and level, for copying and saving.
+    fn filtered_text(&self) -> String {
+        self.entries
+            .iter()
+            .filter(|e| e.matches(self.level, &self.search))
+            .map(|e| format!("{} {:<7} {}\n", e.time.format("%Y-%m-%d %H:%M:%S"), e.level.label(), e.line()))
+            .collect()
+    }
+}
+
+/// Contents of the modal error dialog.
+struct ErrorReport {
+    title: String,
+    summary: String,
+    files: Vec<String>,
+}
+
+impl ErrorReport {
+    /// Dialog contents for a failed rename; `what` names the operation ("Rename", "Revert").
+    fn from_failure(failure: &RenameFailure, what: &str) -> Self {
+        match failure {
+            RenameFailure::Staging { error, file, not_restored } if not_restored.is_empty() => ErrorReport {
+                title: format!("{} failed, nothing was changed", what),
+                summary: format!(
+                    "Moving {:?} aside failed ({}). All files were moved back to their original names.",
+                    file, error
+                ),
+                files: vec![file.display().to_string()],
+            },
+            RenameFailure::Staging { error, file, not_restored } => ErrorReport {
+                title: format!("{} failed, rollback incomplete", what),
+                summary: format!(
+                    "Moving {:?} aside failed ({}). These files could not be moved back and still have temporary names:",
+                    file, error
+                ),
+                files: not_restored
+                    .iter()
+                    .map(|(tmp, orig)| format!("{} (was {})", tmp.display(), orig.display()))
+                    .collect(),
+            },
+            RenameFailure::Final { error, file, stranded } => ErrorReport {
+                title: format!("{} partially failed", what),
+                summary: format!(
+                    "Renaming {:?} failed ({}). Earlier files were renamed; these still have temporary names:",
+                    file, error
+                ),
+                files: stranded
+                    .iter()
+                    .map(|(tmp, orig, final_path)| {
+                        format!("{} (was {}, meant to be {})", tmp.display(), orig.display(), final_path.display())
+                    })
+                    .collect(),
+            },
+            RenameFailure::Cancelled { not_restored } => ErrorReport {
+                title: format!("{} cancelled, rollback incomplete", what),
+                summary: "These files could not be put back:".into(),
+                files: not_restored
+                    .iter()
+                    .map(|(now, orig)| format!("{} (was {})", now.display(), orig.display()))
+                    .collect(),
+            },
+            RenameFailure::Copy { error, file, not_removed } => ErrorReport {
+                title: format!("{} failed", what),
+                summary: if not_removed.is_empty() {
+                    format!("Copying {:?} failed ({}). The copies made so far were deleted.", file, error)
+                } else {
+                    format!(
+                        "Copying {:?} failed ({}). These copies were not deleted \
+                         (could not be, or they replaced a file that was there before):",
+                        file, error
+                    )
+                },
+                files: not_removed.iter().map(|p| p.display().to_string()).collect(),
+            },
+        }
+    }
+
+    /// Plain text for bug reports.
+    fn details(&self) -> String {
+        let mut out = format!("{}\n{}\n", self.title, self.summary);
+        for f in &self.files {
+            out.push_str(&format!("  {}\n", f));
+        }
+        out.push_str(&format!(
+            "\nBulkReName {} ({} {})\n",
+            env!("CARGO_PKG_VERSION"),
+            std::env::consts::OS,
+            std::env::consts::ARCH
+        ));
+        out
+    }
+}
+
+/// Copies renamed by "Test on copies".
+struct Sandbox {
+    dir: PathBuf,
+    // original name, resulting name of the copy
+    results: Vec<(String, String)>,
+}
+
+/// Show a folder in Explorer / Finder / the desktop's file manager.
+fn open_in_file_manager(dir: &Path) -> std::io::Result<()> {
+    let program = if cfg!(windows) {
+        "explorer"
+    } else if cfg!(target_os = "macos") {
+        "open"
+    } else {
+        "xdg-open"
+    };
+    std::process::Command::new(program).arg(dir).spawn().map(|_| ())
+}
+
+/// Batches at least this large are confirmed before running.
+const CONFIRM_BATCH_SIZE: usize = 100;
+
+/// How often the list is checked for files changed outside the app.
+const STALE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A resolved batch; risky ones wait here for confirmation.
+struct PendingBatch {
+    pairs: Vec<(PathBuf, PathBuf)>,
+    duplicates: Vec<PathBuf>,
+    // mtime post-step: rows of `files` and the dates to set
+    mtime: Option<(Vec<usize>, Vec<DateTime<Local>>)>,
+    // numbers the batch took, see `advance_counters`
+    counted: usize,
+    done: String,
+    // targets replacing a file that is not part of the batch (or a deleted duplicate)
+    overwrites: usize,
+    // targets in another directory than their source
+    cross_dir: usize,
+    summary: BatchSummary,
+}
+
+/// What a batch did, for the summary dialog shown when it is done.
+#[derive(Default)]
+struct BatchSummary {
+    copy: bool,
+    renamed: usize,
+    // renamed with a number appended
+    suffixed: usize,
+    // sources left as they were, with the reason
+    skipped: Vec<(PathBuf, &'static str)>,
+    unchanged: usize,
+    // the failure message and the files it is about
+    error: Option<String>,
+    failed: Vec<PathBuf>,
+    elapsed: Duration,
+}
+
+impl PendingBatch {
+    fn new(pairs: Vec<(PathBuf, PathBuf)>, duplicates: Vec<PathBuf>) -> Self {
+        let moving: HashSet<&PathBuf> = pairs.iter().map(|(from, _)| from).collect();
+        let overwrites = duplicates.len()
+            + pairs.iter().filter(|(_, to)| !moving.contains(to) && to.exists()).count();
+        let cross_dir = pairs.iter().filter(|(from, to)| from.parent() != to.parent()).count();
+        Self {
+            pairs,
+            duplicates,
+            mtime: None,
+            counted: 0,
+            done: String::new(),
+            overwrites,
+            cross_dir,
+            summary: BatchSummary::default(),
+        }
+    }
+
+    fn risky(&self) -> bool {
+        self.overwrites > 0 || self.cross_dir > 0 || self.pairs.len() >= CONFIRM_BATCH_SIZE
+    }
+}
+
+/// A batch paused by the Ask collision strategy, asking about one conflict at a time.
+struct CollisionPrompt {
+    origs: Vec<PathBuf>,
+    final_paths: Vec<PathBuf>,
+    mtime: Option<(Vec<usize>, Vec<DateTime<Local>>)>,
+    counted: usize,
+    done: String,
+    // (member, the member holding the name or None for a file on disk), see `find_collisions`
+    conflicts: Vec<(usize, Option<usize>)>,
+    answers: Vec<Option<CollisionStrategy>>,
+    apply_to_all: bool,
+    // name being typed with "Rename manually"
+    manual: Option<String>,
+}
+
+impl CollisionPrompt {
+    /// The first conflict without an answer.
+    fn current(&self) -> Option<usize> {
+        self.answers.iter().position(Option::is_none)
+    }
+
+    /// The strategy for every member; the others are only skipped should they collide after all.
+    fn member_answers(&self) -> Vec<CollisionStrategy> {
+        let mut answers = vec![CollisionStrategy::Ask; self.origs.len()];
+        for (&(i, _), answer) in self.conflicts.iter().zip(&self.answers) {
+            answers[i] = answer.unwrap_or(CollisionStrategy::Ask);
+        }
+        answers
+    }
+}
+
+/// A folder whose new files are renamed with a saved template as they arrive.
+struct WatchFolder {
+    // copy taken at start, so editing the template doesn't change a running watch
+    template: Template,
+    _watcher: notify::RecommendedWatcher,
+    rx: Receiver<notify::Result<notify::Event>>,
+    // new files with their size and when it last changed
+    pending: HashMap<PathBuf, (u64, Instant)>,
+    // names given by the watch, whose own events are no arrivals
+    produced: HashSet<PathBuf>,
+    // settled arrivals waiting for their turn
+    ready: Vec<PathBuf>,
+    // (arrival, new path) of the batch running for it
+    current: Option<(PathBuf, PathBuf)>,
+    // files renamed in this session
+    renamed: usize,
+}
+
+// how long a new file's size must stay the same before it is renamed
+const WATCH_SETTLE: Duration = Duration::from_secs(2);
+
+/// Downloads in progress and hidden or lock files, which a watch folder leaves alone.
+fn is_partial_file(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let ext = path.extension().unwrap_or_default().to_string_lossy().to_lowercase();
+    name.starts_with('.')
+        || name.starts_with("~$")
+        || ["part", "partial", "crdownload", "download", "tmp"].contains(&ext.as_str())
+}
+
+/// What `prepare_target_dirs` set up for a batch, taken back again when it fails.
+struct Prepared {
+    created_dirs: Vec<PathBuf>,
+    // files the batch replaces: (original path, hidden name next to it)
+    aside: Vec<(PathBuf, PathBuf)>,
+    // existing files the batch overwrites for good (trash turned off)
+    overwritten: Vec<PathBuf>,
+}
+
+/// A batch handed to the elevated helper, see `run_elevated`.
+struct PendingElevation {
+    plan: PathBuf,
+    pairs: Vec<(PathBuf, PathBuf)>,
+    started: Instant,
+}
+
+/// How long the elevated helper has to pick up its plan; the UAC prompt is answered by then.
+const ELEVATION_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// A batch renaming on a worker thread.
+struct RunningBatch {
+    batch: PendingBatch,
+    started: Instant,
+    prepared: Prepared,
+    progress: Arc<Progress>,
+    rx: Receiver<Result<(), RenameFailure>>,
+}
+
+/// Full-window layer that dims the app and swallows input below a modal dialog.
+fn modal_backdrop(ctx: &egui::Context) -> egui::LayerId {
+    egui::Area::new("modal_backdrop".into())
+        .order(egui::Order::Middle)
+        .fixed_pos(egui::Pos2::ZERO)
+        .show(ctx, |ui| {
+            let rect = ctx.screen_rect();
+            ui.allocate_rect(rect, egui::Sense::click_and_drag());
+            ui.painter().rect_filled(rect, 0.0, egui::Color32::from_black_alpha(140));
+        })
+        .response
+        .layer_id
+}
+
+const APPLY_PLAN_ARG: &str = "--apply-plan";
+
+fn plan_result_path(plan_path: &Path) -> PathBuf {
+    plan_path.with_extension("result.json")
+}
+
+/// Helper mode (`--apply-plan <file>`): run a saved plan without a window and write the result next to it.
+fn apply_plan_file(plan_path: &Path) {
+    let result = fs::read_to_string(plan_path)
+        .map_err(|e| e.to_string())
+        .and_then(|text| {
+            // gone: the app knows the helper has started
+            let _ = fs::remove_file(plan_path);
+            serde_json::from_str::<Vec<(PathBuf, PathBuf)>>(&text).map_err(|e| e.to_string())
+        })
+        .and_then(|pairs| {
+            two_phase_rename_journaled(&pairs, RetryPolicy::default(), Some(&BulkRename::transaction_path()), None)
+                .map_err(|f| f.message())
+        });
+    if let Ok(json) = serde_json::to_string(&result) {
+        let _ = fs::write(plan_result_path(plan_path), json);
+    }
+}
+
+// Explorer context-menu entries for files and folders (per user, no admin rights needed)
+#[cfg(windows)]
+const SHELL_MENU_KEYS: [&str; 2] = [
+    r"HKCU\Software\Classes\*\shell\BulkReName",
+    r"HKCU\Software\Classes\Directory\shell\BulkReName",
+];
+
+/// Run `reg.exe` without flashing a console window.
+#[cfg(windows)]
+fn reg_command(args: &[&str]) -> Result<(), String> {
+    use std::os::windows::process::CommandExt;
+    const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+    let out = std::process::Command::new("reg")
+        .args(args)
+        .creation_flags(CREATE_NO_WINDOW)
+        .stdin(std::process::Stdio::null())
+        .output()
+        .map_err(|e| e.to_string())?;
+    if out.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&out.stderr).trim().to_string())
+    }
+}
+
+/// Whether "Rename with BulkReName" is in the Explorer context menu.
+#[cfg(windows)]
+fn shell_menu_installed() -> bool {
+    reg_command(&["query", SHELL_MENU_KEYS[0]]).is_ok()
+}
+
+/// Add or remove "Rename with BulkReName" in the Explorer context menu. Explorer starts one
+/// process per selected file; the single-instance handoff gathers them in one window.
+#[cfg(windows)]
+fn set_shell_menu(on: bool) -> Result<(), String> {
+    if !on {
+        for key in SHELL_MENU_KEYS {
+            // a key that is already gone is fine
+            let _ = reg_command(&["delete", key, "/f"]);
+        }
+        return Ok(());
+    }
+    let exe = std::env::current_exe().map_err(|e| e.to_string())?.display().to_string();
+    let command = format!("\"{}\" \"%1\"", exe);
+    for key in SHELL_MENU_KEYS {
+        reg_command(&["add", key, "/ve", "/d", "Rename with BulkReName", "/f"])?;
+        reg_command(&["add", key, "/v", "Icon", "/d", &exe, "/f"])?;
+        // lift Explorer's 15-item limit for the entry
+        reg_command(&["add", key, "/v", "MultiSelectModel", "/d", "Player", "/f"])?;
+        reg_command(&["add", &format!(r"{}\command", key), "/ve", "/d", &command, "/f"])?;
+    }
+    Ok(())
+}
+
+#[cfg(not(windows))]
+fn shell_menu_installed() -> bool {
+    false
+}
+
+#[cfg(not(windows))]
+fn set_shell_menu(_on: bool) -> Result<(), String> {
+    Err("only supported on Windows".into())
+}
+
+/// Relaunch this executable with administrator rights (UAC prompt).
+#[cfg(windows)]
+fn spawn_elevated(params: &str) -> Result<(), String> {
+    use std::ffi::OsStr;
+    use std::os::windows::ffi::OsStrExt;
+    use winapi::um::shellapi::ShellExecuteW;
+    use winapi::um::winuser::SW_HIDE;
+
+    fn wide(s: &OsStr) -> Vec<u16> {
+        s.encode_wide().chain(Some(0)).collect()
+    }
+    let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+    let verb = wide(OsStr::new("runas"));
+    let file = wide(exe.as_os_str());
+    let params = wide(OsStr::new(params));
+    let ret = unsafe {
+        ShellExecuteW(
+            std::ptr::null_mut(),
+            verb.as_ptr(),
+            file.as_ptr(),
+            params.as_ptr(),
+            std::ptr::null(),
+            SW_HIDE,
+        )
+    };
+    // values <= 32 are errors (e.g. the user declined the prompt)
+    if ret as isize > 32 {
+        Ok(())
+    } else {
+        Err(format!("ShellExecuteW returned {}", ret as isize))
+    }
+}
+
+#[cfg(not(windows))]
+fn spawn_elevated(_params: &str) -> Result<(), String> {
+    Err("elevation is only supported on Windows".into())
+}
+
+const CLI_ARG: &str = "--cli";
+
+const CLI_USAGE: &str = "\
+usage: BulkReName --cli (--template NAME | --pattern PATTERN) [options] PATH...
+
+  -t, --template NAME    use a saved template
+  -p, --pattern PATTERN  inline pattern, e.g. \"{date:%Y%m%d}_{n:3}{ext}\"
+  -n, --dry-run          print the renames without touching any file
+  -r, --recursive        walk folders given as PATH into subfolders
+  -s, --scope EXPR       only rename files matching the scope expression
+  -e, --ext POLICY       extension for {ext}: keep, lower, upper, strip or a new one (.jpg)
+  -d, --move-to DIR      move the renamed files into DIR
+  -c, --copy             write renamed copies, leaving the originals
+  -f, --folders PATTERN  sort into date subfolders, e.g. \"%Y/%m\" (EXIF date, else mtime)
+  -w, --portable         make the names valid on Windows (<>:\"/\\|?* → _, no CON, NUL...)
+  -h, --help             show this help
+
+PATH may be a file, a folder or a wildcard in the last part (photos/*.jpg).
+Pattern fields: {name} {ext} {n} {n:WIDTH} {n:WIDTH:START} {n:WIDTH:START:STEP}
+{date:FORMAT} {mtime:FORMAT} {created:FORMAT} {exif:FORMAT}; {{ and }} are literal braces.";
+
+/// A GUI-subsystem exe has no console; borrow the one of the shell that started us.
+#[cfg(windows)]
+fn attach_parent_console() {
+    use winapi::um::wincon::{AttachConsole, ATTACH_PARENT_PROCESS};
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(not(windows))]
+fn attach_parent_console() {}
+
+/// Headless mode (`--cli ...`): the same planning and rename steps as the window, without eframe.
+/// Returns the process exit code.
+fn run_cli(args: &[String]) -> i32 {
+    attach_parent_console();
+    let mut app = BulkRename {
+        saved_templates: BulkRename::read_templates().unwrap_or_default(),
+        ..Default::default()
+    };
+    app.load_undo_journal();
+    app.check_interrupted();
+    if !app.interrupted.is_empty() {
+        eprintln!("an earlier rename did not finish; open the window to complete or roll it back");
+    }
+    let mut template = None;
+    let mut pattern = None;
+    let mut dry_run = false;
+    let mut recursive = false;
+    let mut ext_policy = None;
+    let mut inputs = Vec::new();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "-t" | "--template" => template = it.next().cloned(),
+            "-p" | "--pattern" => pattern = it.next().cloned(),
+            "-s" | "--scope" => app.scope_expr = it.next().cloned().unwrap_or_default(),
+            "-n" | "--dry-run" => dry_run = true,
+            "-r" | "--recursive" => recursive = true,
+            "-d" | "--move-to" => app.move_to = it.next().map(PathBuf::from),
+            "-c" | "--copy" => app.copy_mode = true,
+            "-w" | "--portable" => app.sanitize = true,
+            "-f" | "--folders" => app.subfolder_pattern = it.next().cloned().unwrap_or_default(),
+            "-e" | "--ext" => {
+                ext_policy = it.next().map(|p| match p.as_str() {
+                    "keep" => ExtPolicy::Keep,
+                    "lower" => ExtPolicy::Lower,
+                    "upper" => ExtPolicy::Upper,
+                    "strip" => ExtPolicy::Strip,
+                    ext => ExtPolicy::Replace(ext.to_string()),
+                })
+            }
+            "-h" | "--help" => {
+                println!("{}", CLI_USAGE);
+                return 0;
+            }
+            a if a.starts_with('-') && a.len() > 1 => {
+                eprintln!("unknown option {}\n\n{}", a, CLI_USAGE);
+                return 2;
+            }
+            _ => inputs.push(arg.clone()),
+        }
+    }
+
+    match (template, pattern) {
+        (Some(name), None) => match BulkRename::find_template(&app.saved_templates, &name).cloned() {
+            Some(tpl) => app.apply_template(&tpl),
+            None => {
+                eprintln!("no saved template named \"{}\"", name);
+                return 2;
+            }
+        },
+        (None, Some(pattern)) => match parse_pattern(&pattern) {
+            Ok(blocks) => app.blocks = blocks,
+            Err(e) => {
+                eprintln!("invalid pattern: {}", e);
+                return 2;
+            }
+        },
+        _ => {
+            eprintln!("give either --template or --pattern\n\n{}", CLI_USAGE);
+            return 2;
+        }
+    }
+    if let Some(policy) = ext_policy {
+        app.ext_policy = policy;
+    }
+    match expand_cli_paths(&inputs, recursive) {
+        Ok(paths) => app.add_files(paths),
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    }
+    if app.blocks.iter().any(|b| matches!(b.inner(), Block::PHash { .. })) {
+        for fe in &app.files {
+            app.phashes.insert(fe.path.clone(), dhash(&fe.path));
+        }
+    }
+
+    let planned = app.plan_rename(false);
+    let print_messages = |app: &BulkRename| {
+        for entry in &app.log.entries {
+            eprintln!("{}", entry.line());
+        }
+    };
+    let Some((rows, origs, final_paths)) = planned else {
+        print_messages(&app);
+        return 1;
+    };
+    if dry_run {
+        let (resolved, duplicates) = app.resolve_targets(&origs, &final_paths);
+        for (from, to) in origs.iter().zip(&resolved).filter(|(from, to)| from != to) {
+            println!("{} -> {}", from.display(), to.display());
+        }
+        for dup in &duplicates {
+            println!("{} (identical to its target, would be deleted)", dup.display());
+        }
+        print_messages(&app);
+        return 0;
+    }
+
+    let mtime = app.mtime_dates(&rows);
+    let ok = match app.prepare_batch(&origs, &final_paths) {
+        Some(mut batch) => {
+            for (from, to) in &batch.pairs {
+                println!("{} -> {}", from.display(), to.display());
+            }
+            batch.mtime = mtime;
+            let verb = if app.copy_mode { "Copied" } else { "Renamed" };
+            batch.done = format!("{} {} file(s).", verb, batch.pairs.len());
+            app.commit_batch(batch)
+        }
+        None => true,
+    };
+    print_messages(&app);
+    if ok {
+        0
+    } else {
+        1
+    }
+}
+
+/// `ui.columns(2, ..)` with a divider that can be dragged; `split` is the left column's share.
+fn split_columns<R>(ui: &mut egui::Ui, split: &mut f32, add: impl FnOnce(&mut [egui::Ui]) -> R) -> R {
+    let rect = ui.available_rect_before_wrap();
+    let gap = ui.spacing().item_spacing.x;
+    let x = rect.left() + rect.width() * *split;
+    let rects = [
+        egui::Rect::from_min_max(rect.min, egui::pos2(x - gap, rect.bottom())),
+        egui::Rect::from_min_max(egui::pos2(x + gap, rect.top()), rect.max),
+    ];
+    let mut cols = rects.map(|r| {
+        let mut col = ui.child_ui(r, *ui.layout());
+        col.set_width(r.width());
+        col
+    });
+    let result = add(&mut cols);
+
+    let bottom = cols[0].min_rect().bottom().max(cols[1].min_rect().bottom());
+    let handle = egui::Rect::from_min_max(egui::pos2(x - gap, rect.top()), egui::pos2(x + gap, bottom));
+    let response = ui.interact(handle, ui.id().with("column_split"), egui::Sense::drag());
+    let active = response.hovered() || response.dragged();
+    if active {
+        ui.ctx().set_cursor_icon(egui::CursorIcon::ResizeHorizontal);
+    }
+    if let Some(pos) = response.interact_pointer_pos().filter(|_| response.dragged()) {
+        *split = ((pos.x - rect.left()) / rect.width()).clamp(0.2, 0.8);
+    }
+    let visuals = ui.visuals();
+    let stroke = if active { visuals.widgets.hovered.fg_stroke } else { visuals.widgets.noninteractive.bg_stroke };
+    ui.painter().vline(x, rect.top()..=bottom, stroke);
+    ui.allocate_rect(egui::Rect::from_min_max(rect.min, egui::pos2(rect.right(), bottom)), egui::Sense::hover());
+    result
+}
+
+/// Stable id of a file row's name, so keyboard focus can follow a moved file.
+fn file_name_id(row: usize) -> egui::Id {
+    egui::Id::new("file_name").with(row)
+}
+
+/// The name of the block as shown in the editor, e.g. "Name date".
+fn block_kind(block: &Block) -> &'static str {
+    match block {
+        Block::Literal(_) => "Literal",
+        Block::Number { .. } => "Number",
+        Block::Date { .. } => "Date",
+        Block::ExifDate { .. } => "EXIF date",
+        Block::NameDate { .. } => "Name date",
+        Block::Original { .. } => "Original",
+        Block::OriginalSlice { .. } => "Orig slice",
+        Block::RegexReplace { .. } => "Regex",
+        Block::Cleanup { .. } => "Cleanup",
+        Block::Transliterate { .. } => "ASCII",
+        Block::Extension => "Extension",
+        Block::AudioTag { .. } => "Audio tag",
+        Block::Camera { .. } => "Camera",
+        Block::FileSize { .. } => "Size",
+        Block::Shape { .. } => "Shape",
+        Block::Dimensions => "Dimensions",
+        Block::Script { .. } => "Script",
+        Block::Tag => "Tag",
+        Block::Rating { .. } => "Rating",
+        Block::PHash { .. } => "Image hash",
+        Block::Audio { .. } => "Audio",
+        Block::Conditional { block, .. } => block_kind(block),
+    }
+}
+
+impl eframe::App for BulkRename {
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        self.autosave_session();
+    }
+
+    /// The window's size and position are stored by eframe itself (`persist_window`).
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        let layout = Layout {
+            column_split: self.column_split,
+            show_thumbnails: self.show_thumbnails,
+            columns: self.list_columns.clone(),
+        };
+        eframe::set_value(storage, LAYOUT_KEY, &layout);
+    }
+
+    /// Take Alt+↑/↓ before egui turns them into focus moves; they reorder the current file.
+    fn raw_input_hook(&mut self, _ctx: &egui::Context, raw_input: &mut egui::RawInput) {
+        raw_input.events.retain(|e| match e {
+            egui::Event::Key { key, pressed, modifiers, .. }
+                if modifiers.alt && matches!(key, egui::Key::ArrowUp | egui::Key::ArrowDown) =>
+            {
+                if *pressed {
+                    self.key_move += if *key == egui::Key::ArrowUp { -1 } else { 1 };
+                }
+                false
+            }
+            _ => true,
+        });
+    }
+
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        // Process thumbnail loading results
+        if let Some(rx) = &self.thumb_rx {
+            while let Ok((key, result)) = rx.try_recv() {
+                match result {
+                    Ok((rgba, (w, h))) => {
+                        let color_image = egui::ColorImage::from_rgba_unmultiplied([w, h], &rgba.into_vec());
+                        let tex = ctx.load_texture(key.clone(), color_image, egui::TextureOptions::NEAREST);
+                        let orig_size = egui::Vec2::new(w as f32, h as f32);
+                        self.thumbnails.insert(key, ThumbnailState::Loaded(tex, orig_size));
+                    }
+                    Err(e) => {
+                        self.thumbnails.insert(key, ThumbnailState::Failed);
+                        self.log.error(format!("Thumbnail load failed: {}", e));
+                    }
+                }
+            }
+        }
+
+        self.handle_watch_events();
+        self.check_stale(ctx);
+        self.handle_api_requests();
+        self.update_phashes(ctx);
+        if self.key_move != 0 && self.dragging_idx.is_none() {
+            for _ in 0..self.key_move.abs() {
+                if self.key_move < 0 {
+                    self.move_up();
+                } else {
+                    self.move_down();
+                }
+            }
+            self.focus_row = self.selected_idx;
+        }
+        self.key_move = 0;
+        if let Some(row) = self.focus_row.take() {
+            ctx.memory_mut(|m| m.request_focus(file_name_id(row)));
+            self.reveal_row = Some(row);
+        }
+        if self.loading_phase == LoadingPhase::None {
+            let opened = std::mem::take(&mut *OPENED_FILES.lock().unwrap());
+            if !opened.is_empty() {
+                self.start_loading(opened);
+            }
+        }
+        if RAISE_WINDOW.swap(false, Ordering::Relaxed) {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Minimized(false));
+            ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+        }
+        if self.pending_elevation.is_some() {
+            self.poll_elevation();
+            ctx.request_repaint_after(std::time::Duration::from_millis(500));
+        }
+        if self.running.is_some() {
+            self.poll_running();
+            ctx.request_repaint_after(std::time::Duration::from_millis(100));
+        }
+        // a folder tree step that was cancelled or never started
+        if self.tree_run.is_some()
+            && self.running.is_none()
+            && self.pending_batch.is_none()
+            && self.collision_prompt.is_none()
+        {
+            self.stop_tree();
+        }
+        if self.watch_folder.is_some() {
+            self.poll_watch_folder();
+            ctx.request_repaint_after(std::time::Duration::from_millis(500));
+        }
+        let now = ctx.input(|i| i.time);
+        if now >= self.session_due {
+            self.session_due = now + 30.0;
+            self.autosave_session();
+        }
+        if self.dirs_dirty {
+            self.dirs_dirty = false;
+            self.sync_watches(ctx);
+            self.detect_filesystems();
+        }
+
+        match self.loading_phase {
+            LoadingPhase::AddingFiles => {
+                if self.loading_phase == LoadingPhase::AddingFiles {
+                    let mut finished = false;
+                    if let Some(rx) = self.loader_rx.take() {
+                        use std::sync::mpsc::TryRecvError;
+                        loop {
+                            match rx.try_recv() {
+                                Ok(path) => {
+                                    self.add_files(vec![path]);
+                                    self.loading_count += 1;
+                                }
+                                Err(TryRecvError::Empty) => {
+                                    self.loader_rx = Some(rx);
+                                    break;
+                                }
+                                Err(TryRecvError::Disconnected) => {
+                                    finished = true;
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    if finished {
+                        self.loading_phase = LoadingPhase::LoadingThumbs;
+                        self.loading_count = 0;
+                        self.dirs_dirty = true;
+                    }
+                    ctx.request_repaint();
+                }
+                   
+            }
+            LoadingPhase::LoadingThumbs => {
+                // Check if any thumbnails are still loading
+                let any_loading = self.thumbnails.values().any(|s| matches!(s, ThumbnailState::Loading));
+                if !any_loading {
+                    self.loading_phase = LoadingPhase::None;
+                }
+                ctx.request_repaint();
+            }
+            LoadingPhase::None => {}
+        }
+
+        let dropped = ctx.input_mut(|i| {
+            if i.raw.dropped_files.is_empty() {
+                None
+            } else {
+                Some(std::mem::take(&mut i.raw.dropped_files))
+            }
+        });
+
+        if let Some(dropped_files) = dropped {
+            let paths = dropped_files.into_iter().filter_map(dropped_path).collect();
+            self.start_loading(paths);
+            ctx.request_repaint();
+        }
+
+        // Ctrl+V of files copied in a file manager (text/uri-list); also the way in
+        // on Wayland, where drag-and-drop isn't delivered
+        if ctx.memory(|m| m.focused().is_none()) {
+            let pasted: Vec<PathBuf> = ctx.input(|i| {
+                i.events
+                    .iter()
+                    .filter_map(|e| match e {
+                        egui::Event::Paste(text) => Some(paths_from_uri_list(text)),
+                        _ => None,
+                    })
+                    .flatten()
+                    .collect()
+            });
+            if !pasted.is_empty() && self.loading_phase == LoadingPhase::None {
+                self.start_loading(pasted);
+            }
+        }
+
+        // shortcuts; not while a text field has the keyboard (Ctrl+Z there undoes typing)
+        if !ctx.wants_keyboard_input()
+            && self.running.is_none()
+            && self.pending_batch.is_none()
+            && self.collision_prompt.is_none()
+        {
+            use egui::{Key, KeyboardShortcut, Modifiers};
+            let shortcut =
+                |modifiers, key| ctx.input_mut(|i| i.consume_shortcut(&KeyboardShortcut::new(modifiers, key)));
+            let pressed = |key| shortcut(Modifiers::COMMAND, key);
+            if pressed(Key::O) {
+                self.add_files_dialog();
+            }
+            // Ctrl+Shift+Z first: the plain Ctrl+Z check ignores Shift
+            if shortcut(Modifiers::COMMAND | Modifiers::SHIFT, Key::Z) || pressed(Key::Y) {
+                self.redo();
+            } else if pressed(Key::Z) {
+                self.undo();
+            }
+            // Ctrl+Shift+Enter first, as with Ctrl+Shift+Z
+            if shortcut(Modifiers::COMMAND | Modifiers::SHIFT, Key::Enter) {
+                if self.files.iter().any(|f| f.selected) {
+                    self.execute_rename(true);
+                } else {
+                    self.log.warn("No files selected.");
+                }
+            } else if pressed(Key::Enter) {
+                if self.preview_table().blocked == 0 {
+                    self.execute_rename(false);
+                } else {
+                    self.log.error("Not renamed: fix the conflicts in the preview first.");
+                }
+            }
+            // a focused row handles Delete itself
+            let delete = ctx.memory(|m| m.focused().is_none()) && ctx.input(|i| i.key_pressed(Key::Delete));
+            if delete && self.files.iter().any(|f| f.selected) {
+                self.remove_selection();
+            }
+        }
+
+        if self.loading_phase == LoadingPhase::None
+            && ctx.input(|i| !i.raw.hovered_files.is_empty())
+        {
+            egui::Area::new("drop_overlay".into())
+                .order(egui::Order::Foreground)
+                .interactable(false)
+                .show(ctx, |ui| {
+                    let rect = ctx.available_rect();
+                    ui.painter().rect_filled(
+                        rect,
+                        0.0,
+                        egui::Color32::from_rgba_unmultiplied(50, 100, 200, 80),
+                    );
+                    ui.centered_and_justified(|ui| {
+                        ui.label(
+                            egui::RichText::new(tr("Drop files to add"))
+                                .size(32.0)
+                                .color(egui::Color32::WHITE),
+                        );
+                    });
+                }
+            );
+        }
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading("BulkReName v2");
+
+            // top buttons
+            ui.horizontal(|ui| {
+                if ui.button(tr("Add files...")).on_hover_text("Ctrl+O").clicked() {
+                    self.add_files_dialog();
+                }
+                if ui.button(tr("Add folder..."))
+                    .on_hover_text(tr("Add the files in a folder, using Subfolders and Only names matching from Import options"))
+                    .clicked()
+                {
+                    if let Some(dir) = rfd::FileDialog::new().pick_folder() {
+                        self.start_loading(vec![dir]);
+                    }
+                }
+                if ui.button(tr("Clear files")).clicked() {
+                    self.files.clear();
+                    self.selected_idx = None;
+                    self.dirs_dirty = true;
+                }
+                if ui
+                    .button(tr("Refresh"))
+                    .on_hover_text(tr("Check the listed files again and mark the ones changed outside the app"))
+                    .clicked()
+                {
+                    self.refresh_list();
+                }
+                if ui.button(tr("Clean list"))
+                    .on_hover_text(tr("Remove missing files and flag files changed since they were added"))
+                    .clicked()
+                {
+                    self.clean_list();
+                }
+                if ui.button(tr("Find duplicates"))
+                    .on_hover_text(tr("Hash the listed files and group the ones with identical content"))
+                    .clicked()
+                {
+                    self.start_duplicate_scan(ui.ctx());
+                }
+                let blocked = self.preview_table().blocked;
+                if ui
+                    .add_enabled(blocked == 0, egui::Button::new("ReName"))
+                    .on_hover_text("Ctrl+Enter")
+                    .on_disabled_hover_text(format!("{} conflict(s) in the preview", blocked))
+                    .clicked()
+                {
+                    self.execute_rename(false);
+                }
+                let selected = self.files.iter().filter(|f| f.selected).count();
+                if ui
+                    .add_enabled(selected > 0, egui::Button::new(format!("Rename selected ({})", selected)))
+                    .on_hover_text(tr("Ctrl+click / Shift+click file names to select. Ctrl+Shift+Enter"))
+                    .clicked()
+                {
+                    self.execute_rename(true);
+                }
+                ui.checkbox(&mut self.restart_numbering_in_selection, tr("restart numbering"))
+                    .on_hover_text(tr("Number the selection from the start value instead of by list position"));
+                if ui
+                    .button(tr("Test on copies"))
+                    .on_hover_text(tr("Copy the files (the selected ones, if any) to a temp folder and rename the copies"))
+                    .clicked()
+                {
+                    self.test_on_copies(selected > 0);
+                }
+                if ui.button(tr("Undo")).on_hover_text("Ctrl+Z").clicked() {
+                    self.undo();
+                }
+                if ui
+                    .add_enabled(!self.redo_actions.is_empty(), egui::Button::new(tr("Redo")))
+                    .on_hover_text("Ctrl+Y / Ctrl+Shift+Z")
+                    .clicked()
+                {
+                    self.redo();
+                }
+                if ui
+                    .button(tr("Export preview..."))
+                    .on_hover_text(tr("Save every old → new name, with collision handling and skip reasons, as CSV or JSON"))
+                    .clicked()
+                {
+                    self.export_preview();
+                }
+                if ui.button(tr("Import plan...")).clicked() {
+                    self.import_plan();
+                }
+                if ui
+                    .button(tr("Import mapping..."))
+                    .on_hover_text(tr("Rename list files by a CSV of old_name,new_name rows"))
+                    .clicked()
+                {
+                    self.import_mapping();
+                }
+                if ui.button(tr("Save session...")).on_hover_text(tr("Save the file list and the template")).clicked() {
+                    self.save_session_as();
+                }
+                if ui.button(tr("Load session...")).clicked() {
+                    self.load_session();
+                }
+                if ui
+                    .button(tr("Folder tree..."))
+                    .on_hover_text(tr("Rename a folder tree with rules per depth"))
+                    .clicked()
+                {
+                    self.show_tree = true;
+                }
+                if ui.button(tr("History")).clicked() {
+                    self.history = Self::load_history();
+                    self.show_history = true;
+                }
+                if ui.button(tr("Settings...")).clicked() {
+                    self.show_settings = !self.show_settings;
+                }
+                if ui.button("?").on_hover_text(tr("Help (F1)")).clicked() {
+                    self.show_help = !self.show_help;
+                }
+            });
+
+            if !self.last_actions.is_empty() || !self.redo_actions.is_empty() {
+                ui.collapsing(
+                    format!("Undo history ({} undo / {} redo)", self.last_actions.len(), self.redo_actions.len()),
+                    |ui| {
+                        egui::ScrollArea::vertical().max_height(120.0).id_source("undo_history").show(ui, |ui| {
+                            let weak = ui.visuals().weak_text_color();
+                            for b in self.redo_actions.iter() {
+                                ui.label(RichText::new(undo_history_label(b, "redo")).color(weak));
+                            }
+                            for b in self.last_actions.iter().rev() {
+                                ui.label(undo_history_label(b, "undo"));
+                            }
+                        });
+                    },
+                );
+            }
+            if let Some(last) = self.last_actions.last() {
+                let copied = last.copied;
+                let mut entries: Vec<(PathBuf, PathBuf)> =
+                    last.mapping.iter().map(|(o, f)| (o.clone(), f.clone())).collect();
+                entries.sort();
+                let mut undo_one = None;
+                let mut export = false;
+                let title = if copied { "Last batch, copies" } else { "Last batch" };
+                ui.collapsing(format!("{} ({} files)", title, entries.len()), |ui| {
+                    if !copied && ui.button(tr("Export revert script...")).clicked() {
+                        export = true;
+                    }
+                    egui::ScrollArea::vertical()
+                        .max_height(150.0)
+                        .id_source("last_batch")
+                        .show(ui, |ui| {
+                            for (orig, final_path) in &entries {
+                                ui.horizontal(|ui| {
+                                    let undo_btn = ui
+                                        .small_button("↶")
+                                        .on_hover_text(if copied { "Delete this copy only" } else { "Undo this file only" });
+                                    undo_btn.widget_info(|| {
+                                        egui::WidgetInfo::labeled(
+                                            egui::WidgetType::Button,
+                                            format!("Undo rename of {}", final_path.display()),
+                                        )
+                                    });
+                                    if undo_btn.clicked() {
+                                        undo_one = Some(orig.clone());
+                                    }
+                                    ui.label(format!(
+                                        "{} → {}",
+                                        orig.file_name().unwrap_or_default().to_string_lossy(),
+                                        final_path.file_name().unwrap_or_default().to_string_lossy()
+                                    ));
+                                });
+                            }
+                        });
+                });
+                if let Some(orig) = undo_one {
+                    self.undo_single(&orig);
+                }
+                if export {
+                    self.export_revert_script();
+                }
+            }
+
+            ui.collapsing(tr("Import options"), |ui| {
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.import_filter.use_min_size, tr("Images at least"));
+                    ui.add(DragValue::new(&mut self.import_filter.min_width).clamp_range(0..=100000));
+                    ui.label("×");
+                    ui.add(DragValue::new(&mut self.import_filter.min_height).clamp_range(0..=100000));
+                    ui.label("px");
+                });
+                ui.horizontal(|ui| {
+                    ui.label(tr("Rated at least:"));
+                    ui.add(DragValue::new(&mut self.import_filter.min_rating).clamp_range(0..=5))
+                        .on_hover_text(tr("0 = any; ratings come from XMP (embedded or .xmp sidecar) or EXIF"));
+                    ui.label("★");
+                });
+                ui.horizontal_wrapped(|ui| {
+                    ui.label(tr("Types:"));
+                    for c in FileCategory::ALL {
+                        let mut on = self.import_filter.categories.contains(&c);
+                        if ui.toggle_value(&mut on, c.label()).changed() {
+                            if on {
+                                self.import_filter.categories.insert(c);
+                            } else {
+                                self.import_filter.categories.remove(&c);
+                            }
+                        }
+                    }
+                    ui.checkbox(&mut self.import_filter.sniff_magic, tr("Detect by content"))
+                        .on_hover_text(tr("Read the first bytes of each file instead of trusting the extension"));
+                });
+                ui.horizontal(|ui| {
+                    ui.label(tr("Only names matching regex:"));
+                    ui.text_edit_singleline(&mut self.import_filter.name_pattern);
+                    if let Err(e) = self.import_filter.name_regex() {
+                        ui.label(RichText::new(tr("invalid")).color(self.theme.palette().error))
+                            .on_hover_text(e.to_string());
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label(tr("Only names matching:"));
+                    ui.add(egui::TextEdit::singleline(&mut self.import_filter.glob).hint_text("*.jpg;*.png"))
+                        .on_hover_text(tr("Wildcards separated by ';' (* = anything, ? = one character), case-insensitive"));
+                });
+                ui.checkbox(&mut self.import_filter.folders_as_entries, tr("Add folders as entries"))
+                    .on_hover_text(tr("Added or dropped folders are renamed themselves instead of adding the files inside"));
+                ui.add_enabled_ui(!self.import_filter.folders_as_entries, |ui| ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.import_filter.recursive, tr("Subfolders"))
+                        .on_hover_text(tr("Also add files in subfolders of added folders"));
+                    ui.add_enabled_ui(self.import_filter.recursive, |ui| {
+                        ui.label(tr("levels"));
+                        ui.add(DragValue::new(&mut self.import_filter.max_depth).clamp_range(0..=64))
+                            .on_hover_text(tr("How many levels of subfolders to descend into (0 = all)"));
+                    });
+                }));
+            });
+
+            ui.collapsing("Automation API", |ui| {
+                ui.horizontal(|ui| {
+                    let mut on = self.api_enabled;
+                    if ui.checkbox(&mut on, tr("Enable local API")).changed() {
+                        self.set_api_enabled(ctx, on);
+                    }
+                    ui.label(tr("Port"));
+                    ui.add_enabled(!self.api_enabled, DragValue::new(&mut self.api_port).clamp_range(1024..=65535));
+                });
+                if self.api_enabled {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("http://127.0.0.1:{}  token: {}", self.api_port, self.api_token));
+                        if ui.small_button(tr("Copy token")).clicked() {
+                            ui.output_mut(|o| o.copied_text = self.api_token.clone());
+                        }
+                    });
+                }
+                ui.label(
+                    RichText::new("POST /plan, /execute (body: [{\"from\", \"to\"}]) or /undo with header X-BulkReName-Token")
+                        .small()
+                        .weak(),
+                );
+            });
+
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                ui.label(tr("Sort: "));
+                let namelabel = match (self.sort_key, self.sort_order) {
+                    (Some(SortKey::Name), SortOrder::Asc) => "Name ↓",
+                    (Some(SortKey::Name), SortOrder::Desc) => "Name ↑",
+                    _ => "Name",
+                };
+                let modifiedlabel = match (self.sort_key, self.sort_order) {
+                    (Some(SortKey::Modified), SortOrder::Asc) => "Modified ↓",
+                    (Some(SortKey::Modified), SortOrder::Desc) => "Modified ↑",
+                    _ => "Modified",
+                };
+                let createdlabel = match (self.sort_key, self.sort_order) {
+                    (Some(SortKey::Created), SortOrder::Asc) => "Created ↓",
+                    (Some(SortKey::Created), SortOrder::Desc) => "Created ↑",
+                    _ => "Created",
+                };
+                let sizelabel = match (self.sort_key, self.sort_order) {
+                    (Some(SortKey::Size), SortOrder::Asc) => "Size ↓",
+                    (Some(SortKey::Size), SortOrder::Desc) => "Size ↑",
+                    _ => "Size",
+                };
+                let exiflabel = match (self.sort_key, self.sort_order) {
+                    (Some(SortKey::ExifDate), SortOrder::Asc) => "EXIF date ↓",
+                    (Some(SortKey::ExifDate), SortOrder::Desc) => "EXIF date ↑",
+                    _ => "EXIF date",
+                };
+                if ui.button(namelabel).clicked() {
+                    self.sort_files(SortKey::Name);
+                }
+                if ui.button(modifiedlabel).clicked() {
+                    self.sort_files(SortKey::Modified);
+                }
+                if ui.button(createdlabel).clicked() {
+                    self.sort_files(SortKey::Created);
+                }
+                if ui.button(sizelabel).clicked() {
+                    self.sort_files(SortKey::Size);
+                }
+                if ui
+                    .button(exiflabel)
+                    .on_hover_text(tr("Capture date; files without one use the modified time"))
+                    .clicked()
+                {
+                    self.sort_files(SortKey::ExifDate);
+                }
+            });
+
+            ui.separator();
+
+            let mut split = self.column_split;
+            split_columns(ui, &mut split, |cols| {
+                // Left panel: file list
+                let left = &mut cols[0];
+                left.label(RichText::new(tr("Files (select then move)")).strong());
+                left.horizontal(|ui| {
+                    ui.checkbox(&mut self.show_thumbnails, tr("show thumbnail"));
+                    ui.menu_button(tr("Columns"), |ui| {
+                        for c in ListColumn::ALL {
+                            let mut shown = self.list_columns.contains(&c);
+                            if ui.checkbox(&mut shown, tr(c.label())).changed() {
+                                self.list_columns = ListColumn::ALL
+                                    .into_iter()
+                                    .filter(|&k| if k == c { shown } else { self.list_columns.contains(&k) })
+                                    .collect();
+                            }
+                        }
+                    });
+                    if ui
+                        .small_button(tr("Clear cache"))
+                        .on_hover_text(format!(
+                            "Delete the thumbnails saved on disk (kept under {} MB)",
+                            THUMB_CACHE_LIMIT / 1024 / 1024
+                        ))
+                        .clicked()
+                    {
+                        let dir = Self::thumb_cache_dir();
+                        let size = thumb_cache_size(&dir);
+                        match fs::remove_dir_all(&dir) {
+                            Ok(()) => self.log.info(format!(
+                                "Thumbnail cache cleared ({:.1} MB).",
+                                size as f64 / 1024.0 / 1024.0
+                            )),
+                            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                                self.log.warn("The thumbnail cache is empty.")
+                            }
+                            Err(e) => self.log.error(format!("Could not clear the thumbnail cache: {}", e)),
+                        }
+                    }
+                });
+
+                // batch operations on the multi-selection
+                let selected = self.files.iter().filter(|f| f.selected).count();
+                left.horizontal_wrapped(|ui| {
+                    if ui.small_button(tr("Select all")).clicked() {
+                        for f in &mut self.files {
+                            f.selected = true;
+                        }
+                    }
+                    if selected == 0 {
+                        return;
+                    }
+                    ui.label(format!("{} selected:", selected));
+                    if ui.small_button("Clear").clicked() {
+                        for f in &mut self.files {
+                            f.selected = false;
+                        }
+                    }
+                    if ui
+                        .small_button(tr("Remove"))
+                        .on_hover_text(tr("Remove from the list (files stay on disk)"))
+                        .clicked()
+                    {
+                        self.remove_selection();
+                    }
+                    if ui.small_button(tr("To top")).clicked() {
+                        self.move_selection(true);
+                    }
+                    if ui.small_button(tr("To bottom")).clicked() {
+                        self.move_selection(false);
+                    }
+                    let excluded = self.files.iter().filter(|f| f.selected && f.excluded).count();
+                    let (label, exclude) = if excluded == selected { ("Include", false) } else { ("Exclude", true) };
+                    if ui
+                        .small_button(label)
+                        .on_hover_text(tr("Excluded files stay in the list but keep their names"))
+                        .clicked()
+                    {
+                        for f in self.files.iter_mut().filter(|f| f.selected) {
+                            f.excluded = exclude;
+                        }
+                    }
+                });
+
+                // category chips
+                left.horizontal_wrapped(|ui| {
+                    for c in FileCategory::ALL {
+                        let count = self.files.iter().filter(|f| f.category == c).count();
+                        if count == 0 {
+                            continue;
+                        }
+                        let shown = !self.hidden_categories.contains(&c);
+                        if ui.selectable_label(shown, format!("{} ({})", c.label(), count)).clicked() {
+                            if shown {
+                                self.hidden_categories.insert(c);
+                            } else {
+                                self.hidden_categories.remove(&c);
+                            }
+                        }
+                    }
+                    let hidden = self
+                        .files
+                        .iter()
+                        .filter(|f| self.hidden_categories.contains(&f.category))
+                        .count();
+                    if hidden > 0 {
+                        ui.label(format!("{} hidden (still renamed)", hidden));
+                    }
+                });
+
+                // column headers, right-aligned like the cells; a click sorts
+                if !self.list_columns.is_empty() {
+                    left.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        for c in self.list_columns.clone().into_iter().rev() {
+                            let sorted = self.sort_key == Some(c.sort_key());
+                            let arrow = match self.sort_order {
+                                _ if !sorted => "",
+                                SortOrder::Asc => " ↓",
+                                SortOrder::Desc => " ↑",
+                            };
+                            let head = egui::SelectableLabel::new(sorted, format!("{}{}", tr(c.label()), arrow));
+                            if ui.add_sized([c.width(), 18.0], head).clicked() {
+                                self.sort_files(c.sort_key());
+                            }
+                        }
+                    });
+                }
+
+                // only the rows in view are laid out, so every row has the same height
+                let visible: Vec<usize> = (0..self.files.len())
+                    .filter(|&i| !self.hidden_categories.contains(&self.files[i].category))
+                    .collect();
+                let row_h = if self.show_thumbnails { (self.thumb_max_size.1 as f32).max(40.0) } else { 40.0 };
+                let reveal = self.reveal_row.take().and_then(|row| visible.iter().position(|&i| i == row));
+                egui::ScrollArea::vertical()
+                    .max_height(800.0)
+                    .auto_shrink([false, false])
+                    .id_source("file_list")
+                    .show_rows(left, row_h, visible.len(), |ui, range| {
+                        let mut to_delete = None;
+                        let mut delete_selection = false;
+                        let pointer_y = ui.input(|i| i.pointer.hover_pos().map(|p| p.y));
+                        let mut insert_index = None;
+
+                        if let Some(k) = reveal.filter(|k| !range.contains(k)) {
+                            let step = row_h + ui.spacing().item_spacing.y;
+                            let top = ui.min_rect().top() + (k as f32 - range.start as f32) * step;
+                            let min = egui::pos2(ui.min_rect().left(), top);
+                            let rect = egui::Rect::from_min_size(min, egui::vec2(1.0, row_h));
+                            ui.scroll_to_rect(rect, None);
+                            // the row takes focus once it is laid out
+                            self.focus_row = Some(visible[k]);
+                            ui.ctx().request_repaint();
+                        }
+
+                        let mut row_tops = Vec::new();
+
+                        for &i in &visible[range.clone()] {
+                            let mut name_clicked = false;
+                            let (rect, resp) = ui.push_id(i, |ui| {
+                                let content_ui = ui.horizontal(|ui| {
+                                    ui.set_height(row_h);
+                                    let full = self.files[i].path.file_name().and_then(|s| s.to_str()).unwrap_or("").to_string();
+                                    let labeled = |resp: &egui::Response, text: String| {
+                                        resp.widget_info(|| egui::WidgetInfo::labeled(egui::WidgetType::Button, &text));
+                                    };
                                     // delete
-                                    if ui.add(egui::Button::new("×").fill(egui::Color32::from_rgb(240, 120, 120))).clicked() {
+                                    let del = ui.add(egui::Button::new("×").fill(egui::Color32::from_rgb(240, 120, 120)));
+                                    labeled(&del, format!("Remove {} from the list", full));
+                                    if del.clicked() {
                                         to_delete = Some(i);
                                     }
-                                    ui.separator();
-                                    // ▲▼
-                                    ui.vertical(|ui| {
-                                        if ui.small_button("▲").clicked() { self.selected_idx = Some(i); self.move_up(); }
-                                        if ui.small_button("▼").clicked() { self.selected_idx = Some(i); self.move_down(); }
+                                    ui.separator();
+                                    // ▲▼
+                                    ui.vertical(|ui| {
+                                        let up = ui.small_button("▲");
+                                        labeled(&up, format!("Move {} up", full));
+                                        if up.clicked() { self.selected_idx = Some(i); self.move_up(); }
+                                        let down = ui.small_button("▼");
+                                        labeled(&down, format!("Move {} down", full));
+                                        if down.clicked() { self.selected_idx = Some(i); self.move_down(); }
+                                    });
+                                    // file name
+                                    let disp = {
+                                        let chars: Vec<char> = full.chars().collect();
+                                        if chars.len() > 20 {
+                                            let first: String = chars[..10].iter().collect();
+                                            let last: String = chars[chars.len() - 9..].iter().collect();
+                                            format!("{}…{}", first, last)
+                                        } else {
+                                            full.clone()
+                                        }
+                                    };
+                                    let name_text = if self.files[i].missing {
+                                        RichText::new(disp).color(self.theme.palette().dim).strikethrough()
+                                    } else if self.files[i].excluded {
+                                        RichText::new(disp).color(self.theme.palette().dim).italics()
+                                    } else {
+                                        RichText::new(disp)
+                                    };
+                                    let label_rect = ui.add(egui::Label::new(name_text).selectable(false)).rect;
+                                    let name_resp = ui
+                                        .interact(label_rect, file_name_id(i), egui::Sense::click())
+                                        .on_hover_text(&full);
+                                    let fe = &self.files[i];
+                                    let status = if fe.missing {
+                                        ", missing"
+                                    } else if fe.changed {
+                                        ", modified since added"
+                                    } else {
+                                        ""
+                                    };
+                                    let selected = fe.selected || Some(i) == self.selected_idx;
+                                    name_resp.widget_info(|| {
+                                        egui::WidgetInfo::selected(
+                                            egui::WidgetType::SelectableLabel,
+                                            selected,
+                                            format!("{}{}", full, status),
+                                        )
+                                    });
+                                    if name_resp.clicked() {
+                                        name_clicked = true;
+                                    }
+                                    // keyboard: the focused row is the current one
+                                    if name_resp.gained_focus() {
+                                        self.selected_idx = Some(i);
+                                    }
+                                    if name_resp.has_focus() && ui.input(|inp| inp.key_pressed(egui::Key::Delete)) {
+                                        to_delete = Some(i);
+                                        // Delete on a selected row takes the whole selection
+                                        delete_selection = self.files[i].selected;
+                                    }
+                                    if self.files[i].missing {
+                                        ui.label(RichText::new(tr("⚠ missing")).color(self.theme.palette().warn))
+                                            .on_hover_text(tr("Moved, renamed or deleted outside BulkReName"));
+                                    }
+                                    if self.files[i].changed {
+                                        ui.label(RichText::new("⚠").color(self.theme.palette().warn))
+                                            .on_hover_text(tr("Modified since added"));
+                                    }
+                                    if self.files[i].excluded {
+                                        ui.label(RichText::new(tr("excluded")).color(self.theme.palette().dim));
+                                    }
+                                    let tag = ui.add(
+                                        egui::TextEdit::singleline(&mut self.files[i].tag)
+                                            .hint_text("tag")
+                                            .desired_width(70.0),
+                                    );
+                                    tag.widget_info(|| {
+                                        egui::WidgetInfo::labeled(egui::WidgetType::TextEdit, format!("Tag of {}", full))
+                                    });
+
+                                    // metadata columns, then the thumbnail
+                                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                                        for c in self.list_columns.iter().rev() {
+                                            let value = c.value(&self.files[i]);
+                                            ui.add_sized([c.width(), 18.0], egui::Label::new(value).truncate(true));
+                                        }
+                                        if self.show_thumbnails {
+                                            let key = self.files[i].path.to_string_lossy();
+                                            let folder = self.files[i].category == FileCategory::Folder;
+                                            if !folder && !self.thumbnails.contains_key(key.as_ref()) {
+                                                let path = self.files[i].path.clone();
+                                                self.ensure_thumbnail(ui.ctx(), &path);
+                                            }
+
+                                            match self.thumbnails.get(self.files[i].path.to_string_lossy().as_ref()) {
+                                                Some(ThumbnailState::Loaded(tex, orig_size)) => {
+                                                    let max_w = self.thumb_max_size.0 as f32;
+                                                    let max_h = row_h;
+                                                    let scale = (max_w / orig_size.x).min(max_h / orig_size.y).min(1.0);
+                                                    let size = *orig_size * scale;
+                                                    ui.image((tex.id(), size));
+                                                }
+                                                Some(ThumbnailState::Loading) => { ui.spinner(); }
+                                                _ => {}
+                                            }
+                                        }
+                                    });
+                                });
+
+                                let rect = content_ui.response.rect;
+
+                                // a selected row drags the whole selection
+                                let dragged = self.dragging_idx.is_some_and(|d| {
+                                    d == i || self.files[d].selected && self.files[i].selected
+                                });
+                                let stroke = if dragged {
+                                    Some(egui::Stroke::new(2.0, egui::Color32::WHITE))
+                                } else if Some(i) == self.selected_idx || self.files[i].selected {
+                                    Some(egui::Stroke::new(2.0, egui::Color32::from_rgb(100, 150, 250)))
+                                } else {
+                                    None
+                                };
+
+                                if let Some(s) = stroke {
+                                    ui.painter().rect_stroke(rect, 6.0, s);
+                                }
+
+                                // separator
+                                ui.painter().line_segment(
+                                    [rect.left_bottom(), rect.right_bottom()],
+                                    egui::Stroke::new(1.0, egui::Color32::from_gray(80)),
+                                );
+
+                                let response = ui.interact(rect, ui.id(), egui::Sense::drag());
+
+                                (rect, response)
+                            }).inner;
+
+                            row_tops.push((i, rect.top()));
+
+                            if resp.drag_started() {
+                                self.dragging_idx = Some(i);
+                                self.selected_idx = Some(i);
+                            }
+                            if name_clicked {
+                                let mods = ui.input(|inp| inp.modifiers);
+                                self.click_select(i, mods.command, mods.shift);
+                            }
+
+                            if let Some(py) = pointer_y {
+                                if py < rect.center().y && insert_index.is_none() {
+                                    insert_index = Some(i);
+                                }
+                            }
+                        }// for loop end
+
+                        if insert_index.is_none() {
+                            // below the rows in view: before the next one, if any
+                            insert_index = Some(visible.get(range.end).copied().unwrap_or(self.files.len()));
+                        }
+
+                        // drop line
+                        if self.dragging_idx.is_some() {
+                            if let Some(target) = insert_index {
+                                let y = row_tops
+                                    .iter()
+                                    .find(|(i, _)| *i == target)
+                                    .map(|(_, top)| *top)
+                                    .unwrap_or_else(|| ui.min_rect().bottom());
+
+                                let full_width = ui.available_width();
+                                let left = ui.min_rect().left();
+                                ui.painter().line_segment(
+                                    [egui::pos2(left, y), egui::pos2(left + full_width, y)],
+                                    egui::Stroke::new(3.0, egui::Color32::LIGHT_BLUE),
+                                );
+                            }
+
+                            ui.ctx().set_cursor_icon(egui::CursorIcon::Grabbing);
+                        }
+
+                        // move algorithm
+                        if let Some(drag_i) = self.dragging_idx {
+                            if ui.input(|i| i.pointer.any_released()) {
+                                let group = self.files[drag_i].selected
+                                    && self.files.iter().filter(|f| f.selected).count() > 1;
+                                if let (true, Some(target)) = (group, insert_index) {
+                                    self.move_selection_to(target);
+                                } else if let Some(target) = insert_index {
+                                    let item = self.files.remove(drag_i);
+                                    let mut target = target;
+                                    if drag_i < target { target -= 1; }
+                                    let target = target.min(self.files.len());
+                                    self.files.insert(target, item);
+                                    self.selected_idx = Some(target);
+                                }
+                                self.dragging_idx = None;
+                            }
+                        }
+
+                        // delete algorithm
+                        if self.dragging_idx.is_none() {
+                            if let Some(i) = to_delete {
+                                if i < self.files.len() {
+                                    let had_focus = ui.memory(|m| m.has_focus(file_name_id(i)));
+                                    if delete_selection {
+                                        self.remove_selection();
+                                    } else {
+                                        self.selected_idx = Some(i);
+                                        self.remove_selected();
+                                    }
+                                    if had_focus && !self.files.is_empty() {
+                                        self.focus_row = Some(i.min(self.files.len() - 1));
+                                    }
+                                }
+                            }
+                        }
+                    });
+                
+
+                // Right panel: template, preview, persistence
+                let right = &mut cols[1];
+                right.label(RichText::new(tr("Template Blocks")).strong());
+
+                // blocks editor ...
+                let mut idx = 0;
+                while idx < self.blocks.len() {
+                    let blk = self.blocks[idx].clone();
+                    let mut new_blk = blk.clone();
+                    let mut action: Option<&str> = None;
+                    right.horizontal(|ui| {
+                        let del_block = egui::Button::new("×")
+                            .fill(egui::Color32::from_rgb(240, 150, 150));
+                        let del_resp = ui.add(del_block);
+                        del_resp.widget_info(|| {
+                            egui::WidgetInfo::labeled(egui::WidgetType::Button, format!("Remove block {}", idx))
+                        });
+                        if del_resp.clicked() {
+                            if self.blocks.len() <= 1 {
+                                self.show_delete_error = true;
+                            } else {
+                                action = Some("del");
+                            }
+                        }
+                        ui.separator();
+                        let up = ui.small_button("▲");
+                        up.widget_info(|| egui::WidgetInfo::labeled(egui::WidgetType::Button, format!("Move block {} up", idx)));
+                        if up.clicked() && idx > 0 {
+                            action = Some("up");
+                        }
+                        let down = ui.small_button("▼");
+                        down.widget_info(|| egui::WidgetInfo::labeled(egui::WidgetType::Button, format!("Move block {} down", idx)));
+                        if down.clicked() && idx + 1 < self.blocks.len() {
+                            action = Some("down");
+                        }
+                        ui.label(format!("[{}]", idx));
+                        let conditional = matches!(new_blk, Block::Conditional { .. });
+                        if ui
+                            .selectable_label(conditional, "if")
+                            .on_hover_text(tr("Apply this block only to files with some extensions or names"))
+                            .clicked()
+                        {
+                            new_blk = match new_blk.clone() {
+                                Block::Conditional { block, .. } => *block,
+                                b => Block::Conditional {
+                                    when: Condition::ExtIn(String::new()),
+                                    block: Box::new(b),
+                                    otherwise: String::new(),
+                                },
+                            };
+                        }
+                        let (inner, otherwise) = match &mut new_blk {
+                            Block::Conditional { when, block, otherwise } => {
+                                ui.label("<If>");
+                                egui::ComboBox::from_id_source(format!("condition_{}", idx))
+                                    .selected_text(when.label())
+                                    .show_ui(ui, |ui| {
+                                        let value = match when {
+                                            Condition::ExtIn(v) | Condition::NameMatches(v) | Condition::ShapeIn(v) => {
+                                                v.clone()
+                                            }
+                                        };
+                                        let conditions = [
+                                            Condition::ExtIn(value.clone()),
+                                            Condition::NameMatches(value.clone()),
+                                            Condition::ShapeIn(value),
+                                        ];
+                                        for c in conditions {
+                                            let label = c.label();
+                                            ui.selectable_value(when, c, label);
+                                        }
                                     });
-                                    // file name
-                                    let full = self.files[i].path.file_name().and_then(|s| s.to_str()).unwrap_or("").to_string();
-                                    let disp = {
-                                        let chars: Vec<char> = full.chars().collect();
-                                        if chars.len() > 20 {
-                                            let first: String = chars[..10].iter().collect();
-                                            let last: String = chars[chars.len() - 9..].iter().collect();
-                                            format!("{}…{}", first, last)
-                                        } else {
-                                            full.clone()
+                                match when {
+                                    Condition::ExtIn(list) => {
+                                        let list = egui::TextEdit::singleline(list).hint_text("jpg, png");
+                                        ui.add(list.desired_width(90.0));
+                                    }
+                                    Condition::ShapeIn(list) => {
+                                        let list = egui::TextEdit::singleline(list).hint_text("portrait, 16x9");
+                                        ui.add(list.desired_width(90.0))
+                                            .on_hover_text(tr("portrait, landscape, square or an aspect ratio like 16x9"));
+                                    }
+                                    Condition::NameMatches(pattern) => {
+                                        let edit = egui::TextEdit::singleline(pattern).hint_text("regex");
+                                        ui.add(edit.desired_width(90.0));
+                                        if let Err(e) = Regex::new(pattern) {
+                                            ui.label(RichText::new(tr("invalid")).color(self.theme.palette().error))
+                                                .on_hover_text(e.to_string());
+                                        }
+                                    }
+                                }
+                                ui.label("→");
+                                (&mut **block, Some(otherwise))
+                            }
+                            b => (b, None),
+                        };
+                        match inner {
+                            Block::Literal(s) => {
+                                ui.label("<Literal>");
+                                ui.text_edit_singleline(s);
+                            }
+                            Block::Number { width, start, step, locale, style, pad, next, group } => {
+                                ui.label("<Number>");
+                                egui::ComboBox::from_id_source(format!("number_style_{}", idx))
+                                    .selected_text(style.label())
+                                    .show_ui(ui, |ui| {
+                                        for s in NumberStyle::ALL {
+                                            ui.selectable_value(style, s, s.label());
+                                        }
+                                    });
+                                ui.label(tr("min digits:"));
+                                ui.add(DragValue::new(width).clamp_range(0..=20));
+                                let custom = !matches!(*pad, '0' | ' ');
+                                egui::ComboBox::from_id_source(format!("number_pad_{}", idx))
+                                    .width(60.0)
+                                    .selected_text(match *pad {
+                                        '0' => "0",
+                                        ' ' => "space",
+                                        _ => "custom",
+                                    })
+                                    .show_ui(ui, |ui| {
+                                        ui.selectable_value(pad, '0', "0");
+                                        ui.selectable_value(pad, ' ', "space");
+                                        if ui.selectable_label(custom, "custom").clicked() && !custom {
+                                            *pad = '_';
+                                        }
+                                    })
+                                    .response
+                                    .on_hover_text(tr("Character filling the number up to the min digits"));
+                                if custom {
+                                    let mut text = pad.to_string();
+                                    let edit = egui::TextEdit::singleline(&mut text).char_limit(1).desired_width(16.0);
+                                    if ui.add(edit).changed() {
+                                        if let Some(c) = text.chars().next() {
+                                            *pad = c;
+                                        }
+                                    }
+                                }
+                                ui.label("init:");
+                                ui.add(DragValue::new(start));
+                                ui.label("gain:");
+                                ui.add(DragValue::new(step));
+                                let mut resume = next.is_some();
+                                if ui
+                                    .checkbox(&mut resume, tr("continue"))
+                                    .on_hover_text(tr(
+                                        "Start the next batch after the last number used; kept with the template",
+                                    ))
+                                    .changed()
+                                {
+                                    *next = resume.then_some(*start);
+                                }
+                                if let Some(value) = next {
+                                    ui.label(tr("next:"));
+                                    ui.add(DragValue::new(value));
+                                    if ui.button(tr("Reset counter")).clicked() {
+                                        *value = *start;
+                                    }
+                                    if !self.saved_templates.iter().any(|t| t.name == self.current_template_name) {
+                                        ui.label(RichText::new(tr("not saved")).color(self.theme.palette().warn))
+                                            .on_hover_text(tr(
+                                                "Save the template under this name to keep the counter after a restart",
+                                            ));
+                                    }
+                                }
+                                // e.g. "[0] Date"
+                                let block_label = |i: usize| {
+                                    format!("[{}] {}", i, self.blocks.get(i).map_or("", |b| block_kind(b)))
+                                };
+                                let selected = match &*group {
+                                    GroupKey::Block(i) => format!("{} {}", tr("per"), block_label(*i)),
+                                    g => tr(g.label()).to_string(),
+                                };
+                                egui::ComboBox::from_id_source(format!("number_group_{}", idx))
+                                    .selected_text(selected)
+                                    .show_ui(ui, |ui| {
+                                        ui.selectable_value(group, GroupKey::None, tr("all files"));
+                                        for i in (0..self.blocks.len()).filter(|&i| i != idx) {
+                                            let label = format!("{} {}", tr("per"), block_label(i));
+                                            ui.selectable_value(group, GroupKey::Block(i), label);
+                                        }
+                                        ui.selectable_value(group, GroupKey::Folder, tr("per folder"));
+                                        let by_regex = matches!(group, GroupKey::Pattern(_));
+                                        if ui.selectable_label(by_regex, tr("per regex")).clicked() && !by_regex {
+                                            *group = GroupKey::Pattern(r"^(\d{8})".into());
+                                        }
+                                    })
+                                    .response
+                                    .on_hover_text(tr(
+                                        "Count from init again for each value of the key, e.g. per shooting day",
+                                    ));
+                                if let GroupKey::Pattern(pattern) = group {
+                                    ui.add(egui::TextEdit::singleline(pattern).hint_text("regex").desired_width(90.0))
+                                        .on_hover_text(tr("The first capture group (or the whole match) is the key"));
+                                    if let Err(e) = Regex::new(pattern) {
+                                        ui.label(RichText::new(tr("invalid")).color(self.theme.palette().error))
+                                            .on_hover_text(e.to_string());
+                                    }
+                                }
+                                let grouping = |l: Locale| match l {
+                                    Locale::Default => "no grouping".to_string(),
+                                    l => format!("{} ({})", l.label(), l.group("1234")),
+                                };
+                                egui::ComboBox::from_id_source(format!("number_locale_{}", idx))
+                                    .selected_text(grouping(*locale))
+                                    .show_ui(ui, |ui| {
+                                        for l in Locale::ALL {
+                                            ui.selectable_value(locale, l, grouping(l));
+                                        }
+                                    });
+                            }
+                            Block::Date { .. } | Block::ExifDate { .. } => {
+                                // example for the first file (its date as of the last frame)
+                                let sample = match self.files.first() {
+                                    Some(fe) => self.block_date(blk.inner(), fe),
+                                    None => Local::now(),
+                                };
+                                let (format, locale, source) = match inner {
+                                    Block::Date { format, locale, source, zone } => {
+                                        (format, locale, Some((source, zone)))
+                                    }
+                                    Block::ExifDate { format, locale } => (format, locale, None),
+                                    _ => unreachable!(),
+                                };
+                                ui.label(if source.is_none() { "<EXIF date>" } else { "<Date fmt>" });
+                                let mut sample = sample.fixed_offset();
+                                if let Some((source, zone)) = source {
+                                    egui::ComboBox::from_id_source(format!("date_source_{}", idx))
+                                        .selected_text(source.label())
+                                        .show_ui(ui, |ui| {
+                                            for s in DateSource::ALL {
+                                                ui.selectable_value(source, s, s.label());
+                                            }
+                                        })
+                                        .response
+                                        .on_hover_text(tr("Each file's modification or creation time, or the time of the rename"));
+                                    let offset = match *zone {
+                                        DateZone::Offset(minutes) => minutes,
+                                        _ => sample.offset().local_minus_utc() / 60,
+                                    };
+                                    egui::ComboBox::from_id_source(format!("date_zone_{}", idx))
+                                        .width(70.0)
+                                        .selected_text(match *zone {
+                                            DateZone::Offset(_) => "UTC±".to_string(),
+                                            z => z.label(),
+                                        })
+                                        .show_ui(ui, |ui| {
+                                            ui.selectable_value(zone, DateZone::Local, "local");
+                                            ui.selectable_value(zone, DateZone::Utc, "UTC");
+                                            ui.selectable_value(zone, DateZone::Offset(offset), "UTC±");
+                                        })
+                                        .response
+                                        .on_hover_text(tr("Time zone the date and time are written in"));
+                                    if let DateZone::Offset(minutes) = zone {
+                                        ui.add(
+                                            DragValue::new(minutes)
+                                                .clamp_range(-12 * 60..=14 * 60)
+                                                .speed(15.0)
+                                                .custom_formatter(|m, _| format_offset(m as i32))
+                                                .custom_parser(|s| {
+                                                    let (sign, rest) = match s.trim().strip_prefix('-') {
+                                                        Some(rest) => (-1.0, rest),
+                                                        None => (1.0, s.trim().trim_start_matches('+')),
+                                                    };
+                                                    let (h, m) = rest.split_once(':').unwrap_or((rest, "0"));
+                                                    let (h, m) = (h.parse::<f64>().ok()?, m.parse::<f64>().ok()?);
+                                                    Some(sign * (h * 60.0 + m))
+                                                }),
+                                        );
+                                    }
+                                    sample = zone.convert(sample.with_timezone(&Local));
+                                }
+                                ui.text_edit_singleline(format);
+                                date_preset_menu(ui, format!("date_preset_{}", idx), format, &sample);
+                                match format_date(&sample, format, *locale) {
+                                    Some(example) => ui.label(RichText::new(format!("→ {}", example)).weak()),
+                                    None => ui
+                                        .label(RichText::new(tr("invalid")).color(self.theme.palette().error))
+                                        .on_hover_text(tr("Unknown % specifier (see Help → Date format)")),
+                                };
+                                egui::ComboBox::from_id_source(format!("date_locale_{}", idx))
+                                    .selected_text(locale.label())
+                                    .show_ui(ui, |ui| {
+                                        for l in Locale::ALL {
+                                            ui.selectable_value(locale, l, l.label());
                                         }
+                                    })
+                                    .response
+                                    .on_hover_text("Language of %a %A %b %B; 日本語 adds %EY (令和6年) and %Ey (R6)");
+                            }
+                            Block::NameDate { pattern, format, locale, missing } => {
+                                ui.label("<Name date>");
+                                ui.add(egui::TextEdit::singleline(pattern).hint_text("auto").desired_width(110.0))
+                                    .on_hover_text(tr(NAME_DATE_HELP));
+                                let bad_pattern = !pattern.is_empty() && DatePattern::new(pattern).is_none();
+                                ui.label("→");
+                                ui.add(egui::TextEdit::singleline(format).desired_width(110.0));
+                                let found = self.files.first().map(|fe| {
+                                    let stem = fe.path.file_stem().unwrap_or_default().to_string_lossy();
+                                    date_in_name(&stem, pattern)
+                                });
+                                let sample = found.flatten().unwrap_or_else(Local::now).fixed_offset();
+                                date_preset_menu(ui, format!("name_date_preset_{}", idx), format, &sample);
+                                let example = format_date(&sample, format, *locale);
+                                if bad_pattern || example.is_none() {
+                                    ui.label(RichText::new(tr("invalid")).color(self.theme.palette().error));
+                                } else if let Some(found) = found {
+                                    let text = match found {
+                                        Some(_) => format!("→ {}", example.unwrap_or_default()),
+                                        None => tr("(no date in the first name)").to_string(),
                                     };
-                                    ui.label(disp).on_hover_text(full);
+                                    ui.label(RichText::new(text).weak());
+                                }
+                                egui::ComboBox::from_id_source(format!("name_date_locale_{}", idx))
+                                    .selected_text(locale.label())
+                                    .show_ui(ui, |ui| {
+                                        for l in Locale::ALL {
+                                            ui.selectable_value(locale, l, l.label());
+                                        }
+                                    });
+                                ui.label(tr("if missing:"));
+                                ui.add(egui::TextEdit::singleline(missing).desired_width(60.0));
+                            }
+                            Block::Original { mode, case } => {
+                                ui.label("<Orig>");
 
-                                    // thumbnail
-                                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
-                                        if self.show_thumbnails {
-                                            let path_buf = self.files[i].path.clone();
-                                            let key = path_buf.to_string_lossy().to_string();
+                                egui::ComboBox::from_id_source(format!("orig_mode_{}", idx))
+                                    .selected_text(match mode {
+                                        OriginalMode::Keep => "Keep",
+                                        OriginalMode::RemoveRange { .. } => "Del Range",
+                                        OriginalMode::RemoveSubstring { .. } => "Del Substr.",
+                                    })
+                                    .show_ui(ui, |ui| {
+                                        if ui.selectable_label(matches!(mode, OriginalMode::Keep), "Keep").clicked() {
+                                            *mode = OriginalMode::Keep;
+                                        }
+                                        if ui.selectable_label(matches!(mode, OriginalMode::RemoveRange { .. }), "Range").clicked() {
+                                            *mode = OriginalMode::RemoveRange { start: 0, end: 1 };
+                                        }
+                                        if ui.selectable_label(matches!(mode, OriginalMode::RemoveSubstring { .. }), "Substring").clicked() {
+                                            *mode = OriginalMode::RemoveSubstring { pattern: "".into(), case_sensitive: true, };
+                                        }
+                                    });
 
-                                            if !self.thumbnails.contains_key(&key) {
-                                                self.ensure_thumbnail(ui.ctx(), &path_buf);
-                                            }
+                                match mode {
+                                    OriginalMode::RemoveRange { start, end } => {
+                                        ui.label(tr("range:"));
+                                        ui.add(DragValue::new(start));
+                                        ui.label("～");
+                                        ui.add(DragValue::new(end));
+                                    }
+                                    OriginalMode::RemoveSubstring { pattern, case_sensitive } => {
+                                        ui.vertical(|ui| {
+                                            ui.horizontal(|ui| {
+                                                ui.toggle_value(case_sensitive, "Aa")
+                                                    .on_hover_text(tr("Case sensitive"));
+                                                ui.label(tr("pattern:"));
+                                                ui.text_edit_singleline(pattern);
+                                            });
+                                        });
+                                    }
+                                    _ => {}
+                                }
+                                egui::ComboBox::from_id_source(format!("orig_case_{}", idx))
+                                    .selected_text(case.label())
+                                    .show_ui(ui, |ui| {
+                                        for c in NameCase::ALL {
+                                            ui.selectable_value(case, c, c.label());
+                                        }
+                                    })
+                                    .response
+                                    .on_hover_text(tr("Case of the original name"));
+                            }
+                            Block::OriginalSlice { start, len, from_end } => {
+                                ui.label("<Orig slice>start:");
+                                ui.add(DragValue::new(start).clamp_range(0..=255));
+                                ui.label(tr("length:"));
+                                ui.add(DragValue::new(len).clamp_range(0..=255))
+                                    .on_hover_text(tr("0 = up to the other end"));
+                                ui.checkbox(from_end, tr("from end"))
+                                    .on_hover_text(tr("Count start and length back from the end of the name"));
+                            }
+                            Block::RegexReplace { pattern, replacement } => {
+                                ui.label("<Regex>");
+                                ui.add(egui::TextEdit::singleline(pattern).hint_text("pattern").desired_width(140.0));
+                                ui.label("→");
+                                ui.add(egui::TextEdit::singleline(replacement).hint_text("$1").desired_width(100.0));
+                                if let Err(e) = Regex::new(pattern) {
+                                    ui.label(RichText::new(tr("invalid")).color(self.theme.palette().error))
+                                        .on_hover_text(e.to_string());
+                                }
+                            }
+                            Block::Cleanup { trim, collapse, brackets, strip } => {
+                                ui.label("<Cleanup>");
+                                ui.checkbox(trim, tr("trim")).on_hover_text(tr("Spaces and _ at both ends"));
+                                ui.checkbox(collapse, tr("collapse")).on_hover_text(tr("Repeated spaces or _ → one"));
+                                ui.checkbox(brackets, tr("brackets"))
+                                    .on_hover_text(tr("Remove [..], (..), {..}, 【..】 and （..） segments"));
+                                ui.label(tr("strip:"));
+                                ui.add(egui::TextEdit::singleline(strip).hint_text("#!~").desired_width(60.0))
+                                    .on_hover_text(tr("Characters to remove"));
+                            }
+                            Block::Transliterate { romaji, fallback } => {
+                                ui.label("<ASCII>");
+                                ui.checkbox(romaji, tr("kana → romaji"))
+                                    .on_hover_text(tr("Hepburn, e.g. しゃしん → shashin"));
+                                ui.label(tr("other:"));
+                                ui.add(egui::TextEdit::singleline(fallback).desired_width(30.0))
+                                    .on_hover_text(tr("Put in for characters without an ASCII form (e.g. kanji)"));
+                            }
+                            Block::Extension => {
+                                ui.label("<Extension>");
+                            }
+                            Block::AudioTag { field, placeholder } => {
+                                ui.label("<Audio tag>");
+                                egui::ComboBox::from_id_source(format!("tag_field_{}", idx))
+                                    .selected_text(field.label())
+                                    .show_ui(ui, |ui| {
+                                        for f in TagField::ALL {
+                                            ui.selectable_value(field, f, f.label());
+                                        }
+                                    });
+                                ui.label(tr("if missing:"));
+                                ui.add(egui::TextEdit::singleline(placeholder).desired_width(80.0));
+                            }
+                            Block::Camera { field, placeholder } => {
+                                ui.label("<Camera>");
+                                egui::ComboBox::from_id_source(format!("camera_field_{}", idx))
+                                    .selected_text(field.label())
+                                    .show_ui(ui, |ui| {
+                                        for f in CameraField::ALL {
+                                            ui.selectable_value(field, f, f.label());
+                                        }
+                                    });
+                                ui.label(tr("if missing:"));
+                                ui.add(egui::TextEdit::singleline(placeholder).desired_width(80.0));
+                            }
+                            Block::FileSize { unit } => {
+                                ui.label("<Size>");
+                                egui::ComboBox::from_id_source(format!("size_unit_{}", idx))
+                                    .selected_text(unit.label())
+                                    .show_ui(ui, |ui| {
+                                        for u in SizeUnit::ALL {
+                                            ui.selectable_value(unit, u, u.label());
+                                        }
+                                    });
+                            }
+                            Block::Shape { field } => {
+                                ui.label("<Shape>");
+                                egui::ComboBox::from_id_source(format!("shape_field_{}", idx))
+                                    .selected_text(field.label())
+                                    .show_ui(ui, |ui| {
+                                        for f in ShapeField::ALL {
+                                            ui.selectable_value(field, f, f.label());
+                                        }
+                                    });
+                            }
+                            Block::Dimensions => {
+                                ui.label("<Dimensions>");
+                                ui.label(tr("(1920x1080, images only)"));
+                            }
+                            Block::Script { code } => {
+                                ui.label("<Script>");
+                                ui.add(
+                                    egui::TextEdit::multiline(code)
+                                        .code_editor()
+                                        .desired_rows(1)
+                                        .desired_width(240.0)
+                                        .hint_text(r#"name.to_lower() + "_" + index"#),
+                                )
+                                .on_hover_text(tr(SCRIPT_HELP));
+                                // run on the first file, so unknown variables show up too
+                                let check = match self.files.first() {
+                                    Some(fe) => {
+                                        run_script(code, 0, &ListFile { fe, phash: None, stem: None }).map(|_| ())
+                                    }
+                                    None => check_script(code),
+                                };
+                                if let Err(e) = check {
+                                    ui.label(RichText::new(tr("invalid")).color(self.theme.palette().error))
+                                        .on_hover_text(e);
+                                }
+                            }
+                            Block::Tag => {
+                                ui.label("<Tag>");
+                                ui.label(tr("(tag typed next to each file)"));
+                            }
+                            Block::Rating { style } => {
+                                ui.label("<Rating>");
+                                ui.radio_value(style, RatingStyle::Stars, "★★★");
+                                ui.radio_value(style, RatingStyle::Short, "r3");
+                            }
+                            Block::PHash { len } => {
+                                ui.label("<Image hash>digits:");
+                                ui.add(DragValue::new(len).clamp_range(1..=16));
+                                if self.phash_rx.is_some() {
+                                    ui.spinner();
+                                }
+                            }
+                            Block::Audio { field } => {
+                                ui.label("<Audio>");
+                                egui::ComboBox::from_id_source(format!("audio_field_{}", idx))
+                                    .selected_text(field.label())
+                                    .show_ui(ui, |ui| {
+                                        for f in AudioField::ALL {
+                                            ui.selectable_value(field, f, f.label());
+                                        }
+                                    });
+                            }
+                            // unwrapped above
+                            Block::Conditional { .. } => {}
+                        }
+                        if let Some(otherwise) = otherwise {
+                            ui.label(tr("else:"));
+                            ui.add(egui::TextEdit::singleline(otherwise).desired_width(60.0));
+                        }
+                        if self.show_delete_error {
+                            egui::Window::new("ERROR")
+                                .collapsible(false)
+                                .resizable(false)
+                                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                                .fixed_size(egui::vec2(180.0, 90.0))
+                                .show(ui.ctx(), |ui| {
+                                    ui.vertical_centered(|ui| {
+                                        ui.set_width(160.0);
 
-                                            match self.thumbnails.get(&key) {
-                                                Some(ThumbnailState::Loaded(tex, orig_size)) => {
-                                                    let max_w = self.thumb_max_size.0 as f32;
-                                                    let max_h = self.thumb_max_size.1 as f32;
-                                                    let scale = (max_w / orig_size.x).min(max_h / orig_size.y).min(1.0);
-                                                    let size = *orig_size * scale;
-                                                    ui.image((tex.id(), size));
-                                                }
-                                                Some(ThumbnailState::Loading) => { ui.spinner(); }
-                                                _ => {}
-                                            }
+                                        ui.add(
+                                            egui::Label::new("need at least 1 block")
+                                                .wrap(true)
+                                        );
+
+                                        ui.add_space(8.0);
+
+                                        if ui.button("OK").clicked() {
+                                            self.show_delete_error = false;
                                         }
                                     });
                                 });
+                        }
+                    });
+                    if let Some(act) = action {
+                        match act {
+                            "up" | "down" => {
+                                let other = if act == "up" { idx - 1 } else { idx + 1 };
+                                self.blocks.swap(idx, other);
+                                let swapped = |i| Some(if i == idx { other } else if i == other { idx } else { i });
+                                GroupKey::remap(&mut self.blocks, swapped);
+                                idx = other;
+                                continue;
+                            }
+                            "del" => {
+                                self.blocks.remove(idx);
+                                GroupKey::remap(&mut self.blocks, |i| match i.cmp(&idx) {
+                                    std::cmp::Ordering::Less => Some(i),
+                                    std::cmp::Ordering::Equal => None,
+                                    std::cmp::Ordering::Greater => Some(i - 1),
+                                });
+                                continue;
+                            }
+                            _ => {}
+                        }
+                    }
+                    self.blocks[idx] = new_blk;
+                    idx += 1;
+                }
 
-                                let rect = content_ui.response.rect;
-
-                                let stroke = if Some(i) == self.dragging_idx {
-                                    Some(egui::Stroke::new(2.0, egui::Color32::WHITE))
-                                } else if Some(i) == self.selected_idx {
-                                    Some(egui::Stroke::new(2.0, egui::Color32::from_rgb(100, 150, 250)))
-                                } else {
-                                    None
-                                };
+                right.horizontal_wrapped(|ui| {
+                    if ui.button(tr("Add Literal")).clicked() {
+                        self.blocks.push(Block::Literal(String::new()));
+                    }
+                    if ui.button(tr("Add Number")).clicked() {
+                        self.blocks.push(Block::Number {
+                            width: 4,
+                            start: 1,
+                            step: 1,
+                            locale: Locale::Default,
+                            style: NumberStyle::Decimal,
+                            pad: '0',
+                            next: None,
+                            group: GroupKey::None,
+                        });
+                    }
+                    if ui.button(tr("Add Date")).clicked() {
+                        self.blocks.push(Block::Date {
+                            format: "%Y%m%d".into(),
+                            locale: Locale::Default,
+                            source: DateSource::Modified,
+                            zone: DateZone::Local,
+                        });
+                    }
+                    if ui.button(tr("Add EXIF date")).clicked() {
+                        self.blocks.push(Block::ExifDate {
+                            format: "%Y%m%d_%H%M%S".into(),
+                            locale: Locale::Default,
+                        });
+                    }
+                    if ui.button(tr("Add Name date")).clicked() {
+                        self.blocks.push(Block::NameDate {
+                            pattern: String::new(),
+                            format: "%Y%m%d".into(),
+                            locale: Locale::Default,
+                            missing: String::new(),
+                        });
+                    }
+                    if ui.button(tr("Add Shape")).clicked() {
+                        self.blocks.push(Block::Shape { field: ShapeField::Orientation });
+                    }
+                    if ui.button(tr("Add Camera")).clicked() {
+                        self.blocks.push(Block::Camera { field: CameraField::Model, placeholder: String::new() });
+                    }
+                    if ui.button(tr("Add Original")).clicked() {
+                        self.blocks.push(Block::Original { mode: OriginalMode::Keep, case: NameCase::Keep });
+                    }
+                    if ui.button(tr("Add Orig slice")).clicked() {
+                        self.blocks.push(Block::OriginalSlice { start: 0, len: 8, from_end: false });
+                    }
+                    if ui.button(tr("Add Regex")).clicked() {
+                        self.blocks.push(Block::RegexReplace { pattern: String::new(), replacement: String::new() });
+                    }
+                    if ui.button(tr("Add Cleanup")).clicked() {
+                        self.blocks.push(Block::Cleanup {
+                            trim: true,
+                            collapse: true,
+                            brackets: true,
+                            strip: String::new(),
+                        });
+                    }
+                    if ui.button(tr("Add ASCII")).clicked() {
+                        self.blocks.push(Block::Transliterate { romaji: true, fallback: "_".into() });
+                    }
+                    if ui.button(tr("Add Extension")).clicked() {
+                        self.blocks.push(Block::Extension);
+                    }
+                    if ui.button(tr("Add Size")).clicked() {
+                        self.blocks.push(Block::FileSize { unit: SizeUnit::Auto });
+                    }
+                    if ui.button(tr("Add Dimensions")).clicked() {
+                        self.blocks.push(Block::Dimensions);
+                    }
+                    if ui.button(tr("Add Tag")).clicked() {
+                        self.blocks.push(Block::Tag);
+                    }
+                    if ui.button(tr("Add Rating")).clicked() {
+                        self.blocks.push(Block::Rating { style: RatingStyle::Stars });
+                    }
+                    if ui.button(tr("Add Audio")).clicked() {
+                        self.blocks.push(Block::Audio { field: AudioField::Duration });
+                    }
+                    if ui.button(tr("Add Audio tag")).clicked() {
+                        self.blocks.push(Block::AudioTag { field: TagField::Artist, placeholder: "Unknown".into() });
+                    }
+                    if ui.button(tr("Add Image hash")).clicked() {
+                        self.blocks.push(Block::PHash { len: 6 });
+                    }
+                    if ui.button(tr("Add Script")).on_hover_text(tr(SCRIPT_HELP)).clicked() {
+                        self.blocks.push(Block::Script { code: "name".into() });
+                    }
+                });
+                right.separator();
 
-                                if let Some(s) = stroke {
-                                    ui.painter().rect_stroke(rect, 6.0, s);
+                right.label(tr("Collision strategy:"));
+                right.horizontal(|ui| {
+                    for (strategy, label) in COLLISION_LABELS {
+                        let radio = ui.radio_value(&mut self.collision, strategy, label);
+                        if strategy == CollisionStrategy::Dedup {
+                            radio.on_hover_text(tr(
+                                "If the target or a numbered copy has the same content: skipped; otherwise numbered",
+                            ));
+                        }
+                    }
+                });
+                if self.collision == CollisionStrategy::Dedup {
+                    right.checkbox(
+                        &mut self.dedup_delete_source,
+                        tr("Delete source when identical (cannot be undone)"),
+                    );
+                }
+                //right.checkbox(&mut self.use_mtime_for_date, "Use file mtime for date");
+                right.horizontal(|ui| {
+                    ui.label(tr("Target filesystem:"));
+                    ComboBox::from_id_source("fs_profile")
+                        .selected_text(self.fs_profile.label())
+                        .show_ui(ui, |ui| {
+                            for p in FsProfile::ALL {
+                                ui.selectable_value(&mut self.fs_profile, p, p.label());
+                            }
+                        });
+                    ui.checkbox(&mut self.deconflict, tr("Number near-duplicates")).on_hover_text(
+                        "Add \" (1)\" to names that differ from another name in the folder only by case \
+                         or Unicode normalization (they collide on Windows and macOS)",
+                    );
+                    ui.checkbox(&mut self.sanitize, tr("Portable names")).on_hover_text(
+                        "Replace characters Windows does not allow (<>:\"/\\|?*) with _, drop trailing dots \
+                         and spaces and rename reserved names like CON or NUL, on any OS",
+                    );
+                });
+                right.horizontal(|ui| {
+                    ui.label(tr("Trailing dot/space:"));
+                    ui.radio_value(&mut self.trailing_fix, TrailingFix::Warn, "Warn");
+                    ui.radio_value(&mut self.trailing_fix, TrailingFix::Trim, "Trim");
+                    ui.radio_value(&mut self.trailing_fix, TrailingFix::Replace, "Replace with _");
+                });
+                right.horizontal(|ui| {
+                    ui.label(tr("Extension:"));
+                    ui.radio_value(&mut self.ext_policy, ExtPolicy::Keep, "Keep");
+                    ui.radio_value(&mut self.ext_policy, ExtPolicy::Lower, "lower");
+                    ui.radio_value(&mut self.ext_policy, ExtPolicy::Upper, "UPPER");
+                    ui.radio_value(&mut self.ext_policy, ExtPolicy::Strip, "Strip");
+                    let replacing = matches!(self.ext_policy, ExtPolicy::Replace(_));
+                    if ui.radio(replacing, "Replace with").clicked() && !replacing {
+                        self.ext_policy = ExtPolicy::Replace(String::new());
+                    }
+                    if let ExtPolicy::Replace(ext) = &mut self.ext_policy {
+                        ui.add(egui::TextEdit::singleline(ext).hint_text("jpg").desired_width(60.0));
+                    }
+                });
+                right
+                    .checkbox(&mut self.keep_sidecars, tr("Keep sidecars together"))
+                    .on_hover_text(tr(
+                        "Files with one name and different extensions (RAW+JPEG, video+.srt, .xmp) \
+                         are renamed together, one number",
+                    ));
+                right.horizontal(|ui| {
+                    ui.label(tr("Retries:"));
+                    ui.add(DragValue::new(&mut self.retry.attempts).clamp_range(0..=10));
+                    ui.label(tr("backoff (ms):"));
+                    ui.add(DragValue::new(&mut self.retry.base_delay_ms).clamp_range(0..=10000));
+                })
+                .response
+                .on_hover_text(tr("Retry failed renames with growing delays (useful on network shares)"));
+                right.horizontal(|ui| {
+                    ui.label(tr("Keep timestamps:"));
+                    for keep in KeepTimes::ALL {
+                        ui.radio_value(&mut self.retry.keep_times, keep, tr(keep.label()));
+                    }
+                })
+                .response
+                .on_hover_text(tr(
+                    "Times copies and files moved to another drive take over; All adds access and creation time \
+                     (Windows, macOS)",
+                ));
+                right.horizontal(|ui| {
+                    ui.label(tr("Move to:"));
+                    if ui.button(tr("Choose folder...")).clicked() {
+                        if let Some(dir) = rfd::FileDialog::new().pick_folder() {
+                            self.move_to = Some(dir);
+                        }
+                    }
+                    match &self.move_to {
+                        Some(dir) => {
+                            ui.label(dir.display().to_string());
+                            if ui.small_button("×").on_hover_text(tr("Keep files in their folders")).clicked() {
+                                self.move_to = None;
+                            }
+                        }
+                        None => {
+                            ui.label(tr("(same folder)"));
+                        }
+                    }
+                });
+                right.horizontal(|ui| {
+                    ui.label(tr("Subfolders by date:"));
+                    let pattern = egui::TextEdit::singleline(&mut self.subfolder_pattern)
+                        .hint_text("%Y/%m")
+                        .desired_width(100.0);
+                    ui.add(pattern)
+                        .on_hover_text(tr("strftime pattern; \"/\" makes nested folders. Empty = off"));
+                    ui.checkbox(&mut self.subfolder_exif, tr("EXIF date"))
+                        .on_hover_text(tr("Capture date (mtime when missing); off = modification time"));
+                });
+                right
+                    .checkbox(&mut self.copy_mode, tr("Copy with new names"))
+                    .on_hover_text(
+                        "Leave the originals untouched and write renamed copies (into \"Move to\" if set); \
+                         Undo deletes the copies",
+                    );
+                let mut preserve = self.retry.keep_times != KeepTimes::Off;
+                let toggle = right
+                    .add_enabled(self.copy_mode, egui::Checkbox::new(&mut preserve, tr("Preserve timestamps")))
+                    .on_hover_text(tr("Copies keep the modified, access and creation times of the originals"));
+                if toggle.changed() {
+                    self.retry.keep_times = if preserve { KeepTimes::All } else { KeepTimes::Off };
+                }
+                right.horizontal(|ui| {
+                    ui.label(tr("Set mtime from:"));
+                    let blocks = &self.blocks;
+                    let date_label = |i: usize| match blocks.get(i) {
+                        Some(Block::ExifDate { .. }) => format!("EXIF date [{}]", i),
+                        _ => format!("Date [{}]", i),
+                    };
+                    let from_name = self.mtime_pattern.is_some();
+                    let selected = if from_name {
+                        tr("Date in file name").to_string()
+                    } else {
+                        self.mtime_date_block().map_or("(off)".to_string(), date_label)
+                    };
+                    ComboBox::from_id_source("mtime_block")
+                        .selected_text(selected)
+                        .show_ui(ui, |ui| {
+                            if ui.selectable_label(!from_name && self.mtime_block.is_none(), "(off)").clicked() {
+                                (self.mtime_block, self.mtime_pattern) = (None, None);
+                            }
+                            for (i, b) in blocks.iter().enumerate() {
+                                let chosen = !from_name && self.mtime_block == Some(i);
+                                if matches!(b, Block::Date { .. } | Block::ExifDate { .. })
+                                    && ui.selectable_label(chosen, date_label(i)).clicked()
+                                {
+                                    (self.mtime_block, self.mtime_pattern) = (Some(i), None);
                                 }
+                            }
+                            if ui.selectable_label(from_name, tr("Date in file name")).clicked() && !from_name {
+                                self.mtime_pattern = Some("%Y%m%d_%H%M%S".into());
+                            }
+                        });
+                });
+                let mut set_now = false;
+                if let Some(pattern) = &mut self.mtime_pattern {
+                    right.horizontal(|ui| {
+                        ui.label(tr("Pattern:"));
+                        ui.add(egui::TextEdit::singleline(pattern).hint_text("IMG_%Y%m%d_%H%M%S").desired_width(160.0))
+                            .on_hover_text(tr("Where the date is in the name: %Y %y %m %d %H %M %S; other text must match as typed"));
+                        match DatePattern::new(pattern) {
+                            Some(parser) => {
+                                let name =
+                                    |fe: &FileEntry| fe.path.file_name().unwrap_or_default().to_string_lossy().to_string();
+                                let found = self.files.iter().filter(|fe| parser.parse(&name(fe)).is_some()).count();
+                                ui.label(format!("{} / {}", found, self.files.len()))
+                                    .on_hover_text(tr("Files with a date in their name; the others keep their mtime"));
+                                let button = ui.button(tr("Set now"));
+                                if button.on_hover_text(tr("Set the modification times without renaming")).clicked() {
+                                    set_now = true;
+                                }
+                            }
+                            None => {
+                                ui.label(RichText::new(tr("invalid")).color(self.theme.palette().error));
+                            }
+                        }
+                    });
+                }
+                if set_now {
+                    self.set_mtimes_now();
+                }
 
-                                // separator
-                                ui.painter().line_segment(
-                                    [rect.left_bottom(), rect.right_bottom()],
-                                    egui::Stroke::new(1.0, egui::Color32::from_gray(80)),
-                                );
-
-                                let response = ui.interact(rect, ui.id(), egui::Sense::drag());
+                right.horizontal(|ui| {
+                    ui.label(tr("Checksums:"));
+                    ComboBox::from_id_source("manifest")
+                        .selected_text(self.manifest.label())
+                        .show_ui(ui, |ui| {
+                            for m in Manifest::ALL {
+                                ui.selectable_value(&mut self.manifest, m, m.label());
+                            }
+                        });
+                })
+                .response
+                .on_hover_text(tr("Write SHA-256 checksums of the renamed files after each rename"));
 
-                                (rect, response)
-                            }).inner;
+                right.horizontal(|ui| {
+                    ui.label(tr("Scope:"));
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.scope_expr)
+                            .hint_text(r#"ext == "jpg" && size > 1MB"#),
+                    )
+                    .on_hover_text(
+                        "Only matching files are renamed. Fields: ext, name, size, type\n\
+                         Operators: == != > >= < <= ~ (regex), && || ! ( )",
+                    );
+                    match self.scope_filter() {
+                        Err(e) => {
+                            ui.label(RichText::new(tr("invalid")).color(self.theme.palette().error))
+                                .on_hover_text(e);
+                        }
+                        Ok(Some(_)) => {
+                            ui.label(format!("{} / {}", self.scope_rows().len(), self.files.len()));
+                        }
+                        Ok(None) => {}
+                    }
+                });
 
-                            row_tops.push(rect.top());
+                right.separator();
+                right.horizontal_wrapped(|ui| {
+                    ui.label(tr("Quick:"));
+                    let quick = &mut self.quick;
+                    for (case, label) in [(QuickCase::Lower, "lowercase"), (QuickCase::Title, "Title Case")] {
+                        if ui.selectable_label(quick.case == case, label).clicked() {
+                            quick.case = if quick.case == case { QuickCase::Keep } else { case };
+                        }
+                    }
+                    ui.toggle_value(&mut quick.underscores, "spaces → _");
+                    ui.toggle_value(&mut quick.strip_diacritics, "strip accents")
+                        .on_hover_text("é → e, ü → u (Japanese dakuten are kept)");
+                    if ui.add_enabled(quick.is_active(), egui::Button::new(tr("Clear"))).clicked() {
+                        *quick = QuickActions::default();
+                    }
+                })
+                .response
+                .on_hover_text(tr("Applied to every new name after the blocks"));
+                right.horizontal_wrapped(|ui| {
+                    let fr = &mut self.find_replace;
+                    ui.label(tr("Find:"));
+                    ui.add(egui::TextEdit::singleline(&mut fr.find).desired_width(120.0));
+                    ui.label(tr("Replace with:"));
+                    ui.add(egui::TextEdit::singleline(&mut fr.replace).desired_width(120.0));
+                    ui.checkbox(&mut fr.case_sensitive, tr("case sensitive"));
+                    ui.checkbox(&mut fr.all, tr("replace all occurrences"))
+                        .on_hover_text(tr("Off: only the first occurrence"));
+                    if ui.add_enabled(fr.is_active(), egui::Button::new(tr("Clear"))).clicked() {
+                        *fr = FindReplace::default();
+                    }
+                })
+                .response
+                .on_hover_text(tr("Plain text, applied to the original name before the blocks"));
+                right.horizontal(|ui| {
+                    ui.label(RichText::new(tr("Preview")).strong());
+                    let (label, hint) = if self.preview_detached {
+                        ("Dock", "Bring the preview back into this window")
+                    } else {
+                        ("Pop out", "Show the preview in its own window (e.g. on a second monitor)")
+                    };
+                    if ui.small_button(label).on_hover_text(hint).clicked() {
+                        self.preview_detached = !self.preview_detached;
+                    }
+                });
+                if !self.preview_detached {
+                    self.preview_rows(right, "preview", 300.0);
+                }
 
-                            if resp.drag_started() {
-                                self.dragging_idx = Some(i);
-                                self.selected_idx = Some(i);
+                // Persist template UI
+                right.separator();
+                right.label(RichText::new(tr("Save / Load Template")).strong());
+                right.horizontal(|ui| {
+                    ui.label(tr("Name:"));
+                    ui.text_edit_singleline(&mut self.current_template_name);
+                    if ui.button(tr("Save")).clicked() && !self.current_template_name.is_empty() {
+                        let tpl = self.current_template();
+                        match self.saved_templates.iter().find(|t| t.name == tpl.name) {
+                            _ if self.is_preset(&tpl.name) => self.log.warn(format!(
+                                "\"{}\" is a preset. Save under another name to keep your changes.",
+                                tpl.name
+                            )),
+                            // overwriting a different version needs confirmation
+                            Some(old) if old.diff(&tpl).iter().any(|(k, _)| *k != ' ') => {
+                                self.pending_overwrite.push(tpl);
                             }
-                            if !resp.dragged() && resp.clicked() {
-                                self.selected_idx = Some(i);
+                            Some(_) => {}
+                            None => {
+                                self.saved_templates.push(tpl);
+                                self.save_templates();
+                            }
+                        }
+                    }
+                    if ui.button(tr("Import...")).on_hover_text(tr("Add templates from a shared file")).clicked() {
+                        self.import_templates();
+                    }
+                    let selected = Self::find_template(&self.saved_templates, &self.current_template_name).cloned();
+                    if ui
+                        .add_enabled(selected.is_some(), egui::Button::new(tr("Export...")))
+                        .on_hover_text(tr("Save the template chosen in Load as a file to share"))
+                        .clicked()
+                    {
+                        self.export_templates(&selected.into_iter().collect::<Vec<_>>());
+                    }
+                    let any = !self.saved_templates.is_empty();
+                    if ui.add_enabled(any, egui::Button::new(tr("Export all..."))).clicked() {
+                        self.export_templates(&self.saved_templates.clone());
+                    }
+                });
+                right.horizontal(|ui| {
+                    ui.label(tr("Load:"));
+                    ComboBox::from_id_source("template_load")
+                        .selected_text(&self.current_template_name)
+                        .show_ui(ui, |ui| {
+                            for tpl in &self.saved_templates {
+                                ui.selectable_value(
+                                    &mut self.current_template_name,
+                                    tpl.name.clone(),
+                                    &tpl.name,
+                                );
+                            }
+                            ui.separator();
+                            ui.label(RichText::new(tr("Presets")).small().weak());
+                            for tpl in Self::templates(&self.saved_templates).skip(self.saved_templates.len()) {
+                                ui.selectable_value(&mut self.current_template_name, tpl.name.clone(), &tpl.name);
                             }
+                        });
+                    if ui.button(tr("Apply")).clicked() {
+                        if let Some(tpl) = Self::find_template(&self.saved_templates, &self.current_template_name) {
+                            let tpl = tpl.clone();
+                            self.apply_template(&tpl);
+                        }
+                    }
+                    let preset = self.is_preset(&self.current_template_name);
+                    if preset
+                        && ui
+                            .button(tr("Duplicate"))
+                            .on_hover_text(tr("Save an editable copy of this preset"))
+                            .clicked()
+                    {
+                        let name = self.current_template_name.clone();
+                        self.copy_preset(&name);
+                    }
+                    if ui
+                        .add_enabled(!preset, egui::Button::new(tr("Delete")))
+                        .on_disabled_hover_text(tr("Presets can't be deleted"))
+                        .clicked()
+                    {
+                        if let Some(pos) = self
+                            .saved_templates
+                            .iter()
+                            .position(|t| t.name == self.current_template_name)
+                        {
+                            self.saved_templates.remove(pos);
+                            self.current_template_name.clear();
+                            self.save_templates();
+                            self.log.info("Template deleted.");
+                        }
+                    }
+                    if ui.button(tr("Manage...")).on_hover_text(tr("Rename, duplicate, reorder or delete")).clicked() {
+                        self.show_templates = true;
+                    }
+                    if ui.button(tr("Compare...")).clicked() {
+                        self.show_compare = true;
+                    }
+                    if ui.button(tr("Watch folder...")).clicked() {
+                        self.show_watch = true;
+                    }
+                    if ui.button(tr("Gallery...")).clicked() {
+                        if self.gallery.is_empty() {
+                            self.gallery = Self::load_gallery();
+                        }
+                        self.show_gallery = true;
+                    }
+                });
+            });
+            self.column_split = split;
 
-                            if let Some(py) = pointer_y {
-                                if py < rect.center().y && insert_index.is_none() {
-                                    insert_index = Some(i);
-                                }
+            ui.separator();
+            self.log_panel(ui);
+        });
+
+        if let Some(tpl) = self.pending_overwrite.first() {
+            let mut confirm = false;
+            let mut cancel = false;
+            egui::Window::new(tr("Overwrite template?"))
+                .collapsible(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    ui.label(format!("\"{}\" already exists. Changes against the saved version:", tpl.name));
+                    ui.separator();
+                    if let Some(old) = self.saved_templates.iter().find(|t| t.name == tpl.name) {
+                        egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                            for (kind, line) in old.diff(tpl) {
+                                let text = RichText::new(format!("{} {}", kind, line)).monospace();
+                                ui.label(match kind {
+                                    '-' => text.color(self.theme.palette().error),
+                                    '+' => text.color(self.theme.palette().added),
+                                    _ => text,
+                                });
                             }
-                        }// for loop end
+                        });
+                    }
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        if ui.button(tr("Overwrite")).clicked() {
+                            confirm = true;
+                        }
+                        if ui.button(tr("Cancel")).clicked() {
+                            cancel = true;
+                        }
+                    });
+                });
+            if confirm {
+                if !self.pending_overwrite.is_empty() {
+                    let tpl = self.pending_overwrite.remove(0);
+                    if let Some(pos) = self.saved_templates.iter().position(|t| t.name == tpl.name) {
+                        self.saved_templates[pos] = tpl;
+                    }
+                    self.save_templates();
+                }
+            } else if cancel {
+                self.pending_overwrite.remove(0);
+            }
+        }
 
-                        if insert_index.is_none() {
-                            insert_index = Some(self.files.len());
+        if let Some(rx) = &self.update_rx {
+            match rx.try_recv() {
+                Ok(result) => {
+                    self.update_rx = None;
+                    match result {
+                        Ok(Some(release)) => self.update_available = Some(release),
+                        Ok(None) => self.log.info("BulkReName is up to date."),
+                        Err(e) => self.log.error(format!("Update check failed: {}", e)),
+                    }
+                }
+                Err(mpsc::TryRecvError::Disconnected) => self.update_rx = None,
+                Err(mpsc::TryRecvError::Empty) => {}
+            }
+        }
+        if let Some(release) = &self.update_available {
+            let mut close = false;
+            egui::Window::new(tr("Update available"))
+                .collapsible(false)
+                .default_size([480.0, 360.0])
+                .show(ctx, |ui| {
+                    ui.label(format!(
+                        "BulkReName {} is available (this is {}).",
+                        release.tag_name,
+                        env!("CARGO_PKG_VERSION")
+                    ));
+                    if let Some(name) = release.name.as_deref().filter(|n| !n.is_empty()) {
+                        ui.label(RichText::new(name).strong());
+                    }
+                    ui.separator();
+                    egui::ScrollArea::vertical().max_height(240.0).id_source("release_notes").show(ui, |ui| {
+                        ui.label(release.body.as_deref().unwrap_or("(no release notes)"));
+                    });
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        if ui.button(tr("Open download page")).clicked() {
+                            ctx.open_url(egui::OpenUrl::new_tab(&release.html_url));
+                        }
+                        if ui.button(tr("Close")).clicked() {
+                            close = true;
                         }
+                    });
+                });
+            if close {
+                self.update_available = None;
+            }
+        }
 
-                        // drop line
-                        if self.dragging_idx.is_some() {
-                            if let Some(target) = insert_index {
-                                let y = if target < row_tops.len() { //self.files.len() {
-                                    row_tops[target] //ui.vertical(|_| {}).response.rect.top()
-                                } else {
-                                    ui.min_rect().bottom()
-                                };
+        if let Some(rx) = &self.manifest_rx {
+            match rx.try_recv() {
+                Ok(result) => {
+                    self.manifest_rx = None;
+                    match result {
+                        Ok(n) => self.log.info(format!("Checksums written for {} file(s).", n)),
+                        Err(problems) => {
+                            self.log.error(format!("Checksums: {} problem(s):", problems.len()));
+                            self.log.errors(problems);
+                        }
+                    }
+                }
+                Err(mpsc::TryRecvError::Empty) => ctx.request_repaint_after(std::time::Duration::from_millis(200)),
+                Err(mpsc::TryRecvError::Disconnected) => self.manifest_rx = None,
+            }
+        }
 
-                                let full_width = ui.available_width();
-                                let left = ui.min_rect().left();
-                                ui.painter().line_segment(
-                                    [egui::pos2(left, y), egui::pos2(left + full_width, y)],
-                                    egui::Stroke::new(3.0, egui::Color32::LIGHT_BLUE),
-                                );
+        if let Some(rx) = &self.gallery_rx {
+            if let Ok(result) = rx.try_recv() {
+                self.gallery_rx = None;
+                match result {
+                    Ok(items) => {
+                        self.log.info(format!("Gallery updated ({} templates).", items.len()));
+                        self.gallery = items;
+                        self.gallery_pick = 0;
+                    }
+                    Err(e) => self.log.error(format!("Gallery refresh failed: {}", e)),
+                }
+            }
+        }
+        if self.show_gallery {
+            let mut open = true;
+            let mut install = None;
+            let mut refresh = false;
+            egui::Window::new(tr("Template gallery"))
+                .open(&mut open)
+                .default_size([720.0, 420.0])
+                .show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        if self.gallery_rx.is_some() {
+                            ui.spinner();
+                        } else if ui.button(tr("Refresh")).on_hover_text(GALLERY_URL).clicked() {
+                            refresh = true;
+                        }
+                    });
+                    ui.separator();
+                    ui.columns(2, |cols| {
+                        egui::ScrollArea::vertical().id_source("gallery_list").show(&mut cols[0], |ui| {
+                            for (i, item) in self.gallery.iter().enumerate() {
+                                let resp = ui.selectable_label(i == self.gallery_pick, &item.template.name);
+                                if resp.on_hover_text(&item.description).clicked() {
+                                    self.gallery_pick = i;
+                                }
                             }
-
-                            ui.ctx().set_cursor_icon(egui::CursorIcon::Grabbing);
+                        });
+                        let ui = &mut cols[1];
+                        let Some(item) = self.gallery.get(self.gallery_pick) else { return };
+                        ui.label(RichText::new(&item.template.name).strong());
+                        ui.label(&item.description);
+                        for b in &item.template.blocks {
+                            ui.label(RichText::new(format!("{:?}", b)).monospace().small());
+                        }
+                        if ui.button(tr("Install")).clicked() {
+                            install = Some(item.template.clone());
+                        }
+                        ui.separator();
+                        if self.files.is_empty() {
+                            ui.label(tr("Add files to preview this template."));
+                            return;
                         }
+                        let targets = self.targets_for_template(&item.template);
+                        egui::ScrollArea::vertical().id_source("gallery_preview").show(ui, |ui| {
+                            for (fe, new_name) in self.files.iter().zip(&targets) {
+                                ui.label(format!(
+                                    "{} → {}",
+                                    fe.path.file_name().unwrap_or_default().to_string_lossy(),
+                                    new_name
+                                ));
+                            }
+                        });
+                    });
+                });
+            if refresh {
+                self.refresh_gallery(ctx);
+            }
+            if let Some(tpl) = install {
+                self.install_template(tpl);
+            }
+            self.show_gallery = open;
+        }
 
-                        // move algorithm
-                        if let Some(drag_i) = self.dragging_idx {
-                            if ui.input(|i| i.pointer.any_released()) {
-                                if let Some(target) = insert_index {
-                                    let item = self.files.remove(drag_i);
-                                    let mut target = target;
-                                    if drag_i < target { target -= 1; }
-                                    let target = target.min(self.files.len());
-                                    self.files.insert(target, item);
-                                    self.selected_idx = Some(target);
+        if self.show_templates {
+            let mut open = true;
+            let mut swap = None;
+            let mut duplicate = None;
+            let mut delete = None;
+            let mut renamed = None;
+            let count = self.saved_templates.len();
+            egui::Window::new(tr("Templates"))
+                .open(&mut open)
+                .default_size([420.0, 360.0])
+                .show(ctx, |ui| {
+                    if count == 0 {
+                        ui.label(tr("No saved templates."));
+                    }
+                    egui::ScrollArea::vertical().id_source("template_list").show(ui, |ui| {
+                        egui::Grid::new("template_grid").striped(true).show(ui, |ui| {
+                            for (k, tpl) in self.saved_templates.iter().enumerate() {
+                                match &mut self.template_rename {
+                                    Some((row, name)) if *row == k => {
+                                        let edit = ui.text_edit_singleline(name);
+                                        if !edit.has_focus() && !edit.lost_focus() {
+                                            edit.request_focus();
+                                        }
+                                        if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                                            renamed = Some(None);
+                                        } else if edit.lost_focus() {
+                                            renamed = Some(Some((k, name.clone())));
+                                        }
+                                    }
+                                    _ => {
+                                        let mut label = RichText::new(&tpl.name);
+                                        if tpl.name == self.current_template_name {
+                                            label = label.strong();
+                                        }
+                                        let name = ui.label(label).on_hover_text(tr("Double-click to rename"));
+                                        if name.double_clicked() {
+                                            self.template_rename = Some((k, tpl.name.clone()));
+                                        }
+                                    }
                                 }
-                                self.dragging_idx = None;
+                                ui.horizontal(|ui| {
+                                    if ui.add_enabled(k > 0, egui::Button::new("↑")).clicked() {
+                                        swap = Some((k, k - 1));
+                                    }
+                                    if ui.add_enabled(k + 1 < count, egui::Button::new("↓")).clicked() {
+                                        swap = Some((k, k + 1));
+                                    }
+                                    if ui.button(tr("Rename")).clicked() {
+                                        self.template_rename = Some((k, tpl.name.clone()));
+                                    }
+                                    if ui.button(tr("Duplicate")).clicked() {
+                                        duplicate = Some(k);
+                                    }
+                                    if ui.button(tr("Delete")).clicked() {
+                                        delete = Some(k);
+                                    }
+                                });
+                                ui.end_row();
                             }
-                        }
+                        });
+                    });
+                });
+            if let Some(rename) = renamed {
+                self.template_rename = None;
+                if let Some((k, name)) = rename {
+                    self.rename_template(k, name);
+                }
+            }
+            if let Some((a, b)) = swap {
+                self.saved_templates.swap(a, b);
+                self.save_templates();
+            }
+            if let Some(k) = duplicate {
+                self.duplicate_template(k);
+            }
+            if let Some(k) = delete {
+                let tpl = self.saved_templates.remove(k);
+                if self.current_template_name == tpl.name {
+                    self.current_template_name.clear();
+                }
+                self.save_templates();
+                self.log.info(format!("Template \"{}\" deleted.", tpl.name));
+            }
+            if swap.is_some() || duplicate.is_some() || delete.is_some() {
+                self.template_rename = None;
+            }
+            self.show_templates = open;
+        }
 
-                        // delete algorithm
-                        if self.dragging_idx.is_none() {
-                            if let Some(i) = to_delete {
-                                if i < self.files.len() {
-                                    self.selected_idx = Some(i);
-                                    self.remove_selected();
+        if self.show_watch {
+            let mut open = true;
+            let mut toggle = false;
+            let mut changed = false;
+            egui::Window::new(tr("Watch folder"))
+                .open(&mut open)
+                .default_size([460.0, 320.0])
+                .show(ctx, |ui| {
+                    ui.label(tr("New files in the folder are renamed with the template once they stop growing."));
+                    let active = self.watch_folder.is_some();
+                    let settings = &mut self.settings;
+                    ui.add_enabled_ui(!active, |ui| {
+                        ui.horizontal(|ui| {
+                            if ui.button(tr("Choose folder...")).clicked() {
+                                if let Some(dir) = rfd::FileDialog::new().pick_folder() {
+                                    settings.watch_dir = Some(dir);
+                                    changed = true;
                                 }
                             }
+                            if let Some(dir) = &settings.watch_dir {
+                                ui.label(dir.display().to_string());
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label(tr("Template:"));
+                            let combo = ComboBox::from_id_source("watch_template")
+                                .selected_text(settings.watch_template.as_str());
+                            combo.show_ui(ui, |ui| {
+                                for t in Self::templates(&self.saved_templates) {
+                                    let name = t.name.clone();
+                                    let choice = ui.selectable_value(&mut settings.watch_template, name, &t.name);
+                                    changed |= choice.changed();
+                                }
+                            });
+                        });
+                    });
+                    changed |=
+                        ui.checkbox(&mut settings.watch_at_start, tr("Start watching when the app starts")).changed();
+                    ui.horizontal(|ui| {
+                        let ready = settings.watch_dir.is_some()
+                            && Self::find_template(&self.saved_templates, &settings.watch_template).is_some();
+                        let label = if active { tr("Stop") } else { tr("Start") };
+                        toggle = ui.add_enabled(active || ready, egui::Button::new(label)).clicked();
+                        if let Some(watch) = &self.watch_folder {
+                            ui.spinner();
+                            let waiting = watch.pending.len() + watch.ready.len();
+                            ui.label(format!("{} renamed, {} waiting", watch.renamed, waiting));
                         }
                     });
-                
-
-                // Right panel: template, preview, persistence
-                let right = &mut cols[1];
-                right.label(RichText::new("Template Blocks").strong());
+                    ui.separator();
+                    egui::ScrollArea::vertical().id_source("watch_log").stick_to_bottom(true).show(ui, |ui| {
+                        for line in &self.watch_log {
+                            ui.label(line);
+                        }
+                    });
+                });
+            if changed {
+                self.save_settings();
+            }
+            if toggle {
+                if self.watch_folder.take().is_some() {
+                    self.watch_log.push(format!("{} Stopped.", Local::now().format("%H:%M:%S")));
+                } else {
+                    self.start_watch_folder(ctx);
+                }
+            }
+            self.show_watch = open;
+        }
 
-                // blocks editor ...
-                let mut idx = 0;
-                while idx < self.blocks.len() {
-                    let blk = self.blocks[idx].clone();
-                    let mut new_blk = blk.clone();
-                    let mut action: Option<&str> = None;
-                    right.horizontal(|ui| {
-                        let del_block = egui::Button::new("×")
-                            .fill(egui::Color32::from_rgb(240, 150, 150));
-                        if ui.add(del_block).clicked() {
-                            if self.blocks.len() <= 1 {
-                                self.show_delete_error = true;
-                            } else {
-                                action = Some("del");
-                            }
+        if self.show_duplicates {
+            if let Some(rx) = &self.dup_rx {
+                if let Ok(groups) = rx.try_recv() {
+                    self.log.info(format!("Find duplicates: {} group(s) of identical files.", groups.len()));
+                    self.dup_groups = groups;
+                    self.dup_rx = None;
+                }
+            }
+            let mut open = true;
+            egui::Window::new(tr("Duplicates"))
+                .open(&mut open)
+                .default_size([460.0, 360.0])
+                .show(ctx, |ui| {
+                    if self.dup_rx.is_some() {
+                        ui.horizontal(|ui| {
+                            ui.spinner();
+                            ui.label(tr("Hashing files..."));
+                        });
+                        return;
+                    }
+                    if self.dup_groups.is_empty() {
+                        ui.label(tr("No duplicates found."));
+                        return;
+                    }
+                    ui.label(tr("The first file of each group is kept; the others count as duplicates."));
+                    ui.horizontal(|ui| {
+                        if ui.button(tr("Exclude duplicates"))
+                            .on_hover_text(tr("Keep them in the list but never rename them"))
+                            .clicked()
+                        {
+                            let n = self.mark_duplicates(|fe| fe.excluded = true);
+                            self.log.info(format!("{} duplicate(s) excluded.", n));
                         }
-                        ui.separator();
-                        if ui.small_button("▲").clicked() && idx > 0 {
-                            action = Some("up");
+                        if ui.button(tr("Select duplicates")).clicked() {
+                            for fe in &mut self.files {
+                                fe.selected = false;
+                            }
+                            self.mark_duplicates(|fe| fe.selected = true);
                         }
-                        if ui.small_button("▼").clicked() && idx + 1 < self.blocks.len() {
-                            action = Some("down");
+                        let tag = self.dup_tag.clone();
+                        if ui.button(tr("Tag duplicates:"))
+                            .on_hover_text(tr("Write this text into their tag, for a Tag block in the template"))
+                            .clicked()
+                        {
+                            let n = self.mark_duplicates(|fe| fe.tag = tag.clone());
+                            self.log.info(format!("{} duplicate(s) tagged \"{}\".", n, tag));
                         }
-                        ui.label(format!("[{}]", idx));
-                        match &mut new_blk {
-                            Block::Literal(s) => {
-                                ui.label("<Literal>");
-                                ui.text_edit_singleline(s);
-                            }
-                            Block::Number { width, start, step } => {
-                                ui.label("<Number>min digits:");
-                                ui.add(DragValue::new(width).clamp_range(0..=20));
-                                ui.label("init:");
-                                ui.add(DragValue::new(start));
-                                ui.label("gain:");
-                                ui.add(DragValue::new(step));
+                        ui.add(egui::TextEdit::singleline(&mut self.dup_tag).desired_width(60.0));
+                    });
+                    ui.separator();
+                    egui::ScrollArea::vertical().id_source("dup_groups").show(ui, |ui| {
+                        for (k, group) in self.dup_groups.iter().enumerate() {
+                            let size = fs::metadata(&group[0]).map(|m| m.len()).unwrap_or(0);
+                            ui.label(RichText::new(format!("[{}] {}", k + 1, SizeUnit::Auto.format(size))).strong());
+                            for (j, path) in group.iter().enumerate() {
+                                let text = RichText::new(path.display().to_string());
+                                ui.label(if j == 0 { text } else { text.weak() });
                             }
-                            Block::Date { format } => {
-                                ui.label("<Date fmt>");
-                                ui.text_edit_singleline(format);
-                                ui.label("(strftime)");
+                        }
+                    });
+                });
+            self.show_duplicates = open;
+        }
+
+        if self.show_compare {
+            let mut open = true;
+            egui::Window::new(tr("Compare templates"))
+                .open(&mut open)
+                .default_size([700.0, 400.0])
+                .show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        for (id, choice) in [("cmp_a", &mut self.compare_a), ("cmp_b", &mut self.compare_b)] {
+                            ComboBox::from_id_source(id)
+                                .selected_text(choice.as_str())
+                                .show_ui(ui, |ui| {
+                                    for tpl in Self::templates(&self.saved_templates) {
+                                        ui.selectable_value(choice, tpl.name.clone(), &tpl.name);
+                                    }
+                                });
+                        }
+                    });
+                    let find = |name: &str| Self::find_template(&self.saved_templates, name);
+                    let (Some(a), Some(b)) = (find(&self.compare_a), find(&self.compare_b)) else {
+                        ui.label(tr("Choose two saved templates."));
+                        return;
+                    };
+                    let (ta, tb) = (self.targets_for_template(a), self.targets_for_template(b));
+                    let differing = ta.iter().zip(&tb).filter(|(x, y)| x != y).count();
+                    ui.label(format!("{} of {} names differ", differing, ta.len()));
+                    ui.separator();
+                    egui::ScrollArea::both().id_source("compare_rows").show(ui, |ui| {
+                        egui::Grid::new("compare_grid").striped(true).show(ui, |ui| {
+                            ui.label(RichText::new(tr("Original")).strong());
+                            ui.label(RichText::new(&a.name).strong());
+                            ui.label(RichText::new(&b.name).strong());
+                            ui.end_row();
+                            for (fe, (x, y)) in self.files.iter().zip(ta.iter().zip(&tb)) {
+                                ui.label(fe.path.file_name().unwrap_or_default().to_string_lossy());
+                                let palette = self.theme.palette();
+                                let color = if x != y { palette.accent } else { palette.dim };
+                                ui.label(RichText::new(x).color(color));
+                                ui.label(RichText::new(y).color(color));
+                                ui.end_row();
                             }
-                            Block::Original { mode } => {
-                                ui.label("<Orig>");
+                        });
+                    });
+                });
+            self.show_compare = open;
+        }
 
-                                egui::ComboBox::from_id_source(format!("orig_mode_{}", idx))
-                                    .selected_text(match mode {
-                                        OriginalMode::Keep => "Keep",
-                                        OriginalMode::RemoveRange { .. } => "Del Range",
-                                        OriginalMode::RemoveSubstring { .. } => "Del Substr.",
-                                    })
-                                    .show_ui(ui, |ui| {
-                                        if ui.selectable_label(matches!(mode, OriginalMode::Keep), "Keep").clicked() {
-                                            *mode = OriginalMode::Keep;
-                                        }
-                                        if ui.selectable_label(matches!(mode, OriginalMode::RemoveRange { .. }), "Range").clicked() {
-                                            *mode = OriginalMode::RemoveRange { start: 0, end: 1 };
-                                        }
-                                        if ui.selectable_label(matches!(mode, OriginalMode::RemoveSubstring { .. }), "Substring").clicked() {
-                                            *mode = OriginalMode::RemoveSubstring { pattern: "".into(), case_sensitive: true, };
+        if ctx.input(|i| i.key_pressed(egui::Key::F1)) {
+            self.show_help = !self.show_help;
+        }
+        if self.show_help {
+            let mut tour = false;
+            let mut check_now = false;
+            egui::Window::new(tr("Help"))
+                .open(&mut self.show_help)
+                .default_size([560.0, 480.0])
+                .show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        if ui.button(tr("Show tour")).clicked() {
+                            tour = true;
+                        }
+                        let idle = self.update_rx.is_none();
+                        if ui.add_enabled(idle, egui::Button::new(tr("Check for updates"))).clicked() {
+                            check_now = true;
+                        }
+                    });
+                    egui::ScrollArea::vertical().id_source("help").show(ui, |ui| {
+                        for (k, section) in HELP.iter().enumerate() {
+                            egui::CollapsingHeader::new(section.title)
+                                .default_open(k == 0)
+                                .show(ui, |ui| {
+                                    ui.label(section.intro);
+                                    egui::Grid::new(("help_grid", k)).striped(true).show(ui, |ui| {
+                                        for (example, text) in section.rows {
+                                            ui.label(RichText::new(*example).monospace());
+                                            ui.label(*text);
+                                            if ui.small_button(tr("Copy")).clicked() {
+                                                ui.output_mut(|o| o.copied_text = example.to_string());
+                                            }
+                                            ui.end_row();
                                         }
                                     });
+                                });
+                        }
+                    });
+                });
+            if tour {
+                self.tour_step = Some(0);
+            }
+            if check_now {
+                self.check_for_updates(ctx, false);
+            }
+        }
 
-                                match mode {
-                                    OriginalMode::RemoveRange { start, end } => {
-                                        ui.label("range:");
-                                        ui.add(DragValue::new(start));
-                                        ui.label("～");
-                                        ui.add(DragValue::new(end));
-                                    }
-                                    OriginalMode::RemoveSubstring { pattern, case_sensitive } => {
-                                        ui.vertical(|ui| {
-                                            ui.horizontal(|ui| {
-                                                ui.toggle_value(case_sensitive, "Aa")
-                                                    .on_hover_text("Case sensitive");
-                                                ui.label("pattern:");
-                                                ui.text_edit_singleline(pattern);
-                                            });
-                                        });
-                                    }
-                                    _ => {}
+        if self.show_settings {
+            let mut open = true;
+            let mut changed = false;
+            let mut toggle_shell_menu = false;
+            egui::Window::new(tr("Settings"))
+                .open(&mut open)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    let settings = &mut self.settings;
+                    egui::Grid::new("settings_grid").num_columns(2).show(ui, |ui| {
+                        ui.label(tr("Theme"));
+                        ComboBox::from_id_source("theme")
+                            .selected_text(tr(settings.theme.label()))
+                            .show_ui(ui, |ui| {
+                                for t in Theme::ALL {
+                                    changed |= ui.selectable_value(&mut settings.theme, t, tr(t.label())).changed();
+                                }
+                            });
+                        ui.end_row();
+                        ui.label(tr("Language"));
+                        ComboBox::from_id_source("language")
+                            .selected_text(settings.language.label())
+                            .show_ui(ui, |ui| {
+                                for l in Language::ALL {
+                                    changed |= ui.selectable_value(&mut settings.language, l, l.label()).changed();
+                                }
+                            });
+                        ui.end_row();
+                        ui.label(tr("UI scale"));
+                        ComboBox::from_id_source("ui_scale")
+                            .selected_text(format!("{:.0}%", settings.ui_scale * 100.0))
+                            .show_ui(ui, |ui| {
+                                for scale in UI_SCALES {
+                                    let label = format!("{:.0}%", scale * 100.0);
+                                    changed |= ui.selectable_value(&mut settings.ui_scale, scale, label).changed();
                                 }
+                            });
+                        ui.end_row();
+                        ui.label(tr("Thumbnail width"));
+                        changed |= ui
+                            .add(DragValue::new(&mut settings.thumb_size).clamp_range(48..=512).suffix(" px"))
+                            .changed();
+                        ui.end_row();
+                        ui.label(tr("Collision at startup"));
+                        let current = COLLISION_LABELS.iter().find(|(c, _)| *c == settings.collision);
+                        ComboBox::from_id_source("default_collision")
+                            .selected_text(current.map_or("", |(_, label)| *label))
+                            .show_ui(ui, |ui| {
+                                for (strategy, label) in COLLISION_LABELS {
+                                    changed |= ui.selectable_value(&mut settings.collision, strategy, label).changed();
+                                }
+                            });
+                        ui.end_row();
+                        ui.label(tr("Log in each folder"))
+                            .on_hover_text(tr("Each batch adds its old and new names to a log file in the folders"));
+                        ComboBox::from_id_source("folder_log")
+                            .selected_text(settings.folder_log.label())
+                            .show_ui(ui, |ui| {
+                                for f in FolderLog::ALL {
+                                    let label = match f.file_name() {
+                                        Some(name) => format!("{} ({})", f.label(), name),
+                                        None => f.label().to_string(),
+                                    };
+                                    changed |= ui.selectable_value(&mut settings.folder_log, f, label).changed();
+                                }
+                            });
+                        ui.end_row();
+                    });
+                    ui.separator();
+                    changed |= ui.checkbox(&mut settings.check_updates, tr("Check for updates at startup")).changed();
+                    changed |= ui
+                        .checkbox(&mut settings.allow_multiple, tr("Allow multiple windows"))
+                        .on_hover_text(tr("Off: launching again adds files to this window (after a restart)"))
+                        .changed();
+                    changed |= ui
+                        .checkbox(&mut settings.trash_replaced, tr("Move replaced files to the trash"))
+                        .on_hover_text(tr("Files replaced by Overwrite or deleted by Dedup can be restored"))
+                        .changed();
+                    if cfg!(windows) {
+                        let installed = *self.shell_menu.get_or_insert_with(shell_menu_installed);
+                        let label = if installed { "Remove from Explorer menu" } else { "Add to Explorer menu" };
+                        if ui
+                            .button(tr(label))
+                            .on_hover_text(tr("Adds \"Rename with BulkReName\" to the right-click menu"))
+                            .clicked()
+                        {
+                            toggle_shell_menu = true;
+                        }
+                    }
+                });
+            self.show_settings = open;
+            if changed {
+                self.apply_settings(ctx);
+                self.save_settings();
+            }
+            if toggle_shell_menu {
+                let on = self.shell_menu != Some(true);
+                match set_shell_menu(on) {
+                    Ok(()) if on => self.log.info("Added \"Rename with BulkReName\" to the Explorer menu."),
+                    Ok(()) => self.log.info("Removed \"Rename with BulkReName\" from the Explorer menu."),
+                    Err(e) => self.log.error(format!("Explorer menu: {}", e)),
+                }
+                self.shell_menu = None;
+            }
+        }
+
+        if let Some(step) = self.tour_step {
+            let (title, text) = TOUR[step];
+            let mut next = Some(step);
+            egui::Window::new(tr("Welcome to BulkReName"))
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_TOP, [0.0, 60.0])
+                .show(ctx, |ui| {
+                    ui.set_max_width(420.0);
+                    ui.heading(title);
+                    ui.label(text);
+                    ui.add_space(8.0);
+                    ui.horizontal(|ui| {
+                        ui.label(format!("{}/{}", step + 1, TOUR.len()));
+                        if ui.add_enabled(step > 0, egui::Button::new(tr("Back"))).clicked() {
+                            next = Some(step - 1);
+                        }
+                        if step + 1 < TOUR.len() {
+                            if ui.button(tr("Next")).clicked() {
+                                next = Some(step + 1);
                             }
-                            Block::Extension => {
-                                ui.label("<Extension>");
+                            if ui.button(tr("Skip")).clicked() {
+                                next = None;
                             }
+                        } else if ui.button(tr("Done")).clicked() {
+                            next = None;
                         }
-                        if self.show_delete_error {
-                            egui::Window::new("ERROR")
-                                .collapsible(false)
-                                .resizable(false)
-                                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
-                                .fixed_size(egui::vec2(180.0, 90.0))
-                                .show(ui.ctx(), |ui| {
-                                    ui.vertical_centered(|ui| {
-                                        ui.set_width(160.0);
-
-                                        ui.add(
-                                            egui::Label::new("need at least 1 block")
-                                                .wrap(true)
-                                        );
+                    });
+                });
+            self.tour_step = next;
+        }
 
-                                        ui.add_space(8.0);
+        if self.preview_detached {
+            let builder = egui::ViewportBuilder::default()
+                .with_title("BulkReName - Preview")
+                .with_inner_size([480.0, 720.0]);
+            ctx.show_viewport_immediate(egui::ViewportId::from_hash_of("preview"), builder, |ctx, class| {
+                let mut open = true;
+                let rows = |ui: &mut egui::Ui| self.preview_rows(ui, "preview_window", f32::INFINITY);
+                if class == egui::ViewportClass::Embedded {
+                    // no native multi-window support: fall back to a window inside the app
+                    egui::Window::new(tr("Preview")).open(&mut open).default_size([480.0, 600.0]).show(ctx, rows);
+                } else {
+                    egui::CentralPanel::default().show(ctx, rows);
+                    open = !ctx.input(|i| i.viewport().close_requested());
+                }
+                if !open {
+                    self.preview_detached = false;
+                }
+            });
+        }
 
-                                        if ui.button("OK").clicked() {
-                                            self.show_delete_error = false;
-                                        }
-                                    });
+        if self.show_tree {
+            let mut open = true;
+            let mut rescan = false;
+            let mut run = false;
+            egui::Window::new(tr("Folder tree"))
+                .open(&mut open)
+                .default_size([700.0, 500.0])
+                .show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        if ui.button(tr("Choose folder...")).clicked() {
+                            if let Some(dir) = rfd::FileDialog::new().pick_folder() {
+                                self.tree_root = Some(dir);
+                                rescan = true;
+                            }
+                        }
+                        match &self.tree_root {
+                            Some(root) => ui.label(root.display().to_string()),
+                            None => ui.label(tr("No folder chosen.")),
+                        };
+                    });
+                    ui.separator();
+                    ui.label(tr("Rules (depth 1 = entries directly in the folder; the first matching rule wins):"));
+                    let mut remove = None;
+                    for (i, rule) in self.tree_rules.iter_mut().enumerate() {
+                        ui.horizontal(|ui| {
+                            ui.label(tr("Depth"));
+                            rescan |= ui.add(egui::DragValue::new(&mut rule.depth).clamp_range(1..=32)).changed();
+                            ComboBox::from_id_source(("tree_target", i))
+                                .selected_text(format!("{:?}", rule.target))
+                                .show_ui(ui, |ui| {
+                                    for t in TreeTarget::ALL {
+                                        ui.selectable_value(&mut rule.target, t, format!("{:?}", t));
+                                    }
+                                });
+                            ComboBox::from_id_source(("tree_template", i))
+                                .selected_text(if rule.template.is_empty() { "(current blocks)" } else { &rule.template })
+                                .show_ui(ui, |ui| {
+                                    ui.selectable_value(&mut rule.template, String::new(), "(current blocks)");
+                                    for tpl in Self::templates(&self.saved_templates) {
+                                        ui.selectable_value(&mut rule.template, tpl.name.clone(), &tpl.name);
+                                    }
                                 });
+                            if ui.button("×").clicked() {
+                                remove = Some(i);
+                            }
+                        });
+                    }
+                    if let Some(i) = remove {
+                        self.tree_rules.remove(i);
+                    }
+                    ui.horizontal(|ui| {
+                        if ui.button(tr("+ Rule")).clicked() {
+                            let depth = self.tree_rules.iter().map(|r| r.depth + 1).max().unwrap_or(1);
+                            self.tree_rules.push(DepthRule { depth, target: TreeTarget::Files, template: String::new() });
+                            rescan = true;
+                        }
+                        if ui.button(tr("Rescan")).clicked() {
+                            rescan = true;
                         }
                     });
-                    if let Some(act) = action {
-                        match act {
-                            "up" => {
-                                self.blocks.swap(idx, idx - 1);
-                                idx -= 1;
-                                continue;
+                    ui.separator();
+                    let targets = self.tree_targets();
+                    let changes = targets.iter().flatten().count();
+                    ui.horizontal(|ui| {
+                        ui.label(format!("{} of {} entries will be renamed.", changes, self.tree_entries.len()));
+                        if ui.add_enabled(changes > 0, egui::Button::new(tr("Rename tree"))).clicked() {
+                            run = true;
+                        }
+                    });
+                    let palette = self.theme.palette();
+                    egui::ScrollArea::vertical().id_source("tree_rows").show(ui, |ui| {
+                        for (e, t) in self.tree_entries.iter().zip(&targets) {
+                            let name = e.path.file_name().unwrap_or_default().to_string_lossy();
+                            let indent = "    ".repeat(e.depth - 1);
+                            let icon = if e.is_dir { "📁" } else { "" };
+                            match t {
+                                Some(t) => {
+                                    let issues = self.profile_for(e.path.parent()).check_name(t);
+                                    let color = if issues.is_empty() { palette.accent } else { palette.error };
+                                    let label = ui.label(
+                                        RichText::new(format!("{}{}{} → {}", indent, icon, name, t)).color(color),
+                                    );
+                                    if !issues.is_empty() {
+                                        label.on_hover_text(issues.join("\n"));
+                                    }
+                                }
+                                None => {
+                                    ui.label(RichText::new(format!("{}{}{}", indent, icon, name)).color(palette.dim));
+                                }
                             }
-                            "down" => {
-                                self.blocks.swap(idx, idx + 1);
-                                idx += 1;
-                                continue;
+                        }
+                    });
+                });
+            if rescan {
+                self.scan_tree();
+            }
+            if run {
+                self.rename_tree();
+            }
+            self.show_tree = open;
+        }
+
+        if let Some(sandbox) = &self.sandbox {
+            let mut open = true;
+            let mut delete = false;
+            egui::Window::new(tr("Test on copies"))
+                .open(&mut open)
+                .default_size([600.0, 400.0])
+                .show(ctx, |ui| {
+                    ui.label(format!("Renamed copies in {}", sandbox.dir.display()));
+                    ui.horizontal(|ui| {
+                        if ui.button(tr("Open folder")).clicked() {
+                            if let Err(e) = open_in_file_manager(&sandbox.dir) {
+                                self.log.error(format!("Failed to open {:?}: {}", sandbox.dir, e));
                             }
-                            "del" => {
-                                self.blocks.remove(idx);
-                                continue;
+                        }
+                        if ui.button(tr("Delete copies")).clicked() {
+                            delete = true;
+                        }
+                    });
+                    ui.separator();
+                    egui::ScrollArea::vertical().id_source("sandbox_rows").show(ui, |ui| {
+                        egui::Grid::new("sandbox_grid").striped(true).show(ui, |ui| {
+                            for (orig, result) in &sandbox.results {
+                                ui.label(orig);
+                                ui.label("→");
+                                ui.label(result);
+                                ui.end_row();
                             }
-                            _ => {}
+                        });
+                    });
+                });
+            if delete {
+                if let Err(e) = fs::remove_dir_all(&sandbox.dir) {
+                    self.log.error(format!("Failed to delete {:?}: {}", sandbox.dir, e));
+                }
+            }
+            if !open || delete {
+                self.sandbox = None;
+            }
+        }
+
+        if self.show_history {
+            let mut open = true;
+            let mut revert = None;
+            egui::Window::new(tr("History"))
+                .open(&mut open)
+                .default_size([700.0, 400.0])
+                .show(ctx, |ui| {
+                    if ui.button(tr("Reload")).clicked() {
+                        self.history = Self::load_history();
+                    }
+                    if self.history.is_empty() {
+                        ui.label(tr("No history yet."));
+                    }
+                    egui::ScrollArea::vertical().id_source("history_rows").show(ui, |ui| {
+                        for (i, batch) in self.history.iter().enumerate().rev() {
+                            let first = &batch[0];
+                            ui.horizontal(|ui| {
+                                if ui.button(tr("Revert")).clicked() {
+                                    revert = Some(i);
+                                }
+                                ui.collapsing(
+                                    format!("{}  {}  ({} files)", first.time, first.action, batch.len()),
+                                    |ui| {
+                                        for r in batch {
+                                            ui.label(format!(
+                                                "{} → {}",
+                                                r.old.display(),
+                                                r.new.display()
+                                            ));
+                                        }
+                                    },
+                                );
+                            });
                         }
+                    });
+                });
+            if let Some(i) = revert {
+                let records = self.history[i].clone();
+                self.revert_batch(&records);
+                self.history = Self::load_history();
+            }
+            self.show_history = open;
+        }
+
+        if let Some(batch) = &self.pending_batch {
+            let palette = self.theme.palette();
+            // Overwrite strategy destroying files needs an explicit tick
+            let trash = self.settings.trash_replaced;
+            let needs_ack = batch.overwrites > 0 && self.collision == CollisionStrategy::Overwrite && !trash;
+            let mut confirm = false;
+            let mut cancel = false;
+            let backdrop = modal_backdrop(ctx);
+            let dialog = egui::Window::new(RichText::new(tr("Confirm rename")).strong())
+                .id(egui::Id::new("confirm_dialog"))
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    let verb = if self.copy_mode { "copied with new names" } else { "renamed" };
+                    ui.label(format!("{} file(s) will be {}.", batch.pairs.len(), verb));
+                    if batch.overwrites > 0 && trash {
+                        ui.label(
+                            RichText::new(format!("{} existing file(s) will go to the trash.", batch.overwrites))
+                                .color(palette.warn),
+                        );
+                    } else if batch.overwrites > 0 {
+                        ui.label(
+                            RichText::new(format!("{} existing file(s) will be replaced.", batch.overwrites))
+                                .color(palette.error),
+                        );
                     }
-                    self.blocks[idx] = new_blk;
-                    idx += 1;
+                    if batch.cross_dir > 0 && !self.copy_mode {
+                        ui.label(
+                            RichText::new(format!("{} file(s) will move to another folder.", batch.cross_dir))
+                                .color(palette.warn),
+                        );
+                    }
+                    if needs_ack {
+                        ui.checkbox(&mut self.confirm_ack, tr("I understand the replaced files will be lost"));
+                    }
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        if ui
+                            .add_enabled(
+                                !needs_ack || self.confirm_ack,
+                                egui::Button::new(if self.copy_mode { "Copy" } else { "Rename" }),
+                            )
+                            .clicked()
+                        {
+                            confirm = true;
+                        }
+                        if ui.button(tr("Cancel")).clicked() || ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                            cancel = true;
+                        }
+                    });
+                });
+            ctx.move_to_top(backdrop);
+            if let Some(dialog) = dialog {
+                ctx.move_to_top(dialog.response.layer_id);
+            }
+            if confirm {
+                if let Some(batch) = self.pending_batch.take() {
+                    self.start_batch(batch);
                 }
+            } else if cancel {
+                self.pending_batch = None;
+                self.log.warn("Rename cancelled.");
+            }
+        }
 
-                right.horizontal(|ui| {
-                    if ui.button("Add Literal").clicked() {
-                        self.blocks.push(Block::Literal(String::new()));
-                    }
-                    if ui.button("Add Number").clicked() {
-                        self.blocks.push(Block::Number {
-                            width: 4,
-                            start: 1,
-                            step: 1,
+        if let Some(prompt) = &mut self.collision_prompt {
+            let palette = self.theme.palette();
+            let name = |p: &Path| p.file_name().unwrap_or_default().to_string_lossy().to_string();
+            let k = prompt.current().unwrap_or_default();
+            let (i, holder) = prompt.conflicts[k];
+            let mut answer = None;
+            let mut manual_ok = false;
+            let mut cancel = false;
+            let backdrop = modal_backdrop(ctx);
+            let dialog = egui::Window::new(RichText::new(tr("Name conflict")).strong())
+                .id(egui::Id::new("collision_dialog"))
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    ui.label(format!("Conflict {} of {}", k + 1, prompt.conflicts.len()));
+                    let (from, to) = (name(&prompt.origs[i]), name(&prompt.final_paths[i]));
+                    ui.label(RichText::new(format!("{} → {}", from, to)).strong());
+                    let reason = match holder {
+                        Some(j) if prompt.origs[j] == prompt.final_paths[j] || self.copy_mode => {
+                            format!("{} keeps this name.", name(&prompt.origs[j]))
+                        }
+                        Some(j) => format!("{} is renamed to this name too.", name(&prompt.origs[j])),
+                        None => "A file with this name exists.".into(),
+                    };
+                    ui.label(RichText::new(reason).color(palette.warn));
+                    ui.separator();
+                    if let Some(manual) = &mut prompt.manual {
+                        ui.horizontal(|ui| {
+                            ui.label(tr("New name:"));
+                            let edit = ui.text_edit_singleline(manual);
+                            edit.request_focus();
+                            if edit.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                                manual_ok = true;
+                            }
                         });
-                    }
-                    if ui.button("Add Date").clicked() {
-                        self.blocks.push(Block::Date {
-                            format: "%Y%m%d".into(),
+                        if let Some(e) = self.collision_error.as_ref() {
+                            ui.label(RichText::new(e).color(palette.error));
+                        }
+                        ui.horizontal(|ui| {
+                            manual_ok |= ui.button("OK").clicked();
+                            if ui.button(tr("Back")).clicked() {
+                                prompt.manual = None;
+                                self.collision_error = None;
+                            }
                         });
+                        return;
                     }
-                    if ui.button("Add Original").clicked() {
-                        self.blocks.push(Block::Original { mode: OriginalMode::Keep } );
-                    }
-                    if ui.button("Add Extension").clicked() {
-                        self.blocks.push(Block::Extension);
+                    ui.horizontal(|ui| {
+                        // two files of the batch on one name: one of them would be lost
+                        let overwrite = ui
+                            .add_enabled(holder.is_none(), egui::Button::new(tr("Overwrite")))
+                            .on_hover_text(tr("The existing file is replaced (cannot be undone)"))
+                            .on_disabled_hover_text(tr("Another file of the batch has this name"));
+                        if overwrite.clicked() {
+                            answer = Some(CollisionStrategy::Overwrite);
+                        }
+                        if ui.button(tr("Skip")).on_hover_text(tr("This file keeps its name")).clicked() {
+                            answer = Some(CollisionStrategy::Skip);
+                        }
+                        if ui.button(tr("Suffix")).on_hover_text(tr("A number is appended")).clicked() {
+                            answer = Some(CollisionStrategy::Suffix);
+                        }
+                        if ui.button(tr("Rename manually")).clicked() {
+                            prompt.manual = Some(name(&prompt.final_paths[i]));
+                        }
+                    });
+                    ui.checkbox(&mut prompt.apply_to_all, tr("Apply to all remaining conflicts"));
+                    ui.separator();
+                    if ui.button(tr("Cancel")).clicked() || ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                        cancel = true;
                     }
                 });
-                right.separator();
+            ctx.move_to_top(backdrop);
+            if let Some(dialog) = dialog {
+                ctx.move_to_top(dialog.response.layer_id);
+            }
+            if let Some(answer) = answer {
+                self.answer_collision(answer);
+            } else if manual_ok {
+                self.collision_error = self.rename_collision_manually().err();
+            } else if cancel {
+                self.collision_prompt = None;
+                self.collision_error = None;
+                self.log.warn("Rename cancelled.");
+            }
+        }
 
-                right.label("Collision strategy:");
-                right.horizontal(|ui| {
-                    ui.radio_value(&mut self.collision, CollisionStrategy::Overwrite, "Overwrite");
-                    ui.radio_value(&mut self.collision, CollisionStrategy::Skip, "Skip");
-                    ui.radio_value(&mut self.collision, CollisionStrategy::Suffix, "Suffix (1)");
+        if let Some(run) = &self.running {
+            let (done, total) = run.progress.counts();
+            let files = run.batch.pairs.len();
+            // a rename takes two steps per file, a copy one
+            let per_file = if total > files { 2 } else { 1 };
+            let title = if self.copy_mode { "Copying..." } else { "Renaming..." };
+            let backdrop = modal_backdrop(ctx);
+            let dialog = egui::Window::new(RichText::new(title).strong())
+                .id(egui::Id::new("progress_dialog"))
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    ui.add(
+                        egui::ProgressBar::new(if total == 0 { 0.0 } else { done as f32 / total as f32 })
+                            .desired_width(320.0)
+                            .text(format!("{} / {}", done / per_file, files)),
+                    );
+                    if run.progress.cancelled() {
+                        ui.label(tr("Cancelling, putting the files back..."));
+                    } else if ui.button(tr("Cancel")).clicked() {
+                        run.progress.cancel();
+                    }
                 });
-                //right.checkbox(&mut self.use_mtime_for_date, "Use file mtime for date");
-
-                right.separator();
-                right.label(RichText::new("Preview").strong());
-                egui::ScrollArea::vertical()
-                    .max_height(300.0)
-                    .auto_shrink([false, false])
-                    .id_source("preview")
-                    .show(right, |ui| {
-                        let w = ui.available_width();
-                        for (old, new_name) in self.preview_table().iter() {
-                            let txt = {
-                                let chars: Vec<char> = old.chars().collect();
-                                if chars.len() > 20 {
-                                    let first_10: String = chars[..10].iter().collect();
-                                    let last_9: String = chars[chars.len().saturating_sub(9)..].iter().collect();
-                                    format!("{}…{}", first_10, last_9)
-                                } else {
-                                    old.clone()
-                                }
-                            };
-                            let lbl = ui.label(txt);
-                            lbl.on_hover_text(old);
+            ctx.move_to_top(backdrop);
+            if let Some(dialog) = dialog {
+                ctx.move_to_top(dialog.response.layer_id);
+            }
+        }
 
-                            ui.horizontal(|ui| {
-                                ui.label("→");
-                                ui.add_sized(
-                                    [w * 0.8, 0.0],
-                                    egui::Label::new(
-                                        RichText::new(new_name.clone())
-                                            .color(egui::Color32::BLUE),
-                                    )
-                                    .wrap(true),
-                                );
-                            });
-                            ui.separator();
+        if let Some(report) = &self.error_report {
+            let backdrop = modal_backdrop(ctx);
+            let mut close = false;
+            let dialog = egui::Window::new(RichText::new(&report.title).strong())
+                .id(egui::Id::new("error_dialog"))
+                .collapsible(false)
+                .resizable(true)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    ui.set_max_width(560.0);
+                    ui.label(RichText::new(&report.summary).color(self.theme.palette().error));
+                    if !report.files.is_empty() {
+                        egui::ScrollArea::vertical().max_height(220.0).id_source("error_files").show(ui, |ui| {
+                            for f in &report.files {
+                                ui.label(RichText::new(f).monospace());
+                            }
+                        });
+                    }
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        if ui.button(tr("Copy details")).clicked() {
+                            ui.output_mut(|o| o.copied_text = report.details());
+                        }
+                        if ui.button(tr("Close")).clicked() {
+                            close = true;
                         }
                     });
+                });
+            ctx.move_to_top(backdrop);
+            if let Some(dialog) = dialog {
+                ctx.move_to_top(dialog.response.layer_id);
+            }
+            if close {
+                self.error_report = None;
+            }
+        }
 
-                // Persist template UI
-                right.separator();
-                right.label(RichText::new("Save / Load Template").strong());
-                right.horizontal(|ui| {
-                    ui.label("Name:");
-                    ui.text_edit_singleline(&mut self.current_template_name);
-                    if ui.button("Save").clicked() && !self.current_template_name.is_empty() {
-                        let tpl = Template {
-                            name: self.current_template_name.clone(),
-                            blocks: self.blocks.clone(),
-                            collision: self.collision,
-                            use_mtime_for_date: self.use_mtime_for_date,
+        // after the error and elevation dialogs of a failed batch
+        if let Some(summary) = self.batch_summary.as_ref().filter(|_| {
+            self.error_report.is_none() && self.elevation_offer.is_none() && self.running.is_none()
+        }) {
+            let palette = self.theme.palette();
+            let backdrop = modal_backdrop(ctx);
+            let mut show = None;
+            let mut close = false;
+            let title = match (&summary.error, summary.copy) {
+                (Some(_), false) => "Rename failed",
+                (Some(_), true) => "Copy failed",
+                (None, false) => "Rename finished",
+                (None, true) => "Copy finished",
+            };
+            let dialog = egui::Window::new(RichText::new(tr(title)).strong())
+                .id(egui::Id::new("summary_dialog"))
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    ui.set_max_width(480.0);
+                    if let Some(error) = &summary.error {
+                        ui.label(RichText::new(error).color(palette.error));
+                        ui.separator();
+                    }
+                    let done = if summary.copy { "Copied" } else { "Renamed" };
+                    egui::Grid::new("summary_counts").num_columns(2).show(ui, |ui| {
+                        let mut row = |label: &'static str, n: usize, color: Option<egui::Color32>| {
+                            ui.label(tr(label));
+                            let text = RichText::new(n.to_string()).strong();
+                            ui.label(match color.filter(|_| n > 0) {
+                                Some(c) => text.color(c),
+                                None => text,
+                            });
+                            ui.end_row();
                         };
-                        if let Some(pos) = self
-                            .saved_templates
-                            .iter()
-                            .position(|t| t.name == tpl.name)
-                        {
-                            self.saved_templates[pos] = tpl;
-                        } else {
-                            self.saved_templates.push(tpl);
+                        if summary.error.is_none() {
+                            row(done, summary.renamed, None);
+                            row("Numbered", summary.suffixed, None);
                         }
-                        self.save_templates();
+                        row("Skipped", summary.skipped.len(), Some(palette.warn));
+                        row("Unchanged", summary.unchanged, None);
+                        row("Failed", summary.failed.len(), Some(palette.error));
+                        ui.label(tr("Time"));
+                        ui.label(format!("{:.1} s", summary.elapsed.as_secs_f32()));
+                        ui.end_row();
+                    });
+                    if !summary.skipped.is_empty() {
+                        ui.collapsing(tr("Skipped files"), |ui| {
+                            egui::ScrollArea::vertical().max_height(160.0).id_source("summary_skipped").show(ui, |ui| {
+                                for (path, reason) in &summary.skipped {
+                                    let name = path.file_name().unwrap_or_default().to_string_lossy();
+                                    ui.label(format!("{} ({})", name, tr(reason)));
+                                }
+                            });
+                        });
                     }
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        if !summary.failed.is_empty() && ui.button(tr("Show failed files")).clicked() {
+                            show = Some(summary.failed.clone());
+                        }
+                        if !summary.skipped.is_empty() && ui.button(tr("Show skipped files")).clicked() {
+                            show = Some(summary.skipped.iter().map(|(path, _)| path.clone()).collect());
+                        }
+                        if ui.button(tr("Close")).clicked() || ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                            close = true;
+                        }
+                    });
                 });
-                right.horizontal(|ui| {
-                    ui.label("Load:");
-                    ComboBox::from_id_source("template_load")
-                        .selected_text(&self.current_template_name)
-                        .show_ui(ui, |ui| {
-                            for tpl in &self.saved_templates {
-                                ui.selectable_value(
-                                    &mut self.current_template_name,
-                                    tpl.name.clone(),
-                                    &tpl.name,
-                                );
-                            }
-                        });
-                    if ui.button("Apply").clicked() {
-                        if let Some(tpl) = self
-                            .saved_templates
-                            .iter()
-                            .find(|t| t.name == self.current_template_name)
-                        {
-                            self.blocks = tpl.blocks.clone();
-                            self.collision = tpl.collision;
-                            self.use_mtime_for_date = tpl.use_mtime_for_date;
+            ctx.move_to_top(backdrop);
+            if let Some(dialog) = dialog {
+                ctx.move_to_top(dialog.response.layer_id);
+            }
+            if let Some(paths) = show {
+                self.select_paths(&paths);
+                close = true;
+            }
+            if close {
+                self.batch_summary = None;
+            }
+        }
+
+        if let Some(session) = &self.session_offer {
+            let count = session.files.len();
+            let mut restore = None;
+            egui::Window::new(tr("Restore last session?"))
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    ui.label(format!("The last session had {} file(s) in the list.", count));
+                    ui.add_space(8.0);
+                    ui.horizontal(|ui| {
+                        if ui.button(tr("Restore")).on_hover_text(tr("Load its files and template")).clicked() {
+                            restore = Some(true);
                         }
-                    }
-                    if ui.button("Delete").clicked() {
-                        if let Some(pos) = self
-                            .saved_templates
-                            .iter()
-                            .position(|t| t.name == self.current_template_name)
-                        {
-                            self.saved_templates.remove(pos);
-                            self.current_template_name.clear();
-                            self.save_templates();
-                            self.messages.push("Template deleted.".into());
+                        if ui.button(tr("Discard")).clicked() {
+                            restore = Some(false);
                         }
+                    });
+                });
+            if let Some(restore) = restore {
+                if let Some(session) = self.session_offer.take() {
+                    if restore {
+                        self.restore_session(session);
+                    } else {
+                        let _ = fs::remove_file(Self::session_path());
                     }
+                }
+            }
+        }
+
+        if let Some((_, journal)) = self.interrupted.first() {
+            let states = journal.states();
+            let count = |s: StepState| states.iter().filter(|&&x| x == s).count();
+            let mut action = None;
+            let mut later = false;
+            egui::Window::new(tr("Interrupted rename"))
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    ui.label(format!(
+                        "A rename of {} file(s) did not finish: {} renamed, {} under a temporary name, {} untouched.",
+                        states.len(),
+                        count(StepState::Done),
+                        count(StepState::Staged),
+                        count(StepState::Original),
+                    ));
+                    egui::ScrollArea::vertical().max_height(160.0).id_source("interrupted_files").show(ui, |ui| {
+                        for (step, state) in journal.steps.iter().zip(&states) {
+                            ui.label(
+                                RichText::new(format!(
+                                    "{:?}  {} → {}",
+                                    state,
+                                    step.orig.display(),
+                                    step.target.file_name().unwrap_or_default().to_string_lossy()
+                                ))
+                                .monospace(),
+                            );
+                        }
+                    });
+                    ui.add_space(8.0);
+                    ui.horizontal(|ui| {
+                        if ui.button(tr("Complete")).on_hover_text(tr("Give every file its new name")).clicked() {
+                            action = Some(true);
+                        }
+                        if ui.button(tr("Roll back")).on_hover_text(tr("Give every file its old name back")).clicked() {
+                            action = Some(false);
+                        }
+                        if ui.button(tr("Later")).on_hover_text(tr("Ask again at the next start")).clicked() {
+                            later = true;
+                        }
+                    });
                 });
-            });
+            if let Some(complete) = action {
+                self.resolve_interrupted(0, complete);
+            } else if later {
+                self.interrupted.remove(0);
+            }
+        }
 
-            ui.separator();
-            egui::ScrollArea::vertical()
-                .max_height(120.0)
-                .show(ui, |ui| {
-                    for m in &self.messages {
-                        ui.label(m);
+        if let Some(pending) = &self.pending_elevation {
+            let count = pending.pairs.len();
+            let mut cancel = false;
+            egui::Window::new(tr("Waiting for administrator rights"))
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.label(format!("{} file(s) are being renamed by the elevated helper.", count));
+                    });
+                    ui.label(tr("Answer the administrator prompt, or cancel if it was dismissed."));
+                    ui.add_space(8.0);
+                    if ui.button(tr("Cancel")).clicked() {
+                        cancel = true;
                     }
                 });
-        });
+            if cancel {
+                self.cancel_elevation();
+            }
+        }
+
+        if let Some(pairs) = &self.elevation_offer {
+            let count = pairs.len();
+            let mut retry = false;
+            let mut close = false;
+            egui::Window::new(tr("Access denied"))
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    ui.label(format!(
+                        "{} file(s) are in a protected folder and were not renamed.",
+                        count
+                    ));
+                    ui.label(tr("Retry this batch with administrator rights?"));
+                    ui.add_space(8.0);
+                    ui.horizontal(|ui| {
+                        if ui.button(tr("Retry as administrator")).clicked() {
+                            retry = true;
+                        }
+                        if ui.button(tr("Cancel")).clicked() {
+                            close = true;
+                        }
+                    });
+                });
+            if retry {
+                if let Some(pairs) = self.elevation_offer.take() {
+                    self.run_elevated(pairs);
+                }
+            } else if close {
+                self.elevation_offer = None;
+            }
+        }
 
         if self.loading_phase == LoadingPhase::AddingFiles || self.loading_phase == LoadingPhase::LoadingThumbs {
             ctx.request_repaint();
@@ -1222,7 +10372,385 @@ impl eframe::App for BulkRename {
     }
 }
 
+/// Decode `%XX` escapes; invalid escapes are kept as they are.
+fn percent_decode(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = |b: u8| (b as char).to_digit(16).map(|d| d as u8);
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let (Some(hi), Some(lo)) = (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                out.push(hi << 4 | lo);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    out
+}
+
+/// Local path of a `file://` URI (as handed out by file managers and the XDG portal).
+/// Plain absolute paths are passed through; other URIs give None.
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let uri = uri.trim();
+    let Some(rest) = uri.strip_prefix("file://") else {
+        let path = PathBuf::from(uri);
+        return path.is_absolute().then_some(path);
+    };
+    // optional host part: file://localhost/...
+    let rest = rest.strip_prefix("localhost").unwrap_or(rest);
+    if !rest.starts_with('/') {
+        return None;
+    }
+    let bytes = percent_decode(rest);
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Some(PathBuf::from(std::ffi::OsStr::from_bytes(&bytes)))
+    }
+    #[cfg(not(unix))]
+    {
+        // file:///C:/dir/file → C:/dir/file
+        let s = String::from_utf8_lossy(&bytes).into_owned();
+        let s = match s.as_bytes() {
+            [b'/', _, b':', ..] => s[1..].to_string(),
+            _ => s,
+        };
+        Some(PathBuf::from(s))
+    }
+}
+
+/// Paths in a `text/uri-list` (one URI per line, `#` lines are comments).
+fn paths_from_uri_list(text: &str) -> Vec<PathBuf> {
+    text.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .filter_map(uri_to_path)
+        .collect()
+}
+
+/// Path of a dropped item; some backends hand over the URI instead of a path.
+fn dropped_path(file: egui::DroppedFile) -> Option<PathBuf> {
+    match file.path {
+        Some(path) => match path.to_str() {
+            Some(s) if s.starts_with("file://") => uri_to_path(s),
+            _ => Some(path),
+        },
+        None => uri_to_path(&file.name),
+    }
+}
+
+/// System fonts tried as fallbacks, one group per script; the first existing file of
+/// each group is used (the bundled Noto Sans JP lacks Hangul and many Hanzi).
+const SYSTEM_FONT_FALLBACKS: &[(&str, &[&str])] = &[
+    (
+        "fallback_zh",
+        &[
+            "C:\\Windows\\Fonts\\msyh.ttc",
+            "C:\\Windows\\Fonts\\simsun.ttc",
+            "/System/Library/Fonts/PingFang.ttc",
+            "/System/Library/Fonts/STHeiti Light.ttc",
+            "/usr/share/fonts/opentype/noto/NotoSansCJK-Regular.ttc",
+            "/usr/share/fonts/noto-cjk/NotoSansCJK-Regular.ttc",
+            "/usr/share/fonts/google-noto-cjk/NotoSansCJK-Regular.ttc",
+            "/usr/share/fonts/truetype/wqy/wqy-microhei.ttc",
+            "/usr/share/fonts/wenquanyi/wqy-microhei/wqy-microhei.ttc",
+        ],
+    ),
+    (
+        "fallback_ko",
+        &[
+            "C:\\Windows\\Fonts\\malgun.ttf",
+            "/System/Library/Fonts/AppleSDGothicNeo.ttc",
+            "/usr/share/fonts/truetype/nanum/NanumGothic.ttf",
+            "/usr/share/fonts/nanum/NanumGothic.ttf",
+        ],
+    ),
+    (
+        "fallback_symbols",
+        &[
+            "C:\\Windows\\Fonts\\seguisym.ttf",
+            "C:\\Windows\\Fonts\\seguiemj.ttf",
+            "/System/Library/Fonts/Apple Symbols.ttf",
+            "/Library/Fonts/Arial Unicode.ttf",
+            "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+            "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+        ],
+    ),
+];
+
+/// Font file contents egui can parse (it panics on bad font data).
+fn read_font(path: &Path) -> Option<Vec<u8>> {
+    let bytes = fs::read(path).ok()?;
+    ab_glyph::FontRef::try_from_slice_and_index(&bytes, 0).ok()?;
+    Some(bytes)
+}
+
+/// Add fallback fonts behind the bundled Japanese font: first any `.ttf`/`.otf`/`.ttc`
+/// the user put in `<config dir>/fonts`, then the system fonts found from
+/// `SYSTEM_FONT_FALLBACKS`. Returns the names of the loaded fonts.
+fn add_fallback_fonts(fonts: &mut egui::FontDefinitions) -> Vec<String> {
+    let mut user: Vec<PathBuf> = fs::read_dir(BulkRename::config_dir().join("fonts"))
+        .map(|rd| rd.flatten().map(|e| e.path()).collect())
+        .unwrap_or_default();
+    user.retain(|p| {
+        p.extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| ["ttf", "otf", "ttc"].contains(&e.to_ascii_lowercase().as_str()))
+    });
+    user.sort();
+
+    let mut loaded = Vec::new();
+    let mut user_names = Vec::new();
+    for path in user {
+        if let Some(bytes) = read_font(&path) {
+            let name = format!("user_{}", path.file_name().unwrap_or_default().to_string_lossy());
+            fonts.font_data.insert(name.clone(), egui::FontData::from_owned(bytes));
+            user_names.push(name.clone());
+            loaded.push(name);
+        }
+    }
+    let mut system_names = Vec::new();
+    for (name, candidates) in SYSTEM_FONT_FALLBACKS {
+        if let Some(bytes) = candidates.iter().find_map(|c| read_font(Path::new(c))) {
+            fonts.font_data.insert(name.to_string(), egui::FontData::from_owned(bytes));
+            system_names.push(name.to_string());
+            loaded.push(name.to_string());
+        }
+    }
+
+    for family in [egui::FontFamily::Proportional, egui::FontFamily::Monospace] {
+        let list = fonts.families.entry(family).or_default();
+        // user fonts right after the bundled one, system fonts last
+        let at = list.iter().position(|f| f == "noto_jp").map_or(0, |i| i + 1);
+        for (k, name) in user_names.iter().enumerate() {
+            list.insert(at + k, name.clone());
+        }
+        list.extend(system_names.iter().cloned());
+    }
+    loaded
+}
+
+/// Files handed over by Finder ("Open With", Services menu) or a second launch, loaded on the next frame.
+static OPENED_FILES: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+static OPENED_FILES_CTX: std::sync::OnceLock<egui::Context> = std::sync::OnceLock::new();
+// set by a second launch: bring this window to the front
+static RAISE_WINDOW: AtomicBool = AtomicBool::new(false);
+
+// in the per-user config dir: "<port> <token>" of the running window
+const INSTANCE_FILE: &str = "instance.lock";
+
+/// Hand `paths` to an already running window of this user. True when it took them.
+fn forward_to_running(paths: &[PathBuf]) -> bool {
+    use std::io::{BufRead, BufReader, Write};
+    let Ok(info) = fs::read_to_string(BulkRename::config_dir().join(INSTANCE_FILE)) else {
+        return false;
+    };
+    let Some((port, token)) = info.trim().split_once(' ') else { return false };
+    let Ok(port) = port.parse::<u16>() else { return false };
+    let addr = std::net::SocketAddr::from(([127, 0, 0, 1], port));
+    let Ok(mut stream) = TcpStream::connect_timeout(&addr, std::time::Duration::from_millis(500)) else {
+        return false;
+    };
+    let _ = stream.set_read_timeout(Some(std::time::Duration::from_secs(3)));
+    let Ok(json) = serde_json::to_string(paths) else { return false };
+    if writeln!(stream, "{}\n{}", token, json).is_err() {
+        return false;
+    }
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply).is_ok() && reply.trim() == "ok"
+}
+
+/// Listen for later launches on a free localhost port, published with a fresh token in a file
+/// only this user can read. Another user's window (or any other process) never gets the token.
+fn claim_instance() -> Option<(TcpListener, String)> {
+    use std::io::Write;
+    let listener = TcpListener::bind(("127.0.0.1", 0)).ok()?;
+    let token = new_api_token();
+    let port = listener.local_addr().ok()?.port();
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(BulkRename::config_dir().join(INSTANCE_FILE)).ok()?;
+    write!(file, "{} {}", port, token).ok()?;
+    Some((listener, token))
+}
+
+/// Accept files from later launches until the app exits.
+fn serve_instance(listener: TcpListener, token: String) {
+    for stream in listener.incoming().flatten() {
+        let _ = handle_instance_connection(stream, &token);
+    }
+}
+
+fn handle_instance_connection(stream: TcpStream, token: &str) -> std::io::Result<()> {
+    use std::io::{BufRead, BufReader, Write};
+    stream.set_read_timeout(Some(std::time::Duration::from_secs(3)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut hello = String::new();
+    reader.read_line(&mut hello)?;
+    if hello.trim() != token {
+        return Ok(());
+    }
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let paths: Vec<PathBuf> = serde_json::from_str(&line).map_err(std::io::Error::other)?;
+    writeln!(&stream, "ok")?;
+    RAISE_WINDOW.store(true, Ordering::Relaxed);
+    if let Some(ctx) = OPENED_FILES_CTX.get() {
+        ctx.request_repaint();
+    }
+    queue_opened_files(paths);
+    Ok(())
+}
+
+fn queue_opened_files(paths: Vec<PathBuf>) {
+    if paths.is_empty() {
+        return;
+    }
+    OPENED_FILES.lock().unwrap().extend(paths);
+    if let Some(ctx) = OPENED_FILES_CTX.get() {
+        ctx.request_repaint();
+    }
+}
+
+/// Local paths of an `NSArray<NSURL>`.
+#[cfg(target_os = "macos")]
+unsafe fn nsurl_paths(urls: *mut objc::runtime::Object) -> Vec<PathBuf> {
+    use objc::runtime::{Object, NO};
+    use objc::{msg_send, sel, sel_impl};
+    use std::ffi::{CStr, OsStr};
+    use std::os::raw::c_char;
+    use std::os::unix::ffi::OsStrExt;
+
+    if urls.is_null() {
+        return Vec::new();
+    }
+    let count: usize = msg_send![urls, count];
+    let mut paths = Vec::new();
+    for i in 0..count {
+        let url: *mut Object = msg_send![urls, objectAtIndex: i];
+        let is_file: objc::runtime::BOOL = msg_send![url, isFileURL];
+        if is_file == NO {
+            continue;
+        }
+        let repr: *const c_char = msg_send![url, fileSystemRepresentation];
+        if !repr.is_null() {
+            paths.push(PathBuf::from(OsStr::from_bytes(CStr::from_ptr(repr).to_bytes())));
+        }
+    }
+    paths
+}
+
+// -[NSApplicationDelegate application:openURLs:]
+#[cfg(target_os = "macos")]
+extern "C" fn app_open_urls(
+    _this: &objc::runtime::Object,
+    _cmd: objc::runtime::Sel,
+    _app: *mut objc::runtime::Object,
+    urls: *mut objc::runtime::Object,
+) {
+    queue_opened_files(unsafe { nsurl_paths(urls) });
+}
+
+// Services menu entry `renameFiles` (see macos/Info.plist)
+#[cfg(target_os = "macos")]
+extern "C" fn service_rename_files(
+    _this: &objc::runtime::Object,
+    _cmd: objc::runtime::Sel,
+    pboard: *mut objc::runtime::Object,
+    _user_data: *mut objc::runtime::Object,
+    _error: *mut objc::runtime::Object,
+) {
+    use objc::runtime::Object;
+    use objc::{class, msg_send, sel, sel_impl};
+    unsafe {
+        let classes: *mut Object = msg_send![class!(NSArray), arrayWithObject: class!(NSURL)];
+        let urls: *mut Object =
+            msg_send![pboard, readObjectsForClasses: classes options: std::ptr::null_mut::<Object>()];
+        queue_opened_files(nsurl_paths(urls));
+    }
+}
+
+/// Answer Finder's "open files" events. Must run before the event loop starts,
+/// since files opened at launch arrive while the app is still finishing launching.
+///
+/// winit's application delegate doesn't implement `application:openURLs:`, so the
+/// method is added to NSObject, which the delegate inherits from.
+#[cfg(target_os = "macos")]
+fn install_open_handler() {
+    use objc::runtime::{class_addMethod, Class, Imp};
+    use objc::{class, sel, sel_impl};
+    type OpenUrls = extern "C" fn(
+        &objc::runtime::Object,
+        objc::runtime::Sel,
+        *mut objc::runtime::Object,
+        *mut objc::runtime::Object,
+    );
+    unsafe {
+        let nsobject = class!(NSObject) as *const Class as *mut Class;
+        let imp: Imp = std::mem::transmute::<OpenUrls, Imp>(app_open_urls);
+        class_addMethod(nsobject, sel!(application:openURLs:), imp, c"v@:@@".as_ptr());
+    }
+}
+
+/// Register the Services menu provider; NSApp exists once eframe creates the app.
+#[cfg(target_os = "macos")]
+fn install_services_provider() {
+    use objc::declare::ClassDecl;
+    use objc::runtime::{Object, Sel};
+    use objc::{class, msg_send, sel, sel_impl};
+    type RenameFiles = extern "C" fn(&Object, Sel, *mut Object, *mut Object, *mut Object);
+    unsafe {
+        let Some(mut decl) = ClassDecl::new("BulkReNameServices", class!(NSObject)) else {
+            return;
+        };
+        decl.add_method(
+            sel!(renameFiles:userData:error:),
+            service_rename_files as RenameFiles,
+        );
+        let provider: *mut Object = msg_send![decl.register(), new];
+        let app: *mut Object = msg_send![class!(NSApplication), sharedApplication];
+        let () = msg_send![app, setServicesProvider: provider];
+    }
+}
+
+/// Files and folders given on the command line ("Open with", `BulkReName a.jpg b.jpg`).
+/// Anything that isn't an existing path (flags, macOS `-psn_…`) is ignored.
+fn launch_paths(args: &[String]) -> Vec<PathBuf> {
+    args.iter()
+        .map(Path::new)
+        .filter(|p| p.exists())
+        .map(|p| std::path::absolute(p).unwrap_or_else(|_| p.to_path_buf()))
+        .collect()
+}
+
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some(CLI_ARG) {
+        std::process::exit(run_cli(&args[2..]));
+    }
+    if let Some(pos) = args.iter().position(|a| a == APPLY_PLAN_ARG) {
+        if let Some(plan) = args.get(pos + 1) {
+            apply_plan_file(Path::new(plan));
+        }
+        return;
+    }
+    let opened = launch_paths(args.get(1..).unwrap_or_default());
+    if !BulkRename::read_settings().allow_multiple {
+        if forward_to_running(&opened) {
+            return;
+        }
+        // no window of ours answered (none running, or a stale file): this one takes over
+        if let Some((listener, token)) = claim_instance() {
+            thread::spawn(move || serve_instance(listener, token));
+        }
+    }
+
     let bytes = include_bytes!("../BulkReName.png");
     let img = image::load_from_memory(bytes).expect("Failed to load icon");
     let (w, h) = img.dimensions();
@@ -1237,8 +10765,13 @@ fn main() {
         .with_min_inner_size(egui::Vec2::new(300.0, 250.0));
     let options = eframe::NativeOptions {
         viewport,
+        // size and position from the last run (the size above is for the first one)
+        persist_window: true,
         ..Default::default()
     };
+    #[cfg(target_os = "macos")]
+    install_open_handler();
+
     let result = eframe::run_native(
         "BulkReName",
         options,
@@ -1260,10 +10793,38 @@ fn main() {
                 .entry(FontFamily::Monospace)
                 .or_default()
                 .insert(0, "noto_jp".to_owned());
+            let fallbacks = add_fallback_fonts(&mut fonts);
             cc.egui_ctx.set_fonts(fonts);
 
+            let _ = OPENED_FILES_CTX.set(cc.egui_ctx.clone());
+            #[cfg(target_os = "macos")]
+            install_services_provider();
+
             let mut app = BulkRename::default();
+            if let Some(layout) = cc.storage.and_then(|s| eframe::get_value::<Layout>(s, LAYOUT_KEY)) {
+                app.column_split = layout.column_split.clamp(0.2, 0.8);
+                app.show_thumbnails = layout.show_thumbnails;
+                app.list_columns = layout.columns;
+            }
             app.load_templates();
+            app.load_settings();
+            app.collision = app.settings.collision;
+            app.apply_settings(&cc.egui_ctx);
+            app.load_undo_journal();
+            app.check_interrupted();
+            app.check_session();
+            if !opened.is_empty() {
+                app.start_loading(opened);
+            }
+            if app.settings.check_updates {
+                app.check_for_updates(&cc.egui_ctx, true);
+            }
+            if app.settings.watch_at_start {
+                app.start_watch_folder(&cc.egui_ctx);
+            }
+            if !fallbacks.is_empty() {
+                app.log.info(format!("Fallback fonts: {}", fallbacks.join(", ")));
+            }
             Box::new(app)
         }),
     );